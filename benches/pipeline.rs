@@ -0,0 +1,151 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#[macro_use]
+extern crate criterion;
+extern crate pitot;
+
+use criterion::Criterion;
+use pitot::config::Config;
+use pitot::pitot::clock::SystemClock;
+use pitot::pitot::handle::{BasicHandle, PushableHandle};
+use pitot::processor::traffic::{AddressType, AltitudeType, HeadingType, SpeedType, Target,
+                                Traffic, TrafficSource};
+use pitot::processor::{Processor, Report};
+use pitot::protocol::gdl90::{AltitudeFrame, GDL90};
+use pitot::sensor::gnss::ublox::parse_ubx_nav_pvt;
+use pitot::sensor::sdr::uat::parse_adsb_downlink;
+use pitot::sensor::sdr::TrafficData;
+use pitot::sensor::SensorData;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+const NUM_TARGETS: u32 = 500;
+
+fn bench_parse_adsb_downlink(c: &mut Criterion) {
+    let payload = [
+        11, 43, 3, 200, 53, 69, 117, 82, 61, 248, 8, 22, 16, 238, 31, 192, 17, 5, 196, 230, 196,
+        230, 196, 10, 218, 130, 3, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    c.bench_function("parse_adsb_downlink", move |b| {
+        b.iter(|| parse_adsb_downlink(&payload))
+    });
+}
+
+fn bench_parse_ubx_nav_pvt(c: &mut Criterion) {
+    let payload = [
+        148, 99, 86, 7, 225, 7, 5, 22, 10, 11, 24, 55, 60, 3, 0, 0, 88, 166, 244, 5, 3, 0, 6, 6,
+        28, 27, 253, 182, 131, 185, 113, 22, 117, 202, 255, 255, 175, 63, 0, 0, 45, 71, 1, 0, 91,
+        36, 7, 0, 150, 253, 255, 255, 47, 1, 0, 0, 117, 0, 0, 0, 176, 2, 0, 0, 0, 0, 0, 0, 79, 15,
+        0, 0, 128, 168, 18, 1, 105, 3, 0, 0, 248, 74, 35, 0, 0, 0, 0, 0, 0, 0, 246, 255,
+    ];
+
+    c.bench_function("parse_ubx_nav_pvt", move |b| {
+        b.iter(|| parse_ubx_nav_pvt(&payload))
+    });
+}
+
+fn bench_generate_traffic(c: &mut Criterion) {
+    let clock = Instant::now();
+    let mut trfc = Target::new(
+        (0xA1B2C3, AddressType::ADSBICAO),
+        clock,
+        TrafficSource::ES,
+        None,
+    );
+
+    trfc.altitude = Some((12375, AltitudeType::Baro, clock));
+    trfc.gnss_delta = Some(1000);
+    trfc.heading = Some((123, HeadingType::True, clock));
+    trfc.speed = Some((66, SpeedType::GS, clock));
+    trfc.vs = Some((-1000, clock));
+    trfc.callsign = Some("TEST123".into());
+    trfc.lat_lon = Some(((37.750374, -122.52676), clock));
+    trfc.nic = Some(7);
+    trfc.nacp = Some(9);
+
+    c.bench_function("GDL90::generate_traffic", move |b| {
+        b.iter(|| GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Pressure, true))
+    });
+}
+
+fn bench_prepare_payload(c: &mut Criterion) {
+    // worst case, every byte needs to be escaped
+    let buf = [0x7E_u8; 28 + 2];
+
+    c.bench_function("GDL90::prepare_payload", move |b| {
+        b.iter(|| GDL90::prepare_payload(&mut buf.clone()))
+    });
+}
+
+fn traffic_data(i: u32) -> TrafficData {
+    TrafficData {
+        addr: (0xA00000 + i, AddressType::ADSBICAO),
+        altitude: Some((3000 + i as i32 * 100, AltitudeType::Baro)),
+        gnss_delta: None,
+        heading: Some(((i * 7 % 360) as u16, HeadingType::True)),
+        speed: Some((120, SpeedType::GS)),
+        vs: Some(0),
+        squawk: Some(1200),
+        callsign: Some(format!("N{}", i)),
+        category: Some(1),
+        lat_lon: Some((37.0 + i as f32 * 0.01, -122.0)),
+        nic: Some(8),
+        nacp: Some(9),
+        nacv: None,
+        sil: None,
+        ident: None,
+        capability: None,
+        on_ground: Some(false),
+        source: TrafficSource::ES,
+        received: None,
+        trace: None,
+        raw: None,
+    }
+}
+
+fn bench_traffic_update(c: &mut Criterion) {
+    let updates: Vec<SensorData> = (0..NUM_TARGETS)
+        .map(|i| SensorData::Traffic(traffic_data(i)))
+        .collect();
+
+    c.bench_function("Traffic update, 500 targets", move |b| {
+        let mut t = Traffic::new(&Config::default());
+        let mut reports = VecDeque::<Report>::new();
+
+        b.iter(|| {
+            {
+                let mut bh = BasicHandle::new(10, &SystemClock);
+                let mut h = PushableHandle::new(&mut bh, &mut reports);
+
+                t.run(&mut h, updates.iter().chain([].iter()));
+            }
+
+            reports.clear();
+        });
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_parse_adsb_downlink,
+    bench_parse_ubx_nav_pvt,
+    bench_generate_traffic,
+    bench_prepare_payload,
+    bench_traffic_update
+);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Records when Pitot was built as `PITOT_BUILD_TIME` (UNIX time), GNSS time
+//! earlier than that can not be right. `SOURCE_DATE_EPOCH` is honored for
+//! reproducible builds.
+
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let build_time = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+
+    println!("cargo:rustc-env=PITOT_BUILD_TIME={}", build_time);
+}
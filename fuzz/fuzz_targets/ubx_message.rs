@@ -0,0 +1,32 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate nom;
+extern crate pitot;
+
+use nom::IResult;
+use pitot::sensor::gnss::ublox::parse_ubx_message;
+
+fuzz_target!(|data: &[u8]| {
+    // the serial line carries any number of messages with garbage in between
+    let mut rest = data;
+    while let IResult::Done(rem, _) = parse_ubx_message(rest) {
+        rest = rem;
+    }
+});
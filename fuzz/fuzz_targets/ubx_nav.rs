@@ -0,0 +1,35 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate pitot;
+
+use pitot::sensor::gnss::ublox::{
+    parse_ubx_nav_dop, parse_ubx_nav_pvt, parse_ubx_nav_sat, parse_ubx_nav_sig,
+    parse_ubx_nav_timels,
+};
+
+fuzz_target!(|data: &[u8]| {
+    // payloads passed the checksum, but may be truncated or from a newer
+    // protocol version
+    parse_ubx_nav_pvt(data);
+    parse_ubx_nav_sat(data);
+    parse_ubx_nav_dop(data);
+    parse_ubx_nav_sig(data);
+    parse_ubx_nav_timels(data);
+});
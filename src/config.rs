@@ -0,0 +1,1015 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Optional user configuration, read once at startup from a JSON file.
+//! Every field has a sensible default so Pitot keeps working out of the box
+//! when the file is absent. Some defaults depend on the configured `region`.
+
+use processor::events::EVENT_NAMES;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use units::Units;
+
+const CONFIG_PATH: &str = "/boot/pitot.json";
+const CONFIG_PATH_ENV: &str = "PITOT_CONFIG";
+const DATA_DIR: &str = "/var/lib/pitot";
+const UPDATE_PUBLIC_KEY: &str = "/etc/pitot/update.pem";
+/// NOTAM-TFR, AIRMET, SIGMET, regional and CONUS NEXRAD and METAR/TAF/PIREP text
+const ESSENTIAL_PRODUCTS: [u16; 6] = [8, 11, 12, 63, 64, 413];
+const GDL90_PORT: u16 = 4000;
+const PEER_PORT: u16 = 30100;
+/// largest UDP payload not fragmented with a 1500 byte MTU
+const GDL90_MAX_DATAGRAM: usize = 1472;
+/// in minutes, a busy ground station keeps about 15 MB per minute
+const MAX_TRAFFIC_HISTORY: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where Pitot is flown, adjusts the defaults of region specific settings
+    pub region: Region,
+    /// Run as a fixed receiver instead of in an aircraft
+    pub ground_station: bool,
+    /// Receive UAT on 978 MHz, only used in the US
+    pub uat: bool,
+    /// Units of the unit named measurements sent over WebSocket
+    pub units: Units,
+    /// ICAO address of our own transponder, written as a hex string
+    /// (e.g. `"A1B2C3"`) in the config file
+    #[serde(deserialize_with = "deserialize_icao")]
+    pub ownship_icao: Option<u32>,
+    /// Squawk codes traffic is annotated with, besides the emergency codes
+    pub squawks: Vec<Squawk>,
+    /// Volumes traffic raises an event when entering, even on the ground
+    pub alert_zones: Vec<AlertZone>,
+    /// Aircraft raising an event when they show up
+    pub watch_list: Vec<Watch>,
+    /// Flight phases traffic alerts are held back in, never when unset
+    pub alert_inhibit: Option<AlertInhibit>,
+    /// Prefix GDL90 traffic callsigns with source/address type letters
+    pub gdl90_callsign_annotation: bool,
+    /// Which FIS-B uplink frames are forwarded to GDL90 clients
+    pub gdl90_uplink: Uplink,
+    /// FIS-B product IDs forwarded when `gdl90_uplink` is `essential`
+    pub gdl90_uplink_products: Vec<u16>,
+    /// How UAT ADS-B traffic is sent to GDL90 clients
+    pub gdl90_uat_downlink: UATDownlink,
+    /// UDP ports GDL90 is sent to on every client
+    pub gdl90_ports: Vec<u16>,
+    /// Clients sent to on other ports than `gdl90_ports`, by IP address
+    pub gdl90_client_ports: BTreeMap<Ipv4Addr, Vec<u16>>,
+    /// Receive and check the GDL90 output on this device
+    pub gdl90_loopback: bool,
+    /// Largest UDP datagram GDL90 messages are packed into, in bytes
+    pub gdl90_max_datagram: usize,
+    /// How long a datagram that is not full may be held back to be filled
+    /// further, in ms. Not held when 0.
+    pub gdl90_coalesce_ms: u64,
+    /// Waypoint `[lat, lon]` in degrees the flight computer computes ETE to
+    pub waypoint: Option<(f32, f32)>,
+    /// Token clients must send along with reboot and power off commands,
+    /// those commands are disabled when unset
+    pub admin_token: Option<String>,
+    /// Power off after the supply has been undervoltage for this many seconds
+    pub low_voltage_shutdown: Option<u64>,
+    /// Idle the SDRs and lower the GNSS update rate once there have been no
+    /// clients for this many seconds while on the ground
+    pub power_save: Option<u64>,
+    /// Minutes of traffic reports kept for exporting, none when unset
+    pub traffic_history: Option<u64>,
+    /// Largest correction in seconds the system clock is stepped by, larger
+    /// ones are refused
+    pub clock_max_step: Option<u64>,
+    /// Only log how the system clock would have been corrected
+    pub clock_dry_run: bool,
+    /// Writable directory for data that should survive a restart
+    pub data_dir: String,
+    /// PEM encoded public key update bundles must be signed with
+    pub update_public_key: String,
+    /// `ip:port` of a syslog collector logs are also sent to over UDP
+    pub syslog: Option<SocketAddr>,
+    /// URLs events are POSTed to
+    pub webhooks: Vec<Webhook>,
+    /// Lua scripts to run against every report, needs the `scripting` feature
+    pub scripts: Vec<String>,
+    /// Directory reports are recorded into, recording is disabled when unset
+    pub record: Option<String>,
+    /// Directory the tracks around traffic alerts are saved into, disabled
+    /// when unset
+    pub encounters: Option<String>,
+    /// Directory raw GNSS measurements are logged into, disabled when unset
+    pub gnss_raw_log: Option<String>,
+    /// Directory received UAT frames are logged into in the dump978 format,
+    /// disabled when unset
+    pub uat_raw_log: Option<String>,
+    /// TCP port received UAT frames are served on in the dump978 format,
+    /// disabled when unset
+    pub uat_raw_port: Option<u16>,
+    /// Directory the pipeline is traced into, disabled when unset
+    pub trace: Option<String>,
+    /// Where RTCM corrections for an RTK capable receiver come from
+    pub rtcm: Option<Rtcm>,
+    /// Role in a pair of units sharing what they receive over the LAN
+    pub peer: Option<Peer>,
+    /// Registered extensions to link, by name, along with their settings
+    pub extensions: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    /// Names of the events to send, all events when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Uplink {
+    /// Do not forward uplink frames
+    Off,
+    /// Forward frames containing at least one product in `gdl90_uplink_products`
+    Essential,
+    /// Forward every uplink frame
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UATDownlink {
+    /// Decoded into traffic reports
+    Translated,
+    /// As raw basic and long UAT reports, for clients decoding UAT themselves
+    Raw,
+    /// Both translated and raw
+    Both,
+}
+
+/// Traffic squawking `code` is of the given kind
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Squawk {
+    /// Written as the 4 octal digits, e.g. `1200`
+    pub code: u16,
+    pub kind: SquawkKind,
+    /// Do not raise traffic alerts for it, e.g. for the other aircraft of
+    /// a formation
+    #[serde(default)]
+    pub quiet: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SquawkKind {
+    Emergency,
+    VFR,
+    Glider,
+    Formation,
+}
+
+/// Named volume that traffic entering raises a `ZoneEntered` event for
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertZone {
+    pub name: String,
+    pub shape: Shape,
+    /// Lowest altitude in ft, unlimited when unset
+    #[serde(default)]
+    pub floor: Option<i32>,
+    /// Highest altitude in ft, unlimited when unset
+    #[serde(default)]
+    pub ceiling: Option<i32>,
+}
+
+/// Aircraft of interest, e.g. those of friends or the club, matched by ICAO
+/// address or callsign
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+    pub name: String,
+    /// Written as a hex string like `ownship_icao`
+    #[serde(default, deserialize_with = "deserialize_icao")]
+    pub icao: Option<u32>,
+    /// Compared ignoring case
+    #[serde(default)]
+    pub callsign: Option<String>,
+    /// Only within this many NM of ownship, at any range when unset
+    #[serde(default)]
+    pub range: Option<f32>,
+}
+
+/// When traffic alerts are held back, to avoid nuisance alerts about
+/// pattern traffic at busy airports. Alerts are held back when any of the
+/// set conditions holds.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertInhibit {
+    /// Below this height in ft above the field we took off from
+    #[serde(default)]
+    pub below_agl: Option<i32>,
+    /// Below this ground speed in kts, e.g. taxiing and on the takeoff roll
+    #[serde(default)]
+    pub below_gs: Option<f32>,
+    /// For this many seconds after takeoff
+    #[serde(default)]
+    pub after_takeoff: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    /// `radius` NM around `center`, `[lat, lon]` in degrees
+    Cylinder { center: (f32, f32), radius: f32 },
+    /// Area enclosed by `[lat, lon]` vertices in degrees, not crossing
+    /// the antimeridian
+    Polygon { points: Vec<(f32, f32)> },
+}
+
+impl AlertZone {
+    /// Reason the zone can never be entered or is malformed
+    pub fn check(&self) -> Option<String> {
+        let points = match self.shape {
+            Shape::Cylinder { center, radius } => {
+                if radius <= 0.0 {
+                    return Some(format!("{}: radius must be positive", self.name));
+                }
+
+                vec![center]
+            }
+            Shape::Polygon { ref points } => {
+                if points.len() < 3 {
+                    return Some(format!("{}: at least 3 points are needed", self.name));
+                }
+
+                points.clone()
+            }
+        };
+
+        if let Some(&(lat, lon)) = points
+            .iter()
+            .find(|&&(lat, lon)| lat < -90.0 || lat > 90.0 || lon < -180.0 || lon > 180.0)
+        {
+            return Some(format!(
+                "{}: [{}, {}] is not a valid [lat, lon] in degrees",
+                self.name, lat, lon
+            ));
+        }
+
+        match (self.floor, self.ceiling) {
+            (Some(f), Some(c)) if f >= c => {
+                Some(format!("{}: floor must be below ceiling", self.name))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    US,
+    Europe,
+    Australia,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rtcm {
+    /// Mountpoint of an NTRIP caster, needs an internet connection
+    Ntrip {
+        host: String,
+        #[serde(default = "default_ntrip_port")]
+        port: u16,
+        mountpoint: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Serial port corrections arrive on, e.g. from a radio link
+    Serial { path: String, baud: usize },
+}
+
+fn default_ntrip_port() -> u16 {
+    2101
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Peer {
+    /// Take in the traffic and uplink another unit forwards to `port`, only
+    /// from `forwarder` if set
+    Aggregator {
+        #[serde(default = "default_peer_port")]
+        port: u16,
+        #[serde(default)]
+        forwarder: Option<Ipv4Addr>,
+    },
+    /// Forward the traffic and uplink received to the aggregator at `host`
+    Forwarder {
+        host: String,
+        #[serde(default = "default_peer_port")]
+        port: u16,
+    },
+}
+
+fn default_peer_port() -> u16 {
+    PEER_PORT
+}
+
+impl Region {
+    /// PRNs of the regional SBAS: WAAS, EGNOS or SouthPAN
+    pub fn sbas_prns(&self) -> &'static [u8] {
+        match *self {
+            Region::US => &[131, 133, 135, 138],
+            Region::Europe => &[120, 121, 123, 124, 126, 136],
+            Region::Australia => &[122],
+        }
+    }
+
+    /// Conventional squawk codes of VFR traffic and gliders
+    pub fn squawks(&self) -> Vec<Squawk> {
+        let codes: &[(u16, SquawkKind)] = match *self {
+            Region::US => &[(1200, SquawkKind::VFR), (1202, SquawkKind::Glider)],
+            Region::Europe => &[(7000, SquawkKind::VFR)],
+            Region::Australia => &[(1200, SquawkKind::VFR)],
+        };
+
+        codes
+            .iter()
+            .map(|&(code, kind)| Squawk {
+                code,
+                kind,
+                quiet: false,
+            })
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            region: Region::US,
+            ground_station: false,
+            uat: true,
+            units: Units::Aviation,
+            ownship_icao: None,
+            squawks: Region::US.squawks(),
+            alert_zones: Vec::new(),
+            watch_list: Vec::new(),
+            alert_inhibit: None,
+            gdl90_callsign_annotation: true,
+            gdl90_uplink: Uplink::Full,
+            gdl90_uplink_products: ESSENTIAL_PRODUCTS.to_vec(),
+            gdl90_uat_downlink: UATDownlink::Translated,
+            gdl90_ports: vec![GDL90_PORT],
+            gdl90_client_ports: BTreeMap::new(),
+            gdl90_loopback: false,
+            gdl90_max_datagram: GDL90_MAX_DATAGRAM,
+            gdl90_coalesce_ms: 0,
+            waypoint: None,
+            admin_token: None,
+            low_voltage_shutdown: None,
+            power_save: None,
+            traffic_history: None,
+            clock_max_step: None,
+            clock_dry_run: false,
+            data_dir: DATA_DIR.to_string(),
+            update_public_key: UPDATE_PUBLIC_KEY.to_string(),
+            syslog: None,
+            webhooks: Vec::new(),
+            scripts: Vec::new(),
+            record: None,
+            encounters: None,
+            gnss_raw_log: None,
+            uat_raw_log: None,
+            uat_raw_port: None,
+            trace: None,
+            rtcm: None,
+            peer: None,
+            extensions: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Path of the config file, can be overridden with `PITOT_CONFIG`
+    pub fn path() -> String {
+        env::var(CONFIG_PATH_ENV).unwrap_or_else(|_| CONFIG_PATH.to_string())
+    }
+
+    /// Load the config file, falling back to defaults if it is
+    /// missing or invalid
+    pub fn load() -> Self {
+        let path = Config::path();
+
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                info!("unable to open config file {}: {}, using defaults", path, e);
+                return Config::default();
+            }
+        };
+
+        match Config::parse(file) {
+            Ok(c) => {
+                info!("loaded config from {}", path);
+                c
+            }
+            Err(errors) => {
+                for e in errors {
+                    error!("config file {}: {}", path, e);
+                }
+                error!("config file {} is invalid, using defaults", path);
+
+                Config::default()
+            }
+        }
+    }
+
+    /// Validate the config file and print the result, for `check-config`.
+    /// Returns whether Pitot would use it as is.
+    pub fn check() -> bool {
+        let path = Config::path();
+
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("unable to open {}: {}, defaults would be used", path, e);
+                return false;
+            }
+        };
+
+        match Config::parse(file) {
+            Ok(_) => {
+                println!("{} is valid", path);
+                true
+            }
+            Err(errors) => {
+                for e in errors {
+                    println!("{}: {}", path, e);
+                }
+                false
+            }
+        }
+    }
+
+    /// Parse and validate a config, returning every problem found
+    pub fn parse<R: Read>(mut r: R) -> Result<Self, Vec<String>> {
+        let mut s = String::new();
+        try!(r.read_to_string(&mut s).map_err(|e| vec![e.to_string()]));
+
+        let mut c: Config = try!(serde_json::from_str(&s).map_err(|e| vec![e.to_string()]));
+        // valid JSON at this point
+        let set: Value = serde_json::from_str(&s).unwrap();
+        c.apply_region(&set);
+
+        let errors = c.validate();
+
+        if errors.is_empty() {
+            Ok(c)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// UDP ports GDL90 is sent to on client `ip`
+    pub fn gdl90_ports_of(&self, ip: &Ipv4Addr) -> &[u16] {
+        self.gdl90_client_ports.get(ip).unwrap_or(&self.gdl90_ports)
+    }
+
+    /// Replace the defaults of settings not in `set` (the config as
+    /// written) by those of the configured region. UAT and FIS-B are only
+    /// broadcast in the US.
+    fn apply_region(&mut self, set: &Value) {
+        let unset = |key| set.get(key).is_none();
+
+        if unset("squawks") {
+            self.squawks = self.region.squawks();
+        }
+
+        if unset("uat") {
+            self.uat = self.region == Region::US;
+        }
+
+        if unset("gdl90_uplink") && self.region != Region::US {
+            self.gdl90_uplink = Uplink::Off;
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for s in &self.squawks {
+            if s.code > 7777 || s.code.to_string().chars().any(|c| c > '7') {
+                errors.push(format!("squawks: {} is not a valid squawk code", s.code));
+            }
+        }
+
+        for z in &self.alert_zones {
+            if let Some(e) = z.check() {
+                errors.push(format!("alert_zones: {}", e));
+            }
+        }
+
+        for w in &self.watch_list {
+            if w.icao.is_none() && w.callsign.is_none() {
+                errors.push(format!(
+                    "watch_list: {}: icao or callsign is needed",
+                    w.name
+                ));
+            }
+
+            if w.range.map_or(false, |r| r <= 0.0) {
+                errors.push(format!("watch_list: {}: range must be positive", w.name));
+            }
+        }
+
+        if let Some(i) = self.alert_inhibit {
+            if i.below_agl.map_or(false, |a| a <= 0) || i.below_gs.map_or(false, |s| s <= 0.0) {
+                errors.push("alert_inhibit: below_agl and below_gs must be positive".to_string());
+            }
+        }
+
+        for p in &self.gdl90_uplink_products {
+            if *p > 0x7FF {
+                errors.push(format!(
+                    "gdl90_uplink_products: {} is not a valid FIS-B product ID",
+                    p
+                ));
+            }
+        }
+
+        let mut ports = vec![("gdl90_ports".to_string(), &self.gdl90_ports)];
+        for (ip, p) in &self.gdl90_client_ports {
+            ports.push((format!("gdl90_client_ports: {}", ip), p));
+        }
+        for (key, p) in ports {
+            if p.is_empty() {
+                errors.push(format!("{}: at least one port is needed", key));
+            } else if p.contains(&0) {
+                errors.push(format!("{}: 0 is not a valid port", key));
+            }
+        }
+
+        if self.gdl90_max_datagram < 512 || self.gdl90_max_datagram > 65507 {
+            errors.push(format!(
+                "gdl90_max_datagram: {} is not between 512 and 65507 bytes",
+                self.gdl90_max_datagram
+            ));
+        }
+
+        if self.gdl90_coalesce_ms > 1000 {
+            errors.push("gdl90_coalesce_ms: must be at most 1000 ms".to_string());
+        }
+
+        if let Some((lat, lon)) = self.waypoint {
+            if lat < -90.0 || lat > 90.0 || lon < -180.0 || lon > 180.0 {
+                errors.push(format!(
+                    "waypoint: [{}, {}] is not a valid [lat, lon] in degrees",
+                    lat, lon
+                ));
+            }
+        }
+
+        if self.admin_token.as_ref().map_or(false, |t| t.is_empty()) {
+            errors.push(
+                "admin_token: must not be empty, omit it to disable power commands".to_string(),
+            );
+        }
+
+        if self.low_voltage_shutdown == Some(0) {
+            errors.push("low_voltage_shutdown: must be at least 1 second".to_string());
+        }
+
+        if self.power_save == Some(0) {
+            errors.push("power_save: must be at least 1 second".to_string());
+        }
+
+        if self
+            .traffic_history
+            .map_or(false, |h| h == 0 || h > MAX_TRAFFIC_HISTORY)
+        {
+            errors.push(format!(
+                "traffic_history: must be between 1 and {} minutes",
+                MAX_TRAFFIC_HISTORY
+            ));
+        }
+
+        if self.clock_max_step == Some(0) {
+            errors.push(
+                "clock_max_step: must be at least 1 second, use clock_dry_run to never set the clock"
+                    .to_string(),
+            );
+        }
+
+        if !Path::new(&self.data_dir).is_absolute() {
+            errors.push(format!("data_dir: {} is not an absolute path", self.data_dir));
+        }
+
+        if !Path::new(&self.update_public_key).is_absolute() {
+            errors.push(format!(
+                "update_public_key: {} is not an absolute path",
+                self.update_public_key
+            ));
+        }
+
+        for h in &self.webhooks {
+            if !h.url.starts_with("http://") && !h.url.starts_with("https://") {
+                errors.push(format!("webhooks: {} is not an HTTP(S) URL", h.url));
+            }
+
+            for e in &h.events {
+                if !EVENT_NAMES.contains(&e.as_str()) {
+                    errors.push(format!(
+                        "webhooks: unknown event {}, must be one of {}",
+                        e,
+                        EVENT_NAMES.join(", ")
+                    ));
+                }
+            }
+        }
+
+        for s in &self.scripts {
+            if !Path::new(s).is_absolute() {
+                errors.push(format!("scripts: {} is not an absolute path", s));
+            }
+        }
+
+        if let Some(ref r) = self.record {
+            if !Path::new(r).is_absolute() {
+                errors.push(format!("record: {} is not an absolute path", r));
+            }
+        }
+
+        if let Some(ref e) = self.encounters {
+            if !Path::new(e).is_absolute() {
+                errors.push(format!("encounters: {} is not an absolute path", e));
+            }
+        }
+
+        if let Some(ref r) = self.gnss_raw_log {
+            if !Path::new(r).is_absolute() {
+                errors.push(format!("gnss_raw_log: {} is not an absolute path", r));
+            }
+        }
+
+        if let Some(ref r) = self.uat_raw_log {
+            if !Path::new(r).is_absolute() {
+                errors.push(format!("uat_raw_log: {} is not an absolute path", r));
+            }
+        }
+
+        if self.uat_raw_port == Some(0) {
+            errors.push("uat_raw_port: 0 is not a valid port".to_string());
+        }
+
+        if let Some(ref t) = self.trace {
+            if !Path::new(t).is_absolute() {
+                errors.push(format!("trace: {} is not an absolute path", t));
+            }
+        }
+
+        match self.rtcm {
+            Some(Rtcm::Ntrip { ref mountpoint, .. }) if mountpoint.is_empty() => {
+                errors.push("rtcm: mountpoint must not be empty".to_string())
+            }
+            Some(Rtcm::Serial { ref path, .. }) if !Path::new(path).is_absolute() => {
+                errors.push(format!("rtcm: {} is not an absolute path", path))
+            }
+            _ => {}
+        }
+
+        match self.peer {
+            Some(Peer::Aggregator { port: 0, .. }) | Some(Peer::Forwarder { port: 0, .. }) => {
+                errors.push("peer: 0 is not a valid port".to_string())
+            }
+            Some(Peer::Forwarder { ref host, .. }) if host.is_empty() => {
+                errors.push("peer: host must not be empty".to_string())
+            }
+            _ => {}
+        }
+
+        errors
+    }
+}
+
+fn deserialize_icao<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = try!(Option::deserialize(deserializer));
+
+    match s {
+        Some(s) => match u32::from_str_radix(s.trim_left_matches("0x"), 16) {
+            Ok(icao) if icao <= 0xFFFFFF => Ok(Some(icao)),
+            _ => Err(de::Error::custom(format!("invalid ICAO address: {}", s))),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let c: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(c.ownship_icao, None);
+        assert_eq!(c.gdl90_callsign_annotation, true);
+
+        let c: Config = serde_json::from_str(r#"{"gdl90_callsign_annotation": false}"#).unwrap();
+        assert_eq!(c.gdl90_callsign_annotation, false);
+
+        let c: Config = serde_json::from_str(r#"{"gdl90_uplink": "essential"}"#).unwrap();
+        assert_eq!(c.gdl90_uplink, Uplink::Essential);
+        assert!(c.gdl90_uplink_products.contains(&413));
+        assert!(serde_json::from_str::<Config>(r#"{"gdl90_uplink": "some"}"#).is_err());
+
+        let c: Config = serde_json::from_str(r#"{"gdl90_uat_downlink": "both"}"#).unwrap();
+        assert_eq!(c.gdl90_uat_downlink, UATDownlink::Both);
+
+        let c: Config = serde_json::from_str(
+            r#"{"gdl90_ports": [4000, 43211], "gdl90_client_ports": {"192.168.10.5": [43211]}}"#,
+        ).unwrap();
+        assert_eq!(
+            c.gdl90_ports_of(&"192.168.10.4".parse().unwrap()),
+            &[4000, 43211]
+        );
+        assert_eq!(c.gdl90_ports_of(&"192.168.10.5".parse().unwrap()), &[43211]);
+        assert!(
+            serde_json::from_str::<Config>(r#"{"gdl90_client_ports": {"ipad": [4000]}}"#).is_err()
+        );
+
+        let c: Config = serde_json::from_str(r#"{"units": "metric"}"#).unwrap();
+        assert_eq!(c.units, Units::Metric);
+
+        let c: Config = serde_json::from_str(r#"{"waypoint": [37.5, -122.25]}"#).unwrap();
+        assert_eq!(c.waypoint, Some((37.5, -122.25)));
+
+        let c: Config =
+            serde_json::from_str(r#"{"extensions": {"my-imu": {"bus": 1}}}"#).unwrap();
+        assert_eq!(c.extensions["my-imu"]["bus"], 1);
+
+        let c: Config = serde_json::from_str(r#"{"syslog": "192.168.0.10:514"}"#).unwrap();
+        assert_eq!(c.syslog, Some("192.168.0.10:514".parse().unwrap()));
+
+        let c: Config = serde_json::from_str(
+            r#"{"rtcm": {"ntrip": {"host": "rtk2go.com", "mountpoint": "BASE"}}}"#,
+        ).unwrap();
+        match c.rtcm {
+            Some(Rtcm::Ntrip { port, username, .. }) => {
+                assert_eq!(port, 2101);
+                assert_eq!(username, None);
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let c: Config = serde_json::from_str(r#"{"peer": {"aggregator": {}}}"#).unwrap();
+        assert_eq!(
+            c.peer,
+            Some(Peer::Aggregator {
+                port: PEER_PORT,
+                forwarder: None,
+            })
+        );
+
+        let c: Config =
+            serde_json::from_str(r#"{"peer": {"aggregator": {"forwarder": "192.168.10.2"}}}"#)
+                .unwrap();
+        assert_eq!(
+            c.peer,
+            Some(Peer::Aggregator {
+                port: PEER_PORT,
+                forwarder: Some(Ipv4Addr::new(192, 168, 10, 2)),
+            })
+        );
+
+        let c: Config = serde_json::from_str(
+            r#"{"peer": {"forwarder": {"host": "192.168.10.1", "port": 4100}}}"#,
+        ).unwrap();
+        assert_eq!(
+            c.peer,
+            Some(Peer::Forwarder {
+                host: "192.168.10.1".to_string(),
+                port: 4100,
+            })
+        );
+
+        let c: Config = serde_json::from_str(r#"{"ownship_icao": "A1B2C3"}"#).unwrap();
+        assert_eq!(c.ownship_icao, Some(0xA1B2C3));
+
+        let c: Config = serde_json::from_str(r#"{"ownship_icao": "0xa1b2c3"}"#).unwrap();
+        assert_eq!(c.ownship_icao, Some(0xA1B2C3));
+
+        assert!(serde_json::from_str::<Config>(r#"{"ownship_icao": "1A1B2C3"}"#).is_err());
+        assert!(serde_json::from_str::<Config>(r#"{"ownship_icao": "N12345"}"#).is_err());
+    }
+
+    #[test]
+    fn test_region() {
+        let c = Config::parse(&b"{}"[..]).unwrap();
+        assert_eq!(c.region, Region::US);
+        assert!(c.uat);
+        assert_eq!(c.gdl90_uplink, Uplink::Full);
+
+        let c = Config::parse(&br#"{"region": "europe"}"#[..]).unwrap();
+        assert!(!c.uat);
+        assert_eq!(c.gdl90_uplink, Uplink::Off);
+
+        // explicit settings win
+        let c = Config::parse(&br#"{"region": "australia", "uat": true}"#[..]).unwrap();
+        assert!(c.uat);
+        assert_eq!(c.gdl90_uplink, Uplink::Off);
+
+        assert!(Config::parse(&br#"{"region": "asia"}"#[..]).is_err());
+
+        assert_eq!(Config::default().squawks, Region::US.squawks());
+        let c = Config::parse(&br#"{"region": "europe"}"#[..]).unwrap();
+        assert_eq!(c.squawks[0].code, 7000);
+        let c = Config::parse(
+            &br#"{"region": "europe", "squawks": [{"code": 4321, "kind": "formation", "quiet": true}]}"#[..],
+        ).unwrap();
+        assert_eq!(
+            c.squawks,
+            vec![Squawk {
+                code: 4321,
+                kind: SquawkKind::Formation,
+                quiet: true,
+            }]
+        );
+        assert!(Config::parse(&br#"{"squawks": [{"code": 1280, "kind": "vfr"}]}"#[..]).is_err());
+    }
+
+    #[test]
+    fn test_alert_zones() {
+        let c = Config::parse(
+            &br#"{"alert_zones": [
+                {"name": "home", "shape": {"cylinder": {"center": [37.5, -122.0], "radius": 0.5}}, "ceiling": 500},
+                {"name": "practice", "shape": {"polygon": {"points": [[37, -122], [37, -121], [38, -121]]}}}
+            ]}"#[..],
+        ).unwrap();
+        assert_eq!(
+            c.alert_zones[0].shape,
+            Shape::Cylinder {
+                center: (37.5, -122.0),
+                radius: 0.5,
+            }
+        );
+        assert_eq!(c.alert_zones[0].ceiling, Some(500));
+        assert_eq!(c.alert_zones[1].floor, None);
+
+        let errors = Config::parse(
+            &br#"{"alert_zones": [
+                {"name": "a", "shape": {"cylinder": {"center": [37.5, -122.0], "radius": 0}}},
+                {"name": "b", "shape": {"polygon": {"points": [[37, -122], [37, -121]]}}},
+                {"name": "c", "shape": {"polygon": {"points": [[37, -122], [37, -121], [97, 0]]}}},
+                {"name": "d", "shape": {"cylinder": {"center": [37.5, -122.0], "radius": 1}}, "floor": 500, "ceiling": 500}
+            ]}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].starts_with("alert_zones: a: radius"));
+        assert!(errors[1].starts_with("alert_zones: b: at least 3"));
+        assert!(errors[2].starts_with("alert_zones: c: [97, 0]"));
+        assert!(errors[3].starts_with("alert_zones: d: floor"));
+    }
+
+    #[test]
+    fn test_watch_list() {
+        let c = Config::parse(
+            &br#"{"watch_list": [
+                {"name": "Bob", "icao": "A1B2C3"},
+                {"name": "club", "callsign": "N123AB", "range": 20}
+            ]}"#[..],
+        ).unwrap();
+        assert_eq!(c.watch_list[0].icao, Some(0xA1B2C3));
+        assert_eq!(c.watch_list[1].icao, None);
+        assert_eq!(c.watch_list[1].range, Some(20.0));
+
+        let errors = Config::parse(
+            &br#"{"watch_list": [{"name": "a"}, {"name": "b", "icao": "A1B2C3", "range": 0}]}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("watch_list: a: icao"));
+        assert!(errors[1].starts_with("watch_list: b: range"));
+    }
+
+    #[test]
+    fn test_alert_inhibit() {
+        let c = Config::parse(&br#"{"alert_inhibit": {"below_gs": 50}}"#[..]).unwrap();
+        assert_eq!(
+            c.alert_inhibit,
+            Some(AlertInhibit {
+                below_agl: None,
+                below_gs: Some(50.0),
+                after_takeoff: None,
+            })
+        );
+
+        let errors = Config::parse(&br#"{"alert_inhibit": {"below_agl": -100}}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("alert_inhibit:"));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        assert!(Config::parse(&b"{}"[..]).is_ok());
+
+        let errors = Config::parse(&br#"{"way_point": [37.5, -122.25]}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("unknown field `way_point`"));
+        assert!(errors[0].contains("line 1"));
+
+        let errors = Config::parse(
+            &br#"{"waypoint": [137.5, -122.25], "admin_token": "", "data_dir": "pitot"}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("waypoint:"));
+        assert!(errors[1].starts_with("admin_token:"));
+        assert!(errors[2].starts_with("data_dir:"));
+
+        let errors = Config::parse(
+            &br#"{"webhooks": [{"url": "example.com", "events": ["Takeoff", "Crash"]}]}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("example.com"));
+        assert!(errors[1].contains("Crash"));
+
+        let errors = Config::parse(&br#"{"gdl90_uplink_products": [413, 2048]}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("2048"));
+
+        let errors = Config::parse(
+            &br#"{"gdl90_ports": [], "gdl90_client_ports": {"192.168.10.5": [0]}}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("gdl90_ports:"));
+        assert!(errors[1].starts_with("gdl90_client_ports: 192.168.10.5:"));
+
+        let errors = Config::parse(
+            &br#"{"gdl90_max_datagram": 100000, "gdl90_coalesce_ms": 5000}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("gdl90_max_datagram:"));
+        assert!(errors[1].starts_with("gdl90_coalesce_ms:"));
+
+        let errors = Config::parse(&br#"{"traffic_history": 61}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("traffic_history:"));
+
+        let errors = Config::parse(&br#"{"clock_max_step": 0}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("clock_max_step:"));
+
+        let errors = Config::parse(&br#"{"record": "logs"}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("record:"));
+
+        let errors = Config::parse(&br#"{"encounters": "encounters"}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("encounters:"));
+
+        let errors = Config::parse(&br#"{"uat_raw_log": "raw"}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("uat_raw_log:"));
+
+        let errors = Config::parse(&br#"{"uat_raw_port": 0}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("uat_raw_port:"));
+
+        let errors = Config::parse(&br#"{"gnss_raw_log": "raw"}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("gnss_raw_log:"));
+
+        let errors = Config::parse(&br#"{"trace": "trace"}"#[..]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("trace:"));
+
+        let errors = Config::parse(
+            &br#"{"rtcm": {"serial": {"path": "ttyUSB0", "baud": 57600}}}"#[..],
+        ).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("rtcm:"));
+    }
+}
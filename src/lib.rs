@@ -0,0 +1,67 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pitot is split into a library holding the whole pipeline and a thin
+//! binary wiring it up, so benchmarks and other tools can reuse the pipeline.
+
+#[macro_use]
+extern crate log;
+extern crate chrono;
+#[cfg(any(feature = "es", feature = "uat"))]
+extern crate crossbeam_channel;
+#[cfg(feature = "gnss-ublox")]
+extern crate serial;
+#[macro_use]
+extern crate nom;
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(all(target_os = "linux", feature = "baro-bmp280"))]
+extern crate i2cdev_bmp280;
+#[cfg(all(target_os = "linux", feature = "baro-bmp280"))]
+extern crate i2csensors;
+#[cfg(all(
+    target_os = "linux",
+    any(
+        feature = "baro-bmp280",
+        feature = "baro-bme280",
+        feature = "airspeed-ms4525do"
+    )
+))]
+extern crate i2cdev;
+#[cfg(target_os = "linux")]
+extern crate inotify;
+extern crate libc;
+#[cfg(feature = "scripting")]
+extern crate rlua;
+#[cfg(feature = "websocket")]
+extern crate ws;
+
+#[macro_use]
+pub mod utils;
+pub mod config;
+pub mod logging;
+pub mod pitot;
+pub mod processor;
+pub mod protocol;
+pub mod recording;
+pub mod sensor;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+pub mod transport;
+pub mod units;
@@ -0,0 +1,220 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Logger writing to stderr and optionally to a remote syslog collector over
+//! UDP. Levels start out from `RUST_LOG` (e.g. `info,pitot::sensor=debug`) and
+//! can be changed at runtime through `LogControl`.
+
+use log::{self, Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord, MaxLogLevelFilter};
+use std::env;
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, RwLock};
+
+// syslog facility "user"
+const FACILITY: u8 = 1;
+
+/// Log level per module, the longest matching module prefix wins
+#[derive(Debug, PartialEq)]
+struct Filters {
+    default: LogLevelFilter,
+    modules: Vec<(String, LogLevelFilter)>,
+}
+
+impl Filters {
+    /// Parses `RUST_LOG` style specs, invalid directives are ignored
+    fn parse(spec: &str) -> Self {
+        let mut f = Filters {
+            default: LogLevelFilter::Error,
+            modules: Vec::new(),
+        };
+
+        for d in spec.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+            let mut parts = d.splitn(2, '=');
+            let (name, level) = (parts.next().unwrap(), parts.next());
+
+            match level {
+                Some(l) => if let Ok(l) = l.parse() {
+                    f.set(Some(name), l);
+                },
+                None => match name.parse() {
+                    Ok(l) => f.default = l,
+                    // a bare module name enables everything for it
+                    Err(_) => f.set(Some(name), LogLevelFilter::Trace),
+                },
+            }
+        }
+
+        f
+    }
+
+    fn set(&mut self, module: Option<&str>, level: LogLevelFilter) {
+        match module {
+            Some(m) => {
+                self.modules.retain(|&(ref n, _)| n != m);
+                self.modules.push((m.to_string(), level));
+            }
+            None => self.default = level,
+        }
+    }
+
+    fn level(&self, target: &str) -> LogLevelFilter {
+        self.modules
+            .iter()
+            .filter(|&&(ref n, _)| target.starts_with(n.as_str()))
+            .max_by_key(|&&(ref n, _)| n.len())
+            .map_or(self.default, |&(_, l)| l)
+    }
+
+    fn max(&self) -> LogLevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, l)| l)
+            .fold(self.default, |a, b| a.max(b))
+    }
+}
+
+struct Syslog {
+    socket: UdpSocket,
+    addr: SocketAddr,
+}
+
+struct Shared {
+    filters: RwLock<Filters>,
+    syslog: Mutex<Option<Syslog>>,
+}
+
+struct Logger {
+    shared: Arc<Shared>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.shared.filters.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{}:{}: {}",
+            record.level(),
+            record.location().module_path(),
+            record.args()
+        );
+
+        let _ = writeln!(&mut io::stderr(), "{}", line);
+
+        if let Some(ref s) = *self.shared.syslog.lock().unwrap() {
+            let msg = format!("<{}>pitot: {}", FACILITY * 8 + severity(record.level()), line);
+            // logging must never block or fail the caller
+            let _ = s.socket.send_to(msg.as_bytes(), s.addr);
+        }
+    }
+}
+
+fn severity(l: LogLevel) -> u8 {
+    match l {
+        LogLevel::Error => 3,
+        LogLevel::Warn => 4,
+        LogLevel::Info => 6,
+        LogLevel::Debug | LogLevel::Trace => 7,
+    }
+}
+
+/// Changes the running logger
+#[derive(Clone)]
+pub struct LogControl {
+    shared: Arc<Shared>,
+    max: Arc<MaxLogLevelFilter>,
+}
+
+impl LogControl {
+    /// Set the level of `module` and everything below it, or the default
+    /// level if `module` is `None`
+    pub fn set_level(&self, module: Option<&str>, level: LogLevelFilter) {
+        let mut filters = self.shared.filters.write().unwrap();
+        filters.set(module, level);
+        self.max.set(filters.max());
+    }
+
+    /// Start or stop sending logs to a syslog collector
+    pub fn set_syslog(&self, addr: Option<SocketAddr>) {
+        let syslog = addr.and_then(|addr| match UdpSocket::bind("0.0.0.0:0") {
+            Ok(socket) => {
+                socket.set_nonblocking(true).ok();
+                Some(Syslog { socket, addr })
+            }
+            Err(e) => {
+                error!("unable to create syslog socket: {}", e);
+                None
+            }
+        });
+
+        *self.shared.syslog.lock().unwrap() = syslog;
+    }
+}
+
+/// Install the logger, must be called once before anything is logged
+pub fn init() -> LogControl {
+    let filters = Filters::parse(&env::var("RUST_LOG").unwrap_or_default());
+    let shared = Arc::new(Shared {
+        filters: RwLock::new(filters),
+        syslog: Mutex::new(None),
+    });
+
+    let mut max = None;
+    log::set_logger(|m| {
+        m.set(shared.filters.read().unwrap().max());
+        max = Some(m);
+
+        Box::new(Logger {
+            shared: shared.clone(),
+        })
+    }).expect("logger already initialized");
+
+    LogControl {
+        shared,
+        max: Arc::new(max.unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let f = Filters::parse("");
+        assert_eq!(f.level("pitot::sensor"), LogLevelFilter::Error);
+
+        let mut f = Filters::parse("info, pitot::sensor=debug,pitot::sensor::sdr=warn,bogus=loud");
+        assert_eq!(f.level("pitot"), LogLevelFilter::Info);
+        assert_eq!(f.level("pitot::sensor::gnss"), LogLevelFilter::Debug);
+        assert_eq!(f.level("pitot::sensor::sdr::uat"), LogLevelFilter::Warn);
+        assert_eq!(f.level("bogus"), LogLevelFilter::Info);
+        assert_eq!(f.max(), LogLevelFilter::Debug);
+
+        f.set(Some("pitot::sensor::sdr"), LogLevelFilter::Trace);
+        assert_eq!(f.level("pitot::sensor::sdr::uat"), LogLevelFilter::Trace);
+        assert_eq!(f.max(), LogLevelFilter::Trace);
+
+        f.set(None, LogLevelFilter::Off);
+        assert_eq!(f.level("pitot"), LogLevelFilter::Off);
+    }
+}
@@ -14,71 +14,563 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-#[macro_use]
-extern crate log;
-extern crate chrono;
-extern crate env_logger;
-extern crate serial;
-#[macro_use]
-extern crate nom;
-extern crate serde_json;
-extern crate time;
-#[macro_use]
-extern crate serde_derive;
-extern crate i2cdev_bmp280;
-extern crate i2csensors;
-extern crate i2cdev;
-extern crate icmp;
-extern crate inotify;
-extern crate libc;
-extern crate ws;
-
-#[macro_use]
-mod utils;
-mod pitot;
-mod processor;
-mod protocol;
-mod sensor;
-mod transport;
-
-use pitot::Pitot;
-use processor::Processor;
-use sensor::Sensor;
+extern crate pitot;
+
+use pitot::config::{Config, Peer};
+use pitot::logging::{self, LogControl};
+use pitot::pitot::clock::{Clock, SystemClock, VirtualClock};
+use pitot::pitot::Pitot;
+use pitot::processor::Processor;
+use pitot::protocol;
+use pitot::recording::LogReader;
+#[cfg(any(feature = "es", feature = "uat", not(target_os = "linux")))]
+use pitot::sensor::Sensor;
+#[cfg(any(feature = "es", feature = "uat"))]
+use pitot::sensor::SensorData;
+use pitot::storage::Storage;
+#[cfg(any(target_os = "linux", feature = "websocket"))]
+use pitot::transport;
+#[cfg(feature = "websocket")]
+use pitot::units;
+use pitot::{processor, sensor};
+use std::env;
+use std::fs::File;
+#[cfg(any(feature = "es", feature = "uat"))]
+use std::io;
+use std::io::BufReader;
+#[cfg(feature = "gdl90")]
+use std::io::Read;
+use std::process;
+#[cfg(any(target_os = "linux", feature = "gnss-ublox"))]
+use std::sync::atomic::AtomicBool;
+#[cfg(any(target_os = "linux", feature = "gnss-ublox"))]
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "usage: pitot [command]
+
+commands:
+    run                                    receive and serve data, the default
+    help                                   show this message
+    check-config                           validate the config file
+    export <log> [json | csv <type>]       convert a recorded log
+    replay <log> [fast]                    play a recorded log back in real time, or as fast
+                                           as possible";
+#[cfg(any(feature = "es", feature = "uat"))]
+const USAGE_SDR: &str = "
+    scan <start MHz> <end MHz> [device]    measure noise and signals across a band";
+#[cfg(feature = "uat")]
+const USAGE_UAT: &str = "
+    decode-uat <capture>                   decode a 978 MHz I/Q capture";
+#[cfg(feature = "es")]
+const USAGE_ES: &str = "
+    decode-es <capture>                    decode a 1090 MHz I/Q capture";
+#[cfg(feature = "gdl90")]
+const USAGE_GDL90: &str = "
+    gdl90-dump <file>                      decode a GDL 90 byte stream
+    pcap <capture>                         run with traffic from a pcap of GDL 90 UDP packets
+                                           instead of the SDRs";
 
 fn main() {
-    env_logger::init().unwrap();
+    let log = logging::init();
+    let args: Vec<String> = env::args().collect();
+
+    let ok = match args.get(1).map(|a| a.as_str()) {
+        None | Some("run") => run(log, None),
+        Some("check-config") => Config::check(),
+        Some("help") => {
+            usage();
+            true
+        }
+        Some("export") if args.len() >= 3 => export(&args[2..]),
+        Some("replay") if args.len() >= 3 => replay(&args[2..]),
+        #[cfg(any(feature = "es", feature = "uat"))]
+        Some("scan") if args.len() >= 4 => scan(&args[2..]),
+        #[cfg(feature = "uat")]
+        Some("decode-uat") if args.len() == 3 => decode(&args[2], |c, send| {
+            sensor::sdr::uat::decode_capture(c, send)
+        }),
+        #[cfg(feature = "es")]
+        Some("decode-es") if args.len() == 3 => {
+            decode(&args[2], |c, send| sensor::sdr::es::decode_capture(c, send))
+        }
+        #[cfg(feature = "gdl90")]
+        Some("gdl90-dump") if args.len() == 3 => gdl90_dump(&args[2]),
+        #[cfg(feature = "gdl90")]
+        Some("pcap") if args.len() == 3 => run(log, Some(&args[2])),
+        _ => {
+            usage();
+            false
+        }
+    };
+
+    process::exit(if ok { 0 } else { 1 });
+}
+
+fn usage() {
+    let mut usage = USAGE.to_string();
+    #[cfg(any(feature = "es", feature = "uat"))]
+    usage.push_str(USAGE_SDR);
+    #[cfg(feature = "uat")]
+    usage.push_str(USAGE_UAT);
+    #[cfg(feature = "es")]
+    usage.push_str(USAGE_ES);
+    #[cfg(feature = "gdl90")]
+    usage.push_str(USAGE_GDL90);
+
+    println!("{}", usage);
+}
 
+/// `run`, the receiver itself, only returns if Pitot is unable to start.
+/// With `pcap`, traffic and uplink come from that capture instead of the SDRs.
+fn run(log: LogControl, pcap: Option<&str>) -> bool {
+    let config = Config::load();
+    log.set_syslog(config.syslog);
+    let storage = Storage::new(&config.data_dir);
+    let warm_start = processor::warmstart::WarmStart::new(storage.clone());
     let mut p = Pitot::new(10); // 10 Hz
+    p.situation()
+        .lock()
+        .unwrap()
+        .keep_history(config.traffic_history);
+
+    // sent over WebSocket, kept up to date by the UDP transport
+    #[cfg(any(target_os = "linux", feature = "websocket"))]
+    let transport_status = transport::SharedStatus::default();
 
-    sensor::gnss::ublox::UbloxGNSSProvider::new().and_then(&mut |g| {
-        p.link_sensor(g);
+    // set by the power save processor, lowers the GNSS update rate
+    #[cfg(any(target_os = "linux", feature = "gnss-ublox"))]
+    let gnss_low_rate = Arc::new(AtomicBool::new(false));
+
+    #[cfg(feature = "gnss-ublox")]
+    sensor::gnss::ublox::UbloxGNSSProvider::new(
+        config.gnss_raw_log.as_ref().map(|d| d.as_str()),
+        config.rtcm.as_ref(),
+        config.region,
+        warm_start.position(),
+        gnss_low_rate.clone(),
+    ).and_then(&mut |g| {
+        p.link_sensor("ublox", g);
+        Some(())
+    });
+    #[cfg(target_os = "linux")]
+    sensor::gnss::pps::PPSProvider::new().and_then(&mut |t| {
+        p.link_sensor("pps", t);
+        Some(())
+    });
+    #[cfg(all(target_os = "linux", feature = "baro-bme280"))]
+    sensor::barometer::bme280::BME280Provider::new().and_then(&mut |b| {
+        p.link_sensor("bme280", b);
         Some(())
     });
+    #[cfg(all(target_os = "linux", feature = "baro-bmp280"))]
     sensor::barometer::bmp280::BMP280BaroProvider::new().and_then(&mut |b| {
-        p.link_sensor(b);
+        p.link_sensor("bmp280", b);
         Some(())
     });
-    sensor::sdr::es::ES::new().and_then(&mut |e| {
-        p.link_sensor(Box::new(e) as Box<Sensor>);
+    #[cfg(all(target_os = "linux", feature = "airspeed-ms4525do"))]
+    sensor::airspeed::ms4525do::MS4525DOProvider::new().and_then(&mut |a| {
+        p.link_sensor("ms4525do", a);
         Some(())
     });
-    sensor::sdr::uat::UAT::new().and_then(&mut |e| {
-        p.link_sensor(Box::new(e) as Box<Sensor>);
+    // no GNSS receiver on a development machine, simulate one
+    #[cfg(not(target_os = "linux"))]
+    sensor::gnss::fake::FakeGNSSProvider::new().and_then(&mut |g| {
+        p.link_sensor("fake-gnss", g as Box<Sensor>);
         Some(())
     });
+    sensor::power::UndervoltageProvider::new().and_then(&mut |u| {
+        p.link_sensor("power", u);
+        Some(())
+    });
+    sensor::config::ConfigProvider::new(config.clone()).and_then(&mut |c| {
+        p.link_sensor("config", c);
+        Some(())
+    });
+    if let Some(Peer::Aggregator { port, forwarder }) = config.peer {
+        sensor::peer::PeerProvider::new(port, forwarder).and_then(&mut |f| {
+            p.link_sensor("peer", f);
+            Some(())
+        });
+    }
+    // commands are only received over WebSocket
+    #[cfg(feature = "websocket")]
+    let (commands, command_sender) = sensor::command::CommandProvider::new();
+    #[cfg(feature = "websocket")]
+    p.link_sensor("commands", commands);
+    match pcap {
+        #[cfg(feature = "gdl90")]
+        Some(path) => match sensor::pcap::PcapProvider::new(path) {
+            Some(c) => p.link_sensor("pcap", c),
+            None => return false,
+        },
+        _ => {
+            #[cfg(feature = "es")]
+            sensor::sdr::es::ES::new(if config.ground_station {
+                sensor::sdr::es::Beast::new()
+            } else {
+                None
+            }).and_then(&mut |e| {
+                p.link_sensor("es", Box::new(e) as Box<Sensor>);
+                Some(())
+            });
+            #[cfg(feature = "uat")]
+            sensor::sdr::uat::UAT::new(sensor::sdr::uat::RawLog::new(
+                config.uat_raw_log.as_ref().map(|d| d.as_str()),
+                config.uat_raw_port,
+            )).and_then(&mut |e| {
+                p.link_sensor("uat", Box::new(e) as Box<Sensor>);
+                // kept linked so it can be enabled without a restart
+                if !config.uat {
+                    p.set_paused("uat", true);
+                }
+                Some(())
+            });
+        }
+    }
 
-    p.link_processor(processor::ownship::Ownship::new());
-    p.link_processor(Box::new(processor::clock::Clock::new()) as Box<Processor>);
-    p.link_processor(Box::new(processor::traffic::Traffic::new()) as Box<Processor>);
-    p.link_processor(Box::new(processor::fisb::FISB::new()) as Box<Processor>);
-    p.link_processor(Box::new(processor::gnss::GNSS::new()) as Box<Processor>);
+    // a ground station is not an aircraft
+    if !config.ground_station {
+        p.link_processor("ownship", processor::ownship::Ownship::new());
+    }
+    #[cfg(target_os = "linux")]
+    p.link_processor(
+        "clock",
+        Box::new(processor::clock::Clock::new(&config, storage.clone())) as Box<Processor>,
+    );
+    p.link_processor(
+        "traffic",
+        Box::new(processor::traffic::Traffic::new(&config)) as Box<Processor>,
+    );
+    p.link_processor(
+        "fisb",
+        Box::new(processor::fisb::FISB::new()) as Box<Processor>,
+    );
+    p.link_processor(
+        "downlink",
+        processor::downlink::Downlink::new(config.ownship_icao),
+    );
+    p.link_processor("receiver", processor::receiver::Receiver::new());
+    p.link_processor(
+        "gnss",
+        Box::new(processor::gnss::GNSS::new()) as Box<Processor>,
+    );
+    p.link_processor("e6b", processor::e6b::E6B::new(config.waypoint));
+    p.link_processor(
+        "navigation",
+        processor::navigation::Navigation::new(storage.clone()),
+    );
+    p.link_processor(
+        "system",
+        processor::system::System::new(&config, log),
+    );
+    p.link_processor(
+        "update",
+        processor::update::Updater::new(config.update_public_key.clone(), storage.clone()),
+    );
+    p.link_processor(
+        "transponder",
+        processor::transponder::Transponder::new(config.ownship_icao),
+    );
+    p.link_processor("settings", processor::settings::Settings::new());
+    p.link_processor("cabin", processor::cabin::CabinEnvironment::new());
+    p.link_processor("warmstart", Box::new(warm_start) as Box<Processor>);
+    p.link_processor_after(
+        "antenna",
+        processor::antenna::Antenna::new(storage.clone(), config.ground_station),
+        &["traffic"],
+    );
+    if let Some(Peer::Forwarder { ref host, port }) = config.peer {
+        p.link_processor(
+            "forwarder",
+            processor::forwarder::Forwarder::new(host, port),
+        );
+    }
+    // a ground station is expected to keep receiving
+    #[cfg(target_os = "linux")]
+    {
+        if !config.ground_station {
+            p.link_processor_after(
+                "powersave",
+                processor::powersave::PowerSave::new(
+                    &config,
+                    transport_status.clone(),
+                    gnss_low_rate,
+                ),
+                &["ownship"],
+            );
+        }
+    }
+    p.link_processor_after(
+        "events",
+        processor::events::Events::new(&config),
+        &["traffic", "ownship"],
+    );
 
-    p.link_protocol(protocol::gdl90::GDL90::new());
-    p.link_protocol(protocol::websocket::WebSocket::new(
+    #[cfg(feature = "gdl90")]
+    p.link_protocol("gdl90", protocol::gdl90::GDL90::new(&config));
+    #[cfg(feature = "websocket")]
+    let websocket = protocol::websocket::WebSocket::new(
         "0.0.0.0:9001".to_string(),
-    ));
+        command_sender,
+        config.units,
+        transport_status.clone(),
+        p.situation(),
+    );
+    #[cfg(feature = "websocket")]
+    let websocket_gdl90 = websocket.gdl90();
+    #[cfg(feature = "websocket")]
+    websocket.serve_events("0.0.0.0:9002", storage.clone());
+    #[cfg(feature = "websocket")]
+    p.link_protocol("websocket", websocket);
+    #[cfg(feature = "webhook")]
+    p.link_protocol(
+        "webhook",
+        protocol::webhook::Webhook::new(config.webhooks.clone()),
+    );
+    #[cfg(feature = "scripting")]
+    for s in &config.scripts {
+        protocol::script::Script::new(s).and_then(&mut |s| {
+            p.link_protocol("script", s);
+            Some(())
+        });
+    }
+    #[cfg(feature = "grpc")]
+    protocol::grpc::GRPC::new("0.0.0.0:50051").and_then(&mut |g| {
+        p.link_protocol("grpc", g);
+        Some(())
+    });
+    if config.ground_station {
+        protocol::sbs::SBS::new().and_then(&mut |s| {
+            p.link_protocol("sbs", s);
+            Some(())
+        });
+    }
+    if let Some(ref dir) = config.record {
+        protocol::recorder::Recorder::new(dir).and_then(&mut |r| {
+            p.link_protocol("recorder", r);
+            Some(())
+        });
+    }
+    if let Some(ref dir) = config.encounters {
+        p.link_protocol("encounters", protocol::encounters::Encounters::new(dir));
+    }
+
+    #[cfg(target_os = "linux")]
+    p.link_transport("udp", transport::udp::UDP::new(&config, transport_status));
+    // paused and resumed along with the protocol
+    #[cfg(feature = "websocket")]
+    p.link_transport("websocket", websocket_gdl90);
 
-    p.link_transport(transport::udp::UDP::new());
+    p.link_extensions(&config.extensions);
 
+    if let Some(ref dir) = config.trace {
+        p.trace(dir);
+    }
+
+    #[cfg(unix)]
+    pitot::pitot::stop_on_signals();
     p.run();
+
+    true
+}
+
+/// `scan <start MHz> <end MHz> [device index]`, sweeps an SDR across the
+/// range and prints the noise floor and strongest signal of every step
+#[cfg(any(feature = "es", feature = "uat"))]
+fn scan(args: &[String]) -> bool {
+    let mhz = |s: &String| s.parse::<f32>().ok().map(|f| (f * 1e6) as u32);
+
+    let (start, end, index) = match (
+        mhz(&args[0]),
+        mhz(&args[1]),
+        args.get(2).map_or(Some(0), |i| i.parse().ok()),
+    ) {
+        (Some(s), Some(e), Some(i)) if s < e => (s, e, i),
+        _ => {
+            println!("usage: pitot scan <start MHz> <end MHz> [device index]");
+            return false;
+        }
+    };
+
+    let res = sensor::sdr::scan::scan(index, start, end, |s| {
+        println!(
+            "{:9.3} MHz  noise floor {:6.1} dBFS  peak {:6.1} dBFS at {:9.3} MHz",
+            s.frequency as f32 / 1e6,
+            s.noise_floor,
+            s.peak,
+            s.peak_frequency as f32 / 1e6
+        );
+    });
+
+    if let Err(e) = res {
+        println!("{}", e);
+        return false;
+    }
+
+    true
+}
+
+/// `export <log> [json | csv <type>]`, prints a recorded log as one JSON
+/// object per line, or the reports of one type as CSV
+fn export(args: &[String]) -> bool {
+    let kind = match (args.get(1).map(|s| s.as_str()), args.get(2)) {
+        (None, None) | (Some("json"), None) => None,
+        (Some("csv"), Some(k)) => Some(k),
+        _ => {
+            println!("usage: pitot export <log> [json | csv <type>]");
+            return false;
+        }
+    };
+
+    let log = match open_log(&args[0]) {
+        Some(l) => l,
+        None => return false,
+    };
+
+    // CSV columns are taken from the first report of the type
+    let mut columns: Option<Vec<String>> = None;
+
+    for r in log {
+        let r = match r {
+            Ok(r) => r,
+            Err(e) => {
+                println!("{} is corrupted: {}", args[0], e);
+                return false;
+            }
+        };
+
+        match kind {
+            None => println!("{}", r.to_json()),
+            Some(k) if k == r.kind => {
+                if columns.is_none() {
+                    let c: Vec<String> = r.value.keys().cloned().collect();
+                    println!("time,{}", c.join(","));
+                    columns = Some(c);
+                }
+
+                println!("{}", r.to_csv(columns.as_ref().unwrap()));
+            }
+            _ => {}
+        }
+    }
+
+    true
+}
+
+fn open_log(path: &str) -> Option<LogReader<BufReader<File>>> {
+    match File::open(path).and_then(|f| LogReader::new(BufReader::new(f))) {
+        Ok(l) => Some(l),
+        Err(e) => {
+            println!("unable to read {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// `replay <log> [fast]`, prints a recorded log at the pace it was recorded
+/// and, in builds with WebSocket, also sends it to clients of port 9001 so
+/// the web interface shows the recorded flight. With `fast`, the log is
+/// played on a `VirtualClock`, which does not wait for its time to come.
+fn replay(args: &[String]) -> bool {
+    let clock: Box<Clock> = match args.get(1).map(|s| s.as_str()) {
+        None => Box::new(SystemClock),
+        Some("fast") => Box::new(VirtualClock::new(SystemClock.utc())),
+        _ => {
+            println!("usage: pitot replay <log> [fast]");
+            return false;
+        }
+    };
+    let path = &args[0];
+
+    let log = match open_log(path) {
+        Some(l) => l,
+        None => return false,
+    };
+
+    // commands from clients are ignored
+    #[cfg(feature = "websocket")]
+    let (commands, _ignored) = std::sync::mpsc::channel();
+    #[cfg(feature = "websocket")]
+    let ws = protocol::websocket::WebSocket::new(
+        "0.0.0.0:9001".to_string(),
+        commands,
+        units::Units::Aviation,
+        transport::SharedStatus::default(),
+        pitot::pitot::situation::SharedSituation::default(),
+    );
+
+    let start = clock.now();
+
+    for r in log {
+        let r = match r {
+            Ok(r) => r,
+            Err(e) => {
+                println!("{} is corrupted: {}", path, e);
+                return false;
+            }
+        };
+
+        let due = Duration::from_millis(r.time as u64);
+        let elapsed = clock.now() - start;
+        if due > elapsed {
+            clock.sleep(due - elapsed);
+        }
+
+        let js = r.to_json();
+        println!("{}", js);
+        #[cfg(feature = "websocket")]
+        ws.broadcast(&js);
+    }
+
+    true
+}
+
+/// `decode-uat <capture>` and `decode-es <capture>`, prints everything the
+/// decoder finds in an I/Q capture
+#[cfg(any(feature = "es", feature = "uat"))]
+fn decode<F>(path: &str, decoder: F) -> bool
+where
+    F: FnOnce(BufReader<File>, &mut FnMut(SensorData)) -> io::Result<()>,
+{
+    let capture = match File::open(path) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => {
+            println!("unable to read {}: {}", path, e);
+            return false;
+        }
+    };
+
+    let res = decoder(capture, &mut |d| match d {
+        SensorData::Traffic(t) => println!("{:?}", t),
+        SensorData::FISB(f) => println!("Uplink, products {:?}", f.product_ids()),
+        _ => {}
+    });
+
+    if let Err(e) = res {
+        println!("unable to read {}: {}", path, e);
+        return false;
+    }
+
+    true
+}
+
+/// `gdl90-dump <file>`, prints every message of a GDL 90 byte stream, e.g. the
+/// payload of captured UDP packets
+#[cfg(feature = "gdl90")]
+fn gdl90_dump(path: &str) -> bool {
+    let mut stream = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut stream)) {
+        println!("unable to read {}: {}", path, e);
+        return false;
+    }
+
+    for m in protocol::gdl90::parse_stream(&stream) {
+        match m {
+            Ok(m) => println!("{}", protocol::gdl90::describe(&m)),
+            Err(e) => println!("invalid message: {}", e),
+        }
+    }
+
+    true
 }
@@ -0,0 +1,106 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Time source of the main loop. Components never read the time directly,
+//! they get it from their handle, which is stamped from a `Clock` every
+//! iteration.
+//!
+//! There are two kinds of time: the wall clock, `DateTime<UTC>`, which is
+//! what gets displayed, logged and sent to clients, and the monotonic clock,
+//! `Instant`, which everything measuring elapsed time or staleness has to
+//! use. The wall clock can jump at any time, e.g. when it is set from GNSS.
+
+use chrono::prelude::*;
+use chrono::Duration as UTCDuration;
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait Clock {
+    /// Current wall clock time
+    fn utc(&self) -> DateTime<UTC>;
+    /// Current monotonic time
+    fn now(&self) -> Instant;
+    /// Wait until `d` has passed
+    fn sleep(&self, d: Duration);
+}
+
+/// The real system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn utc(&self) -> DateTime<UTC> {
+        UTC::now()
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, d: Duration) {
+        thread::sleep(d);
+    }
+}
+
+/// A clock that only moves when slept on, letting the main loop run as
+/// fast as the CPU allows, e.g. for replaying recorded data
+pub struct VirtualClock {
+    utc: DateTime<UTC>,
+    start: Instant,
+    elapsed: Cell<Duration>,
+}
+
+impl VirtualClock {
+    /// Start the clock at wall clock time `utc`
+    pub fn new(utc: DateTime<UTC>) -> Self {
+        Self {
+            utc,
+            start: Instant::now(),
+            elapsed: Cell::new(Duration::from_secs(0)),
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn utc(&self) -> DateTime<UTC> {
+        self.utc + UTCDuration::from_std(self.elapsed.get()).unwrap()
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn sleep(&self, d: Duration) {
+        self.elapsed.set(self.elapsed.get() + d);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let c = VirtualClock::new(UTC::now());
+        let (utc, now) = (c.utc(), c.now());
+
+        assert_eq!(c.now(), now);
+
+        c.sleep(Duration::from_millis(1500));
+        assert_eq!(c.now() - now, Duration::from_millis(1500));
+        assert_eq!(c.utc().signed_duration_since(utc).num_milliseconds(), 1500);
+    }
+}
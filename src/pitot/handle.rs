@@ -14,12 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::clock::Clock;
+use chrono::prelude::*;
 use std::collections::VecDeque;
 use std::time::Instant;
-use time::{now_utc, Tm};
 
 pub trait Handle {
-    fn get_utc(&self) -> Tm;
+    /// Wall clock time of this iteration, only for displaying or recording
+    /// when something happened since it may jump
+    fn get_utc(&self) -> DateTime<UTC>;
+    /// Monotonic time of this iteration, for measuring elapsed time
     fn get_clock(&self) -> Instant;
     fn get_frequency(&self) -> u16;
 }
@@ -29,13 +33,13 @@ pub trait Pushable<D>: Handle {
 }
 
 pub struct BasicHandle {
-    utc: Tm,
+    utc: DateTime<UTC>,
     clock: Instant,
     freq: u16,
 }
 
 impl Handle for BasicHandle {
-    fn get_utc(&self) -> Tm {
+    fn get_utc(&self) -> DateTime<UTC> {
         self.utc
     }
 
@@ -49,10 +53,11 @@ impl Handle for BasicHandle {
 }
 
 impl BasicHandle {
-    pub fn new(freq: u16) -> Self {
+    /// Snapshot the time from `clock`
+    pub fn new(freq: u16, clock: &Clock) -> Self {
         Self {
-            utc: now_utc(),
-            clock: Instant::now(),
+            utc: clock.utc(),
+            clock: clock.now(),
             freq,
         }
     }
@@ -71,7 +76,7 @@ impl<'a, H, D> Handle for PushableHandle<'a, H, D>
 where
     H: 'a + Handle,
 {
-    fn get_utc(&self) -> Tm {
+    fn get_utc(&self) -> DateTime<UTC> {
         self.handle.get_utc()
     }
 
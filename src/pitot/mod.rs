@@ -14,33 +14,161 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod clock;
 pub mod handle;
+pub mod registry;
+pub mod situation;
+pub mod trace;
 
-use self::handle::{BasicHandle, PushableHandle};
+use self::clock::{Clock, SystemClock};
+use self::handle::{BasicHandle, Handle, PushableHandle};
+use self::registry::Registry;
+use self::situation::SharedSituation;
+use self::trace::{Stage, Tracer};
 use processor::{Processor, Report};
 use protocol::{Payload, Protocol};
 use sensor::Sensor;
 use sensor::SensorData;
 use std::collections::VecDeque;
-use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::fs::File;
+use std::io::BufWriter;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use transport::Transport;
 
+/// Set by `stop` to leave the main loop
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Make `Pitot::run` return after the current iteration. Only stores to an
+/// atomic, so it is safe to call from a signal handler.
+pub fn stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// `stop` on SIGTERM, which systemd sends on shutdown and reboot, and on
+/// SIGINT. A second signal kills Pitot right away.
+#[cfg(unix)]
+pub fn stop_on_signals() {
+    use libc::{c_int, sighandler_t, signal, SIGINT, SIGTERM, SIG_DFL};
+
+    extern "C" fn handler(sig: c_int) {
+        stop();
+        unsafe {
+            signal(sig, SIG_DFL);
+        }
+    }
+
+    unsafe {
+        signal(SIGTERM, handler as sighandler_t);
+        signal(SIGINT, handler as sighandler_t);
+    }
+}
+
+/// A component linked into the pipeline under `name`, which clients use to
+/// pause and resume it at runtime
+struct Linked<T: ?Sized> {
+    name: String,
+    paused: bool,
+    /// Components of the same stage this one runs after
+    after: Vec<String>,
+    /// Report topics this processor subscribed to, see `Report::topic`
+    topics: Vec<String>,
+    inner: Box<T>,
+}
+
+impl<T: ?Sized> Linked<T> {
+    fn new(name: &str, inner: Box<T>) -> Self {
+        Linked {
+            name: name.to_string(),
+            paused: false,
+            after: Vec::new(),
+            topics: Vec::new(),
+            inner,
+        }
+    }
+}
+
+/// Reorder `linked` so every component comes after those it was linked
+/// after, otherwise keeping the order they were linked in
+fn order<T: ?Sized>(linked: &mut Vec<Linked<T>>) {
+    for l in linked.iter() {
+        for a in &l.after {
+            if !linked.iter().any(|o| &o.name == a) {
+                warn!("{} is linked after {}, which is not linked", l.name, a);
+            }
+        }
+    }
+
+    let mut remaining = mem::replace(linked, Vec::new());
+
+    while !remaining.is_empty() {
+        let next = remaining.iter().position(|l| {
+            l.after
+                .iter()
+                .all(|a| !remaining.iter().any(|r| &r.name == a))
+        });
+
+        let i = match next {
+            Some(i) => i,
+            None => {
+                let names: Vec<&str> = remaining.iter().map(|l| l.name.as_str()).collect();
+                warn!(
+                    "{} are linked after each other, running them in link order",
+                    names.join(", ")
+                );
+                0
+            }
+        };
+
+        linked.push(remaining.remove(i));
+    }
+}
+
+/// Set `paused` on every component in `linked` named `name`, calling `hook`
+/// on those whose state changes. Returns whether any matched.
+fn set_paused<T: ?Sized, F>(linked: &mut [Linked<T>], name: &str, paused: bool, hook: F) -> bool
+where
+    F: Fn(&mut T),
+{
+    let mut found = false;
+
+    for l in linked.iter_mut().filter(|l| l.name == name) {
+        found = true;
+
+        if l.paused != paused {
+            l.paused = paused;
+            hook(&mut *l.inner);
+        }
+    }
+
+    found
+}
+
 pub struct Pitot {
-    sensors: Vec<Box<Sensor>>,
-    processors: Vec<Box<Processor>>,
-    protocols: Vec<Box<Protocol>>,
-    transports: Vec<Box<Transport>>,
+    sensors: Vec<Linked<Sensor>>,
+    processors: Vec<Linked<Processor>>,
+    protocols: Vec<Linked<Protocol>>,
+    transports: Vec<Linked<Transport>>,
     interval: Duration,
     frequency: u16,
     sensor_queue: VecDeque<SensorData>,
     report_queue: VecDeque<Report>,
     payload_queue: VecDeque<Payload>,
     handle: BasicHandle,
+    clock: Box<Clock>,
+    registry: Registry,
+    tracer: Option<Tracer<BufWriter<File>>>,
+    situation: SharedSituation,
 }
 
 impl Pitot {
     pub fn new(freq: u16) -> Self {
+        Pitot::with_clock(freq, Box::new(SystemClock))
+    }
+
+    /// Drive the main loop from `clock` instead of the system clock
+    pub fn with_clock(freq: u16, clock: Box<Clock>) -> Self {
         Pitot {
             sensors: vec![],
             processors: vec![],
@@ -51,37 +179,122 @@ impl Pitot {
             payload_queue: VecDeque::new(),
             frequency: freq,
             interval: Duration::from_millis((1000 / freq) as u64),
-            handle: BasicHandle::new(freq),
+            handle: BasicHandle::new(freq, &*clock),
+            clock,
+            registry: Registry::default(),
+            tracer: None,
+            situation: SharedSituation::default(),
         }
     }
 
-    pub fn link_sensor(&mut self, s: Box<Sensor>) {
-        self.sensors.push(s);
+    pub fn link_sensor(&mut self, name: &str, s: Box<Sensor>) {
+        self.sensors.push(Linked::new(name, s));
+    }
+
+    pub fn link_processor(&mut self, name: &str, p: Box<Processor>) {
+        self.processors.push(Linked::new(name, p));
     }
 
-    pub fn link_processor(&mut self, p: Box<Processor>) {
-        self.processors.push(p);
+    /// Link a processor that runs after the processors named in `after`
+    /// and gets the reports they produced in the same iteration through
+    /// `Processor::run_reports`
+    pub fn link_processor_after(&mut self, name: &str, p: Box<Processor>, after: &[&str]) {
+        let mut linked = Linked::new(name, p);
+        linked.after = after.iter().map(|a| a.to_string()).collect();
+        self.processors.push(linked);
     }
 
-    pub fn link_protocol(&mut self, p: Box<Protocol>) {
-        self.protocols.push(p);
+    /// Link a processor that also gets every report of the given `topics`
+    /// produced in the same iteration, whichever processor produced it,
+    /// through `Processor::run_reports`. Reports it produces from those are
+    /// in turn delivered to the other subscribers.
+    pub fn link_subscriber(&mut self, name: &str, p: Box<Processor>, topics: &[&str]) {
+        let mut linked = Linked::new(name, p);
+        linked.topics = topics.iter().map(|t| t.to_string()).collect();
+        self.processors.push(linked);
     }
 
-    pub fn link_transport(&mut self, t: Box<Transport>) {
-        self.transports.push(t);
+    pub fn link_protocol(&mut self, name: &str, p: Box<Protocol>) {
+        self.protocols.push(Linked::new(name, p));
+    }
+
+    pub fn link_transport(&mut self, name: &str, t: Box<Transport>) {
+        self.transports.push(Linked::new(name, t));
+    }
+
+    /// Trace every message through the pipeline into a new file in `dir`,
+    /// see `trace`
+    pub fn trace(&mut self, dir: &str) {
+        self.tracer = Tracer::create(dir, self.clock.now());
+    }
+
+    /// Latest state of the world, updated from the reports of every
+    /// iteration before the protocols run
+    pub fn situation(&self) -> SharedSituation {
+        self.situation.clone()
+    }
+
+    /// Pause or resume every linked component named `name`, whatever stage
+    /// it is in. Returns whether any component has that name.
+    pub fn set_paused(&mut self, name: &str, paused: bool) -> bool {
+        let found = [
+            set_paused(&mut self.sensors, name, paused, |s| {
+                if paused {
+                    s.pause()
+                } else {
+                    s.resume()
+                }
+            }),
+            set_paused(&mut self.processors, name, paused, |p| {
+                if paused {
+                    p.pause()
+                } else {
+                    p.resume()
+                }
+            }),
+            set_paused(&mut self.protocols, name, paused, |p| {
+                if paused {
+                    p.pause()
+                } else {
+                    p.resume()
+                }
+            }),
+            set_paused(&mut self.transports, name, paused, |t| {
+                if paused {
+                    t.pause()
+                } else {
+                    t.resume()
+                }
+            }),
+        ].contains(&true);
+
+        if !found {
+            warn!("no component named {} to pause or resume", name);
+        } else if paused {
+            info!("{} paused", name);
+        } else {
+            info!("{} resumed", name);
+        }
+
+        found
     }
 
     fn run_sensors(&mut self) {
-        let mut handle = PushableHandle::new(&mut self.handle, &mut self.sensor_queue);
+        for s in self.sensors.iter_mut().filter(|s| !s.paused) {
+            let before = self.sensor_queue.len();
+            s.inner.run(&mut PushableHandle::new(
+                &mut self.handle,
+                &mut self.sensor_queue,
+            ));
 
-        for s in self.sensors.iter_mut() {
-            s.run(&mut handle);
+            if let Some(ref mut t) = self.tracer {
+                let items = self.sensor_queue.iter_mut().skip(before);
+                t.sensed(self.clock.now(), &s.name, items);
+            }
         }
     }
 
     fn run_processors(&mut self) {
-        let mut handle = PushableHandle::new(&mut self.handle, &mut self.report_queue);
-
         {
             let (first, second) = self.sensor_queue.as_slices();
             debug!(
@@ -91,17 +304,117 @@ impl Pitot {
             trace!("{:?}", first);
             trace!("{:?}", second);
 
-            for s in self.processors.iter_mut() {
-                s.run(&mut handle, first.iter().chain(second));
+            let names: Vec<String> = self.processors.iter().map(|p| p.name.clone()).collect();
+            // which processor produced each report of this iteration
+            let mut producers: Vec<usize> = Vec::new();
+            let mut consumed = VecDeque::new();
+
+            for (n, s) in self.processors.iter_mut().enumerate() {
+                if s.paused {
+                    continue;
+                }
+
+                let before = self.report_queue.len();
+                s.inner.run(
+                    &mut PushableHandle::new(&mut self.handle, &mut self.report_queue),
+                    first.iter().chain(second),
+                );
+
+                if !s.after.is_empty() {
+                    let reports: Vec<&Report> = self
+                        .report_queue
+                        .iter()
+                        .zip(&producers)
+                        .filter(|&(_, &p)| s.after.contains(&names[p]))
+                        .map(|(r, _)| r)
+                        .collect();
+
+                    s.inner.run_reports(
+                        &mut PushableHandle::new(&mut self.handle, &mut consumed),
+                        &reports,
+                    );
+                }
+                self.report_queue.append(&mut consumed);
+                producers.resize(self.report_queue.len(), n);
+
+                if let Some(ref mut t) = self.tracer {
+                    let items = self.report_queue.iter().skip(before);
+                    t.produced(self.clock.now(), Stage::Processor, &s.name, items);
+                }
+            }
+
+            // deliver the reports of each round to the subscribers, the
+            // reports they produce make up the next round. Bounded so
+            // subscribers answering each other can not stall the loop.
+            let mut round = 0;
+            for _ in 0..self.processors.len() {
+                let end = self.report_queue.len();
+                if round == end {
+                    break;
+                }
+
+                for (n, s) in self.processors.iter_mut().enumerate() {
+                    if s.paused || s.topics.is_empty() {
+                        continue;
+                    }
+
+                    let reports: Vec<&Report> = self
+                        .report_queue
+                        .iter()
+                        .zip(&producers)
+                        .skip(round)
+                        .take(end - round)
+                        .filter(|&(r, &p)| p != n && s.topics.iter().any(|t| t == r.topic()))
+                        .map(|(r, _)| r)
+                        .collect();
+
+                    if reports.is_empty() {
+                        continue;
+                    }
+
+                    s.inner.run_reports(
+                        &mut PushableHandle::new(&mut self.handle, &mut consumed),
+                        &reports,
+                    );
+
+                    if let Some(ref mut t) = self.tracer {
+                        t.produced(self.clock.now(), Stage::Processor, &s.name, consumed.iter());
+                    }
+                    self.report_queue.append(&mut consumed);
+                    producers.resize(self.report_queue.len(), n);
+                }
+
+                round = end;
+            }
+
+            if round < self.report_queue.len() {
+                warn!("subscribers still producing reports, not delivering them this iteration");
             }
         }
 
         self.sensor_queue.clear();
     }
 
-    fn run_protocols(&mut self) {
-        let mut handle = PushableHandle::new(&mut self.handle, &mut self.payload_queue);
+    /// Apply the lifecycle changes requested by processors
+    fn apply_lifecycle(&mut self) {
+        let changes: Vec<(String, bool)> = self
+            .report_queue
+            .iter()
+            .filter_map(|r| match *r {
+                Report::Lifecycle {
+                    ref component,
+                    paused,
+                } => Some((component.clone(), paused)),
+                _ => None,
+            })
+            .collect();
 
+        for (component, paused) in changes {
+            self.set_paused(&component, paused);
+        }
+    }
+
+    fn run_protocols(&mut self) {
         {
             let (first, second) = self.report_queue.as_slices();
             debug!(
@@ -111,8 +424,23 @@ impl Pitot {
             trace!("{:?}", first);
             trace!("{:?}", second);
 
-            for s in self.protocols.iter_mut() {
-                s.run(&mut handle, first.iter().chain(second));
+            for s in self.protocols.iter_mut().filter(|s| !s.paused) {
+                let before = self.payload_queue.len();
+                s.inner.run(
+                    &mut PushableHandle::new(&mut self.handle, &mut self.payload_queue),
+                    first.iter().chain(second),
+                );
+                // locked per protocol so the web server isn't held up for
+                // all of them at once
+                s.inner.run_situation(
+                    &mut PushableHandle::new(&mut self.handle, &mut self.payload_queue),
+                    &self.situation.lock().unwrap(),
+                );
+
+                if let Some(ref mut t) = self.tracer {
+                    let items = self.payload_queue.iter().skip(before);
+                    t.produced(self.clock.now(), Stage::Protocol, &s.name, items);
+                }
             }
         }
 
@@ -129,35 +457,60 @@ impl Pitot {
             trace!("{:?}", first);
             trace!("{:?}", second);
 
-            for s in self.transports.iter_mut() {
-                s.run(&mut self.handle, first.iter().chain(second));
+            for s in self.transports.iter_mut().filter(|s| !s.paused) {
+                s.inner.run(&mut self.handle, first.iter().chain(second));
+
+                if let Some(ref mut t) = self.tracer {
+                    t.delivered(self.clock.now(), &s.name);
+                }
             }
         }
 
         self.payload_queue.clear();
     }
 
+    /// Run the main loop until `stop` is called, then let the processors
+    /// shut down
     pub fn run(&mut self) {
-        loop {
+        order(&mut self.processors);
+
+        while !STOP.load(Ordering::SeqCst) {
             // main event loop
-            let before = Instant::now();
+            let before = self.clock.now();
 
             // update the handle
-            self.handle = BasicHandle::new(self.frequency);
+            self.handle = BasicHandle::new(self.frequency, &*self.clock);
+            if let Some(ref mut t) = self.tracer {
+                t.next_iteration();
+            }
 
             self.run_sensors();
             self.run_processors();
+            self.apply_lifecycle();
+            self.situation
+                .lock()
+                .unwrap()
+                .update(self.report_queue.iter(), self.handle.get_clock());
             self.run_protocols();
             self.run_transports();
+            if let Some(ref mut t) = self.tracer {
+                t.flush();
+            }
 
-            let elapsed = before.elapsed();
+            let elapsed = self.clock.now() - before;
 
             if elapsed < self.interval {
-                sleep(self.interval - elapsed);
+                self.clock.sleep(self.interval - elapsed);
             } else {
                 warn!("loop unable to keep up with the set frequency");
             }
         }
+
+        info!("stopping");
+
+        for p in &mut self.processors {
+            p.inner.shutdown();
+        }
     }
 }
 
@@ -166,3 +519,157 @@ impl Default for Pitot {
         Pitot::new(10)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Pushable;
+    use std::cell::Cell;
+    use std::iter::Chain;
+    use std::rc::Rc;
+    use std::slice::Iter;
+
+    /// Counts runs and pauses
+    struct Counter(Rc<Cell<(u32, u32)>>);
+
+    impl Sensor for Counter {
+        fn run(&mut self, _h: &mut Pushable<SensorData>) {
+            let (runs, pauses) = self.0.get();
+            self.0.set((runs + 1, pauses));
+        }
+
+        fn pause(&mut self) {
+            let (runs, pauses) = self.0.get();
+            self.0.set((runs, pauses + 1));
+        }
+    }
+
+    impl Processor for Counter {
+        fn run(
+            &mut self,
+            _h: &mut Pushable<Report>,
+            _i: Chain<Iter<SensorData>, Iter<SensorData>>,
+        ) {
+        }
+
+        fn shutdown(&mut self) {
+            let (runs, pauses) = self.0.get();
+            self.0.set((runs + 1, pauses));
+        }
+    }
+
+    /// Reports its name, and how many reports it got from the processors it
+    /// runs after or subscribed to
+    struct Named(&'static str);
+
+    impl Processor for Named {
+        fn run(&mut self, h: &mut Pushable<Report>, _i: Chain<Iter<SensorData>, Iter<SensorData>>) {
+            h.push_data(Report::Lifecycle {
+                component: self.0.to_string(),
+                paused: false,
+            });
+        }
+
+        fn run_reports(&mut self, h: &mut Pushable<Report>, reports: &[&Report]) {
+            h.push_data(Report::Lifecycle {
+                component: format!("{} got {}", self.0, reports.len()),
+                paused: false,
+            });
+        }
+    }
+
+    fn components(p: &Pitot) -> Vec<String> {
+        p.report_queue
+            .iter()
+            .filter_map(|r| match *r {
+                Report::Lifecycle { ref component, .. } => Some(component.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_processor_order() {
+        let mut p = Pitot::new(10);
+        p.link_processor_after("c", Box::new(Named("c")), &["b"]);
+        p.link_processor_after("b", Box::new(Named("b")), &["a"]);
+        p.link_processor("a", Box::new(Named("a")));
+        p.link_processor_after("x", Box::new(Named("x")), &["unknown"]);
+        order(&mut p.processors);
+
+        p.run_processors();
+        assert_eq!(
+            components(&p),
+            vec!["a", "b", "b got 1", "c", "c got 2", "x", "x got 0"]
+        );
+
+        // a cycle runs in link order
+        let mut p = Pitot::new(10);
+        p.link_processor_after("a", Box::new(Named("a")), &["b"]);
+        p.link_processor_after("b", Box::new(Named("b")), &["a"]);
+        order(&mut p.processors);
+
+        p.run_processors();
+        assert_eq!(components(&p), vec!["a", "a got 0", "b", "b got 2"]);
+    }
+
+    #[test]
+    fn test_subscriber() {
+        let mut p = Pitot::new(10);
+        p.link_subscriber("b", Box::new(Named("b")), &["Lifecycle"]);
+        p.link_processor("a", Box::new(Named("a")));
+        p.link_subscriber("x", Box::new(Named("x")), &["Traffic"]);
+
+        p.run_processors();
+        // b does not get its own reports
+        assert_eq!(components(&p), vec!["b", "a", "x", "b got 2"]);
+
+        // subscribers answering each other stop after a round per processor
+        let mut p = Pitot::new(10);
+        p.link_subscriber("a", Box::new(Named("a")), &["Lifecycle"]);
+        p.link_subscriber("b", Box::new(Named("b")), &["Lifecycle"]);
+
+        p.run_processors();
+        assert_eq!(
+            components(&p),
+            vec!["a", "b", "a got 1", "b got 1", "a got 1", "b got 1"]
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut p = Pitot::new(10);
+        let counts = Rc::new(Cell::new((0, 0)));
+        p.link_processor("counter", Box::new(Counter(counts.clone())));
+        p.set_paused("counter", true);
+
+        stop();
+        p.run();
+        // paused processors are shut down too
+        assert_eq!(counts.get(), (1, 0));
+    }
+
+    #[test]
+    fn test_set_paused() {
+        let mut p = Pitot::new(10);
+        let counts = Rc::new(Cell::new((0, 0)));
+        p.link_sensor("counter", Box::new(Counter(counts.clone())));
+
+        p.run_sensors();
+        assert!(p.set_paused("counter", true));
+        // pausing twice only calls the hook once
+        assert!(p.set_paused("counter", true));
+        p.run_sensors();
+        assert_eq!(counts.get(), (1, 1));
+
+        assert!(!p.set_paused("unknown", false));
+
+        p.report_queue.push_back(Report::Lifecycle {
+            component: "counter".to_string(),
+            paused: false,
+        });
+        p.apply_lifecycle();
+        p.run_sensors();
+        assert_eq!(counts.get(), (2, 1));
+    }
+}
@@ -0,0 +1,153 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Named factories for components living outside of Pitot. Other crates
+//! register them against the public stage traits, and users enable them
+//! by name in the `extensions` section of the config file.
+
+use super::Pitot;
+use processor::Processor;
+use protocol::Protocol;
+use sensor::Sensor;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use transport::Transport;
+
+/// A factory gets the settings from its config entry and returns `None`
+/// if the component can not run, e.g. because its hardware is missing.
+pub type Factory<T> = Box<Fn(&Value) -> Option<Box<T>>>;
+
+enum Component {
+    Sensor(Factory<Sensor>),
+    Processor(Factory<Processor>),
+    Protocol(Factory<Protocol>),
+    Transport(Factory<Transport>),
+}
+
+#[derive(Default)]
+pub struct Registry {
+    factories: HashMap<String, Component>,
+}
+
+impl Registry {
+    fn register(&mut self, name: &str, c: Component) {
+        if self.factories.insert(name.to_string(), c).is_some() {
+            warn!("extension {} registered more than once, using the last one", name);
+        }
+    }
+
+    /// Create and link every extension listed in `extensions`
+    fn link(&self, extensions: &BTreeMap<String, Value>, p: &mut Pitot) {
+        for (name, settings) in extensions {
+            let linked = match self.factories.get(name) {
+                Some(&Component::Sensor(ref f)) => f(settings).map(|s| p.link_sensor(name, s)),
+                Some(&Component::Processor(ref f)) => {
+                    f(settings).map(|s| p.link_processor(name, s))
+                }
+                Some(&Component::Protocol(ref f)) => f(settings).map(|s| p.link_protocol(name, s)),
+                Some(&Component::Transport(ref f)) => {
+                    f(settings).map(|s| p.link_transport(name, s))
+                }
+                None => {
+                    error!("unknown extension {}, is it registered?", name);
+                    continue;
+                }
+            };
+
+            match linked {
+                Some(_) => info!("extension {} linked", name),
+                None => warn!("extension {} is not available", name),
+            }
+        }
+    }
+}
+
+impl Pitot {
+    pub fn register_sensor_factory<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&Value) -> Option<Box<Sensor>> + 'static,
+    {
+        self.registry.register(name, Component::Sensor(Box::new(factory)));
+    }
+
+    pub fn register_processor_factory<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&Value) -> Option<Box<Processor>> + 'static,
+    {
+        self.registry.register(name, Component::Processor(Box::new(factory)));
+    }
+
+    pub fn register_protocol_factory<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&Value) -> Option<Box<Protocol>> + 'static,
+    {
+        self.registry.register(name, Component::Protocol(Box::new(factory)));
+    }
+
+    pub fn register_transport_factory<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&Value) -> Option<Box<Transport>> + 'static,
+    {
+        self.registry.register(name, Component::Transport(Box::new(factory)));
+    }
+
+    /// Link the registered extensions enabled in the config, call this
+    /// after registering all factories
+    pub fn link_extensions(&mut self, extensions: &BTreeMap<String, Value>) {
+        let registry = mem::replace(&mut self.registry, Registry::default());
+        registry.link(extensions, self);
+        self.registry = registry;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Pushable;
+    use serde_json;
+    use sensor::SensorData;
+
+    struct Dummy;
+
+    impl Sensor for Dummy {
+        fn run(&mut self, _h: &mut Pushable<SensorData>) {}
+    }
+
+    #[test]
+    fn test_link_extensions() {
+        let mut p = Pitot::new(10);
+
+        p.register_sensor_factory("dummy", |settings| {
+            if settings["enabled"] == Value::Bool(true) {
+                Some(Box::new(Dummy) as Box<Sensor>)
+            } else {
+                None
+            }
+        });
+
+        let mut extensions = BTreeMap::new();
+        extensions.insert("unknown".to_string(), Value::Null);
+        extensions.insert("dummy".to_string(), serde_json::from_str(r#"{"enabled": false}"#).unwrap());
+        p.link_extensions(&extensions);
+        assert_eq!(p.sensors.len(), 0);
+
+        extensions.insert("dummy".to_string(), serde_json::from_str(r#"{"enabled": true}"#).unwrap());
+        p.link_extensions(&extensions);
+        assert_eq!(p.sensors.len(), 1);
+        assert_eq!(p.sensors[0].name, "dummy");
+    }
+}
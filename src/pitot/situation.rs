@@ -0,0 +1,603 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The latest state of the world as reported by the processors.
+//!
+//! `Pitot` updates the `Situation` from the reports of every iteration, so
+//! other threads can look at all of it at once instead of each protocol
+//! keeping its own partial copy, e.g. the WebSocket answering `GetSituation`.
+//!
+//! With `traffic_history` set, the traffic reports of the last minutes are
+//! kept too, up to `MAX_HISTORY`, so the traffic table can be exported as it
+//! was over time. Exports are written out row by row, so the whole history
+//! is never formatted in memory at once.
+
+use processor::antenna::Day;
+use processor::cabin::Cabin;
+use processor::gnss::{SkyView, GNSS};
+use processor::ownship::Ownship;
+use processor::traffic::Target;
+use processor::Report;
+use sensor::sdr::DecodeStats;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use units::{self, Units};
+
+/// Ownship, GNSS, cabin environment and traffic not reported for this long
+/// are gone, in seconds
+const MAX_AGE: u64 = 3;
+/// FIS-B products not uplinked for this long are left out of the inventory,
+/// in seconds. Most products are uplinked at least every 10 minutes.
+const FISB_MAX_AGE: u64 = 600;
+/// Most traffic reports kept in the history whatever its length, about
+/// 25 MB of them
+const MAX_HISTORY: usize = 100_000;
+
+/// Columns of the CSV traffic export
+const CSV_HEADER: &str = "time,addr,band,callsign,lat,lon,altitude,heading,speed,vs,squawk,\
+                          on_ground,nic,nacp,nacv,sil,range,bearing\r\n";
+
+/// Kept up to date by `Pitot`, see `Pitot::situation`
+pub type SharedSituation = Arc<Mutex<Situation>>;
+
+#[derive(Debug, Default)]
+pub struct Situation {
+    /// when it was last updated
+    clock: Option<Instant>,
+    ownship: Option<(Ownship, Instant)>,
+    gnss: Option<(GNSS, Instant)>,
+    sky_view: Option<(SkyView, Instant)>,
+    cabin: Option<(Cabin, Instant)>,
+    /// today's antenna summaries, the rest of the history is in storage
+    antenna: Option<Day>,
+    /// by address and sub ID
+    traffic: BTreeMap<(u32, u8), (Arc<Target>, Instant)>,
+    /// latest statistics of each band
+    stats: Vec<DecodeStats>,
+    /// number of times a target showed up on an address another target
+    /// was using
+    duplicate_addresses: u32,
+    /// times each FIS-B product was uplinked, and when it last was
+    fisb: BTreeMap<u16, (u32, Instant)>,
+    /// how long traffic reports are kept in `history`, if at all
+    history_len: Option<Duration>,
+    /// traffic reports, oldest first
+    history: VecDeque<(Arc<Target>, Instant)>,
+    /// when the newest report dropped from `history` for `MAX_HISTORY`
+    /// rather than its age was made
+    evicted: Option<Instant>,
+}
+
+/// Clear `item` if it was reported more than `secs` before `clock`
+fn expire<T>(item: &mut Option<(T, Instant)>, clock: Instant, secs: u64) {
+    if item
+        .as_ref()
+        .map_or(false, |i| clock - i.1 >= Duration::from_secs(secs))
+    {
+        *item = None;
+    }
+}
+
+/// Empty for unknown values
+fn cell<T: Display>(v: Option<T>) -> String {
+    v.map_or(String::new(), |v| v.to_string())
+}
+
+/// `v` with the measurements of report `kind` also in `units`
+fn annotated<T: Serialize>(kind: &str, v: &T, units: Units) -> Value {
+    let mut js = serde_json::to_value(v).unwrap();
+    if let Value::Object(ref mut o) = js {
+        units::annotate(kind, o, units);
+    }
+
+    js
+}
+
+impl Situation {
+    /// Keep traffic reports for this many minutes, or none
+    pub fn keep_history(&mut self, minutes: Option<u64>) {
+        self.history_len = minutes.map(|m| Duration::from_secs(m * 60));
+        if self.history_len.is_none() {
+            self.history.clear();
+            self.evicted = None;
+        }
+    }
+
+    /// How long traffic reports are kept for, if at all
+    pub fn history_len(&self) -> Option<Duration> {
+        self.history_len
+    }
+
+    /// Take in the reports of the iteration run at `clock`
+    pub fn update<'a, I>(&mut self, reports: I, clock: Instant)
+    where
+        I: Iterator<Item = &'a Report>,
+    {
+        for r in reports {
+            match *r {
+                Report::Ownship(o) => self.ownship = Some((o, clock)),
+                Report::GNSS(ref g) => self.gnss = Some((g.clone(), clock)),
+                Report::SkyView(ref s) => self.sky_view = Some((s.clone(), clock)),
+                Report::Cabin(ref c) => self.cabin = Some((c.clone(), clock)),
+                Report::Antenna(ref a) => self.antenna = Some(a.clone()),
+                Report::Traffic(ref t) => {
+                    let new = self
+                        .traffic
+                        .insert((t.addr.0, t.sub_id), (t.clone(), clock))
+                        .is_none();
+                    if new && t.sub_id != 0 {
+                        self.duplicate_addresses += 1;
+                    }
+                    if self.history_len.is_some() {
+                        if self.history.len() >= MAX_HISTORY {
+                            self.evicted = self.history.pop_front().map(|e| e.1);
+                        }
+                        self.history.push_back((t.clone(), clock));
+                    }
+                }
+                Report::Config(ref c) => self.keep_history(c.traffic_history),
+                Report::DecodeStats(ref s) => {
+                    // kept apart from those of a paired unit
+                    self.stats.retain(|b| b.band != s.band || b.peer != s.peer);
+                    self.stats.push(s.clone());
+                }
+                Report::FISB(ref f) => {
+                    for id in f.product_ids() {
+                        let p = self.fisb.entry(id).or_insert((0, clock));
+                        p.0 += 1;
+                        p.1 = clock;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        expire(&mut self.ownship, clock, MAX_AGE);
+        expire(&mut self.gnss, clock, MAX_AGE);
+        expire(&mut self.sky_view, clock, MAX_AGE);
+        expire(&mut self.cabin, clock, MAX_AGE);
+
+        let gone: Vec<(u32, u8)> = self
+            .traffic
+            .iter()
+            .filter(|&(_, t)| clock - t.1 >= Duration::from_secs(MAX_AGE))
+            .map(|(k, _)| *k)
+            .collect();
+        for k in gone {
+            self.traffic.remove(&k);
+        }
+
+        if let Some(len) = self.history_len {
+            while self.history.front().map_or(false, |t| clock - t.1 > len) {
+                self.history.pop_front();
+            }
+        }
+
+        let gone: Vec<u16> = self
+            .fisb
+            .iter()
+            .filter(|&(_, p)| clock - p.1 >= Duration::from_secs(FISB_MAX_AGE))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in gone {
+            self.fisb.remove(&id);
+        }
+
+        self.clock = Some(clock);
+    }
+
+    pub fn ownship(&self) -> Option<&Ownship> {
+        self.ownship.as_ref().map(|o| &o.0)
+    }
+
+    pub fn gnss(&self) -> Option<&GNSS> {
+        self.gnss.as_ref().map(|g| &g.0)
+    }
+
+    pub fn sky_view(&self) -> Option<&SkyView> {
+        self.sky_view.as_ref().map(|s| &s.0)
+    }
+
+    pub fn cabin(&self) -> Option<&Cabin> {
+        self.cabin.as_ref().map(|c| &c.0)
+    }
+
+    pub fn antenna(&self) -> Option<&Day> {
+        self.antenna.as_ref()
+    }
+
+    /// Every known target, by address
+    pub fn traffic(&self) -> Vec<&Target> {
+        self.traffic.values().map(|t| &*t.0).collect()
+    }
+
+    /// The closest target to ownship, among those at a known, finite range
+    pub fn nearest(&self) -> Option<&Target> {
+        self.traffic
+            .values()
+            .map(|t| &*t.0)
+            .filter(|t| t.range.map_or(false, |r| r.is_finite()))
+            .min_by(|a, b| a.range.partial_cmp(&b.range).unwrap())
+    }
+
+    /// Latest statistics of each band
+    pub fn stats(&self) -> &[DecodeStats] {
+        &self.stats
+    }
+
+    /// Known targets and when they were last reported, or every report of
+    /// the last `history`, which must not be longer than is kept. Cheap to
+    /// take, formatting is left to `Export` so it can be done without
+    /// holding the lock of a `SharedSituation`.
+    pub fn export(&self, history: Option<Duration>) -> Export {
+        let clock = match self.clock {
+            Some(c) => c,
+            None => {
+                return Export {
+                    rows: vec![],
+                    clock: None,
+                    shortened: false,
+                }
+            }
+        };
+        let shortened = match (history, self.evicted) {
+            (Some(h), Some(e)) => clock - e <= h,
+            _ => false,
+        };
+
+        let rows = match history {
+            Some(h) => self
+                .history
+                .iter()
+                .filter(|t| clock - t.1 <= h)
+                .cloned()
+                .collect(),
+            None => self.traffic.values().cloned().collect(),
+        };
+
+        Export {
+            rows,
+            clock: Some(clock),
+            shortened,
+        }
+    }
+
+    /// `Situation` message with everything known, measurements also in
+    /// `units`. The age of FIS-B products is in seconds as of the last
+    /// update.
+    pub fn to_json(&self, units: Units) -> Value {
+        let clock = self.clock;
+        let traffic: Vec<Value> = self
+            .traffic
+            .values()
+            .map(|t| annotated("Traffic", &*t.0, units))
+            .collect();
+        let fisb: Vec<Value> = self
+            .fisb
+            .iter()
+            .map(|(id, p)| {
+                let mut js = serde_json::Map::new();
+                js.insert("product".to_string(), (*id).into());
+                js.insert("count".to_string(), p.0.into());
+                js.insert(
+                    "age".to_string(),
+                    clock.map_or(0, |c| (c - p.1).as_secs()).into(),
+                );
+
+                Value::Object(js)
+            })
+            .collect();
+
+        let mut js = serde_json::Map::new();
+        js.insert("type".to_string(), "Situation".into());
+        js.insert(
+            "ownship".to_string(),
+            self.ownship
+                .map_or(Value::Null, |o| annotated("Ownship", &o.0, units)),
+        );
+        js.insert(
+            "gnss".to_string(),
+            self.gnss
+                .as_ref()
+                .map_or(Value::Null, |g| annotated("GNSS", &g.0, units)),
+        );
+        js.insert(
+            "sky_view".to_string(),
+            self.sky_view
+                .as_ref()
+                .map_or(Value::Null, |s| annotated("SkyView", &s.0, units)),
+        );
+        js.insert(
+            "cabin".to_string(),
+            self.cabin
+                .as_ref()
+                .map_or(Value::Null, |c| annotated("Cabin", &c.0, units)),
+        );
+        js.insert("traffic".to_string(), traffic.into());
+        js.insert(
+            "stats".to_string(),
+            serde_json::to_value(&self.stats).unwrap(),
+        );
+        js.insert(
+            "duplicate_addresses".to_string(),
+            self.duplicate_addresses.into(),
+        );
+        js.insert("fisb".to_string(), fisb.into());
+
+        Value::Object(js)
+    }
+}
+
+/// Traffic reports taken from the `Situation` to be exported
+pub struct Export {
+    rows: Vec<(Arc<Target>, Instant)>,
+    /// of the last update of the `Situation`
+    clock: Option<Instant>,
+    /// whether reports of the requested history were already dropped for
+    /// `MAX_HISTORY`
+    shortened: bool,
+}
+
+impl Export {
+    /// UNIX time of `at`, `now` being the time of the last update
+    fn unix_time(&self, at: Instant, now: SystemTime) -> u64 {
+        let now = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let age = self.clock.map_or(0, |c| (c - at).as_secs());
+
+        now.saturating_sub(age)
+    }
+
+    /// How far back the rows go when `MAX_HISTORY` cut the requested
+    /// history short, in seconds
+    pub fn shortened_to(&self) -> Option<u64> {
+        match (self.shortened, self.clock, self.rows.first()) {
+            (true, Some(clock), Some(&(_, at))) => Some((clock - at).as_secs()),
+            _ => None,
+        }
+    }
+
+    /// Write the rows to `w` as CSV with measurements in internal units
+    pub fn write_csv<W: Write>(&self, w: &mut W, now: SystemTime) -> io::Result<()> {
+        try!(w.write_all(CSV_HEADER.as_bytes()));
+
+        for &(ref t, at) in &self.rows {
+            let fields = [
+                self.unix_time(at, now).to_string(),
+                format!("{:06X}", t.addr.0),
+                format!("{:?}", t.source),
+                cell(t.callsign.as_ref()),
+                cell(t.lat_lon.map(|l| format!("{:.5}", (l.0).0))),
+                cell(t.lat_lon.map(|l| format!("{:.5}", (l.0).1))),
+                cell(t.altitude.map(|a| a.0)),
+                cell(t.heading.map(|h| h.0)),
+                cell(t.speed.map(|s| s.0)),
+                cell(t.vs.map(|v| v.0)),
+                cell(t.squawk.map(|s| format!("{:04}", s))),
+                cell(t.on_ground),
+                cell(t.nic),
+                cell(t.nacp),
+                cell(t.nacv),
+                cell(t.sil),
+                cell(t.range.map(|r| format!("{:.2}", r))),
+                cell(t.bearing.map(|b| format!("{:.0}", b))),
+            ];
+            try!(write!(w, "{}\r\n", fields.join(",")));
+        }
+
+        Ok(())
+    }
+
+    /// Write the rows to `w` as `TrafficExport` message, measurements also
+    /// in `units`
+    pub fn write_json<W: Write>(&self, w: &mut W, units: Units, now: SystemTime) -> io::Result<()> {
+        try!(write!(
+            w,
+            r#"{{"type":"TrafficExport","shortened_to":{},"targets":["#,
+            Value::from(self.shortened_to())
+        ));
+
+        for (i, &(ref t, at)) in self.rows.iter().enumerate() {
+            let mut js = annotated("Traffic", &**t, units);
+            if let Value::Object(ref mut o) = js {
+                o.insert("time".to_string(), self.unix_time(at, now).into());
+                o.insert("band".to_string(), format!("{:?}", t.source).into());
+            }
+
+            if i > 0 {
+                try!(w.write_all(b","));
+            }
+            try!(serde_json::to_writer(&mut *w, &js));
+        }
+
+        w.write_all(b"]}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::fisb::FISBData;
+    use processor::traffic::{AddressType, AltitudeType, TrafficSource};
+
+    #[test]
+    fn test_situation() {
+        let mut s = Situation::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // METAR text (413) APDU
+        let mut payload = vec![0_u8; 432];
+        payload[8] = 0x02;
+        payload[9] = 0x00;
+        payload[10] = (413 >> 6) as u8;
+        payload[11] = ((413 & 0x3F) << 2) as u8;
+        let fisb = || {
+            Report::FISB(FISBData {
+                payload: payload.clone(),
+                received: None,
+            })
+        };
+
+        let target = |addr, range, sub_id| {
+            let mut t = Target::new(
+                (addr, AddressType::ADSBICAO),
+                start,
+                TrafficSource::ES,
+                None,
+            );
+            t.range = range;
+            t.sub_id = sub_id;
+            Report::Traffic(Arc::new(t))
+        };
+        s.update(
+            [
+                Report::Ownship(Ownship::default()),
+                target(0xA00001, Some(5.0), 0),
+                target(0xA00002, None, 0),
+                target(0xA00003, Some(2.5), 0),
+                target(0xA00003, Some(8.0), 1),
+                target(0xA00003, Some(7.9), 1),
+                target(0xA00004, Some(::std::f32::NAN), 0),
+                fisb(),
+            ].iter(),
+            start,
+        );
+        s.update([fisb()].iter(), at(2));
+
+        let js = s.to_json(Units::Aviation);
+        assert_eq!(js["type"], "Situation");
+        assert!(js["ownship"].is_object());
+        assert!(js["gnss"].is_null());
+        assert_eq!(js["traffic"][0]["addr"], 0xA00001);
+        assert_eq!(s.traffic().len(), 5);
+        assert_eq!(js["duplicate_addresses"], 1);
+        assert_eq!(s.nearest().unwrap().addr.0, 0xA00003);
+        assert_eq!(
+            js["fisb"].to_string(),
+            r#"[{"age":0,"count":2,"product":413}]"#
+        );
+
+        // not reported again
+        s.update([].iter(), at(MAX_AGE));
+        let js = s.to_json(Units::Aviation);
+        assert!(js["ownship"].is_null());
+        assert_eq!(js["traffic"].as_array().unwrap().len(), 0);
+        assert!(s.nearest().is_none());
+        assert_eq!(js["fisb"][0]["age"], MAX_AGE - 2);
+    }
+
+    fn csv(e: &Export, now: SystemTime) -> String {
+        let mut csv = Vec::new();
+        e.write_csv(&mut csv, now).unwrap();
+        String::from_utf8(csv).unwrap()
+    }
+
+    fn json(e: &Export, now: SystemTime) -> Value {
+        let mut js = Vec::new();
+        e.write_json(&mut js, Units::Aviation, now).unwrap();
+        serde_json::from_slice(&js).unwrap()
+    }
+
+    #[test]
+    fn test_traffic_export() {
+        let mut s = Situation::default();
+        s.keep_history(Some(1));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let now = UNIX_EPOCH + Duration::from_secs(1514764800);
+
+        let target = |altitude| {
+            let mut t = Target::new(
+                (0xA00001, AddressType::ADSBICAO),
+                start,
+                TrafficSource::UAT,
+                None,
+            );
+            t.callsign = Some("N12345".to_string());
+            t.altitude = Some((altitude, AltitudeType::Baro, start));
+            t.squawk = Some(1200);
+            Report::Traffic(Arc::new(t))
+        };
+        s.update([target(1000)].iter(), start);
+        s.update([target(1100)].iter(), at(30));
+        s.update([target(1200)].iter(), at(61));
+
+        // the report at start is out of the history by now
+        assert_eq!(
+            csv(&s.export(Some(Duration::from_secs(60))), now),
+            format!(
+                "{}1514764769,A00001,UAT,N12345,,,1100,,,,1200,,,,,,,\r\n\
+                 1514764800,A00001,UAT,N12345,,,1200,,,,1200,,,,,,,\r\n",
+                CSV_HEADER
+            )
+        );
+        assert_eq!(csv(&s.export(None), now).lines().count(), 2);
+
+        let js = json(&s.export(None), now);
+        assert_eq!(js["type"], "TrafficExport");
+        assert!(js["shortened_to"].is_null());
+        assert_eq!(js["targets"][0]["altitude"], 1200);
+        assert_eq!(js["targets"][0]["time"], 1514764800);
+        assert_eq!(js["targets"][0]["band"], "UAT");
+
+        s.keep_history(None);
+        s.update([target(1300)].iter(), at(62));
+        assert!(s.history.is_empty());
+    }
+    #[test]
+    fn test_history_evicted() {
+        let mut s = Situation::default();
+        s.keep_history(Some(10));
+        let start = Instant::now();
+        let now = UNIX_EPOCH + Duration::from_secs(1514764800);
+        let target = Arc::new(Target::new(
+            (0xA00001, AddressType::ADSBICAO),
+            start,
+            TrafficSource::ES,
+            None,
+        ));
+        let report = || Report::Traffic(target.clone());
+
+        for i in 0..MAX_HISTORY as u64 / 2 {
+            s.update([report()].iter(), start + Duration::from_millis(i));
+        }
+        let export = s.export(Some(Duration::from_secs(600)));
+        assert_eq!(export.shortened_to(), None);
+        assert_eq!(export.rows.len(), MAX_HISTORY / 2);
+
+        // twice as many reports in the next minute
+        let minute = start + Duration::from_secs(60);
+        for i in 0..MAX_HISTORY as u64 {
+            s.update([report()].iter(), minute + Duration::from_micros(i));
+        }
+        let mut export = s.export(Some(Duration::from_secs(600)));
+        assert_eq!(export.shortened_to(), Some(0));
+        assert_eq!(export.rows.len(), MAX_HISTORY);
+
+        // the oldest row is enough to check the JSON
+        export.rows.truncate(1);
+        assert_eq!(json(&export, now)["shortened_to"], 0);
+
+        // only the last second was asked for, nothing of it was dropped
+        let export = s.export(Some(Duration::from_secs(1)));
+        assert_eq!(export.shortened_to(), None);
+    }
+}
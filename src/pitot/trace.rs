@@ -0,0 +1,452 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Traces every message through the pipeline into a text file. Each message
+//! gets an ID, `S` for sensor data, `R` for reports and `P` for payloads,
+//! and one line with the time since tracing started in ms, the iteration of
+//! the main loop, the ID, the component that produced it, the IDs of the
+//! messages it was produced from and the message itself:
+//!
+//! ```text
+//! 1203.417 121 S3051 uat Traffic(TrafficData { addr: (10857860, ICAO), .. })
+//! 1203.512 121 R3056 traffic <- S3051 Traffic(Target { .. })
+//! 1203.530 121 R3057 system <- S3049..S3052 Status(..)
+//! 1203.601 121 P3060 gdl90 <- S3051 Payload { .. }
+//! 1203.650 121 P3059..P3060 -> udp
+//! ```
+//!
+//! Traffic carries the ID of the sensor message it came in along, see
+//! `Correlated`, through the target reported and the GDL90 payload
+//! generated from it, so `grep S3051` follows one message to the transport.
+//! Other messages list every message of the previous stage: the pipeline is
+//! synchronous, so a message can only be produced from the messages of the
+//! previous stage in the same iteration, plus whatever state the component
+//! kept from earlier ones.
+
+use chrono::prelude::*;
+use processor::Report;
+use protocol::Payload;
+use recording::create_numbered;
+use sensor::SensorData;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use utils::secs_f32;
+
+// tracing stops once the file is this large, in bytes
+const MAX_SIZE: u64 = 200 * 1024 * 1024;
+
+/// Stage of the pipeline a message is produced by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Sensor = 0,
+    Processor = 1,
+    Protocol = 2,
+}
+
+impl Stage {
+    fn prefix(self) -> char {
+        match self {
+            Stage::Sensor => 'S',
+            Stage::Processor => 'R',
+            Stage::Protocol => 'P',
+        }
+    }
+
+    /// The stage whose messages this one consumes
+    fn input(self) -> Option<Stage> {
+        match self {
+            Stage::Sensor => None,
+            Stage::Processor => Some(Stage::Sensor),
+            Stage::Protocol => Some(Stage::Processor),
+        }
+    }
+}
+
+/// Messages carrying the ID of the sensor message they came from
+pub trait Correlated {
+    /// ID of the sensor message this was produced from, if carried along
+    fn correlation(&self) -> Option<u64> {
+        None
+    }
+
+    /// Carry `id`, given to this sensor message, along
+    fn correlate(&mut self, _id: u64) {}
+}
+
+impl Correlated for SensorData {
+    fn correlation(&self) -> Option<u64> {
+        match *self {
+            SensorData::Traffic(ref t) => t.trace,
+            _ => None,
+        }
+    }
+
+    fn correlate(&mut self, id: u64) {
+        if let SensorData::Traffic(ref mut t) = *self {
+            t.trace = Some(id);
+        }
+    }
+}
+
+impl Correlated for Report {
+    fn correlation(&self) -> Option<u64> {
+        match *self {
+            Report::Traffic(ref t) => t.trace,
+            _ => None,
+        }
+    }
+}
+
+impl Correlated for Payload {
+    fn correlation(&self) -> Option<u64> {
+        self.trace
+    }
+}
+
+pub struct Tracer<W: Write> {
+    /// `None` once writing failed or the file got too large
+    out: Option<W>,
+    path: PathBuf,
+    start: Instant,
+    written: u64,
+    iteration: u64,
+    next_id: u64,
+    /// First and last ID each stage produced in this iteration
+    ids: [Option<(u64, u64)>; 3],
+}
+
+impl Tracer<BufWriter<File>> {
+    /// Trace into a new `trace-<n>.txt` in `dir`
+    pub fn create(dir: &str, start: Instant) -> Option<Self> {
+        match create_numbered(Path::new(dir), "trace", "txt") {
+            Ok((path, file)) => {
+                info!("tracing the pipeline to {}", path.display());
+                Some(Tracer::new(BufWriter::new(file), path, start))
+            }
+            Err(e) => {
+                error!("unable to start tracing into {}: {}", dir, e);
+                None
+            }
+        }
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, path: PathBuf, start: Instant) -> Self {
+        let mut t = Tracer {
+            out: Some(out),
+            path,
+            start,
+            written: 0,
+            iteration: 0,
+            next_id: 0,
+            ids: [None; 3],
+        };
+
+        let header = format!(
+            "# Pitot pipeline trace started at {}",
+            UTC::now().to_rfc3339()
+        );
+        t.line(&header);
+
+        t
+    }
+
+    /// Start the next iteration of the main loop
+    pub fn next_iteration(&mut self) {
+        self.iteration += 1;
+        self.ids = [None; 3];
+    }
+
+    /// Trace `items` just produced by sensor `component`, handing each
+    /// its ID to carry along
+    pub fn sensed<'a, T, I>(&mut self, now: Instant, component: &str, items: I)
+    where
+        T: Debug + Correlated + 'a,
+        I: Iterator<Item = &'a mut T>,
+    {
+        if self.out.is_none() {
+            return;
+        }
+
+        for item in items {
+            item.correlate(self.next_id);
+            self.item(now, Stage::Sensor, component, "", item);
+        }
+    }
+
+    /// Trace `items` just produced by `component`
+    pub fn produced<'a, T, I>(&mut self, now: Instant, stage: Stage, component: &str, items: I)
+    where
+        T: Debug + Correlated + 'a,
+        I: Iterator<Item = &'a T>,
+    {
+        if self.out.is_none() {
+            return;
+        }
+
+        let from = match stage.input().and_then(|s| self.ids[s as usize]) {
+            Some(r) => format!(" <- {}", range(stage.input().unwrap(), r)),
+            None => String::new(),
+        };
+
+        for item in items {
+            let from = item.correlation().map_or_else(
+                || from.clone(),
+                |id| format!(" <- {}{}", Stage::Sensor.prefix(), id),
+            );
+            self.item(now, stage, component, &from, item);
+        }
+    }
+
+    /// Trace the payloads of this iteration being handed to `transport`
+    pub fn delivered(&mut self, now: Instant, transport: &str) {
+        if let Some(r) = self.ids[Stage::Protocol as usize] {
+            let line = format!(
+                "{} {} {} -> {}",
+                self.time(now),
+                self.iteration,
+                range(Stage::Protocol, r),
+                transport
+            );
+            self.line(&line);
+        }
+    }
+
+    /// Write out what is buffered, once per iteration
+    pub fn flush(&mut self) {
+        let res = match self.out {
+            Some(ref mut out) => out.flush(),
+            None => return,
+        };
+
+        self.check(res);
+    }
+
+    fn item<T: Debug>(
+        &mut self,
+        now: Instant,
+        stage: Stage,
+        component: &str,
+        from: &str,
+        item: &T,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let ids = self.ids[stage as usize].get_or_insert((id, id));
+        ids.1 = id;
+
+        let line = format!(
+            "{} {} {}{} {}{} {:?}",
+            self.time(now),
+            self.iteration,
+            stage.prefix(),
+            id,
+            component,
+            from,
+            item
+        );
+        self.line(&line);
+    }
+
+    fn time(&self, now: Instant) -> String {
+        format!("{:.3}", secs_f32(now - self.start) * 1000.0)
+    }
+
+    fn line(&mut self, line: &str) {
+        let res = match self.out {
+            Some(ref mut out) => writeln!(out, "{}", line),
+            None => return,
+        };
+        self.written += line.len() as u64 + 1;
+
+        if self.written >= MAX_SIZE {
+            warn!(
+                "{} reached {} MB, tracing stopped",
+                self.path.display(),
+                MAX_SIZE / 1024 / 1024
+            );
+            let res = self.out.take().unwrap().flush();
+            self.check(res);
+        } else {
+            self.check(res);
+        }
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(e) = res {
+            error!(
+                "unable to write {}, tracing stopped: {}",
+                self.path.display(),
+                e
+            );
+            self.out = None;
+        }
+    }
+}
+
+fn range(stage: Stage, (first, last): (u64, u64)) -> String {
+    if first == last {
+        format!("{}{}", stage.prefix(), first)
+    } else {
+        format!("{}{}..{}{}", stage.prefix(), first, stage.prefix(), last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use processor::traffic::{AddressType, Target, TrafficSource};
+    use sensor::sdr::TrafficData;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    impl Correlated for i32 {}
+    impl Correlated for &'static str {}
+    impl Correlated for () {}
+
+    fn lines(t: Tracer<Vec<u8>>) -> Vec<String> {
+        String::from_utf8(t.out.unwrap())
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_trace() {
+        let start = Instant::now();
+        let mut t = Tracer::new(Vec::new(), PathBuf::from("test"), start);
+
+        t.next_iteration();
+        t.sensed(start, "uat", [1, 2].iter_mut());
+        t.sensed(start, "es", [3].iter_mut());
+        t.produced(start, Stage::Processor, "traffic", ["a"].iter());
+        // nothing to deliver yet
+        t.delivered(start, "udp");
+        t.produced(
+            start + Duration::from_millis(2),
+            Stage::Protocol,
+            "gdl90",
+            [()].iter(),
+        );
+        t.delivered(start + Duration::from_millis(3), "udp");
+
+        t.next_iteration();
+        t.produced(start, Stage::Processor, "system", ["b"].iter());
+
+        assert_eq!(
+            lines(t),
+            vec![
+                "0.000 1 S0 uat 1",
+                "0.000 1 S1 uat 2",
+                "0.000 1 S2 es 3",
+                "0.000 1 R3 traffic <- S0..S2 \"a\"",
+                "2.000 1 P4 gdl90 <- R3 ()",
+                "3.000 1 P4 -> udp",
+                // no sensor data in this iteration
+                "0.000 2 R5 system \"b\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_size() {
+        let start = Instant::now();
+        let mut t = Tracer::new(Vec::new(), PathBuf::from("test"), start);
+        t.written = MAX_SIZE - 10;
+
+        t.next_iteration();
+        t.sensed(start, "uat", [1, 2].iter_mut());
+        assert!(t.out.is_none());
+    }
+    #[test]
+    fn test_correlation() {
+        let start = Instant::now();
+        let mut t = Tracer::new(Vec::new(), PathBuf::from("test"), start);
+        let mut target = Target::new(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            start,
+            TrafficSource::UAT,
+            None,
+        );
+
+        t.next_iteration();
+        let mut sensed = vec![SensorData::Config(Config::default())];
+        t.sensed(start, "commands", sensed.iter_mut());
+        assert_eq!(sensed[0].correlation(), None);
+
+        // carried along by the traffic it came in
+        let mut sensed = vec![SensorData::Traffic(TrafficData {
+            addr: target.addr,
+            altitude: None,
+            gnss_delta: None,
+            heading: None,
+            speed: None,
+            vs: None,
+            squawk: None,
+            callsign: None,
+            category: None,
+            lat_lon: None,
+            nic: None,
+            nacp: None,
+            nacv: None,
+            sil: None,
+            ident: None,
+            capability: None,
+            on_ground: None,
+            source: TrafficSource::UAT,
+            received: None,
+            trace: None,
+            raw: None,
+        })];
+        t.sensed(start, "uat", sensed.iter_mut());
+        let id = sensed[0].correlation();
+        assert_eq!(id, Some(1));
+
+        target.trace = id;
+        let reports = [Report::Traffic(Arc::new(target))];
+        t.produced(start, Stage::Processor, "traffic", reports.iter());
+        let payloads = [
+            Payload {
+                queueable: false,
+                payload: vec![],
+                received: None,
+                trace: id,
+            },
+            Payload {
+                queueable: false,
+                payload: vec![],
+                received: None,
+                trace: None,
+            },
+        ];
+        t.produced(start, Stage::Protocol, "gdl90", payloads.iter());
+
+        let lines = lines(t);
+        assert!(lines[0].starts_with("0.000 1 S0 commands Config("));
+        assert!(lines[1].starts_with("0.000 1 S1 uat Traffic("));
+        assert!(lines[2].starts_with("0.000 1 R2 traffic <- S1 Traffic("));
+        assert!(lines[3].starts_with("0.000 1 P3 gdl90 <- S1 Payload {"));
+        assert!(lines[4].starts_with("0.000 1 P4 gdl90 <- R2 Payload {"));
+    }
+}
@@ -0,0 +1,430 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trends the noise floor of the SDRs and the RF front end status of the
+//! GNSS receiver as daily summaries, so a degrading antenna or connector
+//! shows up as a slow drift before traffic reception suffers. Ground
+//! stations also get the coverage, the farthest traffic was received from
+//! in each direction.
+//!
+//! Only today's summaries are reported, the whole history is read back
+//! with `history` when asked for.
+
+use super::*;
+use chrono::prelude::*;
+use processor::traffic::TrafficSource;
+use sensor::gnss::GNSSData;
+use serde_json;
+use std::collections::VecDeque;
+use std::time::Instant;
+use storage::Storage;
+
+const REPORT_FREQ: f32 = 0.1;
+const HISTORY_FILE: &str = "antenna.json";
+// days kept, the oldest are dropped
+const HISTORY_DAYS: usize = 90;
+// how often the summaries are saved, in seconds
+const SAVE_INTERVAL: u64 = 600;
+// coverage is kept in this many sectors of equal width, clockwise from true
+// north
+const COVERAGE_SECTORS: usize = 12;
+
+/// One metric over one day
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+    pub samples: u32,
+}
+
+impl Summary {
+    fn add(summary: &mut Option<Summary>, v: f32) {
+        let s = summary.get_or_insert(Summary {
+            min: v,
+            mean: 0.0,
+            max: v,
+            samples: 0,
+        });
+
+        s.samples += 1;
+        s.min = s.min.min(v);
+        s.max = s.max.max(v);
+        s.mean += (v - s.mean) / s.samples as f32;
+    }
+}
+
+/// Summaries of one UTC day, metrics that were never measured are `None`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Day {
+    /// e.g. `2018-07-01`
+    pub date: String,
+    /// Noise floor of the UAT receiver in dBFS
+    pub uat_noise_floor: Option<Summary>,
+    /// Noise floor of the 1090ES receiver in dBFS
+    pub es_noise_floor: Option<Summary>,
+    /// Noise level as measured by the GNSS receiver
+    pub gnss_noise: Option<Summary>,
+    /// AGC monitor of the GNSS receiver in percent
+    pub gnss_agc: Option<Summary>,
+    /// CW jamming indicator of the GNSS receiver, 0 to 255
+    pub gnss_jamming: Option<Summary>,
+    /// Farthest range in NM traffic was received from in each 30 degree
+    /// sector, clockwise from true north. Ground stations only.
+    pub coverage: Option<Vec<f32>>,
+}
+
+pub struct Antenna {
+    /// Oldest day first, today last
+    days: VecDeque<Day>,
+    storage: Storage,
+    /// whether coverage is measured
+    coverage: bool,
+    saved: Option<Instant>,
+    dirty: bool,
+    report_counter: u32,
+}
+
+/// Days saved in `storage`, oldest first, with `today` as reported since
+/// the last save
+pub fn history(storage: &Storage, today: Option<&Day>) -> VecDeque<Day> {
+    let mut days: VecDeque<Day> = storage
+        .load(HISTORY_FILE)
+        .and_then(|d| serde_json::from_slice(&d).ok())
+        .unwrap_or_default();
+
+    if let Some(today) = today {
+        if days.back().map_or(false, |d| d.date == today.date) {
+            days.pop_back();
+        }
+        if days.len() == HISTORY_DAYS {
+            days.pop_front();
+        }
+
+        days.push_back(today.clone());
+    }
+
+    days
+}
+
+impl Antenna {
+    /// Resumes the history saved in `storage`, if any. Coverage is only
+    /// meaningful when the antenna does not move, so it is only measured
+    /// when `coverage` is set.
+    pub fn new(storage: Storage, coverage: bool) -> Box<Processor> {
+        let mut a = Antenna::resume(storage);
+        a.coverage = coverage;
+
+        Box::new(a)
+    }
+
+    fn resume(storage: Storage) -> Self {
+        Antenna {
+            days: history(&storage, None),
+            storage,
+            coverage: false,
+            saved: None,
+            dirty: false,
+            report_counter: 0,
+        }
+    }
+
+    /// Summaries of `utc`'s day, starting a new one if needed
+    fn day(&mut self, utc: &DateTime<UTC>) -> &mut Day {
+        let date = utc.format("%Y-%m-%d").to_string();
+
+        if self.days.back().map_or(true, |d| d.date != date) {
+            if self.days.len() == HISTORY_DAYS {
+                self.days.pop_front();
+            }
+
+            self.days.push_back(Day {
+                date,
+                ..Day::default()
+            });
+        }
+
+        self.dirty = true;
+        self.days.back_mut().unwrap()
+    }
+
+    fn save(&mut self, clock: Instant) {
+        self.storage
+            .save(HISTORY_FILE, &serde_json::to_vec(&self.days).unwrap());
+        self.saved = Some(clock);
+        self.dirty = false;
+    }
+}
+
+impl Processor for Antenna {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+        let utc = handle.get_utc();
+
+        for e in i {
+            match *e {
+                // noise floors of a paired unit say nothing about our antenna
+                SensorData::DecodeStats(ref s) if !s.peer => {
+                    if let Some(floor) = s.noise_floor {
+                        let day = self.day(&utc);
+
+                        Summary::add(
+                            match s.band {
+                                TrafficSource::UAT => &mut day.uat_noise_floor,
+                                TrafficSource::ES => &mut day.es_noise_floor,
+                            },
+                            floor,
+                        );
+                    }
+                }
+                SensorData::GNSS(GNSSData::RFStatus {
+                    noise,
+                    agc,
+                    jamming,
+                }) => {
+                    let day = self.day(&utc);
+
+                    Summary::add(&mut day.gnss_noise, noise as f32);
+                    Summary::add(&mut day.gnss_agc, agc);
+                    Summary::add(&mut day.gnss_jamming, jamming as f32);
+                }
+                _ => {} // do nothing
+            }
+        }
+
+        if self.dirty
+            && self
+                .saved
+                .map_or(true, |t| (clock - t).as_secs() >= SAVE_INTERVAL)
+        {
+            self.save(clock);
+        }
+
+        run_every!(REPORT_FREQ, self.report_counter, handle, {
+            if let Some(today) = self.days.back() {
+                handle.push_data(Report::Antenna(today.clone()));
+            }
+        });
+    }
+
+    /// Coverage is measured from the traffic processor's targets, which
+    /// already carry the range and bearing from the station
+    fn run_reports(&mut self, handle: &mut Pushable<Report>, reports: &[&Report]) {
+        if !self.coverage {
+            return;
+        }
+
+        let utc = handle.get_utc();
+
+        for r in reports {
+            let (range, bearing) = match **r {
+                Report::Traffic(ref t) => match (t.range, t.bearing) {
+                    (Some(r), Some(b)) => (r, b),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let sector = (bearing / (360.0 / COVERAGE_SECTORS as f32)) as usize % COVERAGE_SECTORS;
+            let coverage = self
+                .day(&utc)
+                .coverage
+                .get_or_insert_with(|| vec![0.0; COVERAGE_SECTORS]);
+            if range > coverage[sector] {
+                coverage[sector] = range;
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if self.dirty {
+            self.save(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use pitot::handle::Handle;
+    use processor::traffic::{AddressType, Target};
+    use sensor::sdr::DecodeStats;
+    use std::fs;
+    use std::sync::Arc;
+    use testing::{run_processor, run_reports, temp_dir, MockHandle};
+
+    fn rf_status(noise: u16, agc: f32) -> SensorData {
+        SensorData::GNSS(GNSSData::RFStatus {
+            noise,
+            agc,
+            jamming: 5,
+        })
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut s = None;
+        for &v in &[-30.0, -32.0, -28.0, -30.0] {
+            Summary::add(&mut s, v);
+        }
+
+        assert_eq!(
+            s,
+            Some(Summary {
+                min: -32.0,
+                mean: -30.0,
+                max: -28.0,
+                samples: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_days() {
+        let dir = temp_dir("antenna");
+        let storage = Storage::new(dir.to_str().unwrap());
+
+        let mut a = Antenna::resume(storage.clone());
+        let mut h = MockHandle::new(10);
+
+        let mut stats = DecodeStats::new(TrafficSource::ES);
+        // no noise floor, nothing to summarize
+        run_processor(&mut a, &mut h, &[SensorData::DecodeStats(stats.clone())]);
+        assert!(a.days.is_empty());
+
+        stats.noise_floor = Some(-35.0);
+        run_processor(
+            &mut a,
+            &mut h,
+            &[SensorData::DecodeStats(stats), rf_status(80, 40.0)],
+        );
+        assert_eq!(a.days.len(), 1);
+        {
+            let today = &a.days[0];
+            assert_eq!(today.es_noise_floor.unwrap().mean, -35.0);
+            assert_eq!(today.uat_noise_floor, None);
+            assert_eq!(today.gnss_noise.unwrap().max, 80.0);
+        }
+
+        // saved right away, the next samples wait for the interval
+        run_processor(&mut a, &mut h, &[rf_status(90, 50.0)]);
+        assert_eq!(
+            Antenna::resume(storage.clone()).days[0]
+                .gnss_noise
+                .unwrap()
+                .samples,
+            1
+        );
+
+        h.advance_secs(24 * 3600);
+        run_processor(&mut a, &mut h, &[rf_status(100, 60.0)]);
+        assert_eq!(a.days.len(), 2);
+        assert_eq!(a.days[0].gnss_agc.unwrap().mean, 45.0);
+        assert_eq!(a.days[1].gnss_agc.unwrap().samples, 1);
+
+        a.shutdown();
+        assert_eq!(Antenna::resume(storage).days, a.days);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut a = Antenna::resume(Storage::disabled());
+        let mut h = MockHandle::new(10);
+        let clock = h.get_clock();
+        let target = |range, bearing| {
+            let mut t = Target::new(
+                (0xA00001, AddressType::ADSBICAO),
+                clock,
+                TrafficSource::ES,
+                None,
+            );
+            t.range = Some(range);
+            t.bearing = Some(bearing);
+            Report::Traffic(Arc::new(t))
+        };
+
+        run_reports(&mut a, &mut h, &[target(80.0, 10.0)]);
+        assert!(a.days.is_empty());
+
+        a.coverage = true;
+        run_reports(
+            &mut a,
+            &mut h,
+            &[
+                target(80.0, 10.0),
+                target(120.0, 25.0),
+                target(40.0, 359.5),
+                target(150.0, 185.0),
+            ],
+        );
+        let coverage = a.days[0].coverage.as_ref().unwrap();
+        assert_eq!(coverage.len(), COVERAGE_SECTORS);
+        assert_eq!(coverage[0], 120.0);
+        assert_eq!(coverage[6], 150.0);
+        assert_eq!(coverage[11], 40.0);
+        assert_eq!(coverage[3], 0.0);
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = temp_dir("antenna-history");
+        let storage = Storage::new(dir.to_str().unwrap());
+        let day = |date: &str, samples| Day {
+            date: date.to_string(),
+            gnss_noise: Some(Summary {
+                min: 80.0,
+                mean: 80.0,
+                max: 80.0,
+                samples,
+            }),
+            ..Day::default()
+        };
+
+        storage.save(
+            HISTORY_FILE,
+            &serde_json::to_vec(&[day("2018-07-01", 10), day("2018-07-02", 10)]).unwrap(),
+        );
+
+        // today as reported since the last save
+        let days = history(&storage, Some(&day("2018-07-02", 20)));
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[1].gnss_noise.unwrap().samples, 20);
+
+        let days = history(&storage, Some(&day("2018-07-03", 1)));
+        assert_eq!(days.len(), 3);
+        assert_eq!(days[2].date, "2018-07-03");
+
+        assert_eq!(history(&storage, None).len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut a = Antenna::resume(Storage::disabled());
+        let mut utc = UTC::now();
+
+        for _ in 0..HISTORY_DAYS + 5 {
+            a.day(&utc);
+            utc = utc + Duration::days(1);
+        }
+
+        assert_eq!(a.days.len(), HISTORY_DAYS);
+    }
+}
@@ -0,0 +1,145 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cabin environment from the barometer, for comfort monitoring and to tell
+//! when the sensor heats itself up (e.g. temperature well above the outside
+//! air on the ground).
+
+use super::*;
+use std::time::Instant;
+
+const REPORT_FREQ: u16 = 1;
+const MAX_STALE_SECS: u64 = 3;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Cabin {
+    /// Sensor the values are from
+    pub sensor: &'static str,
+    /// Static pressure in hPa
+    pub pressure: f32,
+    /// In Celsius
+    pub temperature: Option<f32>,
+    /// Relative humidity in percent
+    pub humidity: Option<f32>,
+    /// In Celsius, if both temperature and humidity are known
+    pub dew_point: Option<f32>,
+}
+
+/// Dew point in Celsius with the Magnus formula, good to about 0.4 C
+/// between -45 and 60 C
+pub fn dew_point(temperature: f32, humidity: f32) -> Option<f32> {
+    if humidity <= 0.0 {
+        return None;
+    }
+
+    let g = (humidity / 100.0).ln() + 17.62 * temperature / (243.12 + temperature);
+    Some(243.12 * g / (17.62 - g))
+}
+
+#[derive(Default)]
+pub struct CabinEnvironment {
+    latest: Option<(Cabin, Instant)>,
+    report_counter: u32,
+}
+
+impl CabinEnvironment {
+    pub fn new() -> Box<Processor> {
+        Box::new(Self::default())
+    }
+}
+
+impl Processor for CabinEnvironment {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            if let SensorData::Baro(ref b) = *e {
+                // a sensor measuring humidity is preferred over others while
+                // it keeps reporting
+                let preferred = match self.latest {
+                    Some((ref c, i)) if (clock - i).as_secs() <= MAX_STALE_SECS => {
+                        c.sensor == b.sensor_id || b.humidity.is_some() || c.humidity.is_none()
+                    }
+                    _ => true,
+                };
+
+                if preferred {
+                    let c = Cabin {
+                        sensor: b.sensor_id,
+                        pressure: b.pressure,
+                        temperature: b.temperature,
+                        humidity: b.humidity,
+                        dew_point: match (b.temperature, b.humidity) {
+                            (Some(t), Some(h)) => dew_point(t, h),
+                            _ => None,
+                        },
+                    };
+                    self.latest = Some((c, clock));
+                }
+            }
+        }
+
+        run_every!(REPORT_FREQ, self.report_counter, handle, {
+            if let Some((ref c, i)) = self.latest {
+                if (clock - i).as_secs() <= MAX_STALE_SECS {
+                    handle.push_data(Report::Cabin(c.clone()));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sensor::barometer::BaroData;
+    use testing::{run_processor, MockHandle};
+
+    #[test]
+    fn test_dew_point() {
+        assert!((dew_point(25.08, 43.86).unwrap() - 11.92).abs() < 0.01);
+        assert!((dew_point(20.0, 100.0).unwrap() - 20.0).abs() < 0.01);
+        assert_eq!(dew_point(20.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_cabin() {
+        let mut p = CabinEnvironment::new();
+        let mut h = MockHandle::new(1);
+
+        let mut bme280 = BaroData::new("BME280", 1000.0, Some(25.0));
+        bme280.humidity = Some(40.0);
+        let bmp280 = BaroData::new("BMP280", 1001.0, Some(30.0));
+
+        run_processor(
+            &mut *p,
+            &mut h,
+            &[SensorData::Baro(bme280), SensorData::Baro(bmp280)],
+        );
+        match h.take()[..] {
+            [Report::Cabin(ref c)] => {
+                assert_eq!(c.sensor, "BME280");
+                assert_eq!(c.humidity, Some(40.0));
+                assert!(c.dew_point.is_some());
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+
+        h.advance_secs(MAX_STALE_SECS + 1);
+        run_processor(&mut *p, &mut h, &[]);
+        assert!(h.take().is_empty());
+    }
+}
@@ -15,76 +15,312 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Watches GNSS time (if valid) and set system (wall) clock if it gets too far off.
+//!
+//! GNSS time is only trusted once it is plausible: not before Pitot was built
+//! (older receiver firmwares report dates 1024 weeks in the past after a GPS
+//! week number rollover, which are corrected) and advancing along with the
+//! monotonic clock for a few seconds, so a single bad fix never jumps the
+//! clock. The clock is left alone around announced leap seconds.
+//!
+//! Every correction, including those refused for exceeding `clock_max_step`
+//! or skipped in `clock_dry_run` mode, is appended to an audit log in the
+//! data directory.
 
 use super::*;
-use libc::{clock_settime, timespec, CLOCK_REALTIME};
+use chrono::prelude::*;
+use libc::{clock_settime, time_t, timespec, CLOCK_REALTIME};
 use sensor::gnss::GNSSData;
+use std::time::{Duration, Instant};
+use storage::Storage;
 
 // max 3 second tolerance
 const MAX_TOLERANCE: i64 = 2;
+/// GNSS time has to be consistent with the monotonic clock for this long
+const PLAUSIBLE_SECS: u64 = 5;
+/// do not touch the clock this close to a leap second
+const LEAP_WINDOW_SECS: u64 = 10;
+const WEEK_ROLLOVER_SECS: i64 = 1024 * 7 * 86400;
+// allow for the build host clock being a bit ahead
+const BUILD_TIME_MARGIN_SECS: i64 = 86400;
+const BUILD_TIME: &str = env!("PITOT_BUILD_TIME");
+const AUDIT_LOG: &str = "clock.log";
 
-pub struct Clock;
+pub struct Clock {
+    build_time: i64,
+    /// last GNSS time seen, along with when it was seen
+    last: Option<(Instant, i64)>,
+    /// since when GNSS time has been consistent with the monotonic clock
+    consistent_since: Option<Instant>,
+    /// when the next announced leap second happens
+    leap_second: Option<Instant>,
+    max_step: Option<u64>,
+    dry_run: bool,
+    /// correction last logged without being made, so it is only logged once
+    skipped_step: Option<i64>,
+    storage: Storage,
+}
 
 impl Clock {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(config: &Config, storage: Storage) -> Self {
+        Self {
+            build_time: BUILD_TIME.parse().unwrap(),
+            last: None,
+            consistent_since: None,
+            leap_second: None,
+            max_step: config.clock_max_step,
+            dry_run: config.clock_dry_run,
+            skipped_step: None,
+            storage,
+        }
+    }
+
+    /// Step the system clock from `system` to `t` unless that is disallowed,
+    /// returns whether the clock was set
+    fn step(&mut self, t: i64, system: i64) -> bool {
+        let step = t - system;
+
+        let (applied, outcome) = if self.dry_run {
+            (false, "dry run, not applied")
+        } else if self.max_step.map_or(false, |m| step.abs() as u64 > m) {
+            (false, "exceeds clock_max_step, refused")
+        } else if set_system_clock(t) {
+            (true, "applied")
+        } else {
+            (false, "failed")
+        };
+
+        if !applied {
+            if self
+                .skipped_step
+                .map_or(false, |s| (s - step).abs() <= MAX_TOLERANCE)
+            {
+                return false;
+            }
+
+            self.skipped_step = Some(step);
+        }
+
+        let format = |s| UTC.timestamp(s, 0).format("%Y-%m-%dT%H:%M:%SZ");
+        let entry = format!(
+            "{} step {:+} s from {}, {}",
+            format(t),
+            step,
+            format(system),
+            outcome
+        );
+
+        if applied {
+            info!("system clock: {}", entry);
+        } else {
+            warn!("system clock: {}", entry);
+        }
+        self.storage.append(AUDIT_LOG, &entry);
+
+        applied
+    }
+
+    /// Bring `t` into the 1024 week window starting at the build time,
+    /// `None` if it is past that window
+    fn correct_rollover(&self, t: i64) -> Option<i64> {
+        let earliest = self.build_time - BUILD_TIME_MARGIN_SECS;
+
+        if t >= earliest + WEEK_ROLLOVER_SECS {
+            return None;
+        }
+
+        if t < earliest {
+            let rollovers = (earliest - t + WEEK_ROLLOVER_SECS - 1) / WEEK_ROLLOVER_SECS;
+            warn!(
+                "GNSS time is before build date, correcting for {} week number rollover(s)",
+                rollovers
+            );
+
+            return Some(t + rollovers * WEEK_ROLLOVER_SECS);
+        }
+
+        Some(t)
+    }
+
+    /// GNSS time `t` seen at `clock`, returns the time the system clock
+    /// should be set to, if any
+    fn check(&mut self, clock: Instant, t: i64, system: i64) -> Option<i64> {
+        let t = match self.correct_rollover(t) {
+            Some(t) => t,
+            None => {
+                warn!("GNSS time is implausibly far in the future, ignored");
+                self.consistent_since = None;
+                return None;
+            }
+        };
+
+        let consistent = self.last.map_or(false, |(at, last)| {
+            let expected = last + (clock - at).as_secs() as i64;
+            (t - expected).abs() <= MAX_TOLERANCE
+        });
+
+        self.last = Some((clock, t));
+
+        if !consistent {
+            self.consistent_since = Some(clock);
+            return None;
+        }
+
+        match self.consistent_since {
+            Some(since) if clock - since >= Duration::from_secs(PLAUSIBLE_SECS) => {}
+            _ => return None,
+        }
+
+        if let Some(leap) = self.leap_second {
+            let distance = if leap > clock {
+                leap - clock
+            } else {
+                clock - leap
+            };
+
+            if distance < Duration::from_secs(LEAP_WINDOW_SECS) {
+                return None;
+            }
+        }
+
+        if (system - t).abs() > MAX_TOLERANCE {
+            Some(t)
+        } else {
+            None
+        }
     }
 }
 
 impl Processor for Clock {
-    #[cfg(target_pointer_width = "64")]
     fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
         for e in i {
             match *e {
                 SensorData::GNSS(GNSSData::TimeFix {
                     time: Some(ref f), ..
                 }) => {
-                    if (handle.get_utc().to_timespec().sec - f.timestamp()).abs() > MAX_TOLERANCE {
-                        info!("setting system clock");
-
-                        let ts = timespec {
-                            tv_sec: f.timestamp(),
-                            tv_nsec: 0,
-                        };
-                        unsafe {
-                            if clock_settime(CLOCK_REALTIME, &ts) != 0 {
-                                error!("failed to set system clock");
-                            }
+                    let system = handle.get_utc().timestamp();
+
+                    if let Some(t) = self.check(clock, f.timestamp(), system) {
+                        if self.step(t, system) {
+                            break;
+                        }
+                    }
+                }
+                SensorData::Config(ref c) => {
+                    self.max_step = c.clock_max_step;
+                    self.dry_run = c.clock_dry_run;
+                    self.skipped_step = None;
+                }
+                SensorData::GNSS(GNSSData::LeapSecond { change, in_secs }) => {
+                    if change == 0 || in_secs < 0 {
+                        self.leap_second = None;
+                    } else {
+                        if self.leap_second.is_none() {
+                            info!("leap second of {} announced in {} seconds", change, in_secs);
                         }
 
-                        break;
+                        self.leap_second = Some(clock + Duration::from_secs(in_secs as u64));
                     }
                 }
                 _ => {} // do nothing
             }
         }
     }
+}
 
-    #[cfg(target_pointer_width = "32")]
-    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
-        for e in i {
-            match *e {
-                SensorData::GNSS(GNSSData::TimeFix {
-                    time: Some(ref f), ..
-                }) => {
-                    if (handle.get_utc().to_timespec().sec - f.timestamp()).abs() > MAX_TOLERANCE {
-                        info!("setting system clock");
-
-                        let ts = timespec {
-                            tv_sec: f.timestamp() as i32,
-                            tv_nsec: 0,
-                        };
-                        unsafe {
-                            if clock_settime(CLOCK_REALTIME, &ts) != 0 {
-                                error!("failed to set system clock");
-                            }
-                        }
+fn set_system_clock(t: i64) -> bool {
+    let ts = timespec {
+        tv_sec: t as time_t,
+        tv_nsec: 0,
+    };
 
-                        break;
-                    }
-                }
-                _ => {} // do nothing
-            }
+    unsafe { clock_settime(CLOCK_REALTIME, &ts) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use testing::temp_dir;
+
+    // 2018-01-01
+    const BUILT: i64 = 1514764800;
+
+    fn clock() -> Clock {
+        Clock {
+            build_time: BUILT,
+            last: None,
+            consistent_since: None,
+            leap_second: None,
+            max_step: None,
+            dry_run: false,
+            skipped_step: None,
+            storage: Storage::disabled(),
+        }
+    }
+
+    #[test]
+    fn test_correct_rollover() {
+        let c = clock();
+
+        assert_eq!(c.correct_rollover(BUILT + 100), Some(BUILT + 100));
+        assert_eq!(
+            c.correct_rollover(BUILT + 100 - WEEK_ROLLOVER_SECS),
+            Some(BUILT + 100)
+        );
+        assert_eq!(c.correct_rollover(BUILT + 2 * WEEK_ROLLOVER_SECS), None);
+    }
+
+    #[test]
+    fn test_plausibility() {
+        let mut c = clock();
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        let t = BUILT + 1000;
+
+        // only set once consistent for PLAUSIBLE_SECS
+        for s in 0..PLAUSIBLE_SECS {
+            assert_eq!(c.check(at(s), t + s as i64, 0), None);
         }
+        assert_eq!(c.check(at(5), t + 5, 0), Some(t + 5));
+        assert_eq!(c.check(at(6), t + 6, t + 6), None);
+
+        // a jump starts over
+        assert_eq!(c.check(at(7), t + 3600, 0), None);
+        assert_eq!(c.check(at(8), t + 3601, 0), None);
+
+        // not around a leap second
+        let mut c = clock();
+        c.leap_second = Some(at(12));
+        for s in 0..10 {
+            c.check(at(s), t + s as i64, 0);
+        }
+        assert_eq!(c.check(at(10), t + 10, 0), None);
+        assert_eq!(c.check(at(23), t + 23, 0), Some(t + 23));
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let dir = temp_dir("clock");
+        let mut c = clock();
+        c.storage = Storage::new(dir.to_str().unwrap());
+
+        c.dry_run = true;
+        assert!(!c.step(BUILT + 3600, BUILT));
+        // only logged once
+        assert!(!c.step(BUILT + 3601, BUILT + 1));
+
+        c.dry_run = false;
+        c.max_step = Some(60);
+        assert!(!c.step(BUILT - 3600, BUILT));
+
+        assert_eq!(
+            String::from_utf8(c.storage.load(AUDIT_LOG).unwrap()).unwrap(),
+            "2018-01-01T01:00:00Z step +3600 s from 2018-01-01T00:00:00Z, dry run, not applied\n\
+             2017-12-31T23:00:00Z step -3600 s from 2018-01-01T00:00:00Z, exceeds clock_max_step, refused\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
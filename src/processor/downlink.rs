@@ -0,0 +1,116 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Forwards raw UAT ADS-B downlink frames for clients that decode UAT themselves.
+
+use super::*;
+use sensor::sdr::TrafficData;
+
+pub struct Downlink {
+    /// Our own ICAO address, frames from it are not forwarded
+    ownship_icao: Option<u32>,
+}
+
+impl Downlink {
+    pub fn new(ownship_icao: Option<u32>) -> Box<Processor> {
+        Box::new(Self { ownship_icao })
+    }
+}
+
+/// A basic (18 bytes) or long (34 bytes) UAT ADS-B downlink payload,
+/// error corrected
+#[derive(Debug, PartialEq, Clone)]
+pub struct DownlinkData {
+    pub payload: Vec<u8>,
+}
+
+impl Processor for Downlink {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        for e in i {
+            match *e {
+                SensorData::Traffic(TrafficData {
+                    addr: (addr, _),
+                    raw: Some(ref payload),
+                    ..
+                }) if Some(addr) != self.ownship_icao => {
+                    handle.push_data(Report::Downlink(DownlinkData {
+                        payload: payload.clone(),
+                    }))
+                }
+                SensorData::Config(ref c) => self.ownship_icao = c.ownship_icao,
+                _ => {} // do nothing
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::traffic::{AddressType, TrafficSource};
+    use testing::{run_processor, MockHandle};
+
+    #[test]
+    fn test_ownship_not_forwarded() {
+        let mut d = Downlink::new(Some(0xA1B2C3));
+        let mut h = MockHandle::new(10);
+
+        let traffic = |addr, raw: Option<Vec<u8>>| {
+            SensorData::Traffic(TrafficData {
+                addr: (addr, AddressType::ADSBICAO),
+                altitude: None,
+                gnss_delta: None,
+                heading: None,
+                speed: None,
+                vs: None,
+                squawk: None,
+                callsign: None,
+                category: None,
+                lat_lon: None,
+                nic: None,
+                nacp: None,
+                nacv: None,
+                sil: None,
+                ident: None,
+                capability: None,
+                on_ground: None,
+                source: TrafficSource::UAT,
+                received: None,
+                trace: None,
+                raw,
+            })
+        };
+        let frame = vec![0x08; 18];
+
+        run_processor(
+            &mut *d,
+            &mut h,
+            &[
+                traffic(0xA1B2C3, Some(frame.clone())),
+                traffic(0xA1B2C4, Some(frame.clone())),
+                // 1090ES
+                traffic(0xA1B2C5, None),
+            ],
+        );
+
+        let reports = h.take();
+        assert_eq!(reports.len(), 1);
+        match reports[0] {
+            Report::Downlink(ref r) => assert_eq!(r.payload, frame),
+            _ => panic!("unexpected report"),
+        }
+    }
+}
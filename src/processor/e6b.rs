@@ -0,0 +1,170 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Flight computer, derives winds aloft and ETE to the user set waypoint.
+
+use super::*;
+use sensor::airspeed::tas;
+use sensor::gnss::GNSSData;
+use sensor::SensorData;
+use std::time::Instant;
+use utils::distance_nm;
+
+const REPORT_FREQ: u16 = 1;
+const MAX_STALE_SECS: u64 = 3;
+// below this ground speed ETE is meaningless, in kts
+const MIN_ETE_GS: f32 = 5.0;
+
+#[derive(Debug, Default, Copy, Clone, Serialize)]
+pub struct E6B {
+    /// Direction wind is blowing from in degrees true
+    pub wind_direction: Option<f32>,
+    /// Wind speed in kts
+    pub wind_speed: Option<f32>,
+    /// Distance to waypoint in NM
+    pub waypoint_distance: Option<f32>,
+    /// Estimated time enroute to waypoint in seconds
+    pub waypoint_ete: Option<u32>,
+    #[serde(skip)]
+    waypoint: Option<(f32, f32)>,
+    /// Ground speed in kts, true track in degrees and position
+    #[serde(skip)]
+    ground: Option<(f32, f32, (f32, f32), Instant)>,
+    /// True heading in degrees
+    #[serde(skip)]
+    heading: Option<(f32, Instant)>,
+    /// Indicated airspeed in kts
+    #[serde(skip)]
+    ias: Option<(f32, Instant)>,
+    /// Static pressure in hPa
+    #[serde(skip)]
+    static_pressure: Option<f32>,
+    #[serde(skip)]
+    report_counter: u32,
+}
+
+impl E6B {
+    pub fn new(waypoint: Option<(f32, f32)>) -> Box<Processor> {
+        Box::new(E6B {
+            waypoint,
+            ..Default::default()
+        })
+    }
+
+    fn update(&mut self, clock: Instant) {
+        let fresh = |i: Instant| (clock - i).as_secs() <= MAX_STALE_SECS;
+
+        self.wind_direction = None;
+        self.wind_speed = None;
+        self.waypoint_distance = None;
+        self.waypoint_ete = None;
+
+        let (gs, trk, pos) = match self.ground {
+            Some((gs, trk, pos, i)) if fresh(i) => (gs, trk, pos),
+            _ => return,
+        };
+
+        if let (Some((hdg, hi)), Some((ias, ai)), Some(p)) =
+            (self.heading, self.ias, self.static_pressure)
+        {
+            if fresh(hi) && fresh(ai) {
+                let (dir, spd) = wind(tas(ias, p), hdg, gs, trk);
+                self.wind_direction = Some(dir);
+                self.wind_speed = Some(spd);
+            }
+        }
+
+        if let Some(wpt) = self.waypoint {
+            let d = distance_nm(pos, wpt);
+            self.waypoint_distance = Some(d);
+
+            if gs >= MIN_ETE_GS {
+                self.waypoint_ete = Some((d / gs * 3600_f32).round() as u32);
+            }
+        }
+    }
+}
+
+impl Processor for E6B {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::GNSS(GNSSData::TimeFix {
+                    fix: Some(ref f), ..
+                }) => {
+                    self.ground = Some((
+                        mmps_to_kts!(f.gs.0),
+                        f.true_course.0,
+                        f.lat_lon.0,
+                        clock,
+                    ));
+                }
+                SensorData::Heading(h) => self.heading = Some((h, clock)),
+                SensorData::Airspeed(ref a) => self.ias = Some((a.ias, clock)),
+                SensorData::Baro(ref b) => self.static_pressure = Some(b.pressure),
+                SensorData::Config(ref c) => self.waypoint = c.waypoint,
+                _ => {} // do nothing
+            }
+        }
+
+        run_every!(REPORT_FREQ, self.report_counter, handle, {
+            self.update(clock);
+            handle.push_data(Report::E6B(*self));
+        });
+    }
+}
+
+/// Solve the wind triangle. Given TAS and true heading, GS and true track,
+/// return the direction wind is coming from in degrees true and its speed.
+fn wind(tas: f32, hdg: f32, gs: f32, trk: f32) -> (f32, f32) {
+    let (hdg, trk) = (hdg.to_radians(), trk.to_radians());
+
+    // wind vector is the difference between ground and air vector
+    let north = gs * trk.cos() - tas * hdg.cos();
+    let east = gs * trk.sin() - tas * hdg.sin();
+
+    let mut dir = (-east).atan2(-north).to_degrees();
+    if dir < 0_f32 {
+        dir += 360_f32;
+    }
+
+    (dir, north.hypot(east))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wind() {
+        // headwind
+        let (dir, spd) = wind(100_f32, 0_f32, 90_f32, 0_f32);
+        assert!(dir.abs() < 0.1 || (dir - 360_f32).abs() < 0.1);
+        assert!((spd - 10_f32).abs() < 0.1);
+
+        // tailwind
+        let (dir, spd) = wind(100_f32, 90_f32, 120_f32, 90_f32);
+        assert!((dir - 270_f32).abs() < 0.1);
+        assert!((spd - 20_f32).abs() < 0.1);
+
+        // crosswind from the left, crabbing into it
+        let (dir, spd) = wind(100_f32, 350_f32, 98.48, 0_f32);
+        assert!((dir - 270_f32).abs() < 0.1);
+        assert!((spd - 17.36).abs() < 0.1);
+    }
+}
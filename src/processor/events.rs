@@ -0,0 +1,952 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detects notable events (takeoff, landing, nearby traffic, traffic
+//! entering an alert zone, loss of GNSS, an implausible barometer and
+//! undervoltage) for protocols that notify users about them.
+
+use super::*;
+use config::{AlertInhibit, AlertZone, Shape, Watch};
+use processor::ownship::BaroFault;
+use processor::traffic::{clock_position, AltitudeType, Target};
+use sensor::command::Command;
+use sensor::gnss::GNSSData;
+use std::collections::HashMap;
+use std::time::Instant;
+use utils::distance_nm;
+
+const CHECK_FREQ: u16 = 1;
+// ground speed in kts above which we are considered airborne
+const TAKEOFF_SPEED: f32 = 50.0;
+// ground speed in kts below which we are considered on the ground
+const LANDING_SPEED: f32 = 30.0;
+// how long the speed has to stay past the threshold before it counts
+const TRANSITION_SECS: u64 = 10;
+// no position fix for this long means GNSS is lost
+const GNSS_LOST_SECS: u64 = 5;
+// traffic closer than this, both horizontally in NM and vertically in ft,
+// raises an alert
+const ALERT_RADIUS: f32 = 2.0;
+const ALERT_ALTITUDE: i32 = 1000;
+// the same target does not raise another alert for this long
+const ALERT_REPEAT_SECS: u64 = 300;
+// a target not reported inside a zone for this long has left it
+const ZONE_EXIT_SECS: u64 = 60;
+// a watched target not reported for this long is announced again when it
+// shows up
+const WATCH_GONE_SECS: u64 = 600;
+// `below_agl` only holds within this many NM of the field we took off from,
+// its elevation says nothing about the ground further away
+const FIELD_RADIUS: f32 = 5.0;
+
+/// Names of all events, as used in the config file
+pub const EVENT_NAMES: [&str; 8] = [
+    "Takeoff",
+    "Landing",
+    "TrafficAlert",
+    "ZoneEntered",
+    "WatchedTraffic",
+    "GNSSLost",
+    "BaroFault",
+    "Undervoltage",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    Takeoff {
+        lat: f32,
+        lon: f32,
+    },
+    Landing {
+        lat: f32,
+        lon: f32,
+    },
+    TrafficAlert {
+        addr: u32,
+        callsign: Option<String>,
+        /// in NM
+        distance: f32,
+        /// in degrees true
+        bearing: f32,
+        /// relative to our track, 12 o'clock is straight ahead
+        clock: u8,
+        /// traffic altitude relative to ours in ft
+        altitude_diff: i32,
+        confidence: Confidence,
+    },
+    ZoneEntered {
+        /// name of the alert zone
+        zone: String,
+        addr: u32,
+        callsign: Option<String>,
+        lat: f32,
+        lon: f32,
+        /// in ft, as reported by the traffic
+        altitude: Option<i32>,
+    },
+    /// Traffic on the watch list showed up
+    WatchedTraffic {
+        /// name of the watch list entry
+        name: String,
+        addr: u32,
+        callsign: Option<String>,
+        /// in NM, while ownship has a fix
+        distance: Option<f32>,
+        /// in degrees true, while ownship has a fix
+        bearing: Option<f32>,
+        /// in ft, as reported by the traffic
+        altitude: Option<i32>,
+    },
+    GNSSLost,
+    /// Pressure altitude is not used until the barometer is plausible again
+    BaroFault {
+        fault: BaroFault,
+    },
+    Undervoltage,
+}
+
+/// How far a traffic alert can be trusted, from the source integrity level
+/// and velocity accuracy the target reports about itself
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Confidence {
+    /// Integrity not reported (yet)
+    Unknown,
+    /// SIL or NACv is 0, position or velocity may be way off
+    Low,
+    High,
+}
+
+impl Confidence {
+    fn new(sil: Option<u8>, nacv: Option<u8>) -> Self {
+        match (sil, nacv) {
+            (Some(0), _) | (_, Some(0)) => Confidence::Low,
+            (Some(_), Some(_)) => Confidence::High,
+            _ => Confidence::Unknown,
+        }
+    }
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::Takeoff { .. } => "Takeoff",
+            Event::Landing { .. } => "Landing",
+            Event::TrafficAlert { .. } => "TrafficAlert",
+            Event::ZoneEntered { .. } => "ZoneEntered",
+            Event::WatchedTraffic { .. } => "WatchedTraffic",
+            Event::GNSSLost => "GNSSLost",
+            Event::BaroFault { .. } => "BaroFault",
+            Event::Undervoltage => "Undervoltage",
+        }
+    }
+}
+
+pub struct Events {
+    /// our own transponder, never alerted about
+    ownship_icao: Option<u32>,
+    airborne: bool,
+    /// since when the ground speed has been past the takeoff/landing threshold
+    transition: Option<Instant>,
+    /// when we took off, `None` when Pitot was started in flight
+    takeoff: Option<Instant>,
+    /// height above ellipsoid in ft of the field we took off from, or are on.
+    /// `None` when Pitot was started in flight, heights above ground are
+    /// then unknown.
+    field_altitude: Option<i32>,
+    /// where the field we took off from, or are on, is
+    field_lat_lon: Option<(f32, f32)>,
+    /// in kts
+    gs: Option<f32>,
+    lat_lon: Option<(f32, f32)>,
+    /// true track in degrees
+    track: f32,
+    /// height above ellipsoid in ft, compared against GNSS altitude of traffic
+    hae_altitude: Option<i32>,
+    /// as reported by the ownship processor, so an implausible barometer is
+    /// not used
+    pressure_altitude: Option<i32>,
+    baro_fault: Option<BaroFault>,
+    last_fix: Option<Instant>,
+    gnss_lost: bool,
+    undervoltage: bool,
+    /// when each target last raised an alert
+    alerted: HashMap<u32, Instant>,
+    inhibit: Option<AlertInhibit>,
+    zones: Vec<AlertZone>,
+    /// when each target was last reported inside a zone, by zone index and
+    /// address
+    inside: HashMap<(usize, u32), Instant>,
+    watch_list: Vec<Watch>,
+    /// when each watched target was last reported, by address
+    watched: HashMap<u32, Instant>,
+    check_counter: u32,
+}
+
+/// Whether traffic at `lat_lon` and `altitude` is inside `z`. Surface
+/// traffic often reports no altitude, which counts as inside unless the
+/// zone has a floor.
+fn in_zone(z: &AlertZone, lat_lon: (f32, f32), altitude: Option<i32>) -> bool {
+    let in_altitude = match altitude {
+        Some(a) => z.floor.map_or(true, |f| a >= f) && z.ceiling.map_or(true, |c| a <= c),
+        None => z.floor.is_none(),
+    };
+
+    in_altitude
+        && match z.shape {
+            Shape::Cylinder { center, radius } => distance_nm(center, lat_lon) <= radius,
+            Shape::Polygon { ref points } => in_polygon(points, lat_lon),
+        }
+}
+
+/// Whether `t` is the aircraft `w` is about, by ICAO address or callsign
+fn watches(w: &Watch, t: &Target) -> bool {
+    w.icao == Some(t.addr.0)
+        || match (w.callsign.as_ref(), t.callsign.as_ref()) {
+            (Some(a), Some(b)) => a.trim().eq_ignore_ascii_case(b.trim()),
+            _ => false,
+        }
+}
+
+/// Ray casting, with lat/lon taken as planar coordinates which is close
+/// enough for zones a few miles across
+fn in_polygon(points: &[(f32, f32)], (lat, lon): (f32, f32)) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+
+    for (i, &(lat_i, lon_i)) in points.iter().enumerate() {
+        let (lat_j, lon_j) = points[j];
+
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+
+        j = i;
+    }
+
+    inside
+}
+
+impl Events {
+    pub fn new(config: &Config) -> Box<Processor> {
+        Box::new(Self {
+            ownship_icao: config.ownship_icao,
+            airborne: false,
+            transition: None,
+            takeoff: None,
+            field_altitude: None,
+            field_lat_lon: None,
+            gs: None,
+            lat_lon: None,
+            track: 0.0,
+            hae_altitude: None,
+            pressure_altitude: None,
+            baro_fault: None,
+            last_fix: None,
+            gnss_lost: false,
+            undervoltage: false,
+            alerted: HashMap::new(),
+            inhibit: config.alert_inhibit,
+            zones: config.alert_zones.clone(),
+            inside: HashMap::new(),
+            watch_list: config.watch_list.clone(),
+            watched: HashMap::new(),
+            check_counter: 0,
+        })
+    }
+
+    fn set_zones(&mut self, zones: &[AlertZone]) {
+        if zones != &self.zones[..] {
+            self.zones = zones.to_vec();
+            self.inside.clear();
+        }
+    }
+
+    /// Raise `ZoneEntered` for every zone `t` was not inside yet
+    fn check_zones(&mut self, handle: &mut Pushable<Report>, t: &Target, clock: Instant) {
+        let lat_lon = match t.lat_lon {
+            Some((ll, _)) => ll,
+            None => return,
+        };
+        let altitude = t.altitude.map(|a| a.0);
+
+        for (i, z) in self.zones.iter().enumerate() {
+            let key = (i, t.addr.0);
+
+            if !in_zone(z, lat_lon, altitude) {
+                self.inside.remove(&key);
+                continue;
+            }
+
+            if self.inside.insert(key, clock).is_none() {
+                handle.push_data(Report::Event(Event::ZoneEntered {
+                    zone: z.name.clone(),
+                    addr: t.addr.0,
+                    callsign: t.callsign.clone(),
+                    lat: lat_lon.0,
+                    lon: lat_lon.1,
+                    altitude,
+                }));
+            }
+        }
+    }
+
+    /// Whether traffic alerts are held back in the current flight phase
+    fn inhibited(&self, clock: Instant) -> bool {
+        let i = match self.inhibit {
+            Some(ref i) => i,
+            None => return false,
+        };
+
+        let near_field = match (self.lat_lon, self.field_lat_lon) {
+            (Some(pos), Some(field)) => distance_nm(pos, field) <= FIELD_RADIUS,
+            _ => false,
+        };
+        let low = match (i.below_agl, self.hae_altitude, self.field_altitude) {
+            (Some(agl), Some(hae), Some(field)) => near_field && hae - field < agl,
+            _ => false,
+        };
+        let slow = match (i.below_gs, self.gs) {
+            (Some(below), Some(gs)) => gs < below,
+            _ => false,
+        };
+        let climbing_out = match (i.after_takeoff, self.takeoff) {
+            (Some(secs), Some(t)) => (clock - t).as_secs() < secs,
+            _ => false,
+        };
+
+        low || slow || climbing_out
+    }
+
+    /// Raise `WatchedTraffic` if `t` is on the watch list and was not
+    /// reported in range for a while
+    fn check_watch_list(&mut self, handle: &mut Pushable<Report>, t: &Target, clock: Instant) {
+        let w = match self.watch_list.iter().find(|w| watches(w, t)) {
+            Some(w) => w,
+            None => return,
+        };
+
+        if let Some(range) = w.range {
+            if t.range.map_or(true, |r| r > range) {
+                return;
+            }
+        }
+
+        if self.watched.insert(t.addr.0, clock).is_none() {
+            handle.push_data(Report::Event(Event::WatchedTraffic {
+                name: w.name.clone(),
+                addr: t.addr.0,
+                callsign: t.callsign.clone(),
+                distance: t.range,
+                bearing: t.bearing,
+                altitude: t.altitude.map(|a| a.0),
+            }));
+        }
+    }
+
+    fn update_speed(&mut self, gs: f32, clock: Instant) -> Option<Event> {
+        let past_threshold = if self.airborne {
+            gs < LANDING_SPEED
+        } else {
+            gs > TAKEOFF_SPEED
+        };
+
+        if !past_threshold {
+            self.transition = None;
+            return None;
+        }
+
+        let since = *self.transition.get_or_insert(clock);
+        if (clock - since).as_secs() < TRANSITION_SECS {
+            return None;
+        }
+
+        self.transition = None;
+        self.airborne = !self.airborne;
+        // a start in flight is not a takeoff to hold alerts back after
+        self.takeoff = if self.airborne && self.field_altitude.is_some() {
+            Some(clock)
+        } else {
+            None
+        };
+
+        let (lat, lon) = self.lat_lon.unwrap();
+        Some(if self.airborne {
+            Event::Takeoff { lat, lon }
+        } else {
+            Event::Landing { lat, lon }
+        })
+    }
+}
+
+impl Processor for Events {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::GNSS(GNSSData::TimeFix {
+                    fix: Some(ref f), ..
+                }) => {
+                    self.lat_lon = Some(f.lat_lon.0);
+                    self.track = f.true_course.0;
+                    self.hae_altitude = Some(mm_to_ft!(f.height_ellipsoid.0).round() as i32);
+                    self.last_fix = Some(clock);
+                    self.gnss_lost = false;
+
+                    // only while really on the ground, until the takeoff roll
+                    // gets past the takeoff speed
+                    let gs = mmps_to_kts!(f.gs.0);
+                    if !self.airborne && gs < TAKEOFF_SPEED {
+                        self.field_altitude = self.hae_altitude;
+                        self.field_lat_lon = self.lat_lon;
+                    }
+
+                    self.gs = Some(gs);
+                    if let Some(e) = self.update_speed(gs, clock) {
+                        handle.push_data(Report::Event(e));
+                    }
+                }
+                SensorData::Undervoltage(u) => {
+                    if u && !self.undervoltage {
+                        handle.push_data(Report::Event(Event::Undervoltage));
+                    }
+
+                    self.undervoltage = u;
+                }
+                SensorData::Config(ref c) => {
+                    self.ownship_icao = c.ownship_icao;
+                    self.inhibit = c.alert_inhibit;
+                    self.set_zones(&c.alert_zones);
+                    self.watch_list = c.watch_list.clone();
+                }
+                SensorData::Command(Command::AlertZones { ref zones }) => {
+                    let errors: Vec<String> = zones.iter().filter_map(|z| z.check()).collect();
+
+                    if errors.is_empty() {
+                        self.set_zones(zones);
+                    } else {
+                        warn!("alert zones refused: {}", errors.join(", "));
+                    }
+                }
+                _ => {} // do nothing
+            }
+        }
+
+        run_every!(CHECK_FREQ, self.check_counter, handle, {
+            if let Some(last) = self.last_fix {
+                if !self.gnss_lost && (clock - last).as_secs() >= GNSS_LOST_SECS {
+                    self.gnss_lost = true;
+                    handle.push_data(Report::Event(Event::GNSSLost));
+                }
+            }
+
+            self.alerted
+                .retain(|_, &mut i| (clock - i).as_secs() < ALERT_REPEAT_SECS);
+            self.inside
+                .retain(|_, &mut i| (clock - i).as_secs() < ZONE_EXIT_SECS);
+            self.watched
+                .retain(|_, &mut i| (clock - i).as_secs() < WATCH_GONE_SECS);
+        });
+    }
+
+    /// Traffic alerts are raised from the traffic processor's targets, which
+    /// already carry the range and bearing from ownship. Alert zones and
+    /// watch list entries without a range do not depend on ownship, so they
+    /// are watched on the ground too. Barometer faults are detected by the
+    /// ownship processor.
+    fn run_reports(&mut self, handle: &mut Pushable<Report>, reports: &[&Report]) {
+        let clock = handle.get_clock();
+
+        for r in reports {
+            let t = match **r {
+                Report::Traffic(ref t) if Some(t.addr.0) != self.ownship_icao => t,
+                Report::Ownship(ref o) => {
+                    if let (None, Some(fault)) = (self.baro_fault, o.baro_fault) {
+                        handle.push_data(Report::Event(Event::BaroFault { fault }));
+                    }
+
+                    self.baro_fault = o.baro_fault;
+                    self.pressure_altitude = o.pressure_altitude;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            self.check_zones(handle, t, clock);
+            self.check_watch_list(handle, t, clock);
+
+            if t.quiet {
+                continue;
+            }
+
+            let (distance, bearing) = match (t.range, t.bearing) {
+                (Some(d), Some(b)) => (d, b),
+                _ => continue,
+            };
+
+            let altitude_diff = match t.altitude {
+                Some((alt, AltitudeType::Baro, _)) => self.pressure_altitude.map(|a| alt - a),
+                Some((alt, AltitudeType::GNSS, _)) => self.hae_altitude.map(|a| alt - a),
+                None => None,
+            };
+
+            if let Some(altitude_diff) = altitude_diff {
+                if distance <= ALERT_RADIUS
+                    && altitude_diff.abs() <= ALERT_ALTITUDE
+                    && !self.alerted.contains_key(&t.addr.0)
+                    && !self.inhibited(clock)
+                {
+                    self.alerted.insert(t.addr.0, clock);
+
+                    handle.push_data(Report::Event(Event::TrafficAlert {
+                        addr: t.addr.0,
+                        callsign: t.callsign.clone(),
+                        distance,
+                        bearing,
+                        clock: clock_position(bearing, self.track),
+                        altitude_diff,
+                        confidence: Confidence::new(t.sil, t.nacv),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Handle;
+    use processor::ownship::Ownship;
+    use processor::traffic::{AddressType, Target, TrafficSource};
+    use sensor::gnss::{Fix, FixQuality};
+    use testing::{run_processor, run_reports, MockHandle};
+
+    fn fix(gs: f32) -> SensorData {
+        fix_at(gs, 3000)
+    }
+
+    fn fix_at(gs: f32, altitude: i32) -> SensorData {
+        fix_at_pos(gs, altitude, (37.5, -122.0))
+    }
+
+    fn fix_at_pos(gs: f32, altitude: i32, lat_lon: (f32, f32)) -> SensorData {
+        let height = (altitude as f32 * 304.8) as i32;
+
+        SensorData::GNSS(GNSSData::TimeFix {
+            time: None,
+            fix: Some(Fix {
+                quality: FixQuality::ThreeDim,
+                num_sv: 8,
+                lat_lon: (lat_lon, Some(5000)),
+                height_msl: (height, None),
+                height_ellipsoid: (height, None),
+                gs: ((gs / 0.00194384) as u32, None),
+                vs: (0, None),
+                true_course: (0.0, None),
+                mag_dec: None,
+            }),
+        })
+    }
+
+    /// Target `range` NM straight north of ownship
+    fn traffic(addr: u32, range: f32, altitude: i32) -> Report {
+        let clock = Instant::now();
+        let mut t = Target::new(
+            (addr, AddressType::ADSBICAO),
+            clock,
+            TrafficSource::ES,
+            None,
+        );
+        t.altitude = Some((altitude, AltitudeType::GNSS, clock));
+        t.range = Some(range);
+        t.bearing = Some(0.0);
+
+        Report::Traffic(Arc::new(t))
+    }
+
+    fn events(h: &mut MockHandle<Report>) -> Vec<&'static str> {
+        h.take()
+            .into_iter()
+            .filter_map(|r| match r {
+                Report::Event(e) => Some(e.name()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_takeoff_and_landing() {
+        let mut p = Events::new(&Config::default());
+        let mut h = MockHandle::new(1);
+
+        // a short burst of speed while taxiing
+        run_processor(&mut *p, &mut h, &[fix(60.0)]);
+        h.advance_secs(5);
+        run_processor(&mut *p, &mut h, &[fix(20.0)]);
+        h.advance_secs(10);
+        run_processor(&mut *p, &mut h, &[fix(20.0)]);
+        assert!(events(&mut h).is_empty());
+
+        run_processor(&mut *p, &mut h, &[fix(60.0)]);
+        h.advance_secs(10);
+        run_processor(&mut *p, &mut h, &[fix(80.0)]);
+        assert_eq!(events(&mut h), vec!["Takeoff"]);
+
+        h.advance_secs(10);
+        run_processor(&mut *p, &mut h, &[fix(25.0)]);
+        h.advance_secs(10);
+        run_processor(&mut *p, &mut h, &[fix(10.0)]);
+        assert_eq!(events(&mut h), vec!["Landing"]);
+    }
+
+    #[test]
+    fn test_traffic_alert_and_gnss_lost() {
+        let mut c = Config::default();
+        c.ownship_icao = Some(0xA00001);
+        let mut p = Events::new(&c);
+        let mut h = MockHandle::new(1);
+
+        run_processor(&mut *p, &mut h, &[fix(100.0)]);
+        h.take();
+
+        let mut quiet = traffic(0xA00005, 0.5, 3000);
+        if let Report::Traffic(ref mut t) = quiet {
+            Arc::make_mut(t).quiet = true;
+        }
+
+        // ownship, quieted, too high, too far, then close enough twice
+        run_reports(
+            &mut *p,
+            &mut h,
+            &[
+                traffic(0xA00001, 0.0, 3000),
+                quiet,
+                traffic(0xA00002, 0.6, 4500),
+                traffic(0xA00003, 6.0, 3000),
+                traffic(0xA00004, 0.6, 3200),
+                traffic(0xA00004, 0.6, 3200),
+            ],
+        );
+        assert_eq!(events(&mut h), vec!["TrafficAlert"]);
+
+        h.advance_secs(GNSS_LOST_SECS);
+        run_processor(&mut *p, &mut h, &[]);
+        assert_eq!(events(&mut h), vec!["GNSSLost"]);
+        h.advance_secs(1);
+        run_processor(&mut *p, &mut h, &[]);
+        assert!(events(&mut h).is_empty());
+    }
+
+    #[test]
+    fn test_watch_list() {
+        let watch = |name: &str, icao, callsign: Option<&str>, range| Watch {
+            name: name.to_string(),
+            icao,
+            callsign: callsign.map(|c| c.to_string()),
+            range,
+        };
+        let mut c = Config::default();
+        c.watch_list = vec![
+            watch("Bob", Some(0xA00002), None, None),
+            watch("club", None, Some("n123ab"), Some(5.0)),
+        ];
+        let mut p = Events::new(&c);
+        let mut h = MockHandle::new(1);
+
+        let club = |range| {
+            let mut r = traffic(0xA00003, range, 3000);
+            if let Report::Traffic(ref mut t) = r {
+                Arc::make_mut(t).callsign = Some("N123AB".to_string());
+            }
+            r
+        };
+        let watched = |h: &mut MockHandle<Report>| -> Vec<String> {
+            h.take()
+                .into_iter()
+                .filter_map(|r| match r {
+                    Report::Event(Event::WatchedTraffic { name, .. }) => Some(name),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // far away and too far away, then staying around
+        run_reports(
+            &mut *p,
+            &mut h,
+            &[traffic(0xA00002, 30.0, 5000), club(10.0)],
+        );
+        assert_eq!(watched(&mut h), vec!["Bob"]);
+        run_reports(
+            &mut *p,
+            &mut h,
+            &[traffic(0xA00002, 20.0, 5000), club(4.0), club(3.0)],
+        );
+        assert_eq!(watched(&mut h), vec!["club"]);
+
+        // back after a long while
+        h.advance_secs(WATCH_GONE_SECS);
+        run_processor(&mut *p, &mut h, &[]);
+        run_reports(&mut *p, &mut h, &[traffic(0xA00002, 20.0, 5000)]);
+        assert_eq!(watched(&mut h), vec!["Bob"]);
+    }
+
+    #[test]
+    fn test_alert_inhibit() {
+        let mut c = Config::default();
+        c.alert_inhibit = Some(AlertInhibit {
+            below_agl: Some(500),
+            below_gs: Some(50.0),
+            after_takeoff: Some(60),
+        });
+        let mut p = Events::new(&c);
+        let mut h = MockHandle::new(1);
+
+        let alerts = |p: &mut Processor, h: &mut MockHandle<Report>, fix: SensorData| {
+            run_processor(p, h, &[fix]);
+            h.take();
+            run_reports(p, h, &[traffic(0xA00002, 0.6, 3200)]);
+            events(h).len()
+        };
+
+        // taxiing, then on the takeoff roll
+        assert_eq!(alerts(&mut *p, &mut h, fix_at(10.0, 3000)), 0);
+        assert_eq!(alerts(&mut *p, &mut h, fix_at(60.0, 3000)), 0);
+        h.advance_secs(TRANSITION_SECS);
+        run_processor(&mut *p, &mut h, &[fix_at(80.0, 3100)]);
+        assert_eq!(events(&mut h), vec!["Takeoff"]);
+
+        // above 500 ft, but just after takeoff
+        h.advance_secs(30);
+        assert_eq!(alerts(&mut *p, &mut h, fix_at(90.0, 3600)), 0);
+        h.advance_secs(30);
+        assert_eq!(alerts(&mut *p, &mut h, fix_at(90.0, 3600)), 1);
+    }
+
+    #[test]
+    fn test_alert_inhibit_away_from_field() {
+        let mut c = Config::default();
+        c.alert_inhibit = Some(AlertInhibit {
+            below_agl: Some(500),
+            below_gs: None,
+            after_takeoff: None,
+        });
+        let mut p = Events::new(&c);
+        let mut h = MockHandle::new(1);
+
+        // depart a field at 5000 ft
+        run_processor(&mut *p, &mut h, &[fix_at(10.0, 5000)]);
+        run_processor(&mut *p, &mut h, &[fix_at(80.0, 5000)]);
+        h.advance_secs(TRANSITION_SECS);
+        run_processor(&mut *p, &mut h, &[fix_at(80.0, 5100)]);
+        assert_eq!(events(&mut h), vec!["Takeoff"]);
+
+        // still low over the field
+        run_processor(&mut *p, &mut h, &[fix_at(90.0, 5300)]);
+        run_reports(&mut *p, &mut h, &[traffic(0xA00002, 0.6, 5500)]);
+        assert!(events(&mut h).is_empty());
+
+        // just as low en route, 10 NM north of it
+        run_processor(
+            &mut *p,
+            &mut h,
+            &[fix_at_pos(90.0, 5300, (37.6667, -122.0))],
+        );
+        run_reports(&mut *p, &mut h, &[traffic(0xA00003, 0.6, 5500)]);
+        assert_eq!(events(&mut h), vec!["TrafficAlert"]);
+    }
+
+    #[test]
+    fn test_alert_inhibit_started_in_flight() {
+        let mut c = Config::default();
+        c.alert_inhibit = Some(AlertInhibit {
+            below_agl: Some(500),
+            below_gs: Some(50.0),
+            after_takeoff: Some(60),
+        });
+        let mut p = Events::new(&c);
+        let mut h = MockHandle::new(1);
+
+        // no field to measure the height above, and not a takeoff either
+        run_processor(&mut *p, &mut h, &[fix_at(90.0, 3000)]);
+        run_reports(&mut *p, &mut h, &[traffic(0xA00002, 0.6, 3200)]);
+        assert_eq!(events(&mut h), vec!["TrafficAlert"]);
+
+        h.advance_secs(TRANSITION_SECS);
+        run_processor(&mut *p, &mut h, &[fix_at(90.0, 3000)]);
+        assert_eq!(events(&mut h), vec!["Takeoff"]);
+        run_reports(&mut *p, &mut h, &[traffic(0xA00003, 0.6, 3200)]);
+        assert_eq!(events(&mut h), vec!["TrafficAlert"]);
+    }
+
+    #[test]
+    fn test_baro_fault() {
+        let mut p = Events::new(&Config::default());
+        let mut h = MockHandle::new(1);
+
+        let ownship = |fault: Option<BaroFault>| {
+            let mut o = Ownship::default();
+            o.baro_fault = fault;
+            o.pressure_altitude = if fault.is_some() { None } else { Some(3000) };
+            Report::Ownship(o)
+        };
+
+        run_reports(&mut *p, &mut h, &[ownship(None)]);
+        assert!(events(&mut h).is_empty());
+        run_reports(&mut *p, &mut h, &[ownship(Some(BaroFault::Stuck))]);
+        assert_eq!(events(&mut h), vec!["BaroFault"]);
+        run_reports(&mut *p, &mut h, &[ownship(Some(BaroFault::Jump))]);
+        assert!(events(&mut h).is_empty());
+    }
+
+    #[test]
+    fn test_traffic_alert_confidence() {
+        let mut p = Events::new(&Config::default());
+        let mut h = MockHandle::new(1);
+
+        run_processor(&mut *p, &mut h, &[fix(100.0)]);
+        h.take();
+
+        let mut low = traffic(0xA00002, 0.6, 3200);
+        if let Report::Traffic(ref mut t) = low {
+            let t = Arc::make_mut(t);
+            t.sil = Some(0);
+            t.nacv = Some(2);
+        }
+
+        run_reports(&mut *p, &mut h, &[low, traffic(0xA00003, 0.6, 3200)]);
+
+        let alerts: Vec<(Confidence, u8)> = h
+            .take()
+            .into_iter()
+            .filter_map(|r| match r {
+                Report::Event(Event::TrafficAlert {
+                    confidence, clock, ..
+                }) => Some((confidence, clock)),
+                _ => None,
+            })
+            .collect();
+        // straight ahead, we are tracking north
+        assert_eq!(
+            alerts,
+            vec![(Confidence::Low, 12), (Confidence::Unknown, 12)]
+        );
+
+        assert_eq!(Confidence::new(Some(3), Some(2)), Confidence::High);
+        assert_eq!(Confidence::new(Some(3), None), Confidence::Unknown);
+    }
+
+    #[test]
+    fn test_alert_zones() {
+        let zone = |name: &str, shape| AlertZone {
+            name: name.to_string(),
+            shape,
+            floor: None,
+            ceiling: Some(1000),
+        };
+        let mut c = Config::default();
+        c.alert_zones = vec![
+            zone(
+                "home",
+                Shape::Cylinder {
+                    center: (37.5, -122.0),
+                    radius: 1.0,
+                },
+            ),
+            zone(
+                "practice",
+                Shape::Polygon {
+                    points: vec![(37.0, -122.0), (37.0, -121.0), (38.0, -121.0)],
+                },
+            ),
+        ];
+        // on the ground, no GNSS fix
+        let mut p = Events::new(&c);
+        let mut h = MockHandle::new(1);
+
+        let clock = h.get_clock();
+        let at = |lat_lon, altitude: Option<i32>| {
+            let mut t = Target::new(
+                (0xA00002, AddressType::ADSBICAO),
+                clock,
+                TrafficSource::ES,
+                None,
+            );
+            t.lat_lon = Some((lat_lon, clock));
+            t.altitude = altitude.map(|a| (a, AltitudeType::Baro, clock));
+            Report::Traffic(Arc::new(t))
+        };
+        let zones = |h: &mut MockHandle<Report>| -> Vec<String> {
+            h.take()
+                .into_iter()
+                .filter_map(|r| match r {
+                    Report::Event(Event::ZoneEntered { zone, .. }) => Some(zone),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // taxiing in without altitude, then staying
+        run_reports(&mut *p, &mut h, &[at((37.6, -122.0), None)]);
+        assert!(zones(&mut h).is_empty());
+        run_reports(&mut *p, &mut h, &[at((37.51, -122.0), None)]);
+        assert_eq!(zones(&mut h), vec!["home"]);
+        run_reports(&mut *p, &mut h, &[at((37.5, -122.0), Some(800))]);
+        assert!(zones(&mut h).is_empty());
+
+        // climbing out, then back in
+        run_reports(&mut *p, &mut h, &[at((37.5, -122.0), Some(1200))]);
+        run_reports(&mut *p, &mut h, &[at((37.5, -122.0), Some(900))]);
+        assert_eq!(zones(&mut h), vec!["home"]);
+
+        // into the practice area after losing it for a while
+        h.advance_secs(ZONE_EXIT_SECS);
+        run_processor(&mut *p, &mut h, &[]);
+        run_reports(&mut *p, &mut h, &[at((37.2, -121.5), Some(900))]);
+        assert_eq!(zones(&mut h), vec!["practice"]);
+        run_reports(&mut *p, &mut h, &[at((37.8, -121.5), Some(900))]);
+        assert!(zones(&mut h).is_empty());
+
+        // replaced by a client, invalid zones are refused
+        let mut invalid = c.alert_zones[0].clone();
+        invalid.floor = Some(2000);
+        run_processor(
+            &mut *p,
+            &mut h,
+            &[SensorData::Command(Command::AlertZones {
+                zones: vec![invalid],
+            })],
+        );
+        run_reports(&mut *p, &mut h, &[at((37.2, -121.5), Some(900))]);
+        assert_eq!(zones(&mut h), vec!["practice"]);
+
+        run_processor(
+            &mut *p,
+            &mut h,
+            &[SensorData::Command(Command::AlertZones {
+                zones: vec![c.alert_zones[0].clone()],
+            })],
+        );
+        run_reports(&mut *p, &mut h, &[at((37.2, -121.5), Some(900))]);
+        assert!(zones(&mut h).is_empty());
+        run_reports(&mut *p, &mut h, &[at((37.5, -122.0), Some(900))]);
+        assert_eq!(zones(&mut h), vec!["home"]);
+    }
+}
@@ -15,6 +15,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::*;
+use std::time::Instant;
 
 pub struct FISB {
     count: usize,
@@ -29,6 +30,39 @@ impl FISB {
 #[derive(Debug, PartialEq, Clone)]
 pub struct FISBData {
     pub payload: Vec<u8>,
+    /// When the frame was decoded, for measuring latency
+    pub received: Option<Instant>,
+}
+
+// UAT-specific header in front of the information frames (DO-282B 2.2.3.2.1)
+const UPLINK_HEADER_LEN: usize = 8;
+const INFO_FRAME_HEADER_LEN: usize = 2;
+const FRAME_TYPE_FISB: u8 = 0;
+
+impl FISBData {
+    /// FIS-B product IDs of every APDU carried by this uplink frame
+    pub fn product_ids(&self) -> Vec<u16> {
+        let mut ids = Vec::new();
+        let mut pos = UPLINK_HEADER_LEN;
+
+        while pos + INFO_FRAME_HEADER_LEN <= self.payload.len() {
+            let len = ((self.payload[pos] as usize) << 1) | (self.payload[pos + 1] >> 7) as usize;
+            let frame_type = self.payload[pos + 1] & 0x0F;
+
+            if len == 0 {
+                break;
+            }
+
+            let data = &self.payload[pos + INFO_FRAME_HEADER_LEN..];
+            if frame_type == FRAME_TYPE_FISB && data.len() >= 2 {
+                ids.push((((data[0] & 0x1F) as u16) << 6) | (data[1] >> 2) as u16);
+            }
+
+            pos += INFO_FRAME_HEADER_LEN + len;
+        }
+
+        ids
+    }
 }
 
 impl Processor for FISB {
@@ -44,3 +78,38 @@ impl Processor for FISB {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_product_ids() {
+        let mut payload = vec![0_u8; 432];
+        // NEXRAD (63) APDU of 10 bytes
+        payload[8] = 0x05;
+        payload[9] = 0x00;
+        payload[10] = 0x00;
+        payload[11] = 63 << 2;
+        // METAR text (413) APDU of 4 bytes
+        payload[20] = 0x02;
+        payload[21] = 0x00;
+        payload[22] = (413 >> 6) as u8;
+        payload[23] = ((413 & 0x3F) << 2) as u8;
+        // reserved frame type, skipped
+        payload[26] = 0x01;
+        payload[27] = 0x0F;
+
+        let data = FISBData {
+            payload,
+            received: None,
+        };
+        assert_eq!(data.product_ids(), vec![63, 413]);
+
+        let data = FISBData {
+            payload: vec![0_u8; 432],
+            received: None,
+        };
+        assert!(data.product_ids().is_empty());
+    }
+}
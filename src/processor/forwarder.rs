@@ -0,0 +1,46 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Forwards what the receivers decoded to the aggregator this unit is
+//! paired with, see `sensor::peer`.
+
+use super::*;
+use sensor::peer;
+use std::sync::mpsc::SyncSender;
+
+pub struct Forwarder {
+    tx: SyncSender<Vec<u8>>,
+}
+
+impl Forwarder {
+    pub fn new(host: &str, port: u16) -> Box<Processor> {
+        Box::new(Forwarder {
+            tx: peer::forward(host, port),
+        })
+    }
+}
+
+impl Processor for Forwarder {
+    fn run(&mut self, _handle: &mut Pushable<Report>, i: ChainedIter) {
+        for e in i {
+            if let Some(frame) = peer::encode(e) {
+                // dropped while the aggregator is behind, the connection
+                // thread only exits once we are gone
+                self.tx.try_send(frame).ok();
+            }
+        }
+    }
+}
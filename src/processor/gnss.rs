@@ -15,28 +15,188 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::*;
-use sensor::gnss::{FixQuality, GNSSData, SVStatus};
+use chrono::prelude::*;
+use chrono::Duration;
+use sensor::gnss::{Band, Constellation, FixQuality, GNSSData, SVStatus, Signal, DOP};
+use std::collections::BTreeMap;
+use std::time::{Duration as StdDuration, Instant};
+
+// time from fixes is only reported when no pulse was seen for this long,
+// and a pulse is only numbered from a fix this recent, in seconds
+const MAX_PULSE_AGE: u64 = 2;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct GNSS {
     pub quality: FixQuality,
     pub num_sv: u8,
+    /// Horizontal accuracy in millimeters
+    pub h_accuracy: Option<u32>,
+    /// Vertical accuracy in millimeters
+    pub v_accuracy: Option<u32>,
+    pub dop: Option<DOP>,
     pub sv_status: Vec<SVStatus>,
+    /// Latest signals reported per satellite, merged into `sv_status` since
+    /// the receiver reports them separately
+    #[serde(skip)]
+    signals: BTreeMap<(Constellation, u8), Vec<Signal>>,
+    /// Time from the latest fix, only as accurate as the delay between the
+    /// fix and it being processed
+    #[serde(skip)]
+    time: Option<Time>,
+    /// Time at the latest time pulse
+    #[serde(skip)]
+    pulse: Option<Time>,
+}
+
+/// Satellites grouped by constellation, ready to be drawn on a polar plot.
+/// Satellites without a known azimuth or elevation are left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkyView {
+    pub constellations: BTreeMap<Constellation, Vec<Satellite>>,
+    /// Number of satellites with signal acquired
+    pub visible: u8,
+    /// Number of satellites used in solution
+    pub used: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Satellite {
+    pub sv_id: u8,
+    /// Azimuth in degrees, clockwise from true north
+    pub azimuth: u16,
+    /// Elevation in degrees above the horizon
+    pub elevation: i8,
+    /// Signal to noise ratio in dBHz
+    pub snr: Option<u8>,
+    pub in_solution: bool,
+    pub healthy: Option<bool>,
+    /// Bands used for navigation, empty if the receiver does not report them
+    pub bands: Vec<Band>,
+}
+
+/// UTC time reported by the GNSS receiver, only when it flagged it as valid.
+/// Aligned to the time pulse if there is one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Time {
+    pub utc: DateTime<UTC>,
+    /// When `utc` was received
+    pub clock: Instant,
+}
+
+impl Time {
+    /// UTC time at `now`, extrapolated from the one received
+    pub fn at(&self, now: Instant) -> DateTime<UTC> {
+        if now >= self.clock {
+            self.utc + Duration::from_std(now - self.clock).unwrap()
+        } else {
+            self.utc - Duration::from_std(self.clock - now).unwrap()
+        }
+    }
+}
+
+impl SkyView {
+    pub fn new(sv_status: &[SVStatus]) -> Self {
+        let mut constellations = BTreeMap::new();
+        let mut visible = 0;
+        let mut used = 0;
+
+        for s in sv_status {
+            if s.acquired {
+                visible += 1;
+            }
+
+            if s.in_solution {
+                used += 1;
+            }
+
+            if let (Some(azimuth), Some(elevation)) = (s.azimuth, s.elevation) {
+                constellations
+                    .entry(s.system)
+                    .or_insert_with(Vec::new)
+                    .push(Satellite {
+                        sv_id: s.sv_id,
+                        azimuth,
+                        elevation,
+                        snr: s.signal,
+                        in_solution: s.in_solution,
+                        healthy: s.healthy,
+                        bands: s
+                            .signals
+                            .iter()
+                            .filter(|g| g.in_solution)
+                            .map(|g| g.band)
+                            .collect(),
+                    });
+            }
+        }
+
+        Self {
+            constellations,
+            visible,
+            used,
+        }
+    }
 }
 
 impl Processor for GNSS {
     fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
         for e in i {
             match *e {
-                SensorData::GNSS(GNSSData::TimeFix {
-                    fix: Some(ref f), ..
-                }) => {
-                    self.quality = f.quality;
-                    self.num_sv = f.num_sv;
+                SensorData::GNSS(GNSSData::TimeFix { time, ref fix }) => {
+                    if let Some(utc) = time {
+                        self.time = Some(Time { utc, clock });
+
+                        if !recent(self.pulse, clock) {
+                            handle.push_data(Report::Time(self.time.unwrap()));
+                        }
+                    }
+
+                    if let Some(ref f) = *fix {
+                        self.quality = f.quality;
+                        self.num_sv = f.num_sv;
+                        self.h_accuracy = f.lat_lon.1;
+                        self.v_accuracy = f.height_msl.1;
+                    }
+                }
+                SensorData::GNSS(GNSSData::TimePulse(at)) => match self.time {
+                    Some(t) if recent(self.time, at) => {
+                        // the pulse is the start of the second closest to
+                        // the one extrapolated from the fix
+                        let utc = t.at(at) + Duration::milliseconds(500);
+                        let pulse = Time {
+                            utc: utc.with_nanosecond(0).unwrap(),
+                            clock: at,
+                        };
+
+                        self.pulse = Some(pulse);
+                        handle.push_data(Report::Time(pulse));
+                    }
+                    _ => {}
+                },
+                SensorData::GNSS(GNSSData::DOP(d)) => {
+                    self.dop = Some(d);
+                }
+                SensorData::GNSS(GNSSData::SignalInfo(ref s)) => {
+                    self.signals.clear();
+                    for &(system, sv_id, signal) in s {
+                        self.signals
+                            .entry((system, sv_id))
+                            .or_insert_with(Vec::new)
+                            .push(signal);
+                    }
                 }
                 SensorData::GNSS(GNSSData::SatelliteInfo(ref s)) => {
                     self.sv_status = s.to_vec();
+                    for sv in &mut self.sv_status {
+                        if let Some(signals) = self.signals.get(&(sv.system, sv.sv_id)) {
+                            sv.signals = signals.clone();
+                        }
+                    }
+
                     handle.push_data(Report::GNSS(self.clone()));
+                    handle.push_data(Report::SkyView(SkyView::new(&self.sv_status)));
                 }
                 _ => {} // do nothing
             }
@@ -50,6 +210,197 @@ impl GNSS {
             quality: FixQuality::Unknown,
             sv_status: Vec::new(),
             num_sv: 0,
+            h_accuracy: None,
+            v_accuracy: None,
+            dop: None,
+            signals: BTreeMap::new(),
+            time: None,
+            pulse: None,
         }
     }
 }
+
+/// Whether `t` is at most `MAX_PULSE_AGE` away from `clock`
+fn recent(t: Option<Time>, clock: Instant) -> bool {
+    t.map_or(false, |t| {
+        let d = if clock >= t.clock {
+            clock - t.clock
+        } else {
+            t.clock - clock
+        };
+
+        d < StdDuration::from_secs(MAX_PULSE_AGE)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Handle;
+    use testing::{run_processor, MockHandle};
+
+    fn sv(system: Constellation, sv_id: u8, position: bool, in_solution: bool) -> SVStatus {
+        SVStatus {
+            system,
+            sv_id,
+            signal: Some(40),
+            elevation: if position { Some(45) } else { None },
+            azimuth: if position { Some(270) } else { None },
+            healthy: Some(true),
+            acquired: true,
+            in_solution,
+            sbas_in_use: None,
+            signals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_sky_view() {
+        let sv_status = [
+            sv(Constellation::GPS, 1, true, true),
+            sv(Constellation::GPS, 2, false, false),
+            sv(Constellation::GLONASS, 3, true, false),
+            sv(Constellation::GPS, 4, true, true),
+        ];
+
+        let sky = SkyView::new(&sv_status);
+        assert_eq!(sky.visible, 4);
+        assert_eq!(sky.used, 2);
+        assert_eq!(sky.constellations.len(), 2);
+
+        let gps: Vec<u8> = sky.constellations[&Constellation::GPS]
+            .iter()
+            .map(|s| s.sv_id)
+            .collect();
+        assert_eq!(gps, vec![1, 4]);
+        assert_eq!(sky.constellations[&Constellation::GLONASS].len(), 1);
+
+        let js = serde_json::to_value(&sky).unwrap();
+        assert_eq!(js["constellations"]["GPS"][0]["azimuth"], 270);
+    }
+
+    #[test]
+    fn test_signals() {
+        let mut p = GNSS::new();
+        let mut h = MockHandle::new(10);
+        let signal = |band, in_solution| Signal {
+            band,
+            snr: 40,
+            in_solution,
+        };
+
+        run_processor(
+            &mut p,
+            &mut h,
+            &[
+                SensorData::GNSS(GNSSData::SignalInfo(vec![
+                    (Constellation::GPS, 1, signal(Band::L1, true)),
+                    (Constellation::GPS, 1, signal(Band::L5, true)),
+                    (Constellation::GLONASS, 3, signal(Band::L2, false)),
+                ])),
+                SensorData::GNSS(GNSSData::SatelliteInfo(vec![
+                    sv(Constellation::GPS, 1, true, true),
+                    sv(Constellation::GLONASS, 3, true, false),
+                ])),
+            ],
+        );
+
+        let r = h.take();
+        assert_eq!(r.len(), 2);
+        match (&r[0], &r[1]) {
+            (&Report::GNSS(ref g), &Report::SkyView(ref s)) => {
+                assert_eq!(g.sv_status[0].signals.len(), 2);
+                assert_eq!(g.sv_status[1].signals.len(), 1);
+                assert_eq!(
+                    s.constellations[&Constellation::GPS][0].bands,
+                    vec![Band::L1, Band::L5]
+                );
+                assert!(s.constellations[&Constellation::GLONASS][0]
+                    .bands
+                    .is_empty());
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_time() {
+        let mut p = GNSS::new();
+        let mut h = MockHandle::new(10);
+        let utc = UTC.ymd(2018, 7, 1).and_hms(23, 59, 59);
+
+        // invalid time is not reported
+        run_processor(
+            &mut p,
+            &mut h,
+            &[
+                SensorData::GNSS(GNSSData::TimeFix {
+                    time: None,
+                    fix: None,
+                }),
+                SensorData::GNSS(GNSSData::TimeFix {
+                    time: Some(utc),
+                    fix: None,
+                }),
+            ],
+        );
+
+        let r = h.take();
+        assert_eq!(r.len(), 1);
+        match r[0] {
+            Report::Time(t) => {
+                assert_eq!(t.utc, utc);
+                assert_eq!(
+                    t.at(t.clock + StdDuration::from_millis(1500)),
+                    UTC.ymd(2018, 7, 2).and_hms_milli(0, 0, 0, 500)
+                );
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_time_pulse() {
+        let mut p = GNSS::new();
+        let mut h = MockHandle::new(10);
+        let clock = h.get_clock();
+        let time = |utc| {
+            SensorData::GNSS(GNSSData::TimeFix {
+                time: Some(utc),
+                fix: None,
+            })
+        };
+        let pulse =
+            |ms| SensorData::GNSS(GNSSData::TimePulse(clock + StdDuration::from_millis(ms)));
+
+        // no time to number the second with
+        run_processor(&mut p, &mut h, &[pulse(0)]);
+        assert!(h.take().is_empty());
+
+        run_processor(
+            &mut p,
+            &mut h,
+            &[time(UTC.ymd(2018, 7, 1).and_hms(12, 0, 0)), pulse(800)],
+        );
+        let r = h.take();
+        assert_eq!(r.len(), 2);
+        match r[1] {
+            Report::Time(t) => assert_eq!(
+                t,
+                Time {
+                    utc: UTC.ymd(2018, 7, 1).and_hms(12, 0, 1),
+                    clock: clock + StdDuration::from_millis(800),
+                }
+            ),
+            ref r => panic!("unexpected {:?}", r),
+        }
+
+        // only the time pulse is reported while there is one
+        run_processor(
+            &mut p,
+            &mut h,
+            &[time(UTC.ymd(2018, 7, 1).and_hms(12, 0, 1))],
+        );
+        assert!(h.take().is_empty());
+    }
+}
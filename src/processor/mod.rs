@@ -14,23 +14,119 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod antenna;
+pub mod cabin;
+#[cfg(target_os = "linux")]
 pub mod clock;
+pub mod downlink;
+pub mod e6b;
+pub mod events;
 pub mod fisb;
+pub mod forwarder;
 pub mod gnss;
+pub mod navigation;
 pub mod ownship;
+pub mod powersave;
+pub mod receiver;
+pub mod settings;
+pub mod system;
 pub mod traffic;
+pub mod transponder;
+pub mod update;
+pub mod warmstart;
 
+use config::Config;
 use pitot::handle::Pushable;
+use sensor::sdr::DecodeStats;
 use sensor::SensorData;
+use serde_json::{self, Value};
 use std::iter::Chain;
 use std::slice::Iter;
+use std::sync::Arc;
+use units::{self, Units};
 
 #[derive(Debug)]
 pub enum Report {
     Ownship(ownship::Ownship),
-    Traffic(traffic::Target),
+    Traffic(Arc<traffic::Target>),
     FISB(fisb::FISBData),
+    Downlink(downlink::DownlinkData),
+    DecodeStats(DecodeStats),
     GNSS(gnss::GNSS),
+    SkyView(gnss::SkyView),
+    Time(gnss::Time),
+    E6B(e6b::E6B),
+    Navigation(navigation::Navigation),
+    Config(Config),
+    Event(events::Event),
+    WarmStart(warmstart::State),
+    Antenna(antenna::Day),
+    Cabin(cabin::Cabin),
+    /// Pause or resume the linked components named `component`, applied by
+    /// `Pitot` itself before the protocols run
+    Lifecycle { component: String, paused: bool },
+}
+
+impl Report {
+    /// Name of the kind of this report, which processors subscribe to with
+    /// `Pitot::link_subscriber`. Same as the `type` of the JSON representation.
+    pub fn topic(&self) -> &'static str {
+        match *self {
+            Report::Ownship(_) => "Ownship",
+            Report::Traffic(_) => "Traffic",
+            Report::FISB(_) => "FISB",
+            Report::Downlink(_) => "Downlink",
+            Report::DecodeStats(_) => "DecodeStats",
+            Report::GNSS(_) => "GNSS",
+            Report::SkyView(_) => "SkyView",
+            Report::Time(_) => "Time",
+            Report::E6B(_) => "E6B",
+            Report::Navigation(_) => "Navigation",
+            Report::Config(_) => "Config",
+            Report::Event(_) => "Event",
+            Report::WarmStart(_) => "WarmStart",
+            Report::Antenna(_) => "Antenna",
+            Report::Cabin(_) => "Cabin",
+            Report::Lifecycle { .. } => "Lifecycle",
+        }
+    }
+
+    /// JSON representation for clients and scripts, tagged with the report
+    /// type in the `type` field. `None` for reports that have none.
+    pub fn to_json(&self) -> Option<Value> {
+        self.to_json_in(Units::Aviation)
+    }
+
+    /// Same as `to_json`, with measurements also in `units` under fields
+    /// named after the unit
+    pub fn to_json_in(&self, units: Units) -> Option<Value> {
+        let mut js = match *self {
+            Report::Ownship(ref o) => serde_json::to_value(o).unwrap(),
+            Report::Traffic(ref t) => serde_json::to_value(&**t).unwrap(),
+            Report::GNSS(ref g) => serde_json::to_value(g).unwrap(),
+            Report::SkyView(ref s) => serde_json::to_value(s).unwrap(),
+            Report::DecodeStats(ref s) => serde_json::to_value(s).unwrap(),
+            Report::E6B(ref e) => serde_json::to_value(e).unwrap(),
+            Report::Navigation(ref n) => serde_json::to_value(n).unwrap(),
+            Report::Event(ref e) => serde_json::to_value(e).unwrap(),
+            Report::WarmStart(ref w) => serde_json::to_value(w).unwrap(),
+            Report::Antenna(ref a) => serde_json::to_value(a).unwrap(),
+            Report::Cabin(ref c) => serde_json::to_value(c).unwrap(),
+            Report::FISB(_)
+            | Report::Downlink(_)
+            | Report::Time(_)
+            | Report::Config(_)
+            | Report::Lifecycle { .. } => return None,
+        };
+
+        let t = self.topic();
+        if let Value::Object(ref mut o) = js {
+            units::annotate(t, o, units);
+        }
+
+        js["type"] = t.into();
+        Some(js)
+    }
 }
 
 type ChainedIter<'a> = Chain<Iter<'a, SensorData>, Iter<'a, SensorData>>;
@@ -40,4 +136,21 @@ type ChainedIter<'a> = Chain<Iter<'a, SensorData>, Iter<'a, SensorData>>;
 pub trait Processor {
     /// Deliver sensor data `e` to this processor
     fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter);
+
+    /// Deliver the reports produced in this iteration by the processors this
+    /// one was linked after, see `Pitot::link_processor_after`, called right
+    /// after `run`. Also delivers the reports of the topics this one
+    /// subscribed to, see `Pitot::link_subscriber`, once all processors ran.
+    fn run_reports(&mut self, _handle: &mut Pushable<Report>, _reports: &[&Report]) {}
+
+    /// Called when this processor is paused at runtime, it is not run again until
+    /// resumed
+    fn pause(&mut self) {}
+
+    /// Called when this processor is resumed after having been paused
+    fn resume(&mut self) {}
+
+    /// Called once when Pitot stops, e.g. to save state that should survive
+    /// the restart
+    fn shutdown(&mut self) {}
 }
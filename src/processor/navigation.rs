@@ -0,0 +1,261 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Follows a route uploaded by the user, sequencing through its waypoints
+//! and reporting guidance to the active one.
+
+use super::*;
+use sensor::command::Command;
+use sensor::gnss::GNSSData;
+use sensor::SensorData;
+use serde_json;
+use std::time::Instant;
+use storage::Storage;
+use utils::{bearing_deg, cross_track_nm, distance_nm};
+
+const REPORT_FREQ: u16 = 1;
+const MAX_STALE_SECS: u64 = 3;
+// below this ground speed ETE is meaningless, in kts
+const MIN_ETE_GS: f32 = 5.0;
+// waypoint is considered reached when closer than this, in NM
+const ARRIVAL_RADIUS: f32 = 0.5;
+const ROUTE_FILE: &str = "route.json";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Navigation {
+    /// Waypoints `[lat, lon]` of the active route
+    pub route: Vec<(f32, f32)>,
+    /// Index into `route` of the waypoint we are navigating to
+    pub active_waypoint: Option<usize>,
+    /// Bearing to active waypoint in degrees true
+    pub bearing: Option<f32>,
+    /// Distance to active waypoint in NM
+    pub distance: Option<f32>,
+    /// Estimated time enroute to active waypoint in seconds
+    pub ete: Option<u32>,
+    /// Cross track error in NM, positive when right of course
+    pub cross_track: Option<f32>,
+    /// Start of the current leg, the previous waypoint or where
+    /// we were when the route was loaded
+    #[serde(skip)]
+    leg_start: Option<(f32, f32)>,
+    /// Ground speed in kts and position
+    #[serde(skip)]
+    ground: Option<(f32, (f32, f32), Instant)>,
+    #[serde(skip)]
+    report_counter: u32,
+    #[serde(skip)]
+    storage: Storage,
+}
+
+impl Navigation {
+    pub fn new(storage: Storage) -> Box<Processor> {
+        Box::new(Navigation::resume(storage))
+    }
+
+    /// Resumes the route saved in `storage`, if any
+    fn resume(storage: Storage) -> Self {
+        let mut n = Navigation {
+            route: Vec::new(),
+            active_waypoint: None,
+            bearing: None,
+            distance: None,
+            ete: None,
+            cross_track: None,
+            leg_start: None,
+            ground: None,
+            report_counter: 0,
+            storage,
+        };
+
+        if let Some(route) = n.storage
+            .load(ROUTE_FILE)
+            .and_then(|r| serde_json::from_slice::<Vec<(f32, f32)>>(&r).ok())
+        {
+            n.load_route(&route);
+        }
+
+        n
+    }
+
+    fn load_route(&mut self, waypoints: &[(f32, f32)]) {
+        info!("loaded route with {} waypoints", waypoints.len());
+
+        self.storage
+            .save(ROUTE_FILE, &serde_json::to_vec(waypoints).unwrap());
+
+        self.route = waypoints.to_vec();
+        self.active_waypoint = if self.route.is_empty() { None } else { Some(0) };
+        self.leg_start = self.ground.map(|(_, pos, _)| pos);
+    }
+
+    fn update(&mut self, clock: Instant) {
+        self.bearing = None;
+        self.distance = None;
+        self.ete = None;
+        self.cross_track = None;
+
+        let (gs, pos) = match self.ground {
+            Some((gs, pos, i)) if (clock - i).as_secs() <= MAX_STALE_SECS => (gs, pos),
+            _ => return,
+        };
+
+        let mut idx = match self.active_waypoint {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        if self.leg_start.is_none() {
+            self.leg_start = Some(pos);
+        }
+
+        if distance_nm(pos, self.route[idx]) < ARRIVAL_RADIUS {
+            info!("reached waypoint {}", idx + 1);
+
+            self.leg_start = Some(self.route[idx]);
+            idx += 1;
+
+            if idx == self.route.len() {
+                info!("route complete");
+                self.active_waypoint = None;
+                return;
+            }
+
+            self.active_waypoint = Some(idx);
+        }
+
+        let wpt = self.route[idx];
+        let d = distance_nm(pos, wpt);
+
+        self.bearing = Some(bearing_deg(pos, wpt));
+        self.distance = Some(d);
+
+        if gs >= MIN_ETE_GS {
+            self.ete = Some((d / gs * 3600_f32).round() as u32);
+        }
+
+        if let Some(start) = self.leg_start {
+            self.cross_track = Some(cross_track_nm(start, wpt, pos));
+        }
+    }
+}
+
+impl Processor for Navigation {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::GNSS(GNSSData::TimeFix {
+                    fix: Some(ref f), ..
+                }) => {
+                    self.ground = Some((mmps_to_kts!(f.gs.0), f.lat_lon.0, clock));
+                }
+                SensorData::Command(Command::Route { ref waypoints }) => {
+                    self.load_route(waypoints);
+                }
+                _ => {} // do nothing
+            }
+        }
+
+        run_every!(REPORT_FREQ, self.report_counter, handle, {
+            self.update(clock);
+            handle.push_data(Report::Navigation(self.clone()));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sensor::gnss::{Fix, FixQuality};
+    use testing::{run_processor, MockHandle};
+
+    /// 120 kts at `lat_lon`
+    fn fix(lat_lon: (f32, f32)) -> SensorData {
+        SensorData::GNSS(GNSSData::TimeFix {
+            time: None,
+            fix: Some(Fix {
+                quality: FixQuality::ThreeDim,
+                num_sv: 8,
+                lat_lon: (lat_lon, None),
+                height_msl: (914400, None),
+                height_ellipsoid: (914400, None),
+                gs: ((120_f32 / 0.00194384) as u32, None),
+                vs: (0, None),
+                true_course: (270.0, None),
+                mag_dec: None,
+            }),
+        })
+    }
+
+    fn report(h: &mut MockHandle<Report>) -> Navigation {
+        match h.take().pop() {
+            Some(Report::Navigation(n)) => n,
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut n = Navigation::resume(Storage::disabled());
+        let mut h = MockHandle::new(1);
+
+        // 1 NM east of the first waypoint, inbound
+        run_processor(
+            &mut n,
+            &mut h,
+            &[
+                fix((0_f32, 1_f32 / 60_f32)),
+                SensorData::Command(Command::Route {
+                    waypoints: vec![(0_f32, 0_f32), (1_f32, 0_f32)],
+                }),
+            ],
+        );
+        let r = report(&mut h);
+        assert_eq!(r.leg_start, Some((0_f32, 1_f32 / 60_f32)));
+        assert_eq!(r.active_waypoint, Some(0));
+        assert!((r.bearing.unwrap() - 270_f32).abs() < 0.1);
+        assert!((r.distance.unwrap() - 1_f32).abs() < 0.01);
+        assert_eq!(r.ete, Some(30));
+        assert!(r.cross_track.unwrap().abs() < 0.01);
+
+        // reached the first waypoint, sequence to the second
+        h.advance_secs(1);
+        run_processor(&mut n, &mut h, &[fix((0_f32, 0.1 / 60_f32))]);
+        let r = report(&mut h);
+        assert_eq!(r.active_waypoint, Some(1));
+        assert_eq!(r.leg_start, Some((0_f32, 0_f32)));
+
+        // 1 NM right of course halfway along the second leg
+        h.advance_secs(1);
+        run_processor(&mut n, &mut h, &[fix((0.5, 1_f32 / 60_f32))]);
+        assert!((report(&mut h).cross_track.unwrap() - 1_f32).abs() < 0.01);
+
+        // no guidance from a stale position
+        h.advance_secs(MAX_STALE_SECS + 1);
+        run_processor(&mut n, &mut h, &[]);
+        let r = report(&mut h);
+        assert_eq!(r.active_waypoint, Some(1));
+        assert_eq!(r.distance, None);
+
+        // reached the last waypoint
+        run_processor(&mut n, &mut h, &[fix((1_f32, 0_f32))]);
+        let r = report(&mut h);
+        assert_eq!(r.active_waypoint, None);
+        assert_eq!(r.distance, None);
+    }
+}
@@ -15,8 +15,116 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::*;
-use sensor::gnss::GNSSData;
+use sensor::airspeed::tas;
+use sensor::barometer::BaroData;
+use sensor::gnss::{FixQuality, GNSSData};
 use sensor::SensorData;
+use std::time::Instant;
+use utils::secs_f32;
+
+// pressure altitude changing faster than this in ft/min is not believed
+const MAX_BARO_RATE: f32 = 6000.0;
+// a barometer that jumped is trusted again after behaving for this long
+const BARO_RECOVERY_SECS: u64 = 10;
+// the exact same pressure for this long means the sensor is stuck
+const BARO_STUCK_SECS: u64 = 30;
+// altitude trends of barometer and GNSS are compared over this many seconds
+const TREND_SECS: u64 = 30;
+// largest difference in ft between the baro and GNSS altitude change over
+// TREND_SECS
+const MAX_TREND_DIFF: i32 = 500;
+// GNSS altitude older than this is not compared
+const MAX_STALE_SECS: u64 = 3;
+
+/// Why the barometer is not trusted
+#[derive(PartialEq, Debug, Copy, Clone, Serialize)]
+pub enum BaroFault {
+    /// Pressure altitude changed faster than any aircraft climbs or descends
+    Jump,
+    /// Pressure has not changed at all for a while
+    Stuck,
+    /// Pressure altitude does not follow the GNSS altitude trend
+    GNSSDisagreement,
+    /// The barometer can not be read, see `sensor::i2c`
+    BusError,
+}
+
+/// Plausibility checks on barometer readings, so a failing sensor or a
+/// loose connection does not silently feed bad pressure altitude to EFBs
+#[derive(PartialEq, Debug, Default, Copy, Clone)]
+struct BaroHealth {
+    /// Latest reading as pressure in hPa, pressure altitude in ft and when
+    /// it was taken
+    last: Option<(f32, i32, Instant)>,
+    /// Since when the pressure has not changed
+    unchanged_since: Option<Instant>,
+    last_jump: Option<Instant>,
+    /// Latest GNSS MSL altitude in ft
+    gnss: Option<(i32, Instant)>,
+    /// Baro and GNSS altitude at the start of the current trend period
+    trend_start: Option<(i32, i32, Instant)>,
+    disagreement: bool,
+}
+
+impl BaroHealth {
+    fn gnss(&mut self, msl_altitude: i32, clock: Instant) {
+        self.gnss = Some((msl_altitude, clock));
+    }
+
+    /// Check reading `b` taken at `clock`, returns what is wrong with the
+    /// barometer if anything
+    fn check(&mut self, b: &BaroData, clock: Instant) -> Option<BaroFault> {
+        let alt = b.pressure_altitude;
+
+        match self.last {
+            Some((pressure, _, _)) if pressure == b.pressure => {}
+            _ => self.unchanged_since = Some(clock),
+        }
+
+        if let Some((_, last_alt, i)) = self.last {
+            let dt = secs_f32(clock - i);
+
+            if dt > 0.0 && (alt - last_alt).abs() as f32 / dt * 60.0 > MAX_BARO_RATE {
+                self.last_jump = Some(clock);
+            }
+        }
+        self.last = Some((b.pressure, alt, clock));
+
+        match self.gnss {
+            Some((msl, i)) if (clock - i).as_secs() <= MAX_STALE_SECS => match self.trend_start {
+                Some((b0, g0, i)) if (clock - i).as_secs() >= TREND_SECS => {
+                    self.disagreement = ((alt - b0) - (msl - g0)).abs() > MAX_TREND_DIFF;
+                    self.trend_start = Some((alt, msl, clock));
+                }
+                None => self.trend_start = Some((alt, msl, clock)),
+                _ => {}
+            },
+            // the trend can not be judged without GNSS, so there is no
+            // disagreement to hold against the barometer, the only altitude
+            // source left
+            _ => {
+                self.trend_start = None;
+                self.disagreement = false;
+            }
+        }
+
+        if self
+            .last_jump
+            .map_or(false, |i| (clock - i).as_secs() < BARO_RECOVERY_SECS)
+        {
+            Some(BaroFault::Jump)
+        } else if self
+            .unchanged_since
+            .map_or(false, |i| (clock - i).as_secs() >= BARO_STUCK_SECS)
+        {
+            Some(BaroFault::Stuck)
+        } else if self.disagreement {
+            Some(BaroFault::GNSSDisagreement)
+        } else {
+            None
+        }
+    }
+}
 
 #[derive(PartialEq, Debug, Default, Copy, Clone, Serialize)]
 pub struct Ownship {
@@ -29,8 +137,27 @@ pub struct Ownship {
     pub msl_altitude: i32,
     /// Height above WGS-84 ellipsoid in ft
     pub hae_altitude: i32,
-    /// Cabin pressure altitude in ft
+    /// Cabin pressure altitude in ft, `None` while the barometer is not
+    /// trusted
     pub pressure_altitude: Option<i32>,
+    /// Why the barometer is not trusted, if it is not
+    pub baro_fault: Option<BaroFault>,
+    /// Temperature reported by the barometer in Celsius
+    pub baro_temperature: Option<f32>,
+    /// Barometer used for pressure altitude, only one is used
+    /// if multiple are present so readings are not mixed
+    #[serde(skip)]
+    pub baro_sensor: Option<&'static str>,
+    /// Static pressure from the barometer in hPa
+    #[serde(skip)]
+    pub static_pressure: Option<f32>,
+    /// Airspeed sensor the last reading came from
+    #[serde(skip)]
+    pub airspeed_sensor: Option<&'static str>,
+    /// Indicated airspeed in kts
+    pub ias: Option<f32>,
+    /// True airspeed in kts
+    pub tas: Option<f32>,
     /// Vertical speed
     pub vs: Option<i32>,
     /// NIC
@@ -41,6 +168,28 @@ pub struct Ownship {
     pub gs: f32,
     /// True track in degrees
     pub true_track: f32,
+    /// Magnetic track in degrees, if the magnetic declination is known
+    pub magnetic_track: Option<f32>,
+    /// Vertical speed from GNSS in ft/min
+    pub gnss_vs: Option<i32>,
+    /// `None` before the first fix
+    pub fix_quality: Option<FixQuality>,
+    /// Number of satellites used in the fix
+    pub num_sv: u8,
+    /// Horizontal accuracy in millimeters
+    pub h_accuracy: Option<u32>,
+    /// Vertical accuracy in millimeters
+    pub v_accuracy: Option<u32>,
+    /// Seconds since the last GNSS fix, as of this report
+    pub fix_age: Option<f32>,
+    /// Seconds since the last barometer reading, as of this report
+    pub baro_age: Option<f32>,
+    #[serde(skip)]
+    fix_time: Option<Instant>,
+    #[serde(skip)]
+    baro_time: Option<Instant>,
+    #[serde(skip)]
+    baro_health: BaroHealth,
 }
 
 impl Processor for Ownship {
@@ -74,29 +223,94 @@ impl Processor for Ownship {
 
                     self.gs = mmps_to_kts!(f.gs.0);
                     self.true_track = f.true_course.0;
+                    self.magnetic_track = f
+                        .mag_dec
+                        .map(|(dec, _)| (f.true_course.0 - dec + 360.0) % 360.0);
+                    self.gnss_vs = Some((mm_to_ft!(f.vs.0) * 60.0).round() as i32);
+
+                    self.fix_quality = Some(f.quality);
+                    self.num_sv = f.num_sv;
+                    self.h_accuracy = f.lat_lon.1;
+                    self.v_accuracy = f.height_msl.1;
 
                     self.valid = true;
+                    self.fix_time = Some(handle.get_clock());
+                    self.baro_health.gnss(self.msl_altitude, handle.get_clock());
 
-                    handle.push_data(Report::Ownship(*self));
+                    self.report(handle);
                 }
-                SensorData::Baro(b) => {
-                    let dt = 1_f32 / handle.get_frequency() as f32;
-                    let vs_update_pct = 5_f32 / (5_f32 + dt);
-
-                    if let Some(last_pres_alt) = self.pressure_altitude {
-                        self.vs = Some(if let Some(vs) = self.vs {
-                            (vs_update_pct * vs as f32
-                                + (1_f32 - vs_update_pct) * (b - last_pres_alt) as f32
-                                    / (dt / 60_f32))
-                                .round() as i32
-                        } else {
-                            0
-                        });
+                SensorData::Baro(ref b) => {
+                    match self.baro_sensor {
+                        Some(id) if id != b.sensor_id => continue,
+                        None => {
+                            info!("using barometer {} for pressure altitude", b.sensor_id);
+                            self.baro_sensor = Some(b.sensor_id);
+                        }
+                        _ => {}
+                    }
+
+                    let clock = handle.get_clock();
+                    let fault = self.baro_health.check(b, clock);
+
+                    if fault != self.baro_fault {
+                        match fault {
+                            Some(f) => warn!(
+                                "barometer {} failed plausibility checks ({:?}), \
+                                 not using pressure altitude",
+                                b.sensor_id, f
+                            ),
+                            None => info!("barometer {} is plausible again", b.sensor_id),
+                        }
+                        self.baro_fault = fault;
+                    }
+
+                    if fault.is_some() {
+                        self.clear_baro();
+                    } else {
+                        let b_alt = b.pressure_altitude;
+                        let dt = 1_f32 / handle.get_frequency() as f32;
+                        let vs_update_pct = 5_f32 / (5_f32 + dt);
+
+                        if let Some(last_pres_alt) = self.pressure_altitude {
+                            self.vs = Some(if let Some(vs) = self.vs {
+                                (vs_update_pct * vs as f32
+                                    + (1_f32 - vs_update_pct) * (b_alt - last_pres_alt) as f32
+                                        / (dt / 60_f32))
+                                    .round() as i32
+                            } else {
+                                0
+                            });
+                        }
+
+                        self.pressure_altitude = Some(b_alt);
+                        self.static_pressure = Some(b.pressure);
                     }
 
-                    self.pressure_altitude = Some(b);
+                    self.baro_temperature = b.temperature;
+                    self.baro_time = Some(clock);
 
-                    handle.push_data(Report::Ownship(*self));
+                    self.report(handle);
+                }
+                SensorData::Airspeed(ref a) => {
+                    // reported along with the next position or baro update
+                    self.airspeed_sensor = Some(a.sensor_id);
+                    self.ias = Some(a.ias);
+                    self.tas = self.static_pressure.map(|p| tas(a.ias, p));
+                }
+                // recovery is noticed with the next reading
+                SensorData::Degraded(id, true) if Some(id) == self.baro_sensor => {
+                    warn!(
+                        "barometer {} can not be read, not using pressure altitude",
+                        id
+                    );
+                    self.baro_fault = Some(BaroFault::BusError);
+                    self.clear_baro();
+
+                    self.report(handle);
+                }
+                SensorData::Degraded(id, true) if Some(id) == self.airspeed_sensor => {
+                    self.ias = None;
+                    self.tas = None;
                 }
                 _ => {} // do nothing
             }
@@ -108,4 +322,235 @@ impl Ownship {
     pub fn new() -> Box<Processor> {
         Box::new(Ownship::default())
     }
+
+    /// When the fix this report is based on was received
+    pub fn fix_time(&self) -> Option<Instant> {
+        self.fix_time
+    }
+
+    /// Forget everything derived from the barometer while it is not trusted
+    fn clear_baro(&mut self) {
+        self.pressure_altitude = None;
+        self.static_pressure = None;
+        self.vs = None;
+        self.tas = None;
+    }
+
+    /// Push the current state, with data ages as of now
+    fn report(&mut self, handle: &mut Pushable<Report>) {
+        let clock = handle.get_clock();
+        self.fix_age = self.fix_time.map(|t| secs_f32(clock - t));
+        self.baro_age = self.baro_time.map(|t| secs_f32(clock - t));
+
+        handle.push_data(Report::Ownship(*self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Handle;
+    use sensor::gnss::Fix;
+    use testing::{run_processor, MockHandle};
+
+    #[test]
+    fn test_fix_metadata() {
+        let mut o = Ownship::default();
+        let mut h = MockHandle::new(10);
+
+        let fix = SensorData::GNSS(GNSSData::TimeFix {
+            time: None,
+            fix: Some(Fix {
+                quality: FixQuality::SBAS,
+                num_sv: 9,
+                lat_lon: ((37.5, -122.0), Some(2500)),
+                height_msl: (914400, Some(4000)),
+                height_ellipsoid: (914400, Some(4000)),
+                gs: (51444, None),
+                vs: (2540, None),
+                true_course: (5.0, None),
+                mag_dec: Some((13.0, None)),
+            }),
+        });
+        run_processor(&mut o, &mut h, &[fix]);
+
+        match h.take()[0] {
+            Report::Ownship(ref r) => {
+                assert_eq!(r.magnetic_track, Some(352.0));
+                assert_eq!(r.gnss_vs, Some(500));
+                assert_eq!(r.fix_quality, Some(FixQuality::SBAS));
+                assert_eq!(r.num_sv, 9);
+                assert_eq!(r.h_accuracy, Some(2500));
+                assert_eq!(r.v_accuracy, Some(4000));
+                assert_eq!(r.fix_age, Some(0.0));
+                assert_eq!(r.baro_age, None);
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+
+        h.advance_secs(2);
+        let baro = SensorData::Baro(BaroData {
+            sensor_id: "test",
+            pressure: 908.1,
+            pressure_altitude: 3000,
+            temperature: None,
+            humidity: None,
+        });
+        run_processor(&mut o, &mut h, &[baro]);
+
+        match h.take()[0] {
+            Report::Ownship(ref r) => {
+                assert_eq!(r.fix_age, Some(2.0));
+                assert_eq!(r.baro_age, Some(0.0));
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_baro_health() {
+        let baro = |pressure: f32, pressure_altitude: i32| BaroData {
+            sensor_id: "test",
+            pressure,
+            pressure_altitude,
+            temperature: None,
+            humidity: None,
+        };
+        let mut health = BaroHealth::default();
+        let mut h = MockHandle::<Report>::new(10);
+
+        // a 3000 ft jump within a second
+        assert_eq!(health.check(&baro(908.1, 3000), h.get_clock()), None);
+        h.advance_secs(1);
+        assert_eq!(
+            health.check(&baro(1013.2, 0), h.get_clock()),
+            Some(BaroFault::Jump)
+        );
+        h.advance_secs(BARO_RECOVERY_SECS);
+        assert_eq!(health.check(&baro(1013.3, 0), h.get_clock()), None);
+
+        // stuck at the same pressure
+        h.advance_secs(BARO_STUCK_SECS);
+        assert_eq!(
+            health.check(&baro(1013.3, 0), h.get_clock()),
+            Some(BaroFault::Stuck)
+        );
+        h.advance_secs(1);
+        assert_eq!(health.check(&baro(1013.2, 0), h.get_clock()), None);
+
+        // GNSS climbs 1000 ft while the barometer stays level
+        health.gnss(5000, h.get_clock());
+        assert_eq!(health.check(&baro(1013.3, 0), h.get_clock()), None);
+        h.advance_secs(TREND_SECS);
+        health.gnss(6000, h.get_clock());
+        assert_eq!(
+            health.check(&baro(1013.2, 0), h.get_clock()),
+            Some(BaroFault::GNSSDisagreement)
+        );
+        h.advance_secs(TREND_SECS);
+        health.gnss(6000, h.get_clock());
+        assert_eq!(health.check(&baro(1013.3, 0), h.get_clock()), None);
+    }
+
+    #[test]
+    fn test_baro_health_gnss_lost() {
+        let baro = |pressure: f32| BaroData {
+            sensor_id: "test",
+            pressure,
+            pressure_altitude: 0,
+            temperature: None,
+            humidity: None,
+        };
+        let mut health = BaroHealth::default();
+        let mut h = MockHandle::<Report>::new(10);
+
+        health.gnss(5000, h.get_clock());
+        assert_eq!(health.check(&baro(1013.3), h.get_clock()), None);
+        h.advance_secs(TREND_SECS);
+        health.gnss(6000, h.get_clock());
+        assert_eq!(
+            health.check(&baro(1013.2), h.get_clock()),
+            Some(BaroFault::GNSSDisagreement)
+        );
+
+        // without GNSS the barometer is all there is
+        h.advance_secs(MAX_STALE_SECS + 1);
+        assert_eq!(health.check(&baro(1013.3), h.get_clock()), None);
+    }
+
+    #[test]
+    fn test_baro_implausible() {
+        let mut o = Ownship::default();
+        let mut h = MockHandle::new(10);
+
+        let baro = |pressure: f32, pressure_altitude: i32| {
+            SensorData::Baro(BaroData {
+                sensor_id: "test",
+                pressure,
+                pressure_altitude,
+                temperature: None,
+                humidity: None,
+            })
+        };
+        run_processor(&mut o, &mut h, &[baro(908.1, 3000)]);
+        h.advance_secs(1);
+        run_processor(&mut o, &mut h, &[baro(908.2, 3000)]);
+        match h.take()[1] {
+            Report::Ownship(ref r) => {
+                assert_eq!(r.pressure_altitude, Some(3000));
+                assert!(r.vs.is_some());
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+
+        // a 3000 ft jump within a second
+        h.advance_secs(1);
+        run_processor(&mut o, &mut h, &[baro(1013.2, 0)]);
+        match h.take()[0] {
+            Report::Ownship(ref r) => {
+                assert_eq!(r.baro_fault, Some(BaroFault::Jump));
+                assert_eq!(r.pressure_altitude, None);
+                assert_eq!(r.vs, None);
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+
+        h.advance_secs(BARO_RECOVERY_SECS);
+        run_processor(&mut o, &mut h, &[baro(1013.3, 0)]);
+        match h.take()[0] {
+            Report::Ownship(ref r) => {
+                assert_eq!(r.baro_fault, None);
+                assert_eq!(r.pressure_altitude, Some(0));
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_baro_bus_error() {
+        let mut o = Ownship::default();
+        let mut h = MockHandle::new(10);
+
+        let baro = SensorData::Baro(BaroData {
+            sensor_id: "test",
+            pressure: 908.1,
+            pressure_altitude: 3000,
+            temperature: None,
+            humidity: None,
+        });
+        run_processor(&mut o, &mut h, &[baro]);
+        h.take();
+
+        run_processor(&mut o, &mut h, &[SensorData::Degraded("other", true)]);
+        assert!(h.take().is_empty());
+
+        run_processor(&mut o, &mut h, &[SensorData::Degraded("test", true)]);
+        match h.take()[0] {
+            Report::Ownship(ref r) => {
+                assert_eq!(r.baro_fault, Some(BaroFault::BusError));
+                assert_eq!(r.pressure_altitude, None);
+            }
+            ref r => panic!("unexpected {:?}", r),
+        }
+    }
 }
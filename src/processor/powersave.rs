@@ -0,0 +1,244 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Saves battery while nobody is looking: once there have been no GDL90,
+//! WebSocket or Server-Sent Events clients for `power_save` seconds and
+//! ownship is not moving, the SDR receivers are paused and the GNSS
+//! receiver lowers its update rate. Both are back as soon as a client shows
+//! up or ownship starts moving.
+
+use super::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use transport::SharedStatus;
+
+/// Ownship is moving above this ground speed, in kts
+const MOVING_KTS: f32 = 5.0;
+
+pub struct PowerSave {
+    after: Option<Duration>,
+    uat: bool,
+    transport: SharedStatus,
+    /// shared with the GNSS receiver, which lowers its update rate while set
+    gnss_low_rate: Arc<AtomicBool>,
+    moving: bool,
+    /// since when there have been no clients while not moving
+    quiet_since: Option<Instant>,
+    idle: bool,
+}
+
+impl PowerSave {
+    pub fn new(
+        config: &Config,
+        transport: SharedStatus,
+        gnss_low_rate: Arc<AtomicBool>,
+    ) -> Box<Processor> {
+        Box::new(PowerSave {
+            after: config.power_save.map(Duration::from_secs),
+            uat: config.uat,
+            transport,
+            gnss_low_rate,
+            moving: false,
+            quiet_since: None,
+            idle: false,
+        })
+    }
+
+    fn has_clients(&self) -> bool {
+        let status = self.transport.lock().unwrap();
+
+        status.web_clients > 0
+            || status
+                .clients
+                .values()
+                .any(|c| c.active && c.dropped.is_none())
+    }
+
+    /// Pause or resume the SDRs, UAT only if it is enabled
+    fn set_idle(&mut self, handle: &mut Pushable<Report>, idle: bool) {
+        if idle == self.idle {
+            return;
+        }
+        self.idle = idle;
+
+        if idle {
+            info!("no clients and not moving, idling receivers");
+        } else {
+            info!("leaving power save");
+        }
+
+        self.gnss_low_rate.store(idle, Ordering::Relaxed);
+        for &(sdr, enabled) in &[("es", true), ("uat", self.uat)] {
+            if enabled {
+                handle.push_data(Report::Lifecycle {
+                    component: sdr.to_string(),
+                    paused: idle,
+                });
+            }
+        }
+    }
+}
+
+impl Processor for PowerSave {
+    fn run(&mut self, _handle: &mut Pushable<Report>, i: ChainedIter) {
+        for e in i {
+            if let SensorData::Config(ref c) = *e {
+                self.after = c.power_save.map(Duration::from_secs);
+                self.uat = c.uat;
+            }
+        }
+    }
+
+    fn run_reports(&mut self, handle: &mut Pushable<Report>, reports: &[&Report]) {
+        let clock = handle.get_clock();
+
+        for r in reports {
+            if let Report::Ownship(ref o) = **r {
+                self.moving = o.valid && o.gs > MOVING_KTS;
+            }
+        }
+
+        let after = match self.after {
+            Some(a) if !self.moving && !self.has_clients() => a,
+            _ => {
+                self.quiet_since = None;
+                self.set_idle(handle, false);
+                return;
+            }
+        };
+
+        let since = *self.quiet_since.get_or_insert(clock);
+        if clock - since >= after {
+            self.set_idle(handle, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::ownship::Ownship;
+    use testing::*;
+    use transport::{ClientStatus, Profile};
+
+    fn power_save(status: &SharedStatus, low_rate: &Arc<AtomicBool>) -> Box<Processor> {
+        let config = Config {
+            power_save: Some(60),
+            ..Config::default()
+        };
+
+        PowerSave::new(&config, status.clone(), low_rate.clone())
+    }
+
+    fn paused(reports: &[Report]) -> Vec<(String, bool)> {
+        reports
+            .iter()
+            .filter_map(|r| match *r {
+                Report::Lifecycle {
+                    ref component,
+                    paused,
+                } => Some((component.clone(), paused)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_power_save() {
+        let status = SharedStatus::default();
+        let low_rate = Arc::new(AtomicBool::new(false));
+        let mut p = power_save(&status, &low_rate);
+        let mut h = MockHandle::new(10);
+        let ownship = |gs| {
+            let mut o = Ownship::default();
+            o.valid = true;
+            o.gs = gs;
+            Report::Ownship(o)
+        };
+
+        run_reports(&mut *p, &mut h, &[ownship(0.0)]);
+        h.advance_secs(59);
+        run_reports(&mut *p, &mut h, &[]);
+        assert!(h.take().is_empty());
+
+        h.advance_secs(1);
+        run_reports(&mut *p, &mut h, &[]);
+        assert_eq!(
+            paused(&h.take()),
+            vec![("es".to_string(), true), ("uat".to_string(), true)]
+        );
+        assert!(low_rate.load(Ordering::Relaxed));
+
+        // taxiing
+        run_reports(&mut *p, &mut h, &[ownship(12.0)]);
+        assert_eq!(
+            paused(&h.take()),
+            vec![("es".to_string(), false), ("uat".to_string(), false)]
+        );
+        assert!(!low_rate.load(Ordering::Relaxed));
+
+        // stopped again, but a client shows up before long
+        run_reports(&mut *p, &mut h, &[ownship(0.0)]);
+        h.advance_secs(30);
+        status.lock().unwrap().clients.insert(
+            "192.168.10.5".parse().unwrap(),
+            ClientStatus {
+                app: None,
+                profile: Profile::Generic,
+                ports: vec![4000],
+                active: true,
+                in_app: false,
+                connected: 0,
+                last_seen: 0.0,
+                rtt: None,
+                bytes_sent: 0,
+                replays: 0,
+                history: Vec::new(),
+                send_errors: Default::default(),
+                dropped: None,
+            },
+        );
+        h.advance_secs(60);
+        run_reports(&mut *p, &mut h, &[]);
+        assert!(h.take().is_empty());
+        assert!(!low_rate.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_web_clients() {
+        let status = SharedStatus::default();
+        let low_rate = Arc::new(AtomicBool::new(false));
+        let mut p = power_save(&status, &low_rate);
+        let mut h = MockHandle::new(10);
+
+        // the web UI is open
+        status.lock().unwrap().web_clients = 1;
+        run_reports(&mut *p, &mut h, &[]);
+        h.advance_secs(120);
+        run_reports(&mut *p, &mut h, &[]);
+        assert!(h.take().is_empty());
+
+        status.lock().unwrap().web_clients = 0;
+        run_reports(&mut *p, &mut h, &[]);
+        h.advance_secs(60);
+        run_reports(&mut *p, &mut h, &[]);
+        assert_eq!(
+            paused(&h.take()),
+            vec![("es".to_string(), true), ("uat".to_string(), true)]
+        );
+    }
+}
@@ -0,0 +1,52 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Passes decode statistics of the SDR receivers on to clients.
+
+use super::*;
+use processor::traffic::TrafficSource;
+
+pub struct Receiver;
+
+impl Receiver {
+    pub fn new() -> Box<Processor> {
+        Box::new(Receiver)
+    }
+}
+
+impl Processor for Receiver {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        for e in i {
+            if let SensorData::DecodeStats(ref s) = *e {
+                debug!(
+                    "{} decoded {} downlink and {} uplink frames in {} seconds, \
+                     {} needed error correction ({} bytes corrected)",
+                    match s.band {
+                        TrafficSource::UAT => "UAT",
+                        TrafficSource::ES => "1090ES",
+                    },
+                    s.downlink_frames,
+                    s.uplink_frames,
+                    s.interval,
+                    s.corrected_frames,
+                    s.corrected_errors
+                );
+
+                handle.push_data(Report::DecodeStats(s.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reboots or powers off the system, either on request of an authorized
+//! client or when the supply voltage has been too low for too long. Also
+//! lets authorized clients change log levels and pause or resume pipeline
+//! components, and pauses UAT reception when it is disabled in the config.
+
+use super::*;
+#[cfg(unix)]
+use libc::sync;
+use logging::LogControl;
+use sensor::command::Command;
+use std::io::{self, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+/// Components that can not be paused, as nothing could resume them
+const ESSENTIAL: [&str; 2] = ["commands", "system"];
+/// Wait before powering off for low voltage again after `systemctl` could
+/// not be started
+const RETRY_SECS: u64 = 60;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Action {
+    Reboot,
+    PowerOff,
+}
+
+pub struct System {
+    /// Token clients must present, power commands are refused if unset
+    admin_token: Option<String>,
+    /// Power off after being undervoltage for this many seconds
+    low_voltage_shutdown: Option<u64>,
+    uat: bool,
+    undervoltage_since: Option<Instant>,
+    /// Set once an action has been started so it is not run twice
+    pending: bool,
+    /// When the low voltage power off may be tried again after it failed
+    retry_at: Option<Instant>,
+    /// Running logger, absent when not installed (e.g. in tests)
+    log: Option<LogControl>,
+}
+
+impl System {
+    pub fn new(config: &Config, log: LogControl) -> Box<Processor> {
+        Box::new(Self {
+            admin_token: config.admin_token.clone(),
+            low_voltage_shutdown: config.low_voltage_shutdown,
+            uat: config.uat,
+            undervoltage_since: None,
+            pending: false,
+            retry_at: None,
+            log: Some(log),
+        })
+    }
+
+    fn authorized(&self, token: &str) -> bool {
+        match self.admin_token {
+            Some(ref t) => constant_time_eq(t.as_bytes(), token.as_bytes()),
+            None => false,
+        }
+    }
+
+    fn execute(&mut self, action: Action, clock: Instant) {
+        if self.pending {
+            return;
+        }
+        self.pending = true;
+
+        info!("{:?} requested, syncing file systems", action);
+
+        io::stdout().flush().ok();
+        io::stderr().flush().ok();
+        #[cfg(unix)]
+        unsafe {
+            sync();
+        }
+
+        let arg = match action {
+            Action::Reboot => "reboot",
+            Action::PowerOff => "poweroff",
+        };
+
+        if let Err(e) = process::Command::new("systemctl").arg(arg).spawn() {
+            error!("unable to {}: {}", arg, e);
+            self.pending = false;
+            self.retry_at = Some(clock + Duration::from_secs(RETRY_SECS));
+        }
+    }
+}
+
+impl Processor for System {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::Command(Command::Reboot { ref token }) => {
+                    if self.authorized(token) {
+                        self.execute(Action::Reboot, clock);
+                    } else {
+                        warn!("refusing unauthorized reboot command");
+                    }
+                }
+                SensorData::Command(Command::PowerOff { ref token }) => {
+                    if self.authorized(token) {
+                        self.execute(Action::PowerOff, clock);
+                    } else {
+                        warn!("refusing unauthorized power off command");
+                    }
+                }
+                SensorData::Command(Command::LogLevel {
+                    ref token,
+                    ref module,
+                    ref level,
+                }) => {
+                    if !self.authorized(token) {
+                        warn!("refusing unauthorized log level command");
+                        continue;
+                    }
+
+                    match level.parse() {
+                        Ok(l) => {
+                            let module = module.as_ref().map(|m| m.as_str());
+                            info!(
+                                "setting log level of {} to {}",
+                                module.unwrap_or("all modules"),
+                                l
+                            );
+
+                            if let Some(ref log) = self.log {
+                                log.set_level(module, l);
+                            }
+                        }
+                        Err(_) => warn!("invalid log level {}", level),
+                    }
+                }
+                SensorData::Command(Command::Pause {
+                    ref token,
+                    ref component,
+                }) => {
+                    if !self.authorized(token) {
+                        warn!("refusing unauthorized pause command");
+                    } else if ESSENTIAL.contains(&component.as_str()) {
+                        warn!("refusing to pause {}", component);
+                    } else {
+                        handle.push_data(Report::Lifecycle {
+                            component: component.clone(),
+                            paused: true,
+                        });
+                    }
+                }
+                SensorData::Command(Command::Resume {
+                    ref token,
+                    ref component,
+                }) => {
+                    if self.authorized(token) {
+                        handle.push_data(Report::Lifecycle {
+                            component: component.clone(),
+                            paused: false,
+                        });
+                    } else {
+                        warn!("refusing unauthorized resume command");
+                    }
+                }
+                SensorData::Undervoltage(true) => {
+                    if self.undervoltage_since.is_none() {
+                        warn!("undervoltage detected");
+                        self.undervoltage_since = Some(clock);
+                    }
+                }
+                SensorData::Config(ref c) => {
+                    self.admin_token = c.admin_token.clone();
+                    self.low_voltage_shutdown = c.low_voltage_shutdown;
+
+                    if c.uat != self.uat {
+                        self.uat = c.uat;
+                        handle.push_data(Report::Lifecycle {
+                            component: "uat".to_string(),
+                            paused: !c.uat,
+                        });
+                    }
+
+                    if let Some(ref log) = self.log {
+                        log.set_syslog(c.syslog);
+                    }
+                }
+                SensorData::Undervoltage(false) => {
+                    if self.undervoltage_since.take().is_some() {
+                        info!("supply voltage recovered");
+                    }
+                }
+                _ => {} // do nothing
+            }
+        }
+
+        if let (Some(since), Some(limit)) = (self.undervoltage_since, self.low_voltage_shutdown) {
+            if (clock - since).as_secs() >= limit && self.retry_at.map_or(true, |r| clock >= r) {
+                warn!("undervoltage for {} seconds, powering off", limit);
+                self.execute(Action::PowerOff, clock);
+            }
+        }
+    }
+}
+
+/// Compares without returning early, so the time taken does not tell how
+/// much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorized() {
+        let s = System {
+            admin_token: Some("secret".to_string()),
+            low_voltage_shutdown: None,
+            uat: true,
+            undervoltage_since: None,
+            pending: false,
+            retry_at: None,
+            log: None,
+        };
+        assert!(s.authorized("secret"));
+        assert!(!s.authorized("Secret"));
+        assert!(!s.authorized(""));
+        assert!(!s.authorized("secret2"));
+
+        let s = System {
+            admin_token: None,
+            ..s
+        };
+        assert!(!s.authorized(""));
+    }
+}
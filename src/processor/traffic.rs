@@ -17,28 +17,95 @@
 //! Maintains the traffic situation around us.
 
 use super::*;
+use config::{Peer, Squawk, SquawkKind};
+use sensor::gnss::GNSSData;
+use sensor::sdr::{Capability, TrafficData};
 use sensor::SensorData;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::time::Instant;
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use utils::{bearing_deg, distance_nm, secs_f32};
 
 const CLEANUP_FREQ: f32 = 0.1;
 const MAX_STALE_SECS: u64 = 60;
+// ground stations see traffic from much farther away, and keep it longer
+// while it is out of reception
+const GROUND_STATION_STALE_SECS: u64 = 300;
+// the stalest target is forgotten to make room beyond this
+const GROUND_STATION_CAPACITY: usize = 1000;
 const REPORT_FREQ: u16 = 1;
 const LIMITED_ALPHABET: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ";
 // lock out TIS-B and ADS-R updates if ADS-B data is less than
 // ADS_B_LOCKOUT_INTERVAL seconds old
 const ADS_B_LOCKOUT_INTERVAL: u64 = 2;
 const FRESHNESS_DELAY: u64 = 6;
+// max number of distinct targets allowed to share one address
+const MAX_TRACKS_PER_ADDRESS: u8 = 4;
+// fastest ground speed considered plausible, in kts
+const MAX_PLAUSIBLE_SPEED: f32 = 1200.0;
+// position jump always tolerated regardless of elapsed time, in NM
+const POSITION_JUMP_SLACK: f32 = 2.0;
+// an anonymous track silent for longer than this is never re-associated
+const ANONYMOUS_HANDOFF_SECS: u64 = 5;
+// gates for deciding a new anonymous address is an existing track
+const ANONYMOUS_GATE_NM: f32 = 0.5;
+const ANONYMOUS_MAX_ALT_DIFF: i32 = 300;
+const ANONYMOUS_MAX_HDG_DIFF: i32 = 30;
+const ANONYMOUS_MAX_SPD_DIFF: i32 = 30;
+// IDENT is shown for this long after it was last seen, a transponder
+// keeps it on for about 18 seconds
+const IDENT_SECS: u64 = 20;
+// DO-282B emitter categories given to surface targets that do not report one
+const CATEGORY_SURFACE_SERVICE: u8 = 18;
+const CATEGORY_POINT_OBSTACLE: u8 = 19;
+// ownship position older than this is not used for range and bearing
+const OWNSHIP_STALE_SECS: u64 = 5;
+// an update repeating one of this many ms ago is the copy of a frame both
+// paired units received, see `sensor::peer`
+const DUPLICATE_MS: u64 = 1000;
+// callsigns are up to 8 characters, both on UAT and ES
+const MAX_CALLSIGN_LEN: usize = 8;
+// hijack, radio failure and general emergency, recognized in every region
+const EMERGENCY_SQUAWKS: [u16; 3] = [7500, 7600, 7700];
 
 pub struct Traffic {
-    situation: HashMap<u32, Target>,
+    /// shared with the reports pushed every second, so reporting a target
+    /// does not copy it
+    situation: HashMap<TrackKey, Arc<Target>>,
+    /// number of times an address was found being used by more than one target
+    duplicates: u32,
+    /// whether this unit aggregates what a paired unit forwards, only then
+    /// are repeated updates dropped
+    aggregator: bool,
+    /// fingerprints of recent updates by address and when they were
+    /// received, to drop repeated ones
+    recent: HashMap<u32, Vec<(u64, Instant)>>,
+    /// number of repeated updates dropped
+    repeated: u32,
+    /// our own transponder, never reported as traffic
+    ownship_icao: Option<u32>,
+    /// configured special squawk codes
+    squawks: Vec<Squawk>,
+    /// targets not seen for this many seconds are forgotten
+    max_stale: u64,
+    /// most targets tracked at once, if limited
+    max_targets: Option<usize>,
+    /// last GNSS position of ownship and when it was received
+    ownship: Option<((f32, f32), Instant)>,
     cleanup_counter: u32,
     report_counter: u32,
 }
 
 type Address = (u32, AddressType);
+/// `(address, sub_id)`, `sub_id` is non-zero only when the address is shared by
+/// multiple targets at the same time
+type TrackKey = (u32, u8);
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum AddressType {
     ADSBICAO,
     ADSBOther,
@@ -46,29 +113,32 @@ pub enum AddressType {
     ADSROther,
     TISBICAO,
     TISBOther,
+    SurfaceVehicle,
+    /// Fixed ADS-B beacon, e.g. marking an obstacle
+    FixedBeacon,
     Unknown,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum SpeedType {
     GS,
     IAS,
     TAS,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum AltitudeType {
     Baro,
     GNSS,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum HeadingType {
     True,
     Mag,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum TrafficSource {
     UAT,
     ES,
@@ -82,20 +152,42 @@ pub enum TrafficSource {
 #[derive(Debug, Clone)]
 pub struct Target {
     pub addr: Address,
+    /// 0 unless another target is using the same address
+    pub sub_id: u8,
     pub altitude: Option<(i32, AltitudeType, Instant)>,
     pub gnss_delta: Option<i32>,
     pub heading: Option<(u16, HeadingType, Instant)>,
     pub speed: Option<(u16, SpeedType, Instant)>,
     pub vs: Option<(i16, Instant)>,
     pub squawk: Option<u16>,
+    /// What the squawk code stands for, if it is a special one
+    pub squawk_kind: Option<SquawkKind>,
+    /// Squawking a code configured not to raise traffic alerts
+    pub quiet: bool,
     pub callsign: Option<String>,
     pub category: Option<u8>,
     pub lat_lon: Option<((f32, f32), Instant)>,
     pub nic: Option<u8>,
     pub nacp: Option<u8>,
+    pub nacv: Option<u8>,
+    pub sil: Option<u8>,
+    /// When IDENT was last seen, `None` once it is over
+    pub ident: Option<Instant>,
+    /// Equipment the target reports having, UAT targets only
+    pub capability: Option<Capability>,
     pub on_ground: Option<bool>,
+    /// distance from ownship in NM, as of the last report
+    pub range: Option<f32>,
+    /// bearing from ownship in degrees true, as of the last report
+    pub bearing: Option<f32>,
     pub last_seen: Instant,
     pub source: TrafficSource,
+    /// When the newest frame not reported yet was decoded, for measuring
+    /// latency
+    pub received: Option<Instant>,
+    /// ID the pipeline trace gave the latest message the target was
+    /// updated from, see `pitot::trace`
+    pub trace: Option<u64>,
 }
 
 impl Target {
@@ -107,20 +199,42 @@ impl Target {
     ) -> Self {
         Target {
             addr,
+            sub_id: 0,
             altitude: None,
             gnss_delta: None,
             heading: None,
             speed: None,
             vs: None,
             squawk: None,
+            squawk_kind: None,
+            quiet: false,
             callsign,
             category: None,
             lat_lon: None,
             nic: None,
             nacp: None,
+            nacv: None,
+            sil: None,
+            ident: None,
+            capability: None,
             on_ground: None,
+            range: None,
+            bearing: None,
             last_seen: clock,
             source: source,
+            received: None,
+            trace: None,
+        }
+    }
+
+    /// Surface vehicles and obstacles, by address qualifier or emitter category
+    fn is_surface(&self) -> bool {
+        match self.addr.1 {
+            AddressType::SurfaceVehicle | AddressType::FixedBeacon => true,
+            _ => match self.category {
+                Some(17...21) => true,
+                _ => false,
+            },
         }
     }
 
@@ -153,17 +267,232 @@ impl Target {
 
         false
     }
+
+    /// Could this target have moved to `lat_lon` by `now`?
+    fn can_reach(&self, lat_lon: (f32, f32), now: Instant) -> bool {
+        if let Some((ll, i)) = self.lat_lon {
+            let elapsed = now - i;
+
+            if elapsed.as_secs() <= FRESHNESS_DELAY {
+                return distance_nm(ll, lat_lon)
+                    <= POSITION_JUMP_SLACK + MAX_PLAUSIBLE_SPEED * secs_f32(elapsed) / 3600.0;
+            }
+        }
+
+        // no recent position to compare against
+        true
+    }
+
+    /// If `t` looks like this target continuing under a new anonymous address,
+    /// return the distance between the two in NM.
+    fn continuity_distance(&self, t: &TrafficData, now: Instant) -> Option<f32> {
+        let (ll, i) = match self.lat_lon {
+            Some(ll) => ll,
+            None => return None,
+        };
+        let new_ll = match t.lat_lon {
+            Some(ll) => ll,
+            None => return None,
+        };
+
+        let elapsed = now - i;
+        if elapsed.as_secs() > ANONYMOUS_HANDOFF_SECS {
+            return None;
+        }
+
+        if let (Some((a, _, _)), Some((b, _))) = (self.altitude, t.altitude) {
+            if (a - b).abs() > ANONYMOUS_MAX_ALT_DIFF {
+                return None;
+            }
+        }
+
+        if let (Some((a, _, _)), Some((b, _))) = (self.heading, t.heading) {
+            let diff = (a as i32 - b as i32).abs() % 360;
+            if diff.min(360 - diff) > ANONYMOUS_MAX_HDG_DIFF {
+                return None;
+            }
+        }
+
+        let spd = match (self.speed, t.speed) {
+            (Some((a, _, _)), Some((b, _))) => {
+                if (a as i32 - b as i32).abs() > ANONYMOUS_MAX_SPD_DIFF {
+                    return None;
+                }
+
+                a.max(b) as f32
+            }
+            (Some((a, _, _)), None) => a as f32,
+            (None, Some((b, _))) => b as f32,
+            (None, None) => MAX_PLAUSIBLE_SPEED,
+        };
+
+        let d = distance_nm(ll, new_ll);
+        if d <= ANONYMOUS_GATE_NM + spd * secs_f32(elapsed) / 3600.0 {
+            Some(d)
+        } else {
+            None
+        }
+    }
+}
+
+/// Only the latest values are serialized, without their types and timestamps
+impl Serialize for Target {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = try!(serializer.serialize_struct("Target", 20));
+        try!(s.serialize_field("addr", &self.addr.0));
+        try!(s.serialize_field("callsign", &self.callsign));
+        try!(s.serialize_field("lat_lon", &self.lat_lon.map(|l| l.0)));
+        try!(s.serialize_field("altitude", &self.altitude.map(|a| a.0)));
+        try!(s.serialize_field("heading", &self.heading.map(|h| h.0)));
+        try!(s.serialize_field("speed", &self.speed.map(|s| s.0)));
+        try!(s.serialize_field("vs", &self.vs.map(|v| v.0)));
+        try!(s.serialize_field("squawk", &self.squawk));
+        try!(s.serialize_field("squawk_kind", &self.squawk_kind));
+        try!(s.serialize_field("on_ground", &self.on_ground));
+        try!(s.serialize_field("nic", &self.nic));
+        try!(s.serialize_field("containment_radius", &self.nic.and_then(containment_radius)));
+        try!(s.serialize_field("nacp", &self.nacp));
+        try!(s.serialize_field("nacv", &self.nacv));
+        try!(s.serialize_field("sil", &self.sil));
+        try!(s.serialize_field("ident", &self.ident.is_some()));
+        try!(s.serialize_field("cdti", &self.capability.map(|c| c.cdti)));
+        try!(s.serialize_field("tcas", &self.capability.map(|c| c.tcas)));
+        try!(s.serialize_field("range", &self.range));
+        try!(s.serialize_field("bearing", &self.bearing));
+        s.end()
+    }
 }
 
 impl Traffic {
-    pub fn new() -> Self {
-        // 100 should be a good start
+    pub fn new(config: &Config) -> Self {
+        let (capacity, max_stale, max_targets) = if config.ground_station {
+            (
+                GROUND_STATION_CAPACITY,
+                GROUND_STATION_STALE_SECS,
+                Some(GROUND_STATION_CAPACITY),
+            )
+        } else {
+            // 100 should be a good start
+            (100, MAX_STALE_SECS, None)
+        };
+
         Self {
-            situation: HashMap::with_capacity(100),
+            situation: HashMap::with_capacity(capacity),
+            duplicates: 0,
+            aggregator: match config.peer {
+                Some(Peer::Aggregator { .. }) => true,
+                _ => false,
+            },
+            recent: HashMap::new(),
+            repeated: 0,
+            ownship_icao: config.ownship_icao,
+            squawks: config.squawks.clone(),
+            max_stale,
+            max_targets,
+            ownship: None,
             cleanup_counter: 0,
             report_counter: 0,
         }
     }
+
+    /// Whether `t` repeats an update of the last `DUPLICATE_MS`, which is
+    /// what happens when a paired unit forwards a frame this one received
+    /// too. Frames received twice arrive in quick succession, often with
+    /// fresher ones of the other unit in between, so they would move the
+    /// target back.
+    fn is_repeated(&mut self, t: &TrafficData, clock: Instant) -> bool {
+        let f = fingerprint(t);
+
+        let recent = self.recent.entry(t.addr.0).or_insert_with(Vec::new);
+        recent.retain(|&(_, i)| clock - i < Duration::from_millis(DUPLICATE_MS));
+
+        if recent.iter().any(|&(r, _)| r == f) {
+            self.repeated += 1;
+            return true;
+        }
+
+        recent.push((f, clock));
+        false
+    }
+
+    /// Find the track an update belongs to. Normally each address has exactly
+    /// one track, but if the reported position is impossible to reach from
+    /// the existing track, another target is likely using the same address
+    /// (spoofing or misconfigured transponder), split it into a new track
+    /// instead of letting the two merge into one teleporting target.
+    fn match_track(&self, t: &TrafficData, clock: Instant) -> TrackKey {
+        let lat_lon = match t.lat_lon {
+            Some(ll) => ll,
+            None => return (t.addr.0, 0), // can not tell, assume the primary track
+        };
+
+        let mut free = None;
+
+        for sub_id in 0..MAX_TRACKS_PER_ADDRESS {
+            match self.situation.get(&(t.addr.0, sub_id)) {
+                Some(trfc) => {
+                    if trfc.can_reach(lat_lon, clock) {
+                        return (t.addr.0, sub_id);
+                    }
+                }
+                None => {
+                    if free.is_none() {
+                        free = Some(sub_id);
+                    }
+                }
+            }
+        }
+
+        (t.addr.0, free.unwrap_or(0))
+    }
+
+    /// Forget the stalest target if tracking one more would exceed
+    /// `max_targets`
+    fn make_room(&mut self) {
+        if self.max_targets.map_or(true, |m| self.situation.len() < m) {
+            return;
+        }
+
+        let stalest = self
+            .situation
+            .iter()
+            .min_by_key(|&(_, v)| v.last_seen)
+            .map(|(k, _)| *k);
+        if let Some(k) = stalest {
+            debug!("tracking too many targets, forgetting {:06X}", k.0);
+            self.situation.remove(&k);
+        }
+    }
+
+    /// UAT targets using anonymous addresses pick a new random address
+    /// periodically. When an unknown anonymous address shows up right where
+    /// an existing anonymous track is, move that track over to the new address
+    /// instead of leaving a stale duplicate behind until it times out.
+    fn reassociate_anonymous(&mut self, t: &TrafficData, clock: Instant) {
+        let old = self.situation
+            .iter()
+            .filter(|&(k, v)| {
+                k.0 != t.addr.0 && k.1 == 0 && v.addr.1 == AddressType::ADSBOther
+                    && v.source == TrafficSource::UAT
+            })
+            .filter_map(|(k, v)| v.continuity_distance(t, clock).map(|d| (*k, d)))
+            .filter(|&(_, d)| d.is_finite())
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        if let Some((key, _)) = old {
+            debug!(
+                "anonymous address {:06X} continues as {:06X}",
+                key.0, t.addr.0
+            );
+
+            let mut trfc = self.situation.remove(&key).unwrap();
+            Arc::make_mut(&mut trfc).addr = t.addr;
+            self.situation.insert((t.addr.0, 0), trfc);
+        }
+    }
 }
 
 impl Processor for Traffic {
@@ -174,15 +503,50 @@ impl Processor for Traffic {
             // only interested in traffic updates
             match *e {
                 SensorData::Traffic(ref t) => {
+                    if Some(t.addr.0) == self.ownship_icao {
+                        trace!("ignoring traffic from our own transponder");
+                        continue;
+                    }
+
+                    if self.aggregator && self.is_repeated(t, clock) {
+                        trace!("ignoring repeated update of {:06X}", t.addr.0);
+
+                        // still heard from, even if nothing changed
+                        let key = self.match_track(t, clock);
+                        if let Some(v) = self.situation.get_mut(&key) {
+                            Arc::make_mut(v).last_seen = clock;
+                        }
+                        continue;
+                    }
+
                     // got a traffic update, first figure out if we have some info
                     // about this guy already
 
-                    let trfc = self.situation.entry(t.addr.0).or_insert(Target::new(
-                        t.addr,
-                        clock,
-                        t.source,
-                        icao_to_tail(t.addr.0),
-                    ));
+                    if t.addr.1 == AddressType::ADSBOther && t.source == TrafficSource::UAT
+                        && !self.situation.contains_key(&(t.addr.0, 0))
+                    {
+                        self.reassociate_anonymous(t, clock);
+                    }
+
+                    let key = self.match_track(t, clock);
+                    if !self.situation.contains_key(&key) {
+                        if key.1 != 0 {
+                            warn!(
+                                "duplicate ICAO address {:06X}, tracking as separate target #{}",
+                                key.0, key.1
+                            );
+                            self.duplicates += 1;
+                        }
+
+                        self.make_room();
+                    }
+
+                    let trfc = Arc::make_mut(self.situation.entry(key).or_insert_with(|| {
+                        let mut trfc =
+                            Target::new(t.addr, clock, t.source, icao_to_tail(t.addr.0));
+                        trfc.sub_id = key.1;
+                        Arc::new(trfc)
+                    }));
                     // here, the callsign will be overwritten by codes below
                     // if it does exist
 
@@ -203,6 +567,8 @@ impl Processor for Traffic {
                     trfc.addr = t.addr;
                     trfc.last_seen = clock;
                     trfc.source = t.source;
+                    trfc.received = t.received.or(trfc.received);
+                    trfc.trace = t.trace.or(trfc.trace);
 
                     if let Some((alt, typ)) = t.altitude {
                         trfc.altitude = Some((alt, typ, clock));
@@ -225,11 +591,17 @@ impl Processor for Traffic {
                     }
 
                     if let Some(sq) = t.squawk {
+                        if trfc.squawk != Some(sq) {
+                            classify_squawk(trfc, sq, &self.squawks);
+                        }
                         trfc.squawk = Some(sq);
                     }
 
                     if let Some(ref cs) = t.callsign {
-                        trfc.callsign = Some(cs.clone());
+                        match sanitize_callsign(cs) {
+                            Some(cs) => trfc.callsign = Some(cs.to_string()),
+                            None => debug!("invalid callsign {:?} of {:06X} dropped", cs, t.addr.0),
+                        }
                     }
 
                     if let Some(cat) = t.category {
@@ -248,32 +620,234 @@ impl Processor for Traffic {
                         trfc.nacp = Some(na);
                     }
 
+                    if let Some(nv) = t.nacv {
+                        trfc.nacv = Some(nv);
+                    }
+
+                    if let Some(sil) = t.sil {
+                        trfc.sil = Some(sil);
+                    }
+
+                    if let Some(ident) = t.ident {
+                        trfc.ident = if ident { Some(clock) } else { None };
+                    }
+
+                    if let Some(c) = t.capability {
+                        trfc.capability = Some(c);
+                    }
+
                     if let Some(on_gnd) = t.on_ground {
                         trfc.on_ground = Some(on_gnd);
                     }
+
+                    // make sure surface targets are never shown as aircraft
+                    if trfc.category.map_or(true, |c| c == 0) {
+                        match trfc.addr.1 {
+                            AddressType::SurfaceVehicle => {
+                                trfc.category = Some(CATEGORY_SURFACE_SERVICE)
+                            }
+                            AddressType::FixedBeacon => {
+                                trfc.category = Some(CATEGORY_POINT_OBSTACLE)
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    if trfc.is_surface() {
+                        trfc.on_ground = Some(true);
+                    }
+                }
+                SensorData::GNSS(GNSSData::TimeFix {
+                    fix: Some(ref f), ..
+                }) => self.ownship = Some((f.lat_lon.0, clock)),
+                SensorData::Config(ref c) => {
+                    if c.ownship_icao != self.ownship_icao {
+                        self.ownship_icao = c.ownship_icao;
+
+                        if let Some(icao) = self.ownship_icao {
+                            self.situation.retain(|k, _| k.0 != icao);
+                        }
+                    }
+
+                    if c.squawks != self.squawks {
+                        self.squawks = c.squawks.clone();
+
+                        for v in self.situation.values_mut() {
+                            if let Some(sq) = v.squawk {
+                                classify_squawk(Arc::make_mut(v), sq, &self.squawks);
+                            }
+                        }
+                    }
                 }
                 _ => {}
             }
         }
 
         run_every!(CLEANUP_FREQ, self.cleanup_counter, handle, {
-            debug!("clean up traffic map");
+            debug!(
+                "clean up traffic map, {} duplicate address(es) detected and {} repeated \
+                 update(s) dropped so far",
+                self.duplicates, self.repeated
+            );
 
+            self.recent.retain(|_, r| {
+                r.last().map_or(false, |&(_, i)| {
+                    clock - i < Duration::from_millis(DUPLICATE_MS)
+                })
+            });
+
+            let max_stale = self.max_stale;
             self.situation
-                .retain(|_, ref v| (clock - v.last_seen).as_secs() < MAX_STALE_SECS);
+                .retain(|_, ref v| (clock - v.last_seen).as_secs() < max_stale);
         });
 
         run_every!(REPORT_FREQ, self.report_counter, handle, {
-            for v in self.situation.values() {
+            let own = match self.ownship {
+                Some((ll, i)) if (clock - i).as_secs() < OWNSHIP_STALE_SECS => Some(ll),
+                _ => None,
+            };
+
+            for v in self.situation.values_mut() {
+                if v.ident
+                    .map_or(false, |i| (clock - i).as_secs() >= IDENT_SECS)
+                {
+                    Arc::make_mut(v).ident = None;
+                }
+
+                let (range, bearing) = match (own, v.lat_lon) {
+                    (Some(own), Some((ll, _))) => {
+                        (Some(distance_nm(own, ll)), Some(bearing_deg(own, ll)))
+                    }
+                    _ => (None, None),
+                };
+
+                if v.range != range || v.bearing != bearing {
+                    let v = Arc::make_mut(v);
+                    v.range = range;
+                    v.bearing = bearing;
+                }
+
                 if v.is_fresh(clock) {
                     handle.push_data(Report::Traffic(v.clone()));
                     trace!("Traffic: {:?}", v);
+
+                    // later reports without new frames repeat the same data
+                    if v.received.is_some() {
+                        Arc::make_mut(v).received = None;
+                    }
                 }
             }
         });
     }
 }
 
+/// Hash of everything `t` reports, except for the time of reception, which
+/// differs between the copies of a frame both paired units received
+fn fingerprint(t: &TrafficData) -> u64 {
+    let mut h = DefaultHasher::new();
+
+    t.addr.0.hash(&mut h);
+    discriminant(&t.addr.1).hash(&mut h);
+    t.altitude.map(|a| (a.0, discriminant(&a.1))).hash(&mut h);
+    t.gnss_delta.hash(&mut h);
+    t.heading.map(|a| (a.0, discriminant(&a.1))).hash(&mut h);
+    t.speed.map(|a| (a.0, discriminant(&a.1))).hash(&mut h);
+    t.vs.hash(&mut h);
+    t.squawk.hash(&mut h);
+    t.callsign.hash(&mut h);
+    t.category.hash(&mut h);
+    t.lat_lon
+        .map(|(lat, lon)| (lat.to_bits(), lon.to_bits()))
+        .hash(&mut h);
+    (t.nic, t.nacp, t.nacv, t.sil).hash(&mut h);
+    (t.ident, t.on_ground).hash(&mut h);
+    t.capability.map(|c| (c.cdti, c.tcas)).hash(&mut h);
+    discriminant(&t.source).hash(&mut h);
+
+    h.finish()
+}
+
+/// Clock position (1 to 12) of something at `bearing` as seen from an
+/// aircraft tracking `track`, both in degrees true
+pub fn clock_position(bearing: f32, track: f32) -> u8 {
+    let relative = (bearing - track + 360.0) % 360.0;
+
+    match ((relative + 15.0) / 30.0) as u8 % 12 {
+        0 => 12,
+        c => c,
+    }
+}
+
+/// Horizontal containment radius (Rc) in meters of NIC 1 to 11, the same for
+/// 1090ES (DO-260B 2.2.3.2.7.2.6) and UAT (DO-282B 2.2.4.5.2.1). NIC 6 may
+/// also mean 0.5 or 0.3 NM depending on supplement bits libdump1090 does
+/// not report, so the largest, 0.6 NM, is used.
+const NIC_RADIUS_M: [f32; 11] = [
+    37_040.0, // 20 NM
+    14_816.0, // 8 NM
+    7_408.0,  // 4 NM
+    3_704.0,  // 2 NM
+    1_852.0,  // 1 NM
+    1_111.2,  // 0.6 NM
+    370.4,    // 0.2 NM
+    185.2,    // 0.1 NM
+    75.0,
+    25.0,
+    7.5,
+];
+
+/// Radius in meters the reported position is within, `None` for NIC 0
+/// (unknown) and values out of range
+pub fn containment_radius(nic: u8) -> Option<f32> {
+    match nic {
+        1...11 => Some(NIC_RADIUS_M[nic as usize - 1]),
+        _ => None,
+    }
+}
+
+/// 1090ES emitter categories (DO-260B 2.2.3.2.5.2) as reported by libdump1090,
+/// set A to D in the high nibble (`0xA` is set A, type code 4) and category in
+/// the low nibble, along with the DO-282B encoding UAT and GDL90 use
+const ES_CATEGORIES: [(u8, u8); 18] = [
+    (0xA1, 1),  // light
+    (0xA2, 2),  // small
+    (0xA3, 3),  // large
+    (0xA4, 4),  // high vortex large
+    (0xA5, 5),  // heavy
+    (0xA6, 6),  // highly maneuverable
+    (0xA7, 7),  // rotorcraft
+    (0xB1, 9),  // glider/sailplane
+    (0xB2, 10), // lighter than air
+    (0xB3, 11), // parachutist/skydiver
+    (0xB4, 12), // ultralight/hang glider/paraglider
+    (0xB6, 14), // unmanned aerial vehicle
+    (0xB7, 15), // space/transatmospheric vehicle
+    (0xC1, 17), // surface vehicle, emergency
+    (0xC2, 18), // surface vehicle, service
+    (0xC3, 19), // point obstacle
+    (0xC4, 20), // cluster obstacle
+    (0xC5, 21), // line obstacle
+];
+
+/// Normalize a 1090ES emitter category to the DO-282B encoding, unassigned
+/// and reserved categories become 0 (no information)
+pub fn category_from_es(raw: u8) -> u8 {
+    ES_CATEGORIES
+        .iter()
+        .find(|&&(es, _)| es == raw)
+        .map_or(0, |&(_, c)| c)
+}
+
+/// Normalize a UAT emitter category, which already uses the DO-282B encoding,
+/// unassigned and reserved categories become 0 (no information)
+pub fn category_from_uat(raw: u8) -> u8 {
+    if ES_CATEGORIES.iter().any(|&(_, c)| c == raw) {
+        raw
+    } else {
+        0
+    }
+}
+
 fn n_letters(mut rem: u32, reg: &mut String) {
     if rem == 0 {
         return;
@@ -291,6 +865,38 @@ fn n_letters(mut rem: u32, reg: &mut String) {
     reg.push(LIMITED_ALPHABET.as_bytes()[rem as usize] as char);
 }
 
+/// `cs` without padding, `None` unless it only has characters of the
+/// ICAO callsign character set (A-Z and 0-9). Garbled frames decode to
+/// other characters, which EFBs may not display.
+fn sanitize_callsign(cs: &str) -> Option<&str> {
+    let cs = cs.trim();
+
+    if !cs.is_empty()
+        && cs.len() <= MAX_CALLSIGN_LEN
+        && cs
+            .bytes()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        Some(cs)
+    } else {
+        None
+    }
+}
+
+/// Annotate `t` squawking `sq`. Emergency codes take precedence over the
+/// configured ones and are never quiet.
+fn classify_squawk(t: &mut Target, sq: u16, squawks: &[Squawk]) {
+    if EMERGENCY_SQUAWKS.contains(&sq) {
+        t.squawk_kind = Some(SquawkKind::Emergency);
+        t.quiet = false;
+        return;
+    }
+
+    let s = squawks.iter().find(|s| s.code == sq);
+    t.squawk_kind = s.map(|s| s.kind);
+    t.quiet = s.map_or(false, |s| s.quiet);
+}
+
 // from https://github.com/cyoung/stratux/blob/master/main/traffic.go#L1177
 fn icao_to_tail(icao: u32) -> Option<String> {
     match icao {
@@ -355,6 +961,96 @@ fn icao_to_tail(icao: u32) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pitot::handle::Handle;
+    use sensor::gnss::{Fix, FixQuality};
+    use testing::{run_processor, MockHandle};
+
+    fn traffic_data(addr: Address, lat_lon: (f32, f32)) -> TrafficData {
+        TrafficData {
+            addr,
+            altitude: Some((3525, AltitudeType::Baro)),
+            gnss_delta: None,
+            heading: Some((2, HeadingType::True)),
+            speed: Some((118, SpeedType::GS)),
+            vs: None,
+            squawk: None,
+            callsign: None,
+            category: None,
+            lat_lon: Some(lat_lon),
+            nic: None,
+            nacp: None,
+            nacv: None,
+            sil: None,
+            ident: None,
+            capability: None,
+            on_ground: None,
+            source: TrafficSource::UAT,
+            received: None,
+            trace: None,
+            raw: None,
+        }
+    }
+
+    #[test]
+    fn test_repeated() {
+        let mut c = Config::default();
+        c.peer = Some(Peer::Aggregator {
+            port: 30100,
+            forwarder: None,
+        });
+        let mut t = Traffic::new(&c);
+        let mut h = MockHandle::new(1);
+        let at = |lat_lon, received| {
+            let mut data = traffic_data((0xA1B2C3, AddressType::ADSBICAO), lat_lon);
+            data.received = received;
+            SensorData::Traffic(data)
+        };
+        let position = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => t.lat_lon.unwrap().0,
+            _ => panic!("no traffic reported"),
+        };
+
+        // received locally, then the forwarded copy after a newer position
+        let decoded = h.get_clock();
+        run_processor(
+            &mut t,
+            &mut h,
+            &[
+                at((37.5, -122.0), Some(decoded)),
+                at((37.501, -122.0), Some(decoded)),
+                at((37.5, -122.0), None),
+            ],
+        );
+        assert_eq!(position(&mut h), (37.501, -122.0));
+        assert_eq!(t.repeated, 1);
+
+        // the same update again later is news
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[at((37.5, -122.0), None)]);
+        assert_eq!(position(&mut h), (37.5, -122.0));
+        assert_eq!(t.repeated, 1);
+
+        // a repeated update still means the target is around
+        h.advance(Duration::from_millis(500));
+        run_processor(&mut t, &mut h, &[at((37.5, -122.0), None)]);
+        assert_eq!(t.repeated, 2);
+        assert_eq!(t.situation[&(0xA1B2C3, 0)].last_seen, h.get_clock());
+
+        // only aggregators expect copies
+        let mut t = Traffic::new(&Config::default());
+        run_processor(
+            &mut t,
+            &mut h,
+            &[
+                at((37.5, -122.0), Some(decoded)),
+                at((37.501, -122.0), Some(decoded)),
+                at((37.5, -122.0), None),
+            ],
+        );
+        assert_eq!(position(&mut h), (37.5, -122.0));
+        assert_eq!(t.repeated, 0);
+        assert!(t.recent.is_empty());
+    }
 
     #[test]
     fn test_icao_to_tail() {
@@ -366,4 +1062,477 @@ mod tests {
         assert_eq!(icao_to_tail(0xA18FA9), Some(String::from("N20")));
         assert_eq!(icao_to_tail(0x780A2C), None);
     }
+
+    #[test]
+    fn test_callsign() {
+        assert_eq!(sanitize_callsign("UAL123  "), Some("UAL123"));
+        assert_eq!(sanitize_callsign("N12345"), Some("N12345"));
+        assert_eq!(sanitize_callsign("        "), None);
+        assert_eq!(sanitize_callsign("UA#L12"), None);
+        assert_eq!(sanitize_callsign("N1 2"), None);
+        assert_eq!(sanitize_callsign("ual123"), None);
+        assert_eq!(sanitize_callsign("N1\u{7f}\u{e9}"), None);
+        assert_eq!(sanitize_callsign("ABCDEFGHI"), None);
+
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let update = |cs: &str| {
+            let mut data = traffic_data((0xA1B2C3, AddressType::ADSBICAO), (37.5, -122.0));
+            data.callsign = Some(cs.to_string());
+            SensorData::Traffic(data)
+        };
+
+        let callsign = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => t.callsign.clone(),
+            _ => panic!("no traffic reported"),
+        };
+
+        run_processor(&mut t, &mut h, &[update("SWA1234 ")]);
+        assert_eq!(callsign(&mut h), Some("SWA1234".to_string()));
+
+        // the rest of the frame is still used
+        h.advance_secs(1);
+        let mut garbled = traffic_data((0xA1B2C3, AddressType::ADSBICAO), (37.5, -122.0));
+        garbled.callsign = Some("SW@1\u{0}34".to_string());
+        garbled.squawk = Some(1200);
+        run_processor(&mut t, &mut h, &[SensorData::Traffic(garbled)]);
+        match h.take().pop() {
+            Some(Report::Traffic(ref t)) => {
+                assert_eq!(t.callsign, Some("SWA1234".to_string()));
+                assert_eq!(t.squawk, Some(1200));
+            }
+            _ => panic!("no traffic reported"),
+        }
+    }
+
+    #[test]
+    fn test_squawk_kind() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let update = |sq| {
+            let mut data = traffic_data((0xA1B2C3, AddressType::ADSBICAO), (37.5, -122.0));
+            data.squawk = Some(sq);
+            SensorData::Traffic(data)
+        };
+        let kind = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => (t.squawk_kind, t.quiet),
+            _ => panic!("no traffic reported"),
+        };
+
+        run_processor(&mut t, &mut h, &[update(1200)]);
+        assert_eq!(kind(&mut h), (Some(SquawkKind::VFR), false));
+
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[update(4321)]);
+        assert_eq!(kind(&mut h), (None, false));
+
+        // configured codes can not quiet an emergency
+        let mut c = Config::default();
+        c.squawks = vec![
+            Squawk {
+                code: 4321,
+                kind: SquawkKind::Formation,
+                quiet: true,
+            },
+            Squawk {
+                code: 7700,
+                kind: SquawkKind::VFR,
+                quiet: true,
+            },
+        ];
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[SensorData::Config(c)]);
+        assert_eq!(kind(&mut h), (Some(SquawkKind::Formation), true));
+
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[update(7700)]);
+        assert_eq!(kind(&mut h), (Some(SquawkKind::Emergency), false));
+    }
+
+    #[test]
+    fn test_category() {
+        let defined = [
+            (0xA1, 1),
+            (0xA2, 2),
+            (0xA3, 3),
+            (0xA4, 4),
+            (0xA5, 5),
+            (0xA6, 6),
+            (0xA7, 7),
+            (0xB1, 9),
+            (0xB2, 10),
+            (0xB3, 11),
+            (0xB4, 12),
+            (0xB6, 14),
+            (0xB7, 15),
+            (0xC1, 17),
+            (0xC2, 18),
+            (0xC3, 19),
+            (0xC4, 20),
+            (0xC5, 21),
+        ];
+
+        for &(es, uat) in defined.iter() {
+            assert_eq!(category_from_es(es), uat);
+            assert_eq!(category_from_uat(uat), uat);
+        }
+
+        // no information, unassigned and reserved
+        for es in [0xA0, 0xB0, 0xB5, 0xC0, 0xC6, 0xC7, 0xD0, 0xD3, 0x00].iter() {
+            assert_eq!(category_from_es(*es), 0);
+        }
+        for uat in [0, 8, 13, 16, 22, 39].iter() {
+            assert_eq!(category_from_uat(*uat), 0);
+        }
+    }
+
+    #[test]
+    fn test_can_reach() {
+        let clock = Instant::now();
+        let mut trfc = Target::new(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            clock,
+            TrafficSource::ES,
+            None,
+        );
+
+        assert!(trfc.can_reach((37.5, -122.0), clock));
+
+        trfc.lat_lon = Some(((37.5, -122.0), clock));
+        assert!(trfc.can_reach((37.51, -122.0), clock));
+        assert!(!trfc.can_reach((38.5, -122.0), clock));
+    }
+
+    #[test]
+    fn test_reassociate_anonymous() {
+        let clock = Instant::now();
+        let mut t = Traffic::new(&Config::default());
+
+        let mut trfc = Target::new(
+            (0x123456, AddressType::ADSBOther),
+            clock,
+            TrafficSource::UAT,
+            None,
+        );
+        trfc.lat_lon = Some(((37.5, -122.0), clock));
+        trfc.altitude = Some((3500, AltitudeType::Baro, clock));
+        trfc.speed = Some((120, SpeedType::GS, clock));
+        trfc.heading = Some((358, HeadingType::True, clock));
+        t.situation.insert((0x123456, 0), Arc::new(trfc));
+
+        let mut update = traffic_data((0x654321, AddressType::ADSBOther), (37.8, -122.0));
+
+        // too far away
+        t.reassociate_anonymous(&update, clock);
+        assert!(t.situation.contains_key(&(0x123456, 0)));
+
+        // corrupt position
+        update.lat_lon = Some((::std::f32::NAN, -122.0));
+        t.reassociate_anonymous(&update, clock);
+        assert!(t.situation.contains_key(&(0x123456, 0)));
+
+        update.lat_lon = Some((37.501, -122.0));
+        t.reassociate_anonymous(&update, clock);
+        assert!(!t.situation.contains_key(&(0x123456, 0)));
+        assert_eq!(
+            t.situation[&(0x654321, 0)].addr,
+            (0x654321, AddressType::ADSBOther)
+        );
+    }
+
+    #[test]
+    fn test_traffic_expiry() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let update = || {
+            SensorData::Traffic(traffic_data(
+                (0xA1B2C3, AddressType::ADSBICAO),
+                (37.5, -122.0),
+            ))
+        };
+
+        run_processor(&mut t, &mut h, &[update()]);
+        assert_eq!(h.take().len(), 1);
+
+        // no longer fresh, not reported but still tracked
+        h.advance_secs(FRESHNESS_DELAY + 1);
+        run_processor(&mut t, &mut h, &[]);
+        assert!(h.take().is_empty());
+        assert_eq!(t.situation.len(), 1);
+
+        // dropped by the next cleanup once stale
+        h.advance_secs(MAX_STALE_SECS);
+        for _ in 0..10 {
+            run_processor(&mut t, &mut h, &[]);
+        }
+        assert!(t.situation.is_empty());
+
+        // ground stations keep it longer
+        let mut c = Config::default();
+        c.ground_station = true;
+        let mut t = Traffic::new(&c);
+        run_processor(&mut t, &mut h, &[update()]);
+        h.advance_secs(MAX_STALE_SECS);
+        for _ in 0..10 {
+            run_processor(&mut t, &mut h, &[]);
+        }
+        assert_eq!(t.situation.len(), 1);
+    }
+
+    #[test]
+    fn test_ground_station_capacity() {
+        let mut c = Config::default();
+        c.ground_station = true;
+        let mut t = Traffic::new(&c);
+        let mut h = MockHandle::new(1);
+
+        run_processor(
+            &mut t,
+            &mut h,
+            &[SensorData::Traffic(traffic_data(
+                (0xA1B2C3, AddressType::ADSBICAO),
+                (37.5, -122.0),
+            ))],
+        );
+        h.advance_secs(1);
+
+        // the stalest one is forgotten once full
+        let many: Vec<SensorData> = (1..GROUND_STATION_CAPACITY as u32)
+            .map(|i| {
+                SensorData::Traffic(traffic_data(
+                    (0xA00000 + i, AddressType::ADSBICAO),
+                    (37.5, -122.0),
+                ))
+            })
+            .collect();
+        run_processor(&mut t, &mut h, &many);
+        assert_eq!(t.situation.len(), GROUND_STATION_CAPACITY);
+        run_processor(
+            &mut t,
+            &mut h,
+            &[SensorData::Traffic(traffic_data(
+                (0xB00000, AddressType::ADSBICAO),
+                (37.5, -122.0),
+            ))],
+        );
+        assert_eq!(t.situation.len(), GROUND_STATION_CAPACITY);
+        assert!(t.situation.contains_key(&(0xB00000, 0)));
+        assert!(!t.situation.contains_key(&(0xA1B2C3, 0)));
+    }
+
+    #[test]
+    fn test_ident() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let update = |ident| {
+            let mut data = traffic_data((0xA1B2C3, AddressType::ADSBICAO), (37.5, -122.0));
+            data.ident = ident;
+            SensorData::Traffic(data)
+        };
+
+        let identing = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => t.ident.is_some(),
+            _ => panic!("no traffic reported"),
+        };
+
+        run_processor(&mut t, &mut h, &[update(Some(true))]);
+        assert!(identing(&mut h));
+
+        // still shown when only position updates follow
+        h.advance_secs(IDENT_SECS - 1);
+        run_processor(&mut t, &mut h, &[update(None)]);
+        assert!(identing(&mut h));
+
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[update(None)]);
+        assert!(!identing(&mut h));
+    }
+
+    #[test]
+    fn test_capability() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let update = |capability| {
+            let mut data = traffic_data((0xA1B2C3, AddressType::ADSBICAO), (37.5, -122.0));
+            data.source = TrafficSource::ES;
+            data.capability = capability;
+            SensorData::Traffic(data)
+        };
+
+        let json = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => serde_json::to_value(&**t).unwrap(),
+            _ => panic!("no traffic reported"),
+        };
+
+        run_processor(&mut t, &mut h, &[update(None)]);
+        let js = json(&mut h);
+        assert!(js["cdti"].is_null());
+        assert!(js["tcas"].is_null());
+
+        run_processor(
+            &mut t,
+            &mut h,
+            &[update(Some(Capability {
+                cdti: true,
+                tcas: false,
+            }))],
+        );
+        let js = json(&mut h);
+        assert_eq!(js["cdti"], true);
+        assert_eq!(js["tcas"], false);
+
+        // kept while other messages follow
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[update(None)]);
+        let js = json(&mut h);
+        assert_eq!(js["cdti"], true);
+        assert_eq!(js["tcas"], false);
+    }
+
+    #[test]
+    fn test_received() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let decoded = h.get_clock();
+        let mut data = traffic_data((0xA1B2C3, AddressType::ADSBICAO), (37.5, -122.0));
+        data.received = Some(decoded);
+
+        let received = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => t.received,
+            _ => panic!("no traffic reported"),
+        };
+
+        run_processor(&mut t, &mut h, &[SensorData::Traffic(data)]);
+        assert_eq!(received(&mut h), Some(decoded));
+
+        // nothing new, only measured once
+        run_processor(&mut t, &mut h, &[]);
+        assert_eq!(received(&mut h), None);
+    }
+
+    #[test]
+    fn test_range_and_bearing() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+        let update = SensorData::Traffic(traffic_data(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            (37.6, -122.0),
+        ));
+        let fix = SensorData::GNSS(GNSSData::TimeFix {
+            time: None,
+            fix: Some(Fix {
+                quality: FixQuality::ThreeDim,
+                num_sv: 8,
+                lat_lon: ((37.5, -122.0), Some(5000)),
+                height_msl: (914400, None),
+                height_ellipsoid: (914400, None),
+                gs: (0, None),
+                vs: (0, None),
+                true_course: (0.0, None),
+                mag_dec: None,
+            }),
+        });
+
+        let relative = |h: &mut MockHandle<Report>| match h.take().pop() {
+            Some(Report::Traffic(ref t)) => {
+                (t.range.map(|r| r.round()), t.bearing.map(|b| b.round()))
+            }
+            _ => panic!("no traffic reported"),
+        };
+
+        // unknown without an ownship position
+        run_processor(&mut t, &mut h, &[update]);
+        assert_eq!(relative(&mut h), (None, None));
+
+        h.advance_secs(1);
+        run_processor(&mut t, &mut h, &[fix]);
+        assert_eq!(relative(&mut h), (Some(6.0), Some(0.0)));
+
+        // ownship position went stale
+        h.advance_secs(OWNSHIP_STALE_SECS);
+        run_processor(&mut t, &mut h, &[]);
+        assert_eq!(relative(&mut h), (None, None));
+    }
+
+    #[test]
+    fn test_containment_radius() {
+        assert_eq!(containment_radius(0), None);
+        assert_eq!(containment_radius(1), Some(37_040.0));
+        assert_eq!(containment_radius(6), Some(1_111.2));
+        assert_eq!(containment_radius(8), Some(185.2));
+        assert_eq!(containment_radius(11), Some(7.5));
+        assert_eq!(containment_radius(12), None);
+
+        // tighter with every step
+        for n in 1..11 {
+            assert!(containment_radius(n + 1) < containment_radius(n));
+        }
+
+        let mut t = Target::new(
+            (0xA00001, AddressType::ADSBICAO),
+            Instant::now(),
+            TrafficSource::ES,
+            None,
+        );
+        let js = serde_json::to_value(&t).unwrap();
+        assert!(js["containment_radius"].is_null());
+
+        t.nic = Some(9);
+        let js = serde_json::to_value(&t).unwrap();
+        assert_eq!(js["containment_radius"], 75.0);
+    }
+
+    #[test]
+    fn test_clock_position() {
+        assert_eq!(clock_position(0.0, 0.0), 12);
+        assert_eq!(clock_position(90.0, 0.0), 3);
+        assert_eq!(clock_position(350.0, 0.0), 12);
+        assert_eq!(clock_position(40.0, 270.0), 4);
+        assert_eq!(clock_position(180.0, 200.0), 11);
+    }
+
+    #[test]
+    fn test_surface_targets() {
+        let mut t = Traffic::new(&Config::default());
+        let mut h = MockHandle::new(1);
+
+        let vehicle = traffic_data((0x000001, AddressType::SurfaceVehicle), (37.5, -122.0));
+        let beacon = traffic_data((0x000002, AddressType::FixedBeacon), (37.6, -122.0));
+        let mut plane = traffic_data((0x000003, AddressType::ADSBICAO), (37.7, -122.0));
+        plane.on_ground = Some(false);
+        plane.category = Some(1);
+        let mut obstacle = traffic_data((0x000004, AddressType::TISBOther), (37.8, -122.0));
+        obstacle.category = Some(21);
+
+        run_processor(
+            &mut t,
+            &mut h,
+            &[
+                SensorData::Traffic(vehicle),
+                SensorData::Traffic(beacon),
+                SensorData::Traffic(plane),
+                SensorData::Traffic(obstacle),
+            ],
+        );
+
+        let mut targets: Vec<(u32, Option<u8>, Option<bool>)> = h
+            .take()
+            .into_iter()
+            .filter_map(|r| match r {
+                Report::Traffic(t) => Some((t.addr.0, t.category, t.on_ground)),
+                _ => None,
+            })
+            .collect();
+        targets.sort_by_key(|t| t.0);
+
+        assert_eq!(
+            targets,
+            vec![
+                (1, Some(18), Some(true)),
+                (2, Some(19), Some(true)),
+                (3, Some(1), Some(false)),
+                (4, Some(21), Some(true)),
+            ]
+        );
+    }
 }
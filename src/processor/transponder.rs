@@ -0,0 +1,209 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Monitors the altitude our own transponder is reporting and compares it against
+//! the pressure altitude from the barometer, warning about encoder disagreements.
+
+use super::*;
+use processor::traffic::AltitudeType;
+use sensor::SensorData;
+use std::time::Instant;
+
+const CHECK_FREQ: u16 = 1;
+// maximum allowed difference between encoder and baro altitude in ft
+const MAX_DISAGREEMENT: i32 = 200;
+// readings older than this are not compared
+const MAX_STALE_SECS: u64 = 3;
+
+pub struct Transponder {
+    /// Our own ICAO address, nothing is checked when unknown
+    icao: Option<u32>,
+    /// Cabin pressure altitude in ft
+    baro: Option<(i32, Instant)>,
+    /// Pressure altitude reported by our own transponder in ft
+    encoded: Option<(i32, Instant)>,
+    check_counter: u32,
+}
+
+impl Transponder {
+    pub fn new(icao: Option<u32>) -> Box<Processor> {
+        Box::new(Self {
+            icao,
+            baro: None,
+            encoded: None,
+            check_counter: 0,
+        })
+    }
+
+    /// Encoder and pressure altitude when both are recent and disagree
+    fn disagreement(&self, clock: Instant) -> Option<(i32, i32)> {
+        if let (Some((baro, bi)), Some((encoded, ei))) = (self.baro, self.encoded) {
+            if (clock - bi).as_secs() <= MAX_STALE_SECS
+                && (clock - ei).as_secs() <= MAX_STALE_SECS
+                && (encoded - baro).abs() > MAX_DISAGREEMENT
+            {
+                return Some((encoded, baro));
+            }
+        }
+
+        None
+    }
+}
+
+impl Processor for Transponder {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::Baro(ref b) => self.baro = Some((b.pressure_altitude, clock)),
+                SensorData::Traffic(ref t) if Some(t.addr.0) == self.icao => {
+                    if let Some((alt, AltitudeType::Baro)) = t.altitude {
+                        self.encoded = Some((alt, clock));
+                    }
+                }
+                SensorData::Config(ref c) if c.ownship_icao != self.icao => {
+                    self.icao = c.ownship_icao;
+                    self.encoded = None;
+                }
+                _ => {} // do nothing
+            }
+        }
+
+        run_every!(CHECK_FREQ, self.check_counter, handle, {
+            if let Some((encoded, baro)) = self.disagreement(clock) {
+                warn!(
+                    "transponder reports {} ft but pressure altitude is {} ft, \
+                     check altitude encoder",
+                    encoded, baro
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use pitot::handle::Handle;
+    use processor::traffic::{AddressType, TrafficSource};
+    use sensor::barometer::BaroData;
+    use sensor::sdr::TrafficData;
+    use testing::{run_processor, MockHandle};
+
+    fn traffic(addr: u32, altitude: (i32, AltitudeType)) -> SensorData {
+        SensorData::Traffic(TrafficData {
+            addr: (addr, AddressType::ADSBICAO),
+            altitude: Some(altitude),
+            gnss_delta: None,
+            heading: None,
+            speed: None,
+            vs: None,
+            squawk: None,
+            callsign: None,
+            category: None,
+            lat_lon: None,
+            nic: None,
+            nacp: None,
+            nacv: None,
+            sil: None,
+            ident: None,
+            capability: None,
+            on_ground: None,
+            source: TrafficSource::ES,
+            received: None,
+            trace: None,
+            raw: None,
+        })
+    }
+
+    #[test]
+    fn test_disagreement() {
+        let mut t = Transponder {
+            icao: Some(0xA1B2C3),
+            baro: None,
+            encoded: None,
+            check_counter: 0,
+        };
+        let mut h = MockHandle::new(10);
+
+        // 4998 ft
+        let baro = SensorData::Baro(BaroData::new("test", 843.07, None));
+        run_processor(
+            &mut t,
+            &mut h,
+            &[baro, traffic(0xA1B2C3, (5150, AltitudeType::Baro))],
+        );
+        assert_eq!(t.disagreement(h.get_clock()), None);
+
+        run_processor(
+            &mut t,
+            &mut h,
+            &[traffic(0xA1B2C3, (5250, AltitudeType::Baro))],
+        );
+        assert_eq!(t.disagreement(h.get_clock()), Some((5250, 4998)));
+
+        // other targets and GNSS altitudes are not the encoder
+        run_processor(
+            &mut t,
+            &mut h,
+            &[
+                traffic(0xA1B2C4, (5000, AltitudeType::Baro)),
+                traffic(0xA1B2C3, (5000, AltitudeType::GNSS)),
+            ],
+        );
+        assert_eq!(t.disagreement(h.get_clock()), Some((5250, 4998)));
+
+        h.advance_secs(MAX_STALE_SECS + 1);
+        assert_eq!(t.disagreement(h.get_clock()), None);
+
+        // baro reading is stale
+        run_processor(
+            &mut t,
+            &mut h,
+            &[traffic(0xA1B2C3, (5250, AltitudeType::Baro))],
+        );
+        assert_eq!(t.disagreement(h.get_clock()), None);
+    }
+
+    #[test]
+    fn test_icao_changed() {
+        let mut t = Transponder {
+            icao: Some(0xA1B2C3),
+            baro: None,
+            encoded: None,
+            check_counter: 0,
+        };
+        let mut h = MockHandle::new(10);
+
+        run_processor(
+            &mut t,
+            &mut h,
+            &[
+                SensorData::Baro(BaroData::new("test", 843.07, None)),
+                traffic(0xA1B2C3, (5250, AltitudeType::Baro)),
+            ],
+        );
+        assert!(t.disagreement(h.get_clock()).is_some());
+
+        let mut c = Config::default();
+        c.ownship_icao = Some(0xA1B2C4);
+        run_processor(&mut t, &mut h, &[SensorData::Config(c)]);
+        assert_eq!(t.disagreement(h.get_clock()), None);
+        assert!(h.take().is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Over the air updates.
+//!
+//! An update bundle is a gzipped tarball containing the new `pitot` binary,
+//! prefixed with a detached signature of the tarball:
+//! `[signature length: u16 BE][signature][tarball]`. The signature is checked
+//! against the configured public key with `openssl` before the running binary
+//! is replaced and the service restarted.
+//!
+//! Only the binary is installed, anything else in the bundle is ignored.
+//!
+//! The new binary has to stay up for `HEALTHY_SECS` before the update is
+//! committed. Rolling back is left to `dist/pitot-update-check`, which runs
+//! before every start of the service and restores the previous binary when
+//! the update failed to start too many times. It has to live outside of the
+//! new binary, as that one might not start at all.
+
+use super::*;
+use sensor::command::Command;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::Instant;
+use storage::Storage;
+
+const HEALTHY_SECS: u64 = 60;
+const PENDING_FILE: &str = "update-pending";
+const STAGING_DIR: &str = "update";
+const BINARY_NAME: &str = "pitot";
+const BACKUP_EXTENSION: &str = "previous";
+
+pub struct Updater {
+    public_key: String,
+    storage: Storage,
+    /// An update was installed and has not proven itself healthy yet
+    pending: bool,
+    started: Option<Instant>,
+    /// Set while a bundle is being verified and installed
+    busy: Arc<AtomicBool>,
+}
+
+impl Updater {
+    pub fn new(public_key: String, storage: Storage) -> Box<Processor> {
+        // the attempt count is kept by `pitot-update-check`
+        let pending = storage.load(PENDING_FILE).is_some();
+
+        Box::new(Self {
+            public_key,
+            storage,
+            pending,
+            started: None,
+            busy: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn start_update(&mut self, bundle: &[u8]) {
+        if self.busy.swap(true, Ordering::SeqCst) {
+            warn!("an update is already in progress");
+            return;
+        }
+
+        let staging = match self.storage.path(STAGING_DIR) {
+            Some(p) => p,
+            None => {
+                error!("update requires a writable data directory");
+                self.busy.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        info!("received update bundle of {} bytes", bundle.len());
+
+        let bundle = bundle.to_vec();
+        let public_key = self.public_key.clone();
+        let storage = self.storage.clone();
+        let busy = self.busy.clone();
+
+        spawn(move || match install(&bundle, &public_key, &staging) {
+            Ok(_) => {
+                info!("update installed, restarting");
+                storage.save(PENDING_FILE, b"0");
+                restart();
+            }
+            Err(e) => {
+                error!("update failed: {}", e);
+                busy.store(false, Ordering::SeqCst);
+            }
+        });
+    }
+}
+
+impl Processor for Updater {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::Command(Command::Update { ref bundle }) => self.start_update(bundle),
+                SensorData::Config(ref c) => self.public_key = c.update_public_key.clone(),
+                _ => {} // do nothing
+            }
+        }
+
+        if self.pending {
+            let started = *self.started.get_or_insert(clock);
+
+            if (clock - started).as_secs() >= HEALTHY_SECS {
+                info!("update is healthy, committing");
+
+                self.storage.remove(PENDING_FILE);
+                if let Ok(exe) = env::current_exe() {
+                    fs::remove_file(exe.with_extension(BACKUP_EXTENSION)).ok();
+                }
+
+                self.pending = false;
+            }
+        }
+    }
+}
+
+/// Split a bundle into signature and tarball
+fn split_bundle(bundle: &[u8]) -> Option<(&[u8], &[u8])> {
+    if bundle.len() < 2 {
+        return None;
+    }
+
+    let sig_len = (bundle[0] as usize) << 8 | bundle[1] as usize;
+    if sig_len == 0 || bundle.len() <= 2 + sig_len {
+        return None;
+    }
+
+    Some(bundle[2..].split_at(sig_len))
+}
+
+fn install(bundle: &[u8], public_key: &str, staging: &Path) -> io::Result<()> {
+    let (sig, tarball) = try!(split_bundle(bundle).ok_or_else(|| other("malformed bundle")));
+
+    fs::remove_dir_all(staging).ok();
+    try!(fs::create_dir_all(staging));
+
+    let sig_path = staging.join("bundle.sig");
+    let tarball_path = staging.join("bundle.tar.gz");
+    try!(try!(File::create(&sig_path)).write_all(sig));
+    try!(try!(File::create(&tarball_path)).write_all(tarball));
+
+    try!(execute(
+        process::Command::new("openssl")
+            .args(&["dgst", "-sha256", "-verify", public_key, "-signature"])
+            .arg(&sig_path)
+            .arg(&tarball_path)
+    ));
+    info!("update bundle signature verified");
+
+    try!(execute(
+        process::Command::new("tar")
+            .arg("-xzf")
+            .arg(&tarball_path)
+            .arg("-C")
+            .arg(staging)
+    ));
+
+    let new = staging.join(BINARY_NAME);
+    if !new.is_file() {
+        return Err(other("bundle does not contain the pitot binary"));
+    }
+
+    let exe = try!(env::current_exe());
+    try!(fs::copy(&exe, exe.with_extension(BACKUP_EXTENSION)));
+
+    // copy next to the running binary first so the final rename is atomic
+    let tmp = exe.with_extension("new");
+    try!(fs::copy(&new, &tmp));
+    try!(fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755)));
+    try!(fs::rename(&tmp, &exe));
+
+    fs::remove_dir_all(staging).ok();
+
+    Ok(())
+}
+
+fn restart() {
+    if let Err(e) = process::Command::new("systemctl")
+        .args(&["restart", BINARY_NAME])
+        .spawn()
+    {
+        error!("unable to restart: {}", e);
+    }
+}
+
+fn execute(cmd: &mut process::Command) -> io::Result<()> {
+    let output = try!(cmd.output());
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(other(&format!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+fn other(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_bundle() {
+        assert_eq!(
+            split_bundle(&[0x00, 0x02, 0xAA, 0xBB, 0x01, 0x02]),
+            Some((&[0xAA, 0xBB][..], &[0x01, 0x02][..]))
+        );
+        assert_eq!(split_bundle(&[0x00]), None);
+        assert_eq!(split_bundle(&[0x00, 0x00, 0x01]), None);
+        // no tarball after the signature
+        assert_eq!(split_bundle(&[0x00, 0x02, 0xAA, 0xBB]), None);
+        assert_eq!(split_bundle(&[0x01, 0x00, 0xAA, 0xBB]), None);
+    }
+}
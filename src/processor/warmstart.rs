@@ -0,0 +1,289 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keeps what is worth knowing right after a restart: the last good
+//! position and when it was taken, the offset between GNSS and pressure
+//! altitude and how many frames the SDRs decoded over all runs. The position
+//! is also handed to the GNSS receiver at startup for a warm start.
+
+use super::*;
+use processor::traffic::TrafficSource;
+use sensor::gnss::GNSSData;
+use serde_json;
+use std::time::Instant;
+use storage::Storage;
+
+const REPORT_FREQ: u16 = 1;
+const STATE_FILE: &str = "warmstart.json";
+// the Pi is usually powered off without Pitot being stopped first,
+// so state is also saved this often, in seconds
+const SAVE_INTERVAL: u64 = 60;
+// a barometer reading is only compared with a fix this recent, in seconds
+const MAX_FIX_AGE: u64 = 2;
+// weight of a new sample in the running average of the baro offset
+const BARO_OFFSET_WEIGHT: f32 = 0.01;
+
+/// Last good GNSS position
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    /// Latitude in deg
+    pub lat: f32,
+    /// Longitude in deg
+    pub lon: f32,
+    /// MSL altitude in ft
+    pub msl_altitude: i32,
+    /// Height above WGS-84 ellipsoid in mm
+    pub height_ellipsoid: i32,
+    /// UTC time of the fix in seconds since the epoch, if it came with one
+    pub time: Option<i64>,
+}
+
+/// Frames decoded by one SDR receiver over all runs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameTotals {
+    pub downlink_frames: u64,
+    pub uplink_frames: u64,
+}
+
+/// What is kept across restarts, reported once per second
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub position: Option<Position>,
+    /// GNSS MSL altitude minus pressure altitude in ft, averaged while both
+    /// are available
+    pub baro_offset: Option<f32>,
+    pub uat: FrameTotals,
+    pub es: FrameTotals,
+}
+
+pub struct WarmStart {
+    state: State,
+    storage: Storage,
+    /// When the last fix was received
+    fix_time: Option<Instant>,
+    /// When the state was last saved
+    saved: Option<Instant>,
+    /// Whether the state changed since
+    dirty: bool,
+    report_counter: u32,
+}
+
+impl WarmStart {
+    /// Restores the state saved in `storage`, if any
+    pub fn new(storage: Storage) -> Self {
+        let state = match storage.load(STATE_FILE) {
+            Some(s) => match serde_json::from_slice(&s) {
+                Ok(s) => {
+                    info!("restored state saved by the previous run");
+                    s
+                }
+                Err(e) => {
+                    warn!("ignoring unreadable {}: {}", STATE_FILE, e);
+                    State::default()
+                }
+            },
+            None => State::default(),
+        };
+
+        WarmStart {
+            state,
+            storage,
+            fix_time: None,
+            saved: None,
+            dirty: false,
+            report_counter: 0,
+        }
+    }
+
+    /// Last known position and height above ellipsoid in mm, for warm
+    /// starting the GNSS receiver
+    pub fn position(&self) -> Option<((f32, f32), i32)> {
+        self.state
+            .position
+            .map(|p| ((p.lat, p.lon), p.height_ellipsoid))
+    }
+
+    fn save(&mut self, clock: Instant) {
+        self.storage
+            .save(STATE_FILE, &serde_json::to_vec(&self.state).unwrap());
+        self.saved = Some(clock);
+        self.dirty = false;
+    }
+}
+
+impl Processor for WarmStart {
+    fn run(&mut self, handle: &mut Pushable<Report>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                SensorData::GNSS(GNSSData::TimeFix {
+                    ref time,
+                    fix: Some(ref f),
+                }) => {
+                    self.state.position = Some(Position {
+                        lat: (f.lat_lon.0).0,
+                        lon: (f.lat_lon.0).1,
+                        msl_altitude: mm_to_ft!(f.height_msl.0).round() as i32,
+                        height_ellipsoid: f.height_ellipsoid.0,
+                        time: time.map(|t| t.timestamp()),
+                    });
+                    self.fix_time = Some(clock);
+                    self.dirty = true;
+                }
+                SensorData::Baro(ref b) => {
+                    let msl = match (self.state.position, self.fix_time) {
+                        (Some(p), Some(t)) if (clock - t).as_secs() < MAX_FIX_AGE => p.msl_altitude,
+                        _ => continue,
+                    };
+
+                    let sample = (msl - b.pressure_altitude) as f32;
+                    self.state.baro_offset = Some(match self.state.baro_offset {
+                        Some(o) => o + BARO_OFFSET_WEIGHT * (sample - o),
+                        None => sample,
+                    });
+                    self.dirty = true;
+                }
+                SensorData::DecodeStats(ref s) if !s.peer => {
+                    let totals = match s.band {
+                        TrafficSource::UAT => &mut self.state.uat,
+                        TrafficSource::ES => &mut self.state.es,
+                    };
+
+                    totals.downlink_frames += s.downlink_frames as u64;
+                    totals.uplink_frames += s.uplink_frames as u64;
+                    self.dirty = true;
+                }
+                _ => {} // do nothing
+            }
+        }
+
+        if self.dirty
+            && self
+                .saved
+                .map_or(true, |t| (clock - t).as_secs() >= SAVE_INTERVAL)
+        {
+            self.save(clock);
+        }
+
+        run_every!(REPORT_FREQ, self.report_counter, handle, {
+            handle.push_data(Report::WarmStart(self.state.clone()));
+        });
+    }
+
+    fn shutdown(&mut self) {
+        if self.dirty {
+            info!("saving state for the next run");
+            self.save(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sensor::barometer::BaroData;
+    use sensor::gnss::{Fix, FixQuality};
+    use sensor::sdr::DecodeStats;
+    use std::fs;
+    use testing::{run_processor, temp_dir, MockHandle};
+
+    fn fix(msl: i32) -> SensorData {
+        SensorData::GNSS(GNSSData::TimeFix {
+            time: None,
+            fix: Some(Fix {
+                quality: FixQuality::ThreeDim,
+                num_sv: 9,
+                lat_lon: ((37.5, -122.0), Some(2500)),
+                height_msl: (msl, Some(4000)),
+                height_ellipsoid: (msl - 30000, Some(4000)),
+                gs: (0, None),
+                vs: (0, None),
+                true_course: (0.0, None),
+                mag_dec: None,
+            }),
+        })
+    }
+
+    fn baro(pressure_altitude: i32) -> SensorData {
+        SensorData::Baro(BaroData {
+            sensor_id: "test",
+            pressure: 1000.0,
+            pressure_altitude,
+            temperature: None,
+            humidity: None,
+        })
+    }
+
+    #[test]
+    fn test_baro_offset() {
+        let mut w = WarmStart::new(Storage::disabled());
+        let mut h = MockHandle::new(10);
+
+        // no fix to compare with
+        run_processor(&mut w, &mut h, &[baro(1000)]);
+        assert_eq!(w.state.baro_offset, None);
+
+        // 1500 ft MSL
+        run_processor(&mut w, &mut h, &[fix(457200), baro(1000)]);
+        assert_eq!(w.state.baro_offset, Some(500.0));
+        run_processor(&mut w, &mut h, &[baro(900)]);
+        assert_eq!(w.state.baro_offset, Some(501.0));
+
+        // fix is too old
+        h.advance_secs(MAX_FIX_AGE);
+        run_processor(&mut w, &mut h, &[baro(0)]);
+        assert_eq!(w.state.baro_offset, Some(501.0));
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = temp_dir("warmstart");
+        let storage = Storage::new(dir.to_str().unwrap());
+
+        let mut w = WarmStart::new(storage.clone());
+        let mut h = MockHandle::new(10);
+        assert_eq!(w.position(), None);
+
+        let mut stats = DecodeStats::new(TrafficSource::UAT);
+        stats.downlink_frames = 84;
+        stats.uplink_frames = 312;
+        run_processor(
+            &mut w,
+            &mut h,
+            &[fix(457200), SensorData::DecodeStats(stats.clone())],
+        );
+
+        // saved right away, then not again until the interval is over
+        assert_eq!(WarmStart::new(storage.clone()).state, w.state);
+        run_processor(&mut w, &mut h, &[SensorData::DecodeStats(stats)]);
+        assert_eq!(
+            WarmStart::new(storage.clone()).state.uat,
+            FrameTotals {
+                downlink_frames: 84,
+                uplink_frames: 312,
+            }
+        );
+
+        w.shutdown();
+        let w = WarmStart::new(storage);
+        assert_eq!(w.position(), Some(((37.5, -122.0), 427200)));
+        assert_eq!(w.state.uat.downlink_frames, 168);
+        assert_eq!(w.state.es, FrameTotals::default());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
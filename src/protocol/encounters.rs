@@ -0,0 +1,392 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Saves the tracks of ownship and the intruder around every traffic alert
+//! as a GeoJSON `encounter-<n>.geojson` in the configured directory, from
+//! `ENCOUNTER_SECS` before the alert until `ENCOUNTER_SECS` after it.
+
+use super::*;
+use chrono::prelude::*;
+use chrono::Duration;
+use processor::events::Event;
+use processor::traffic::AltitudeType;
+use recording::create_numbered;
+use serde_json::{self, Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration as StdDuration, Instant};
+
+/// Seconds of track kept on each side of the alert
+const ENCOUNTER_SECS: u64 = 60;
+/// Tracks are thinned to one point per this many seconds
+const POINT_INTERVAL_SECS: u64 = 1;
+
+struct Point {
+    time: Instant,
+    lat: f32,
+    lon: f32,
+    /// in ft
+    altitude: Option<(i32, AltitudeType)>,
+}
+
+struct Track {
+    callsign: Option<String>,
+    points: VecDeque<Point>,
+}
+
+impl Track {
+    fn new() -> Self {
+        Self {
+            callsign: None,
+            points: VecDeque::new(),
+        }
+    }
+
+    /// Adds `p` unless the previous point is less than `POINT_INTERVAL_SECS`
+    /// older
+    fn push(&mut self, p: Point) {
+        if self.points.back().map_or(true, |l| {
+            p.time >= l.time + StdDuration::from_secs(POINT_INTERVAL_SECS)
+        }) {
+            self.points.push_back(p);
+        }
+    }
+
+    /// Drops points older than `2 * ENCOUNTER_SECS`, enough for the
+    /// encounters still pending
+    fn prune(&mut self, clock: Instant) {
+        let keep = StdDuration::from_secs(2 * ENCOUNTER_SECS);
+
+        while self.points.front().map_or(false, |p| clock - p.time > keep) {
+            self.points.pop_front();
+        }
+    }
+}
+
+/// An alert waiting for the rest of its encounter to be flown
+struct Pending {
+    time: Instant,
+    addr: u32,
+    alert: Event,
+}
+
+pub struct Encounters {
+    dir: PathBuf,
+    ownship: Track,
+    traffic: HashMap<u32, Track>,
+    pending: Vec<Pending>,
+}
+
+impl Encounters {
+    pub fn new(dir: &str) -> Box<Protocol> {
+        info!("saving traffic alert encounters to {}", dir);
+
+        Box::new(Self {
+            dir: PathBuf::from(dir),
+            ownship: Track::new(),
+            traffic: HashMap::new(),
+            pending: vec![],
+        })
+    }
+
+    fn save(&self, p: &Pending, clock: Instant, utc: &DateTime<UTC>) {
+        let from = p.time - StdDuration::from_secs(ENCOUNTER_SECS);
+        let empty = Track::new();
+        let intruder = self.traffic.get(&p.addr).unwrap_or(&empty);
+
+        let mut ownship = Map::new();
+        ownship.insert("role".to_string(), "ownship".into());
+
+        let mut target = Map::new();
+        target.insert("role".to_string(), "intruder".into());
+        target.insert("addr".to_string(), format!("{:06X}", p.addr).into());
+        target.insert("callsign".to_string(), intruder.callsign.clone().into());
+
+        let alert = match serde_json::to_value(&p.alert).unwrap() {
+            Value::Object(o) => o,
+            _ => unreachable!(),
+        };
+        let position = self
+            .ownship
+            .points
+            .iter()
+            .rev()
+            .find(|o| o.time <= p.time)
+            .map(|o| vec![Value::from(o.lon), Value::from(o.lat)]);
+
+        let mut features = vec![];
+        features.extend(track(ownship, &self.ownship, from, clock, utc));
+        features.extend(track(target, intruder, from, clock, utc));
+        if let Some(position) = position {
+            features.push(feature("Point", position.into(), alert));
+        }
+
+        let mut js = Map::new();
+        js.insert("type".to_string(), "FeatureCollection".into());
+        js.insert("features".to_string(), features.into());
+
+        let res = create_numbered(&self.dir, "encounter", "geojson").and_then(|(path, mut f)| {
+            try!(f.write_all(Value::Object(js).to_string().as_bytes()));
+            Ok(path)
+        });
+        match res {
+            Ok(path) => info!("saved encounter with {:06X} to {}", p.addr, path.display()),
+            Err(e) => error!(
+                "unable to save encounter into {}: {}",
+                self.dir.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// GeoJSON feature of the points of `t` since `from`, a `LineString` with
+/// the UTC time, altitude and altitude type of every point in `properties`,
+/// or a `Point`
+/// when there are not enough points for a line. `None` without any points.
+fn track(
+    mut properties: Map<String, Value>,
+    t: &Track,
+    from: Instant,
+    clock: Instant,
+    utc: &DateTime<UTC>,
+) -> Option<Value> {
+    let points: Vec<&Point> = t.points.iter().filter(|p| p.time >= from).collect();
+
+    let times: Vec<Value> = points
+        .iter()
+        .map(|p| {
+            let at = *utc - Duration::from_std(clock - p.time).unwrap();
+            at.format("%Y-%m-%dT%H:%M:%SZ").to_string().into()
+        })
+        .collect();
+    let altitudes: Vec<Value> = points
+        .iter()
+        .map(|p| p.altitude.map(|a| a.0).into())
+        .collect();
+    let altitude_types: Vec<Value> = points
+        .iter()
+        .map(|p| serde_json::to_value(p.altitude.map(|a| a.1)).unwrap())
+        .collect();
+    let mut coordinates: Vec<Value> = points
+        .iter()
+        .map(|p| vec![Value::from(p.lon), Value::from(p.lat)].into())
+        .collect();
+
+    properties.insert("coordTimes".to_string(), times.into());
+    properties.insert("altitudes".to_string(), altitudes.into());
+    properties.insert("altitudeTypes".to_string(), altitude_types.into());
+
+    match coordinates.len() {
+        0 => None,
+        1 => Some(feature("Point", coordinates.remove(0), properties)),
+        _ => Some(feature("LineString", coordinates.into(), properties)),
+    }
+}
+
+fn feature(kind: &str, coordinates: Value, properties: Map<String, Value>) -> Value {
+    let mut geometry = Map::new();
+    geometry.insert("type".to_string(), kind.into());
+    geometry.insert("coordinates".to_string(), coordinates);
+
+    let mut js = Map::new();
+    js.insert("type".to_string(), "Feature".into());
+    js.insert("geometry".to_string(), geometry.into());
+    js.insert("properties".to_string(), properties.into());
+
+    Value::Object(js)
+}
+
+impl Protocol for Encounters {
+    fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter) {
+        let clock = handle.get_clock();
+
+        for e in i {
+            match *e {
+                Report::Ownship(ref o) if o.valid => {
+                    // pressure altitude when there is one, like most traffic
+                    let altitude = match o.pressure_altitude {
+                        Some(a) => (a, AltitudeType::Baro),
+                        None => (o.msl_altitude, AltitudeType::GNSS),
+                    };
+
+                    self.ownship.push(Point {
+                        time: clock,
+                        lat: o.lat,
+                        lon: o.lon,
+                        altitude: Some(altitude),
+                    })
+                }
+                Report::Traffic(ref t) => {
+                    let track = self.traffic.entry(t.addr.0).or_insert_with(Track::new);
+
+                    if t.callsign.is_some() {
+                        track.callsign = t.callsign.clone();
+                    }
+                    if let Some(((lat, lon), time)) = t.lat_lon {
+                        track.push(Point {
+                            time,
+                            lat,
+                            lon,
+                            altitude: t.altitude.map(|a| (a.0, a.1)),
+                        });
+                    }
+                }
+                Report::Event(ref a) => {
+                    if let Event::TrafficAlert { addr, .. } = *a {
+                        // a repeated alert is part of the same encounter
+                        if !self.pending.iter().any(|p| p.addr == addr) {
+                            self.pending.push(Pending {
+                                time: clock,
+                                addr,
+                                alert: a.clone(),
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let utc = handle.get_utc();
+        let (due, pending): (Vec<_>, Vec<_>) = self
+            .pending
+            .drain(..)
+            .partition(|p| clock - p.time >= StdDuration::from_secs(ENCOUNTER_SECS));
+        self.pending = pending;
+        for p in &due {
+            self.save(p, clock, &utc);
+        }
+
+        self.ownship.prune(clock);
+        for t in self.traffic.values_mut() {
+            t.prune(clock);
+        }
+        self.traffic.retain(|_, t| !t.points.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Handle;
+    use processor::events::Confidence;
+    use processor::ownship::Ownship;
+    use processor::traffic::{AddressType, Target, TrafficSource};
+    use std::fs::{self, File};
+    use std::sync::Arc;
+    use testing::{run_protocol, temp_dir, MockHandle};
+
+    fn alert(addr: u32) -> Report {
+        Report::Event(Event::TrafficAlert {
+            addr,
+            callsign: None,
+            distance: 0.5,
+            bearing: 90.0,
+            clock: 3,
+            altitude_diff: 200,
+            confidence: Confidence::High,
+        })
+    }
+
+    #[test]
+    fn test_encounter() {
+        let dir = temp_dir("encounters");
+        let mut h = MockHandle::new(10);
+        let mut e = Encounters::new(dir.to_str().unwrap());
+
+        let mut o = Ownship::default();
+        o.valid = true;
+        o.msl_altitude = 3000;
+        let mut t = Target::new(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            h.get_clock(),
+            TrafficSource::UAT,
+            Some("N123AB".to_string()),
+        );
+
+        // 3 minutes of flight, alerted half way through
+        for i in 0..1800 {
+            let clock = h.get_clock();
+            o.lat = 37.0 + i as f32 * 0.0001;
+            if i == 1000 {
+                o.pressure_altitude = Some(2950);
+            }
+            o.lon = -122.0;
+            t.lat_lon = Some(((37.0 + i as f32 * 0.0001, -121.99), clock));
+            t.altitude = Some((3200, AltitudeType::Baro, clock));
+
+            let mut reports = vec![Report::Ownship(o), Report::Traffic(Arc::new(t.clone()))];
+            if i == 900 || i == 950 {
+                reports.push(alert(0xA1B2C3));
+            }
+            run_protocol(&mut *e, &mut h, &reports);
+
+            // only saved once the minute after the alert has passed
+            assert_eq!(dir.join("encounter-0.geojson").exists(), i >= 1500);
+            h.advance(StdDuration::from_millis(100));
+        }
+        assert!(!dir.join("encounter-1.geojson").exists());
+
+        let js: Value =
+            serde_json::from_reader(File::open(dir.join("encounter-0.geojson")).unwrap()).unwrap();
+        assert_eq!(js["type"], "FeatureCollection");
+        let features = js["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+
+        let ownship = &features[0];
+        assert_eq!(ownship["geometry"]["type"], "LineString");
+        assert_eq!(ownship["properties"]["role"], "ownship");
+        // one point per second for ±60 s
+        assert_eq!(
+            ownship["geometry"]["coordinates"].as_array().unwrap().len(),
+            121
+        );
+        assert_eq!(
+            ownship["properties"]["coordTimes"]
+                .as_array()
+                .unwrap()
+                .len(),
+            121
+        );
+        assert_eq!(ownship["properties"]["altitudes"][0], 3000);
+        assert_eq!(ownship["properties"]["altitudeTypes"][0], "GNSS");
+        // pressure altitude once the barometer is read
+        assert_eq!(ownship["properties"]["altitudes"][120], 2950);
+        assert_eq!(ownship["properties"]["altitudeTypes"][120], "Baro");
+
+        let intruder = &features[1];
+        assert_eq!(intruder["properties"]["addr"], "A1B2C3");
+        assert_eq!(intruder["properties"]["callsign"], "N123AB");
+        assert_eq!(intruder["properties"]["altitudes"][0], 3200);
+        assert_eq!(intruder["properties"]["altitudeTypes"][0], "Baro");
+        assert_eq!(
+            intruder["geometry"]["coordinates"]
+                .as_array()
+                .unwrap()
+                .len(),
+            121
+        );
+
+        let alert = &features[2];
+        assert_eq!(alert["geometry"]["type"], "Point");
+        assert_eq!(alert["properties"]["event"], "TrafficAlert");
+        assert_eq!(alert["properties"]["altitude_diff"], 200);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! TCP server for the text and binary feeds other receiver software reads,
+//! e.g. SBS on port 30003. Everything sent is written to every connected
+//! client, nothing is read from them.
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+pub struct Feed {
+    name: &'static str,
+    listener: TcpListener,
+    clients: Vec<(SocketAddr, TcpStream)>,
+}
+
+impl Feed {
+    /// Listen on `port` of every interface, `name` is used in logs
+    pub fn bind(name: &'static str, port: u16) -> Option<Self> {
+        let listener = match TcpListener::bind(("0.0.0.0", port))
+            .and_then(|l| l.set_nonblocking(true).map(|_| l))
+        {
+            Ok(l) => l,
+            Err(e) => {
+                error!(
+                    "unable to listen for {} clients on port {}: {}",
+                    name, port, e
+                );
+                return None;
+            }
+        };
+
+        info!("serving {} on port {}", name, port);
+
+        Some(Self {
+            name,
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    /// Port actually listened on
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().unwrap().port()
+    }
+
+    /// Take in clients that connected since the last call
+    pub fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((s, addr)) => {
+                    if let Err(e) = s.set_nonblocking(true) {
+                        warn!("unable to set up {} client {}: {}", self.name, addr, e);
+                        continue;
+                    }
+
+                    // lines are small and latency matters more than packets
+                    s.set_nodelay(true).ok();
+                    info!("{} client {} connected", self.name, addr);
+                    self.clients.push((addr, s));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("unable to accept {} client: {}", self.name, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn has_clients(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Write `data` to every client. Clients that went away, or read too
+    /// slowly for the socket buffer to take all of it, are disconnected.
+    pub fn send(&mut self, data: &[u8]) {
+        let name = self.name;
+
+        self.clients.retain(|&(addr, ref s)| {
+            let mut s = s;
+
+            match s.write_all(data) {
+                Ok(_) => true,
+                Err(e) => {
+                    info!("{} client {} disconnected: {}", name, addr, e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_feed() {
+        let mut f = Feed::bind("test", 0).unwrap();
+        assert!(!f.has_clients());
+
+        let mut c = TcpStream::connect(("127.0.0.1", f.port())).unwrap();
+        // the connection may not be ready to be accepted right away
+        while !f.has_clients() {
+            f.accept();
+        }
+
+        f.send(b"hello\r\n");
+        let mut buf = [0_u8; 7];
+        c.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello\r\n");
+
+        drop(c);
+        // the first write after the peer closed may still succeed
+        for _ in 0..100 {
+            f.send(b"hello\r\n");
+            if !f.has_clients() {
+                break;
+            }
+        }
+        assert!(!f.has_clients());
+    }
+}
@@ -15,12 +15,17 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::*;
+use chrono::prelude::*;
+use config::{Config, UATDownlink, Uplink};
+use processor::downlink::DownlinkData;
 use processor::fisb::FISBData;
+use processor::gnss::Time;
 use processor::ownship::Ownship;
 use processor::traffic::*;
 use processor::Report;
-use std::time::Instant;
-use time::Tm;
+use sensor::sdr::TrafficData;
+use std::cmp;
+use std::time::{Duration, Instant};
 
 const LON_LAT_RESOLUTION: f32 = 180.0 / 8388608.0; // 2^23 (p. 19)
 const TRACK_RESOLUTION: f32 = 360.0 / 256.0;
@@ -53,56 +58,161 @@ const HEARTBEAT_FREQ: u16 = 1;
 const OWNSHIP_FREQ: u16 = 2;
 const MAX_STALE_SECS: u64 = 6; // do not report data more than 6 sec old
 
+// GNSS time is extrapolated for this long before the heartbeat falls back to
+// the system clock, in seconds
+const MAX_TIME_AGE: u64 = 3;
+
 pub struct GDL90 {
     ownship_valid: bool,
-    heartbeat_counter: u32,
-    ownship_counter: u32,
-    /// true if Pressure altitude source exists
-    pres_alt_valid: bool,
+    /// UTC second the last heartbeat was sent in
+    heartbeat_slot: Option<i64>,
+    /// `OWNSHIP_FREQ`th of a UTC second the last ownship report was sent in
+    ownship_slot: Option<i64>,
+    /// frame of the altitudes in the last ownship report, traffic is sent
+    /// in the same one
+    altitude_frame: AltitudeFrame,
+    /// prefix traffic callsign with source and address type letters
+    annotate_callsign: bool,
+    /// which uplink frames are forwarded
+    uplink: Uplink,
+    /// products forwarded when `uplink` is `Uplink::Essential`
+    uplink_products: Vec<u16>,
+    /// whether UAT traffic is sent translated, raw or both
+    uat_downlink: UATDownlink,
+    /// fix the last ownship report was generated from, ownship is sent more
+    /// often than fixes arrive but latency is only measured once per fix
+    ownship_fix: Option<Instant>,
+    /// latest valid GNSS time, the heartbeat time stamp is taken from it
+    time: Option<Time>,
+}
+
+/// Frame the altitudes sent to EFBs are in. Ownship and traffic altitudes
+/// are always sent in the same one, otherwise the relative altitude of
+/// traffic shown by EFBs is off by the GNSS delta.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AltitudeFrame {
+    /// Ownship has a pressure altitude, traffic GNSS altitude is corrected
+    /// to pressure altitude
+    Pressure,
+    /// Ownship has no pressure altitude, EFBs compare traffic against the
+    /// ownship geometric altitude, so traffic pressure altitude is corrected
+    /// to MSL altitude
+    Geometric,
+}
+
+impl AltitudeFrame {
+    fn of(o: &Ownship) -> Self {
+        if o.pressure_altitude.is_some() {
+            AltitudeFrame::Pressure
+        } else {
+            AltitudeFrame::Geometric
+        }
+    }
+
+    /// Altitude for the ownship report, `None` in the geometric frame where
+    /// it is only in the ownship geometric altitude report
+    fn ownship(self, o: &Ownship) -> Option<i32> {
+        match self {
+            AltitudeFrame::Pressure => o.pressure_altitude,
+            AltitudeFrame::Geometric => None,
+        }
+    }
+
+    /// Traffic altitude `alt` of type `typ` in this frame. Sent as is when
+    /// the target does not report a GNSS delta to correct it with.
+    fn traffic(self, alt: i32, typ: AltitudeType, gnss_delta: Option<i32>) -> i32 {
+        match (self, typ, gnss_delta) {
+            (AltitudeFrame::Geometric, AltitudeType::Baro, Some(delta)) => alt + delta,
+            (AltitudeFrame::Pressure, AltitudeType::GNSS, Some(delta)) => alt - delta,
+            _ => alt,
+        }
+    }
 }
 
 impl Protocol for GDL90 {
     fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter) {
         let clock = handle.get_clock();
-
-        self.ownship_counter += 1;
-        self.heartbeat_counter += 1;
+        let utc = handle.get_utc();
 
         for e in i {
             match *e {
                 Report::Ownship(ref o) => {
-                    if self.ownship_counter >= (handle.get_frequency() / OWNSHIP_FREQ) as u32 {
-                        self.ownship_counter = 0;
+                    let slot = slot(&self.gnss_utc(clock).unwrap_or(utc), OWNSHIP_FREQ);
+
+                    if self.ownship_slot != Some(slot) {
+                        self.ownship_slot = Some(slot);
                         self.ownship_valid = o.valid;
+                        self.altitude_frame = AltitudeFrame::of(o);
 
-                        if o.pressure_altitude.is_some() {
-                            self.pres_alt_valid = true;
+                        let mut ownship = GDL90::generate_ownship(o, self.altitude_frame);
+                        if o.fix_time() != self.ownship_fix {
+                            self.ownship_fix = o.fix_time();
+                            ownship.received = self.ownship_fix.map(|t| (Class::Ownship, t));
                         }
 
-                        handle.push_data(GDL90::generate_ownship(o));
+                        handle.push_data(ownship);
                         handle.push_data(GDL90::generate_ownship_geometric_altitude(o));
+
+                        if o.ias.is_some() {
+                            handle.push_data(GDL90::generate_foreflight_ahrs(o));
+                        }
                     }
                 }
-                Report::Traffic(ref o) => {
+                Report::Traffic(ref o)
+                    if self.uat_downlink != UATDownlink::Raw || o.source != TrafficSource::UAT =>
+                {
                     // throttle for Target type is done at traffic processor
-                    handle.push_data(GDL90::generate_traffic(o, clock, self.pres_alt_valid));
+                    handle.push_data(GDL90::generate_traffic(
+                        o,
+                        clock,
+                        self.altitude_frame,
+                        self.annotate_callsign,
+                    ));
+                }
+                Report::Downlink(ref o) if self.uat_downlink != UATDownlink::Translated => {
+                    handle.push_data(GDL90::generate_downlink(o))
+                }
+                Report::FISB(ref o) if self.forward_uplink(o) => {
+                    handle.push_data(self.generate_uplink(o))
+                }
+                Report::Time(t) => self.time = Some(t),
+                Report::Config(ref c) => {
+                    self.annotate_callsign = c.gdl90_callsign_annotation;
+                    self.uplink = c.gdl90_uplink;
+                    self.uplink_products = c.gdl90_uplink_products.clone();
+                    self.uat_downlink = c.gdl90_uat_downlink;
                 }
-                Report::FISB(ref o) => handle.push_data(GDL90::generate_uplink(o)),
                 _ => {}
             }
         }
 
-        if self.heartbeat_counter == (handle.get_frequency() / HEARTBEAT_FREQ) as u32 {
-            self.heartbeat_counter = 0;
-            let utc = handle.get_utc();
-            handle.push_data(self.generate_heartbeat(&utc));
+        // sent right after the top of every UTC second, some clients
+        // synchronize their time to it
+        let slot = slot(&self.gnss_utc(clock).unwrap_or(utc), HEARTBEAT_FREQ);
+        if self.heartbeat_slot != Some(slot) {
+            self.heartbeat_slot = Some(slot);
+            handle.push_data(self.generate_heartbeat(&utc, clock));
             handle.push_data(GDL90::generate_foreflight_id());
         }
     }
 }
 
 impl GDL90 {
-    fn generate_heartbeat(&self, utc: &Tm) -> Payload {
+    fn forward_uplink(&self, e: &FISBData) -> bool {
+        match self.uplink {
+            Uplink::Off => false,
+            Uplink::Essential => e
+                .product_ids()
+                .iter()
+                .any(|p| self.uplink_products.contains(p)),
+            Uplink::Full => true,
+        }
+    }
+
+    /// Time stamp from GNSS time if it is recent, otherwise from the system
+    /// clock `utc` with UTC OK cleared since it may not have been set from
+    /// GNSS yet and could be stepped at any time
+    fn generate_heartbeat(&self, utc: &DateTime<UTC>, clock: Instant) -> Payload {
         let mut buf = [0_u8; 7 + 2]; // incl CRC field
 
         buf[0] = 0x00; // type = heartbeat
@@ -112,24 +222,44 @@ impl GDL90 {
             buf[1] |= 0x80;
         }
 
-        let midnight_utc = Tm {
-            tm_hour: 0,
-            tm_min: 0,
-            tm_sec: 0,
-            ..*utc
+        let (delta, utc_ok) = match self.gnss_utc(clock) {
+            Some(t) => (t.num_seconds_from_midnight(), true),
+            None => (utc.num_seconds_from_midnight(), false),
         };
-        let delta = (*utc - midnight_utc).num_seconds();
 
-        buf[2] = ((delta & 0x10000) >> 9) as u8 | 0x01; // MSB + UTC OK
+        buf[2] = ((delta & 0x10000) >> 9) as u8; // MSB
+        if utc_ok {
+            buf[2] |= 0x01;
+        }
         buf[3] = (delta & 0xFF) as u8;
         buf[4] = ((delta & 0xFF00) >> 8) as u8;
 
         Payload {
             queueable: false,
             payload: GDL90::prepare_payload(&mut buf),
+            received: None,
+            trace: None,
         }
     }
 
+    /// GNSS time at `clock`, unless it is too far from the time last received
+    /// to be extrapolated
+    fn gnss_utc(&self, clock: Instant) -> Option<DateTime<UTC>> {
+        self.time.and_then(|t| {
+            let d = if clock >= t.clock {
+                clock - t.clock
+            } else {
+                t.clock - clock
+            };
+
+            if d < Duration::from_secs(MAX_TIME_AGE) {
+                Some(t.at(clock))
+            } else {
+                None
+            }
+        })
+    }
+
     fn generate_foreflight_id() -> Payload {
         // see: https://www.foreflight.com/connect/spec/
 
@@ -161,23 +291,86 @@ impl GDL90 {
         Payload {
             queueable: false,
             payload: GDL90::prepare_payload(&mut buf),
+            received: None,
+            trace: None,
         }
     }
 
-    fn generate_uplink(e: &FISBData) -> Payload {
+    fn generate_foreflight_ahrs(e: &Ownship) -> Payload {
+        // see: https://www.foreflight.com/connect/spec/
+
+        let mut buf = [0_u8; 12 + 2]; // incl CRC field
+
+        buf[0] = 0x65; // type = FF
+        buf[1] = 0x01; // sub ID = AHRS
+
+        // no attitude source yet
+        buf[2] = 0x7F; // roll = invalid
+        buf[3] = 0xFF;
+        buf[4] = 0x7F; // pitch = invalid
+        buf[5] = 0xFF;
+        buf[6] = 0xFF; // heading = invalid
+        buf[7] = 0xFF;
+
+        let ias = e.ias.map_or(0xFFFF, |s| s.round() as u16);
+        buf[8] = (ias >> 8) as u8;
+        buf[9] = (ias & 0x00FF) as u8;
+
+        let tas = e.tas.map_or(0xFFFF, |s| s.round() as u16);
+        buf[10] = (tas >> 8) as u8;
+        buf[11] = (tas & 0x00FF) as u8;
+
+        Payload {
+            queueable: false,
+            payload: GDL90::prepare_payload(&mut buf),
+            received: None,
+            trace: None,
+        }
+    }
+
+    fn generate_uplink(&self, e: &FISBData) -> Payload {
         let mut buf = [0_u8; 436 + 2]; // incl CRC field
 
         buf[0] = 0x07; // type = uplink
 
+        // time of reception since the start of the UTC second in 80 ns,
+        // 0xFFFFFF if unknown (p. 14)
+        let tor = match e.received.and_then(|r| self.gnss_utc(r)) {
+            Some(t) => cmp::min(t.nanosecond(), 999_999_999) / 80,
+            None => 0xFFFFFF,
+        };
+        buf[1] = (tor & 0xFF) as u8;
+        buf[2] = ((tor & 0xFF00) >> 8) as u8;
+        buf[3] = ((tor & 0xFF0000) >> 16) as u8;
+
+        buf[4..436].clone_from_slice(&e.payload);
+
+        Payload {
+            queueable: true,
+            payload: GDL90::prepare_payload(&mut buf),
+            received: e.received.map(|r| (Class::Uplink, r)),
+            trace: None,
+        }
+    }
+
+    fn generate_downlink(e: &DownlinkData) -> Payload {
+        let mut buf = vec![0_u8; 4 + e.payload.len() + 2]; // incl CRC field
+
+        // type = basic or long UAT report
+        buf[0] = if e.payload.len() > 18 { 0x1F } else { 0x1E };
+
+        // time of reception unknown
         buf[1] = 0xFF;
         buf[2] = 0xFF;
         buf[3] = 0xFF;
 
-        &buf[4..436].clone_from_slice(&e.payload);
+        buf[4..4 + e.payload.len()].clone_from_slice(&e.payload);
 
         Payload {
-            queueable: true,
+            queueable: false,
             payload: GDL90::prepare_payload(&mut buf),
+            received: None,
+            trace: None,
         }
     }
 
@@ -197,10 +390,12 @@ impl GDL90 {
         Payload {
             queueable: false,
             payload: GDL90::prepare_payload(&mut buf),
+            received: None,
+            trace: None,
         }
     }
 
-    fn generate_ownship(e: &Ownship) -> Payload {
+    fn generate_ownship(e: &Ownship, frame: AltitudeFrame) -> Payload {
         let mut buf = [0_u8; 28 + 2]; // incl CRC field
 
         buf[0] = 0x0A;
@@ -222,7 +417,7 @@ impl GDL90 {
         buf[10] = lon3;
 
         // altitude
-        if let Some(alt) = e.pressure_altitude {
+        if let Some(alt) = frame.ownship(e) {
             let alt = alt_to_gdl90(alt as f32);
             buf[11] = ((alt & 0xFF0) >> 4) as u8;
             buf[12] = (((alt & 0x00F) << 4) | 0x09) as u8; // Airborne + True Track
@@ -233,7 +428,7 @@ impl GDL90 {
 
         buf[13] = (e.nic << 4) & 0xF0 | e.nacp & 0x0F;
 
-        let gs = e.gs.round() as u16;
+        let gs = cmp::min(e.gs.round() as u16, 0xFFE);
         let vs = 0x800_u16; // "no vertical rate available"
         buf[14] = ((gs & 0xFF0) >> 4) as u8;
         buf[15] = (((gs & 0x00F) << 4) | ((vs & 0x0F00) >> 8)) as u8;
@@ -252,22 +447,42 @@ impl GDL90 {
         Payload {
             queueable: false,
             payload: GDL90::prepare_payload(&mut buf),
+            received: None,
+            trace: None,
         }
     }
 
-    fn generate_traffic(e: &Target, clock: Instant, pres_alt_valid: bool) -> Payload {
+    pub fn generate_traffic(
+        e: &Target,
+        clock: Instant,
+        frame: AltitudeFrame,
+        annotate_callsign: bool,
+    ) -> Payload {
         let mut buf = [0_u8; 28 + 2]; // incl CRC field
 
         buf[0] = 0x14;
 
+        // another target owns this address, report an ICAO one as non-ICAO
+        // so EFBs do not merge the two
+        let shared = e.sub_id != 0;
         buf[1] = match e.addr.1 {
+            AddressType::ADSBICAO | AddressType::ADSRICAO if shared => 1,
             AddressType::ADSBICAO | AddressType::ADSRICAO => 0,
             AddressType::ADSBOther | AddressType::ADSROther => 1,
+            AddressType::TISBICAO if shared => 3,
             AddressType::TISBICAO => 2,
             AddressType::TISBOther => 3,
+            AddressType::SurfaceVehicle => 4,
+            AddressType::FixedBeacon => 5,
             _ => 3, // unknown
         };
 
+        if e.ident.is_some() {
+            // traffic alert status, makes the target stand out while
+            // it is squawking IDENT
+            buf[1] |= 0x10;
+        }
+
         buf[2] = ((0xFF0000 & e.addr.0) >> 16) as u8; // address
         buf[3] = ((0x00FF00 & e.addr.0) >> 8) as u8;
         buf[4] = (0x0000FF & e.addr.0) as u8;
@@ -292,39 +507,20 @@ impl GDL90 {
             }
         }
 
-        // altitude
+        // altitude, invalid unless recent
+        buf[11] = 0xFF;
+        buf[12] = 0xF0;
+
         if let Some((alt, typ, i)) = e.altitude {
             if (clock - i).as_secs() <= MAX_STALE_SECS {
-                let mut corrected_alt = alt;
-
-                // if ownship pressure altitude is NOT available, use MSL and attempt to correct it
-                // using GNSS delta if needed
-                if !pres_alt_valid && typ == AltitudeType::Baro {
-                    // GDL90 wants pres altitude, try to calculate it from GNSS altitude
-                    // if correction is available
-
-                    // Note: GDL90 wants pressure altitude here,
-                    // but FF currently uses MSL altitude from
-                    // ownship geometric report when calculating altitude
-                    // difference, this is to correct Baro altitude
-                    // to MSL so that the calculation will be as accurate as possible
-                    if let Some(delta) = e.gnss_delta {
-                        corrected_alt += delta;
-                    }
-                } else if pres_alt_valid && typ == AltitudeType::GNSS {
-                    if let Some(delta) = e.gnss_delta {
-                        corrected_alt -= delta;
-                    }
-                }
-
-                let alt = alt_to_gdl90(corrected_alt as f32);
+                // Note: GDL90 wants pressure altitude here, but without
+                // ownship pressure altitude FF uses the MSL altitude from the
+                // ownship geometric report when calculating the altitude
+                // difference, see `AltitudeFrame`
+                let alt = alt_to_gdl90(frame.traffic(alt, typ, e.gnss_delta) as f32);
                 buf[11] = ((alt & 0xFF0) >> 4) as u8;
                 buf[12] = ((alt & 0x00F) << 4) as u8;
             }
-        } else {
-            // invalid altitude
-            buf[11] = 0xFF;
-            buf[12] = 0xF0;
         }
 
         if let Some((_, typ, i)) = e.heading {
@@ -351,6 +547,7 @@ impl GDL90 {
 
         if let Some((spd, _, i)) = e.speed {
             if (clock - i).as_secs() <= MAX_STALE_SECS {
+                let spd = cmp::min(spd, 0xFFE); // 0xFFE is 4094 kts or more (p. 21)
                 buf[14] = ((spd & 0xFF0) >> 4) as u8;
                 buf[15] = ((spd & 0x00F) << 4) as u8;
             }
@@ -359,6 +556,7 @@ impl GDL90 {
         if let Some((vs, i)) = e.vs {
             if (clock - i).as_secs() <= MAX_STALE_SECS {
                 let vs = (vs as f32 / 64_f32).round() as i16; // see p. 21
+                let vs = cmp::max(-0x1FE, cmp::min(vs, 0x1FE)); // more than +/- 32576 fpm
                 buf[15] |= ((vs & 0xF00) >> 8) as u8;
                 buf[16] = (vs & 0xFF) as u8;
             } else {
@@ -377,22 +575,42 @@ impl GDL90 {
             buf[18] = cat;
         }
 
-        // insert traffic source
-        buf[19] = match e.source {
-            TrafficSource::UAT => 'u',
-            TrafficSource::ES => 'e',
-        } as u8;
-
-        buf[20] = match e.addr.1 {
-            AddressType::ADSBICAO | AddressType::ADSBOther => 'a',
-            AddressType::ADSRICAO | AddressType::ADSROther => 'r',
-            AddressType::TISBICAO | AddressType::TISBOther => 't',
-            _ => 'x',
-        } as u8;
+        let callsign_start = if annotate_callsign {
+            // insert traffic source
+            buf[19] = match e.source {
+                TrafficSource::UAT => 'u',
+                TrafficSource::ES => 'e',
+            } as u8;
+
+            buf[20] = match e.addr.1 {
+                AddressType::ADSBICAO | AddressType::ADSBOther => 'a',
+                AddressType::ADSRICAO | AddressType::ADSROther => 'r',
+                AddressType::TISBICAO | AddressType::TISBOther => 't',
+                AddressType::SurfaceVehicle => 'v',
+                AddressType::FixedBeacon => 'b',
+                _ => 'x',
+            } as u8;
+
+            21
+        } else {
+            // leave the callsign field alone and put the same information
+            // into the spare nibble instead
+            buf[27] = match e.source {
+                TrafficSource::UAT => 0x00,
+                TrafficSource::ES => 0x04,
+            } | match e.addr.1 {
+                AddressType::ADSBICAO | AddressType::ADSBOther => 0x00,
+                AddressType::ADSRICAO | AddressType::ADSROther => 0x01,
+                AddressType::TISBICAO | AddressType::TISBOther => 0x02,
+                _ => 0x03,
+            };
+
+            19
+        };
 
         if let Some(ref cs) = e.callsign {
-            for (i, c) in cs.chars().take(6).enumerate() {
-                buf[21 + i] = c as u8;
+            for (i, c) in cs.chars().take(27 - callsign_start).enumerate() {
+                buf[callsign_start + i] = c as u8;
             }
         } else if let Some(sq) = e.squawk {
             // squawk available?
@@ -400,37 +618,31 @@ impl GDL90 {
             debug_assert!(squawk_str.len() == 4);
             let squawk_str = squawk_str.as_bytes();
 
-            buf[21] = squawk_str[0];
-            buf[22] = squawk_str[1];
-            buf[23] = squawk_str[2];
-            buf[24] = squawk_str[3];
+            buf[callsign_start] = squawk_str[0];
+            buf[callsign_start + 1] = squawk_str[1];
+            buf[callsign_start + 2] = squawk_str[2];
+            buf[callsign_start + 3] = squawk_str[3];
         }
 
         if let Some(sq) = e.squawk {
             if sq == 7700 || sq == 7600 || sq == 7500 {
-                buf[27] = 0x10; // emergency aircraft
+                buf[27] |= 0x10; // emergency aircraft
             }
         }
 
         Payload {
             queueable: false,
             payload: GDL90::prepare_payload(&mut buf),
+            received: e.received.map(|r| (Class::Traffic, r)),
+            trace: e.trace,
         }
     }
 
     /// Given a buffer containing everything between "Flag Bytes" (see p. 5)
     /// with the CRC field space allocated but left empty for calculation
-    fn prepare_payload(buf: &mut [u8]) -> Vec<u8> {
+    pub fn prepare_payload(buf: &mut [u8]) -> Vec<u8> {
         let len = buf.len() - 2;
-
-        let crc = buf.iter()
-            .take(len)
-            .scan(0_u16, |crc, b| {
-                *crc = CRC16_TABLE[(*crc >> 8) as usize] ^ (*crc << 8) ^ (*b as u16);
-                Some(*crc)
-            })
-            .last()
-            .unwrap();
+        let crc = crc16(&buf[..len]);
 
         buf[len] = (crc & 0xFF) as u8;
         buf[len + 1] = (crc >> 8) as u8;
@@ -455,16 +667,236 @@ impl GDL90 {
 }
 
 impl GDL90 {
-    pub fn new() -> Box<Protocol> {
+    pub fn new(config: &Config) -> Box<Protocol> {
         Box::new(GDL90 {
             ownship_valid: false,
-            heartbeat_counter: 0,
-            ownship_counter: 0,
-            pres_alt_valid: false,
+            heartbeat_slot: None,
+            ownship_slot: None,
+            altitude_frame: AltitudeFrame::Geometric,
+            annotate_callsign: config.gdl90_callsign_annotation,
+            uplink: config.gdl90_uplink,
+            uplink_products: config.gdl90_uplink_products.clone(),
+            uat_downlink: config.gdl90_uat_downlink,
+            ownship_fix: None,
+            time: None,
         })
     }
 }
 
+fn crc16(buf: &[u8]) -> u16 {
+    buf.iter().fold(0_u16, |crc, b| {
+        CRC16_TABLE[(crc >> 8) as usize] ^ (crc << 8) ^ (*b as u16)
+    })
+}
+
+/// Split a GDL 90 byte stream into messages with flag bytes, byte stuffing
+/// and CRC removed. Messages failing the CRC check are returned as errors.
+pub fn parse_stream(stream: &[u8]) -> Vec<Result<Vec<u8>, String>> {
+    stream
+        .split(|b| *b == 0x7E)
+        .filter(|m| !m.is_empty())
+        .map(|m| {
+            let mut buf = Vec::with_capacity(m.len());
+            let mut escaped = false;
+
+            for b in m {
+                if escaped {
+                    buf.push(*b ^ 0x20);
+                    escaped = false;
+                } else if *b == 0x7D {
+                    escaped = true;
+                } else {
+                    buf.push(*b);
+                }
+            }
+
+            if buf.len() < 3 {
+                return Err(format!("{} byte message too short", buf.len()));
+            }
+
+            let len = buf.len() - 2;
+            let crc = buf[len] as u16 | (buf[len + 1] as u16) << 8;
+            if crc16(&buf[..len]) != crc {
+                return Err(format!("CRC mismatch in message ID {}", buf[0]));
+            }
+
+            buf.truncate(len);
+            Ok(buf)
+        })
+        .collect()
+}
+
+/// Human readable summary of a message returned by `parse_stream`
+pub fn describe(msg: &[u8]) -> String {
+    match msg[0] {
+        0x00 if msg.len() >= 7 => {
+            let secs = ((msg[2] as u32 & 0x80) << 9) | (msg[4] as u32) << 8 | msg[3] as u32;
+
+            format!(
+                "Heartbeat {:02}:{:02}:{:02}Z{}, GPS position {}",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60,
+                if msg[2] & 0x01 != 0 {
+                    ""
+                } else {
+                    " (UTC not OK)"
+                },
+                if msg[1] & 0x80 != 0 {
+                    "valid"
+                } else {
+                    "invalid"
+                }
+            )
+        }
+        0x07 => format!("Uplink, {} bytes", msg.len() - 1),
+        0x0A | 0x14 if msg.len() >= 28 => {
+            let alt = (msg[11] as i32) << 4 | (msg[12] as i32) >> 4;
+            let gs = (msg[14] as u16) << 4 | (msg[15] as u16) >> 4;
+            let callsign: String = msg[19..27]
+                .iter()
+                .map(|c| *c as char)
+                .collect::<String>()
+                .trim_right_matches(|c| c == '\0' || c == ' ')
+                .to_string();
+
+            format!(
+                "{} {:06X} (address type {}), {:.5} {:.5}, {}, {}, track {:.0}, NIC {} NACp {}, category {}, {:?}",
+                if msg[0] == 0x0A { "Ownship" } else { "Traffic" },
+                (msg[2] as u32) << 16 | (msg[3] as u32) << 8 | msg[4] as u32,
+                msg[1] & 0x0F,
+                gdl90_to_latlon(&msg[5..8]),
+                gdl90_to_latlon(&msg[8..11]),
+                if alt == 0xFFF {
+                    "no altitude".to_string()
+                } else {
+                    format!("{} ft", alt * 25 - 1000)
+                },
+                if gs == 0xFFF {
+                    "no speed".to_string()
+                } else {
+                    format!("{} kts", gs)
+                },
+                msg[17] as f32 * TRACK_RESOLUTION,
+                msg[13] >> 4,
+                msg[13] & 0x0F,
+                msg[18],
+                callsign
+            )
+        }
+        0x0B if msg.len() >= 3 => format!(
+            "Ownship geometric altitude {} ft",
+            ((msg[1] as i16) << 8 | msg[2] as i16) as i32 * 5
+        ),
+        0x1E => format!("Basic UAT report, {} bytes", msg.len() - 1),
+        0x1F => format!("Long UAT report, {} bytes", msg.len() - 1),
+        0x65 if msg.len() >= 2 => match msg[1] {
+            0x00 => "ForeFlight ID".to_string(),
+            0x01 => "ForeFlight AHRS".to_string(),
+            id => format!("ForeFlight sub ID {}", id),
+        },
+        id => format!("Message ID {}, {} bytes", id, msg.len() - 1),
+    }
+}
+
+/// Traffic report (message ID 20) from a message returned by `parse_stream`,
+/// as if it was received over the air. GDL 90 does not tell UAT and 1090ES
+/// traffic apart, all of it is treated as 1090ES.
+pub fn parse_traffic(msg: &[u8]) -> Option<TrafficData> {
+    if msg.len() < 28 || msg[0] != 0x14 {
+        return None;
+    }
+
+    let addr_type = match msg[1] & 0x0F {
+        0 => AddressType::ADSBICAO,
+        1 => AddressType::ADSBOther,
+        2 => AddressType::TISBICAO,
+        3 => AddressType::TISBOther,
+        4 => AddressType::SurfaceVehicle,
+        5 => AddressType::FixedBeacon,
+        _ => AddressType::Unknown,
+    };
+
+    let nic = msg[13] >> 4;
+    let lat = gdl90_to_latlon(&msg[5..8]);
+    let lon = gdl90_to_latlon(&msg[8..11]);
+    let alt = (msg[11] as i32) << 4 | (msg[12] as i32) >> 4;
+    let misc = msg[12] & 0x0F;
+    let track = (msg[17] as f32 * TRACK_RESOLUTION).round() as u16 % 360;
+    let gs = (msg[14] as u16) << 4 | (msg[15] as u16) >> 4;
+    // 12 bit two's complement in units of 64 fpm
+    let vs = (((msg[15] as u16 & 0x0F) << 8 | msg[16] as u16) << 4) as i16 >> 4;
+
+    let callsign: String = msg[19..27]
+        .iter()
+        .map(|c| *c as char)
+        .collect::<String>()
+        .trim_right_matches(|c| c == '\0' || c == ' ')
+        .to_string();
+
+    Some(TrafficData {
+        addr: (
+            (msg[2] as u32) << 16 | (msg[3] as u32) << 8 | msg[4] as u32,
+            addr_type,
+        ),
+        altitude: if alt == 0xFFF {
+            None
+        } else {
+            Some((alt * 25 - 1000, AltitudeType::Baro))
+        },
+        gnss_delta: None,
+        heading: match misc & 0x03 {
+            0x01 | 0x03 => Some((track, HeadingType::True)),
+            0x02 => Some((track, HeadingType::Mag)),
+            _ => None,
+        },
+        speed: if gs == 0xFFF {
+            None
+        } else {
+            Some((gs, SpeedType::GS))
+        },
+        vs: if vs == -0x800 { None } else { Some(vs * 64) },
+        squawk: None,
+        callsign: if callsign.is_empty() {
+            None
+        } else {
+            Some(callsign)
+        },
+        category: if msg[18] == 0 { None } else { Some(msg[18]) },
+        // all zero with NIC 0 means no valid position
+        lat_lon: if lat == 0.0 && lon == 0.0 && nic == 0 {
+            None
+        } else {
+            Some((lat, lon))
+        },
+        nic: Some(nic),
+        nacp: Some(msg[13] & 0x0F),
+        nacv: None,
+        sil: None,
+        ident: None,
+        capability: None,
+        on_ground: Some(misc & 0x08 == 0),
+        source: TrafficSource::ES,
+        received: None,
+        trace: None,
+        raw: None,
+    })
+}
+
+/// Number of the `freq`th of a second `t` is in, counted from the UNIX
+/// epoch
+fn slot(t: &DateTime<UTC>, freq: u16) -> i64 {
+    let nanos = cmp::min(t.nanosecond(), 999_999_999) as i64;
+    t.timestamp() * freq as i64 + nanos * freq as i64 / 1_000_000_000
+}
+
+/// Coordinate in degrees from its GDL 90 formatted byte sequence
+fn gdl90_to_latlon(b: &[u8]) -> f32 {
+    // 24 bit two's complement
+    (((b[0] as i32) << 24 | (b[1] as i32) << 16 | (b[2] as i32) << 8) >> 8) as f32
+        * LON_LAT_RESOLUTION
+}
+
 /// Given coordinate in degrees, return the GDL 90 formatted byte sequence
 /// From: https://github.com/cyoung/stratux/blob/master/main/gen_gdl90.go#L206
 fn latlon_to_gdl90(mut d: f32) -> (u8, u8, u8) {
@@ -490,7 +922,7 @@ fn alt_to_gdl90(mut a: f32) -> u16 {
 }
 
 fn crs_to_gdl90(mut c: f32) -> u8 {
-    while c > 360_f32 {
+    while c >= 360_f32 {
         c -= 360_f32;
     }
 
@@ -504,6 +936,161 @@ fn crs_to_gdl90(mut c: f32) -> u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pitot::handle::Handle;
+    use std::sync::Arc;
+    use testing::{run_protocol, MockHandle};
+
+    #[test]
+    fn test_heartbeat_rate() {
+        let mut g = GDL90::new(&Config::default());
+        let mut h = MockHandle::new(10);
+
+        for _ in 0..10 {
+            run_protocol(&mut *g, &mut h, &[]);
+        }
+        h.advance_secs(1);
+        run_protocol(&mut *g, &mut h, &[]);
+
+        // heartbeat and ForeFlight ID, once per second of the system clock
+        // without GNSS time
+        let sent = h.take();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[0].payload[1], 0x00);
+        assert_eq!(sent[1].payload[1], 0x65);
+    }
+
+    #[test]
+    fn test_uplink_filter() {
+        let mut payload = vec![0_u8; 432];
+        // a single METAR text (413) APDU
+        payload[8] = 0x02;
+        payload[10] = (413 >> 6) as u8;
+        payload[11] = ((413 & 0x3F) << 2) as u8;
+        let uplink = [Report::FISB(FISBData {
+            payload,
+            received: None,
+        })];
+
+        let mut c = Config::default();
+        let mut h = MockHandle::new(10);
+
+        for (mode, products, forwarded) in vec![
+            (Uplink::Full, vec![], 1),
+            (Uplink::Off, vec![413], 0),
+            (Uplink::Essential, vec![63, 413], 1),
+            (Uplink::Essential, vec![63], 0),
+        ] {
+            c.gdl90_uplink = mode;
+            c.gdl90_uplink_products = products;
+            let mut g = GDL90::new(&c);
+
+            run_protocol(&mut *g, &mut h, &uplink);
+            let sent = h.take();
+            assert_eq!(
+                sent.iter().filter(|p| p.payload[1] == 0x07).count(),
+                forwarded
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let clock = Instant::now();
+        let mut trfc = Target::new(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            clock,
+            TrafficSource::ES,
+            Some("TEST123".into()),
+        );
+        trfc.altitude = Some((12375, AltitudeType::Baro, clock));
+        trfc.lat_lon = Some(((37.750374, -122.52676), clock));
+
+        let mut stream =
+            GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Pressure, false).payload;
+        // needs byte stuffing
+        stream.extend(GDL90::prepare_payload(&mut [0x0B, 0x7E, 0x7D, 0, 0]));
+        // corrupted
+        stream.extend_from_slice(&[0x7E, 0x0B, 0x00, 0x02, 0xFF, 0xFF, 0x7E]);
+
+        let msgs = parse_stream(&stream);
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(
+            describe(msgs[0].as_ref().unwrap()),
+            "Traffic A1B2C3 (address type 0), 37.75037 -122.52676, 12375 ft, no speed, \
+             track 0, NIC 0 NACp 0, category 0, \"TEST123\""
+        );
+        assert_eq!(
+            describe(msgs[1].as_ref().unwrap()),
+            "Ownship geometric altitude 161905 ft"
+        );
+        assert!(msgs[2].is_err());
+    }
+
+    #[test]
+    fn test_parse_traffic() {
+        let clock = Instant::now();
+        let mut trfc = Target::new(
+            (0xA1B2C3, AddressType::TISBOther),
+            clock,
+            TrafficSource::ES,
+            Some("TEST123".into()),
+        );
+        trfc.altitude = Some((12375, AltitudeType::Baro, clock));
+        trfc.heading = Some((123, HeadingType::True, clock));
+        trfc.speed = Some((66, SpeedType::GS, clock));
+        trfc.vs = Some((-1024, clock));
+        trfc.category = Some(3);
+        trfc.lat_lon = Some(((37.750374, -122.52676), clock));
+        trfc.nic = Some(7);
+        trfc.nacp = Some(9);
+
+        let msg = GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Pressure, false).payload;
+        let t = parse_traffic(parse_stream(&msg)[0].as_ref().unwrap()).unwrap();
+
+        assert_eq!(t.addr, (0xA1B2C3, AddressType::TISBOther));
+        assert_eq!(t.altitude, Some((12375, AltitudeType::Baro)));
+        assert_eq!(t.heading, Some((122, HeadingType::True)));
+        assert_eq!(t.speed, Some((66, SpeedType::GS)));
+        assert_eq!(t.vs, Some(-1024));
+        assert_eq!(t.callsign, Some("TEST123".to_string()));
+        assert_eq!(t.category, Some(3));
+        assert!((t.lat_lon.unwrap().0 - 37.750374).abs() < 0.0001);
+        assert!((t.lat_lon.unwrap().1 + 122.52676).abs() < 0.0001);
+        assert_eq!((t.nic, t.nacp), (Some(7), Some(9)));
+        assert_eq!(t.on_ground, Some(false));
+    }
+
+    #[test]
+    fn test_uat_downlink() {
+        let basic = [Report::Downlink(DownlinkData {
+            payload: vec![0x08; 18],
+        })];
+        let long = [Report::Downlink(DownlinkData {
+            payload: vec![0x08; 34],
+        })];
+
+        let mut c = Config::default();
+        let mut h = MockHandle::new(10);
+
+        let mut g = GDL90::new(&c);
+        run_protocol(&mut *g, &mut h, &basic);
+        assert!(h.take().iter().all(|p| p.payload[1] != 0x1E));
+
+        c.gdl90_uat_downlink = UATDownlink::Raw;
+        let mut g = GDL90::new(&c);
+
+        run_protocol(&mut *g, &mut h, &basic);
+        let sent = h.take();
+        // followed by the heartbeat and ForeFlight ID of the first run
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].payload[1], 0x1E);
+        assert_eq!(sent[0].payload.len(), 1 + 22 + 2 + 1);
+
+        run_protocol(&mut *g, &mut h, &long);
+        let sent = h.take();
+        assert_eq!(sent[0].payload[1], 0x1F);
+        assert_eq!(sent[0].payload.len(), 1 + 38 + 2 + 1);
+    }
 
     #[test]
     fn test_alt_to_gdl90() {
@@ -528,6 +1115,30 @@ mod tests {
         assert_eq!(crs_to_gdl90(360_f32), 0x00);
     }
 
+    #[test]
+    fn test_generate_foreflight_ahrs() {
+        let mut o = Ownship::default();
+        o.ias = Some(100.2);
+        o.tas = Some(110.0);
+
+        let payload = GDL90::generate_foreflight_ahrs(&o);
+        let expected = [
+            0x7E, 0x65, 0x01, 0x7F, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0x00, 0x64, 0x00, 0x6E, 0x47,
+            0xDC, 0x7E,
+        ];
+
+        assert_eq!(payload.payload, &expected);
+
+        o.tas = None;
+        let payload = GDL90::generate_foreflight_ahrs(&o);
+        let expected = [
+            0x7E, 0x65, 0x01, 0x7F, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0x00, 0x64, 0xFF, 0xFF, 0xD6,
+            0x23, 0x7E,
+        ];
+
+        assert_eq!(payload.payload, &expected);
+    }
+
     #[test]
     fn test_generate_traffic() {
         let clock = Instant::now();
@@ -551,7 +1162,7 @@ mod tests {
         trfc.nacp = Some(9);
         trfc.on_ground = Some(false);
 
-        let payload = GDL90::generate_traffic(&trfc, clock, false);
+        let payload = GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Geometric, true);
         let expected = [
             0x7E, 0x14, 0x00, 0xA1, 0xB2, 0xC3, 0x1A, 0xD8, 0x3F, 0xA8, 0xDE, 0xAF, 0x23, 0xF9,
             0x79, 0x04, 0x2F, 0xF0, 0x57, 0x03, 'e' as u8, 'a' as u8, 'T' as u8, 'E' as u8,
@@ -560,7 +1171,7 @@ mod tests {
 
         assert_eq!(payload.payload, &expected);
 
-        let payload = GDL90::generate_traffic(&trfc, clock, true);
+        let payload = GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Pressure, true);
         let expected = [
             0x7E, 0x14, 0x00, 0xA1, 0xB2, 0xC3, 0x1A, 0xD8, 0x3F, 0xA8, 0xDE, 0xAF, 0x21, 0x79,
             0x79, 0x04, 0x2F, 0xF0, 0x57, 0x03, 'e' as u8, 'a' as u8, 'T' as u8, 'E' as u8,
@@ -570,7 +1181,7 @@ mod tests {
         assert_eq!(payload.payload, &expected);
 
         trfc.callsign = None;
-        let payload = GDL90::generate_traffic(&trfc, clock, false);
+        let payload = GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Geometric, true);
         let expected = [
             0x7E, 0x14, 0x00, 0xA1, 0xB2, 0xC3, 0x1A, 0xD8, 0x3F, 0xA8, 0xDE, 0xAF, 0x23, 0xF9,
             0x79, 0x04, 0x2F, 0xF0, 0x57, 0x03, 'e' as u8, 'a' as u8, '0' as u8, '1' as u8,
@@ -580,7 +1191,7 @@ mod tests {
         assert_eq!(payload.payload, &expected);
 
         trfc.altitude = Some((12375, AltitudeType::GNSS, clock));
-        let payload = GDL90::generate_traffic(&trfc, clock, true);
+        let payload = GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Pressure, true);
         let expected = [
             0x7E, 0x14, 0x00, 0xA1, 0xB2, 0xC3, 0x1A, 0xD8, 0x3F, 0xA8, 0xDE, 0xAF, 0x1E, 0xF9,
             0x79, 0x04, 0x2F, 0xF0, 0x57, 0x03, 'e' as u8, 'a' as u8, '0' as u8, '1' as u8,
@@ -588,5 +1199,644 @@ mod tests {
         ];
 
         assert_eq!(payload.payload, &expected);
+
+        trfc.altitude = Some((12375, AltitudeType::Baro, clock));
+        trfc.callsign = Some("TEST123".into());
+        let payload = GDL90::generate_traffic(&trfc, clock, AltitudeFrame::Geometric, false);
+        let expected = [
+            0x7E, 0x14, 0x00, 0xA1, 0xB2, 0xC3, 0x1A, 0xD8, 0x3F, 0xA8, 0xDE, 0xAF, 0x23, 0xF9,
+            0x79, 0x04, 0x2F, 0xF0, 0x57, 0x03, 'T' as u8, 'E' as u8, 'S' as u8, 'T' as u8,
+            '1' as u8, '2' as u8, '3' as u8, 0x00, 0x04, 0x0E, 0x40, 0x7E,
+        ];
+
+        assert_eq!(payload.payload, &expected);
+    }
+
+    #[test]
+    fn test_altitude_frame() {
+        let mut h = MockHandle::new(10);
+        let clock = h.get_clock();
+        let mut o = Ownship::default();
+        let mut trfc = Target::new(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            clock,
+            TrafficSource::ES,
+            None,
+        );
+        trfc.gnss_delta = Some(200);
+
+        // altitude in the ownship and traffic reports
+        let altitude = |m: &[u8]| (((m[11] as i32) << 4) | (m[12] as i32 >> 4)) * 25 - 1000;
+
+        for &(pressure, typ, sent) in &[
+            (Some(5000), AltitudeType::Baro, (5000, 5000)),
+            (Some(5000), AltitudeType::GNSS, (5000, 4800)),
+            // compared against the ownship geometric altitude
+            (None, AltitudeType::Baro, (0xFFF * 25 - 1000, 5200)),
+            (None, AltitudeType::GNSS, (0xFFF * 25 - 1000, 5000)),
+        ] {
+            o.pressure_altitude = pressure;
+            trfc.altitude = Some((5000, typ, clock));
+            let mut g = GDL90::new(&Config::default());
+
+            run_protocol(
+                &mut *g,
+                &mut h,
+                &[Report::Ownship(o), Report::Traffic(Arc::new(trfc.clone()))],
+            );
+            let msgs: Vec<Vec<u8>> = h
+                .take()
+                .iter()
+                .flat_map(|p| parse_stream(&p.payload))
+                .map(|m| m.unwrap())
+                .collect();
+            let ownship = msgs.iter().find(|m| m[0] == 0x0A).unwrap();
+            let traffic = msgs.iter().find(|m| m[0] == 0x14).unwrap();
+
+            assert_eq!((altitude(ownship), altitude(traffic)), sent);
+        }
+    }
+}
+
+/// Test vectors from the GDL 90 Data Interface Specification (560-1058-00
+/// Rev A) and edge cases of the encodings it defines. Page numbers refer
+/// to that document.
+#[cfg(test)]
+mod conformance {
+    use super::*;
+    use pitot::handle::Handle;
+    use testing::{run_protocol, MockHandle};
+
+    /// heartbeat used as the CRC example on p. 7
+    const ICD_HEARTBEAT: [u8; 7] = [0x00, 0x81, 0x41, 0xDB, 0xD0, 0x08, 0x02];
+    /// position in the traffic report example
+    const ICD_LAT_LON: (f32, f32) = (44.907066, -122.994862);
+    /// traffic report example on p. 23
+    const ICD_TRAFFIC: [u8; 28] = [
+        0x14, 0x00, 0xAB, 0x45, 0x49, 0x1F, 0xEF, 0x15, 0xA8, 0x89, 0x78, 0x0F, 0x09, 0xA9, 0x07,
+        0xB0, 0x01, 0x20, 0x01, 0x4E, 0x38, 0x32, 0x35, 0x56, 0x20, 0x20, 0x20, 0x00,
+    ];
+
+    /// `msg` with flag bytes, stuffing and CRC, as sent to clients
+    fn frame(msg: &[u8]) -> Vec<u8> {
+        let mut buf = msg.to_vec();
+        buf.extend_from_slice(&[0, 0]);
+
+        GDL90::prepare_payload(&mut buf)
+    }
+
+    /// the only message in `payload` with flag bytes, stuffing and CRC
+    /// removed
+    fn unframe(payload: &[u8]) -> Vec<u8> {
+        let mut msgs = parse_stream(payload);
+        assert_eq!(msgs.len(), 1);
+
+        msgs.remove(0).unwrap()
+    }
+
+    fn target(clock: Instant) -> Target {
+        Target::new(
+            (0xAB4549, AddressType::ADSBICAO),
+            clock,
+            TrafficSource::UAT,
+            None,
+        )
+    }
+
+    fn traffic(t: &Target, clock: Instant) -> Vec<u8> {
+        unframe(&GDL90::generate_traffic(t, clock, AltitudeFrame::Pressure, false).payload)
+    }
+
+    #[test]
+    fn test_crc_example() {
+        assert_eq!(crc16(&ICD_HEARTBEAT), 0x8BB3);
+        assert_eq!(
+            frame(&ICD_HEARTBEAT),
+            &[0x7E, 0x00, 0x81, 0x41, 0xDB, 0xD0, 0x08, 0x02, 0xB3, 0x8B, 0x7E]
+        );
+        assert_eq!(
+            describe(&unframe(&frame(&ICD_HEARTBEAT))),
+            "Heartbeat 14:51:07Z, GPS position valid"
+        );
+    }
+
+    fn gdl90() -> GDL90 {
+        GDL90 {
+            ownship_valid: false,
+            heartbeat_slot: None,
+            ownship_slot: None,
+            altitude_frame: AltitudeFrame::Geometric,
+            annotate_callsign: false,
+            uplink: Uplink::Full,
+            uplink_products: vec![],
+            uat_downlink: UATDownlink::Translated,
+            ownship_fix: None,
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let mut g = gdl90();
+        g.ownship_valid = true;
+        let clock = Instant::now();
+        // system clock, ignored while GNSS time is valid
+        let system = UTC.ymd(2018, 7, 1).and_hms(1, 0, 0);
+
+        // time stamp of the example, 53467 seconds since midnight
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms(14, 51, 7),
+            clock,
+        });
+        let msg = unframe(&g.generate_heartbeat(&system, clock).payload);
+        assert_eq!(msg, &[0x00, 0x91, 0x01, 0xDB, 0xD0, 0x00, 0x00]);
+
+        // bit 16 of the time stamp is in status byte 2 (p. 10)
+        g.ownship_valid = false;
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms(23, 59, 59),
+            clock,
+        });
+        let msg = unframe(&g.generate_heartbeat(&system, clock).payload);
+        assert_eq!(msg, &[0x00, 0x11, 0x81, 0x7F, 0x51, 0x00, 0x00]);
+        assert_eq!(describe(&msg), "Heartbeat 23:59:59Z, GPS position invalid");
+    }
+
+    #[test]
+    fn test_heartbeat_midnight() {
+        let mut g = gdl90();
+        let clock = Instant::now();
+        let system = UTC.ymd(2018, 7, 1).and_hms(23, 59, 59);
+
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms_milli(23, 59, 59, 500),
+            clock,
+        });
+        let msg = unframe(&g.generate_heartbeat(&system, clock).payload);
+        assert_eq!(describe(&msg), "Heartbeat 23:59:59Z, GPS position invalid");
+
+        // extrapolated past midnight, while the system clock lags behind
+        let clock = clock + Duration::from_millis(600);
+        let msg = unframe(&g.generate_heartbeat(&system, clock).payload);
+        assert_eq!(msg, &[0x00, 0x11, 0x01, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_heartbeat_no_gnss_time() {
+        let mut g = gdl90();
+        let clock = Instant::now();
+        // leap second
+        let system = UTC.ymd(2018, 7, 1).and_hms_milli(23, 59, 59, 1000);
+
+        // system clock with UTC OK cleared
+        let msg = unframe(&g.generate_heartbeat(&system, clock).payload);
+        assert_eq!(msg, &[0x00, 0x11, 0x80, 0x7F, 0x51, 0x00, 0x00]);
+        assert_eq!(
+            describe(&msg),
+            "Heartbeat 23:59:59Z (UTC not OK), GPS position invalid"
+        );
+
+        // GNSS time no longer valid
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms(12, 0, 0),
+            clock,
+        });
+        let clock = clock + Duration::from_secs(MAX_TIME_AGE);
+        let msg = unframe(&g.generate_heartbeat(&system, clock).payload);
+        assert_eq!(
+            describe(&msg),
+            "Heartbeat 23:59:59Z (UTC not OK), GPS position invalid"
+        );
+    }
+
+    #[test]
+    fn test_utc_pacing() {
+        let mut g = gdl90();
+        let mut h = MockHandle::new(10);
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms_milli(14, 51, 7, 750),
+            clock: h.get_clock(),
+        });
+
+        let mut heartbeats = vec![];
+        let mut ownships = vec![];
+        for i in 0..20 {
+            run_protocol(&mut g, &mut h, &[Report::Ownship(Ownship::default())]);
+            h.advance(Duration::from_millis(100));
+
+            for p in h.take() {
+                match p.payload[1] {
+                    0x00 => heartbeats.push(i),
+                    0x0A => ownships.push(i),
+                    _ => {}
+                }
+            }
+        }
+
+        // right after the top of every second of GNSS time, ownship after
+        // every half second
+        assert_eq!(heartbeats, vec![0, 3, 13]);
+        assert_eq!(ownships, vec![0, 3, 8, 13, 18]);
+    }
+
+    #[test]
+    fn test_traffic_example() {
+        assert_eq!(
+            frame(&ICD_TRAFFIC),
+            [&[0x7E][..], &ICD_TRAFFIC, &[0x57, 0xD6, 0x7E]].concat()
+        );
+
+        let t = parse_traffic(&ICD_TRAFFIC).unwrap();
+        assert_eq!(t.addr, (0xAB4549, AddressType::ADSBICAO));
+        assert!((t.lat_lon.unwrap().0 - ICD_LAT_LON.0).abs() < 0.00001);
+        assert!((t.lat_lon.unwrap().1 - ICD_LAT_LON.1).abs() < 0.00001);
+        assert_eq!(t.altitude, Some((5000, AltitudeType::Baro)));
+        assert_eq!(t.on_ground, Some(false));
+        assert_eq!((t.nic, t.nacp), (Some(10), Some(9)));
+        assert_eq!(t.speed, Some((123, SpeedType::GS)));
+        assert_eq!(t.vs, Some(64));
+        assert_eq!(t.heading, Some((45, HeadingType::True)));
+        assert_eq!(t.category, Some(1));
+        assert_eq!(t.callsign, Some("N825V".to_string()));
+
+        let clock = Instant::now();
+        let mut trfc = target(clock);
+        trfc.lat_lon = Some((ICD_LAT_LON, clock));
+        trfc.altitude = Some((5000, AltitudeType::Baro, clock));
+        trfc.on_ground = Some(false);
+        trfc.nic = Some(10);
+        trfc.nacp = Some(9);
+        trfc.speed = Some((123, SpeedType::GS, clock));
+        trfc.vs = Some((64, clock));
+        trfc.heading = Some((45, HeadingType::True, clock));
+        trfc.category = Some(1);
+        trfc.callsign = Some("N825V".into());
+
+        // Pitot pads the call sign with NUL instead of spaces
+        let mut expected = ICD_TRAFFIC;
+        expected[24..27].clone_from_slice(&[0, 0, 0]);
+        assert_eq!(traffic(&trfc, clock), &expected[..]);
+    }
+
+    #[test]
+    fn test_traffic_address() {
+        let clock = Instant::now();
+        let mut trfc = target(clock);
+
+        // alert status and address type (p. 18)
+        for &(typ, expected) in &[
+            (AddressType::ADSBICAO, 0x00),
+            (AddressType::ADSRICAO, 0x00),
+            (AddressType::ADSBOther, 0x01),
+            (AddressType::ADSROther, 0x01),
+            (AddressType::TISBICAO, 0x02),
+            (AddressType::TISBOther, 0x03),
+            (AddressType::SurfaceVehicle, 0x04),
+            (AddressType::FixedBeacon, 0x05),
+        ] {
+            trfc.addr.1 = typ;
+            assert_eq!(traffic(&trfc, clock)[1], expected);
+        }
+
+        // sharing an address only turns ICAO ones into non-ICAO
+        trfc.sub_id = 1;
+        for &(typ, expected) in &[
+            (AddressType::TISBICAO, 0x03),
+            (AddressType::SurfaceVehicle, 0x04),
+        ] {
+            trfc.addr.1 = typ;
+            assert_eq!(traffic(&trfc, clock)[1], expected);
+        }
+
+        trfc.addr.1 = AddressType::ADSBICAO;
+        trfc.ident = Some(clock);
+        assert_eq!(traffic(&trfc, clock)[1], 0x11);
+    }
+
+    #[test]
+    fn test_traffic_position() {
+        // 24 bit two's complement in units of 180 / 2^23 degrees (p. 19)
+        assert_eq!(latlon_to_gdl90(0.0), (0x00, 0x00, 0x00));
+        assert_eq!(latlon_to_gdl90(90.0), (0x40, 0x00, 0x00));
+        assert_eq!(latlon_to_gdl90(-90.0), (0xC0, 0x00, 0x00));
+        assert_eq!(latlon_to_gdl90(180.0), (0x80, 0x00, 0x00));
+        assert_eq!(latlon_to_gdl90(-180.0), (0x80, 0x00, 0x00));
+        assert_eq!(latlon_to_gdl90(-LON_LAT_RESOLUTION), (0xFF, 0xFF, 0xFF));
+        assert_eq!(gdl90_to_latlon(&[0xC0, 0x00, 0x00]), -90.0);
+        assert_eq!(gdl90_to_latlon(&[0x80, 0x00, 0x00]), -180.0);
+
+        let past = Instant::now();
+        let clock = past + Duration::from_secs(MAX_STALE_SECS + 1);
+        let mut trfc = target(clock);
+
+        // no position is all zero with NIC 0
+        trfc.nic = Some(10);
+        trfc.lat_lon = Some(((-33.946111, 151.177222), past));
+        let msg = traffic(&trfc, clock);
+        assert_eq!(&msg[5..11], &[0; 6]);
+        assert_eq!(msg[13] >> 4, 0);
+        assert_eq!(parse_traffic(&msg).unwrap().lat_lon, None);
+
+        trfc.lat_lon = Some(((-33.946111, 151.177222), clock));
+        let msg = traffic(&trfc, clock);
+        assert_eq!(msg[13] >> 4, 10);
+        let (lat, lon) = parse_traffic(&msg).unwrap().lat_lon.unwrap();
+        assert!((lat + 33.946111).abs() < 0.00001);
+        assert!((lon - 151.177222).abs() < 0.00001);
+    }
+
+    #[test]
+    fn test_traffic_altitude() {
+        let past = Instant::now();
+        let clock = past + Duration::from_secs(MAX_STALE_SECS + 1);
+        let mut trfc = target(clock);
+        let altitude = |msg: &[u8]| (msg[11] as u16) << 4 | (msg[12] as u16) >> 4;
+
+        // 25 ft resolution with a -1000 ft offset, 0xFFF is invalid (p. 20)
+        for &(alt, expected) in &[
+            (-1000, 0x000),
+            (0, 0x028),
+            (5000, 0x0F0),
+            (101350, 0xFFE),
+            (101375, 0xFFF),
+        ] {
+            trfc.altitude = Some((alt, AltitudeType::Baro, clock));
+            assert_eq!(altitude(&traffic(&trfc, clock)), expected);
+        }
+
+        trfc.altitude = None;
+        assert_eq!(altitude(&traffic(&trfc, clock)), 0xFFF);
+        trfc.altitude = Some((5000, AltitudeType::Baro, past));
+        assert_eq!(altitude(&traffic(&trfc, clock)), 0xFFF);
+    }
+
+    #[test]
+    fn test_traffic_misc() {
+        let past = Instant::now();
+        let clock = past + Duration::from_secs(MAX_STALE_SECS + 1);
+        let mut trfc = target(clock);
+        let misc = |t: &Target| traffic(t, clock)[12] & 0x0F;
+
+        // airborne unless known to be on ground, track type (p. 20)
+        assert_eq!(misc(&trfc), 0x08);
+        trfc.on_ground = Some(true);
+        assert_eq!(misc(&trfc), 0x00);
+        trfc.heading = Some((359, HeadingType::True, clock));
+        assert_eq!(misc(&trfc), 0x01);
+        trfc.heading = Some((359, HeadingType::Mag, clock));
+        assert_eq!(misc(&trfc), 0x02);
+        trfc.heading = Some((359, HeadingType::True, past));
+        assert_eq!(misc(&trfc), 0x00);
+    }
+
+    #[test]
+    fn test_traffic_velocity() {
+        let past = Instant::now();
+        let clock = past + Duration::from_secs(MAX_STALE_SECS + 1);
+        let mut trfc = target(clock);
+        let velocity = |t: &Target| {
+            let msg = traffic(t, clock);
+            (
+                (msg[14] as u16) << 4 | (msg[15] as u16) >> 4,
+                (msg[15] as u16 & 0x0F) << 8 | msg[16] as u16,
+            )
+        };
+
+        // 0xFFF and 0x800 are no information (p. 21)
+        assert_eq!(velocity(&trfc), (0xFFF, 0x800));
+        trfc.speed = Some((123, SpeedType::GS, past));
+        trfc.vs = Some((64, past));
+        assert_eq!(velocity(&trfc), (0xFFF, 0x800));
+
+        // 1 kt resolution, 0xFFE is 4094 kts or more
+        for &(spd, expected) in &[(0, 0x000), (4093, 0xFFD), (4094, 0xFFE), (4200, 0xFFE)] {
+            trfc.speed = Some((spd, SpeedType::GS, clock));
+            assert_eq!(velocity(&trfc).0, expected);
+        }
+
+        // 12 bit two's complement in 64 fpm, 0x1FE and 0xE02 are more than
+        // 32576 fpm
+        for &(vs, expected) in &[
+            (0, 0x000),
+            (64, 0x001),
+            (-64, 0xFFF),
+            (-1000, 0xFF0),
+            (32576, 0x1FD),
+            (32767, 0x1FE),
+            (-32576, 0xE03),
+            (-32768, 0xE02),
+        ] {
+            trfc.vs = Some((vs, clock));
+            assert_eq!(velocity(&trfc).1, expected);
+        }
+    }
+
+    #[test]
+    fn test_traffic_track() {
+        let clock = Instant::now();
+        let mut trfc = target(clock);
+
+        // 8 bit angular weighted binary, 360 / 256 degrees (p. 21)
+        for &(hdg, expected) in &[(0, 0x00), (45, 0x20), (180, 0x80), (359, 0xFF), (360, 0x00)] {
+            trfc.heading = Some((hdg, HeadingType::True, clock));
+            assert_eq!(traffic(&trfc, clock)[17], expected);
+        }
+    }
+
+    #[test]
+    fn test_traffic_callsign() {
+        let clock = Instant::now();
+        let mut trfc = target(clock);
+
+        // 8 characters (p. 24)
+        trfc.callsign = Some("ABCDEFGHIJ".into());
+        assert_eq!(&traffic(&trfc, clock)[19..27], b"ABCDEFGH");
+
+        // squawk when there is no call sign
+        trfc.callsign = None;
+        trfc.squawk = Some(1200);
+        assert_eq!(&traffic(&trfc, clock)[19..27], b"1200\0\0\0\0");
+
+        // emergency/priority code in the upper nibble of the last byte
+        for &(sq, expected) in &[(1200, 0x00), (7500, 0x10), (7600, 0x10), (7700, 0x10)] {
+            trfc.squawk = Some(sq);
+            assert_eq!(traffic(&trfc, clock)[27] & 0xF0, expected);
+        }
+    }
+
+    #[test]
+    fn test_ownship() {
+        let mut o = Ownship::default();
+        o.lat = ICD_LAT_LON.0;
+        o.lon = ICD_LAT_LON.1;
+        o.pressure_altitude = Some(5000);
+        o.nic = 10;
+        o.nacp = 9;
+        o.gs = 123.0;
+        o.true_track = 45.0;
+
+        // same format as the traffic report (p. 16)
+        let msg = unframe(&GDL90::generate_ownship(&o, AltitudeFrame::of(&o)).payload);
+        assert_eq!(msg.len(), 28);
+        assert_eq!(msg[0], 0x0A);
+        assert_eq!(&msg[5..15], &ICD_TRAFFIC[5..15]);
+        assert_eq!(&msg[15..17], &[0xB8, 0x00]); // no vertical velocity
+        assert_eq!(&msg[17..19], &ICD_TRAFFIC[17..19]);
+
+        o.pressure_altitude = None;
+        o.gs = 5000.0;
+        let msg = unframe(&GDL90::generate_ownship(&o, AltitudeFrame::of(&o)).payload);
+        assert_eq!(&msg[11..13], &[0xFF, 0xF9]);
+        assert_eq!(&msg[14..16], &[0xFF, 0xE8]);
+    }
+
+    #[test]
+    fn test_ownship_geometric_altitude() {
+        let mut o = Ownship::default();
+
+        // 5 ft resolution, VFOM in meters (p. 26)
+        o.hae_altitude = 5000;
+        let msg = unframe(&GDL90::generate_ownship_geometric_altitude(&o).payload);
+        assert_eq!(msg, &[0x0B, 0x03, 0xE8, 0x00, 0x0A]);
+
+        o.hae_altitude = -100;
+        let msg = unframe(&GDL90::generate_ownship_geometric_altitude(&o).payload);
+        assert_eq!(msg, &[0x0B, 0xFF, 0xEC, 0x00, 0x0A]);
+    }
+
+    #[test]
+    fn test_uplink() {
+        // every value, including both that need stuffing
+        let payload: Vec<u8> = (0..432).map(|i| i as u8).collect();
+        let received = Instant::now();
+        let mut g = gdl90();
+        let uplink = FISBData {
+            payload: payload.clone(),
+            received: Some(received),
+        };
+        let p = g.generate_uplink(&uplink);
+
+        // time of reception unavailable and the 432 byte UAT payload (p. 14)
+        assert!(p.queueable);
+        assert_eq!(p.received, Some((Class::Uplink, received)));
+        let msg = unframe(&p.payload);
+        assert_eq!(msg.len(), 436);
+        assert_eq!(&msg[..4], &[0x07, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&msg[4..], &payload[..]);
+
+        // 250 ms into the second, in 80 ns
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms(12, 0, 0),
+            clock: received - Duration::from_millis(250),
+        });
+        let msg = unframe(&g.generate_uplink(&uplink).payload);
+        assert_eq!(&msg[..4], &[0x07, 0x08, 0xAF, 0x2F]);
+
+        // received before the time pulse, in the previous second
+        g.time = Some(Time {
+            utc: UTC.ymd(2018, 7, 1).and_hms(12, 0, 0),
+            clock: received + Duration::from_millis(100),
+        });
+        let msg = unframe(&g.generate_uplink(&uplink).payload);
+        assert_eq!(&msg[..4], &[0x07, 0x50, 0xA9, 0xAB]);
+    }
+
+    /// xorshift, so failures can be reproduced
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        /// mostly flag, control escape and their escaped values
+        fn byte(&mut self) -> u8 {
+            match self.next() % 8 {
+                0 | 1 => 0x7E,
+                2 | 3 => 0x7D,
+                4 => 0x5E,
+                5 => 0x5D,
+                _ => self.next() as u8,
+            }
+        }
+    }
+
+    #[test]
+    fn test_byte_stuffing() {
+        let mut rng = Rng(0x1234_5678);
+        let mut stuffed_crc = 0;
+
+        for _ in 0..5000 {
+            let len = 1 + rng.next() as usize % 600;
+            let msg: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+
+            let framed = frame(&msg);
+            let crc = crc16(&msg);
+            let escaped = msg
+                .iter()
+                .chain(&[crc as u8, (crc >> 8) as u8])
+                .filter(|b| **b == 0x7E || **b == 0x7D)
+                .count();
+            if crc as u8 == 0x7E || crc as u8 == 0x7D {
+                stuffed_crc += 1;
+            }
+
+            // flag bytes only at the ends, every control escape followed by
+            // an escaped flag or control escape (p. 5)
+            assert_eq!(framed.len(), 1 + len + 2 + escaped + 1);
+            assert_eq!(framed[0], 0x7E);
+            assert_eq!(framed[framed.len() - 1], 0x7E);
+            let inner = &framed[1..framed.len() - 1];
+            assert!(!inner.contains(&0x7E));
+            let mut it = inner.iter();
+            while let Some(b) = it.next() {
+                if *b == 0x7D {
+                    assert!(it.next().map_or(false, |e| *e == 0x5E || *e == 0x5D));
+                }
+            }
+
+            assert_eq!(unframe(&framed), msg);
+        }
+
+        // the CRC needed stuffing in some of the messages
+        assert!(stuffed_crc > 0);
+    }
+
+    #[test]
+    fn test_stream_fuzz() {
+        let mut rng = Rng(0x9E37_79B9);
+
+        for _ in 0..500 {
+            let msgs: Vec<Vec<u8>> = (0..1 + rng.next() % 8)
+                .map(|_| (0..1 + rng.next() % 64).map(|_| rng.byte()).collect())
+                .collect();
+            let stream: Vec<u8> = msgs.iter().flat_map(|m| frame(m)).collect();
+
+            let parsed: Vec<Vec<u8>> = parse_stream(&stream)
+                .into_iter()
+                .map(|m| m.unwrap())
+                .collect();
+            assert_eq!(parsed, msgs);
+
+            // any flipped bit fails the CRC or splits the message, never
+            // passes as the original
+            let mut corrupted = stream.clone();
+            let i = 1 + rng.next() as usize % (stream.len() - 2);
+            corrupted[i] ^= 1 << (rng.next() % 8);
+            assert_ne!(
+                parse_stream(&corrupted)
+                    .into_iter()
+                    .filter_map(|m| m.ok())
+                    .collect::<Vec<_>>(),
+                msgs
+            );
+        }
+
+        assert!(parse_stream(&[]).is_empty());
+        assert!(parse_stream(&[0x7E, 0x7E]).is_empty());
+        assert!(parse_stream(&[0x7E, 0x00, 0x7E])[0].is_err());
     }
 }
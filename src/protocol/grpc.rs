@@ -0,0 +1,1442 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! gRPC server for machine consumers, e.g. drone and UTM integrations,
+//! serving the `AirPicture` service of `proto/pitot.proto`.
+//!
+//! There is only one RPC, so the server speaks just enough HTTP/2 for it:
+//! cleartext with prior knowledge, which is what gRPC clients use for
+//! plaintext targets. Request headers are only decoded for the method,
+//! calls to anything but `Subscribe` are answered with `UNIMPLEMENTED`.
+//! Updates a call has no flow control window left for are dropped rather
+//! than queued, newer ones follow soon enough.
+//!
+//! Frames are written by a thread of each connection so a slow client never
+//! holds up the others, clients falling `WRITE_QUEUE` writes behind are
+//! dropped. At most `MAX_CONNS` clients are served with up to `MAX_CALLS`
+//! calls each.
+
+use super::*;
+use processor::gnss::GNSS;
+use processor::ownship::Ownship;
+use processor::traffic::{Target, TrafficSource};
+use sensor::gnss::FixQuality;
+use sensor::sdr::DecodeStats;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+/// Clients are dropped once an update takes longer to write, in ms
+const WRITE_TIMEOUT_MS: u64 = 2000;
+/// Writes waiting for the thread of a connection before it is dropped
+const WRITE_QUEUE: usize = 16;
+/// Largest `Subscription` taken, in bytes
+const MAX_REQUEST: usize = 1024;
+/// Clients served at a time, more are disconnected right away
+const MAX_CONNS: usize = 16;
+/// Calls taken on one connection at a time
+const MAX_CALLS: usize = 8;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// frame flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+// settings
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+
+// error codes
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const REFUSED_STREAM: u32 = 0x7;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: u32 = 0x7FFF_FFFF;
+/// Also the largest frame taken from clients, as we never raise it
+const DEFAULT_MAX_FRAME: usize = 16_384;
+/// Largest frame size a client may ask for
+const MAX_MAX_FRAME: usize = 16_777_215;
+/// HPACK dynamic table size, also the largest taken as we never raise it
+const DEFAULT_HEADER_TABLE: usize = 4096;
+/// Largest header block taken, in bytes
+const MAX_HEADERS: usize = 65_536;
+
+/// `:status: 200` and `content-type: application/grpc`, HPACK encoded
+/// without indexing so the client's dynamic table is left alone
+const RESPONSE_HEADERS: &[u8] = b"\x88\x0f\x10\x10application/grpc";
+/// `INVALID_ARGUMENT`
+const STATUS_INVALID_ARGUMENT: u8 = 3;
+/// `UNIMPLEMENTED`
+const STATUS_UNIMPLEMENTED: u8 = 12;
+
+/// The only method served
+const PATH: &[u8] = b"/pitot.AirPicture/Subscribe";
+
+// kinds of updates, also their field number in `Update`
+const OWNSHIP: u64 = 1;
+const TRAFFIC: u64 = 2;
+const GNSS_STATUS: u64 = 3;
+const STATS: u64 = 4;
+
+/// Connections of every client
+type Conns = Arc<Mutex<Vec<Arc<Mutex<Conn<Queue>>>>>>;
+
+pub struct GRPC {
+    conns: Conns,
+}
+
+impl GRPC {
+    /// Serve clients connecting to `addr`, each on its own thread
+    pub fn new(addr: &str) -> Option<Box<Protocol>> {
+        let listener = match TcpListener::bind(addr) {
+            Ok(l) => l,
+            Err(e) => {
+                error!("unable to listen for gRPC clients on {}: {}", addr, e);
+                return None;
+            }
+        };
+
+        info!("serving gRPC on {}", addr);
+
+        let conns: Conns = Arc::new(Mutex::new(Vec::new()));
+        let accepted = conns.clone();
+        let served = Arc::new(AtomicUsize::new(0));
+        spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        debug!("unable to accept gRPC client: {}", e);
+                        continue;
+                    }
+                };
+
+                if served.fetch_add(1, Ordering::SeqCst) >= MAX_CONNS {
+                    served.fetch_sub(1, Ordering::SeqCst);
+                    warn!(
+                        "refused gRPC client {:?}, already serving {}",
+                        stream.peer_addr().ok(),
+                        MAX_CONNS
+                    );
+                    continue;
+                }
+
+                let conns = accepted.clone();
+                let served = served.clone();
+                spawn(move || {
+                    let addr = stream.peer_addr().ok();
+                    if let Err(e) = serve(stream, &conns) {
+                        debug!("gRPC client {:?} disconnected: {}", addr, e);
+                    }
+                    served.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        Some(Box::new(GRPC { conns }) as Box<Protocol>)
+    }
+}
+
+impl Protocol for GRPC {
+    fn run(&mut self, _handle: &mut Pushable<Payload>, i: ChainedIter) {
+        let mut conns = self.conns.lock().unwrap();
+        conns.retain(|c| !c.lock().unwrap().closed);
+        if conns.is_empty() {
+            return;
+        }
+
+        let updates: Vec<Update> = i.filter_map(update).collect();
+        if updates.is_empty() {
+            return;
+        }
+
+        for c in conns.iter() {
+            c.lock().unwrap().send(&updates);
+        }
+    }
+}
+
+/// Read the frames of one connection until it is closed
+fn serve(stream: TcpStream, conns: &Conns) -> io::Result<()> {
+    try!(stream.set_nodelay(true));
+    try!(stream.set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT_MS))));
+    let mut r = BufReader::new(try!(stream.try_clone()));
+
+    let mut preface = [0_u8; 24];
+    try!(r.read_exact(&mut preface));
+    if preface != PREFACE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an HTTP/2 connection with prior knowledge",
+        ));
+    }
+
+    let (tx, rx) = sync_channel::<Vec<u8>>(WRITE_QUEUE);
+    let mut w = try!(stream.try_clone());
+    spawn(move || {
+        for buf in rx {
+            if w.write_all(&buf).is_err() {
+                break;
+            }
+        }
+        // also ends the reading
+        w.shutdown(Shutdown::Both).ok();
+    });
+
+    let queue = Queue {
+        frames: tx,
+        stream: try!(stream.try_clone()),
+    };
+    let conn = Arc::new(Mutex::new(Conn::new(queue)));
+    conns.lock().unwrap().push(conn.clone());
+
+    let res = read_frames(&mut r, &conn);
+    conn.lock().unwrap().closed = true;
+    // the writer thread sends what is still queued, e.g. a GOAWAY, and
+    // closes the connection once it is dropped
+    stream.shutdown(Shutdown::Read).ok();
+
+    res
+}
+
+fn read_frames<R: Read>(r: &mut R, conn: &Mutex<Conn<Queue>>) -> io::Result<()> {
+    loop {
+        let mut head = [0_u8; 9];
+        try!(r.read_exact(&mut head));
+
+        let len = (head[0] as usize) << 16 | (head[1] as usize) << 8 | head[2] as usize;
+        if len > DEFAULT_MAX_FRAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame too large",
+            ));
+        }
+        let mut payload = vec![0_u8; len];
+        try!(r.read_exact(&mut payload));
+
+        let mut conn = conn.lock().unwrap();
+        if !conn.receive(head[3], head[4], be32(&head[5..]) & 0x7FFF_FFFF, &payload) {
+            return Ok(());
+        }
+        if conn.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "write failed"));
+        }
+    }
+}
+
+fn be32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+fn be32_bytes(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+/// Our SETTINGS, those differing from the defaults
+fn settings() -> Vec<u8> {
+    let mut s = vec![0, MAX_CONCURRENT_STREAMS as u8];
+    s.extend_from_slice(&be32_bytes(MAX_CALLS as u32));
+
+    s
+}
+
+fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len();
+    let mut f = vec![
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+        kind,
+        flags,
+        (stream >> 24) as u8,
+        (stream >> 16) as u8,
+        (stream >> 8) as u8,
+        stream as u8,
+    ];
+    f.extend_from_slice(payload);
+
+    f
+}
+
+/// HPACK integer with a `prefix` bit prefix, the bits above it in `first`
+fn hpack_int(buf: &mut Vec<u8>, first: u8, prefix: u8, mut v: usize) {
+    let max = (1 << prefix) - 1;
+    if v < max {
+        buf.push(first | v as u8);
+        return;
+    }
+
+    buf.push(first | max as u8);
+    v -= max;
+    while v >= 0x80 {
+        buf.push(0x80 | (v & 0x7F) as u8);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// HPACK literal header field without indexing, with a new name
+fn hpack_literal(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.push(0x00);
+    hpack_int(buf, 0, 7, name.len());
+    buf.extend_from_slice(name.as_bytes());
+    hpack_int(buf, 0, 7, value.len());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// HPACK integer with a `prefix` bit prefix at the start of `buf`, and its
+/// length
+fn hpack_read_int(buf: &[u8], prefix: u8) -> Option<(usize, usize)> {
+    let max = (1 << prefix) - 1;
+    let v = match buf.first() {
+        Some(b) => *b as usize & max,
+        None => return None,
+    };
+    if v < max {
+        return Some((v, 1));
+    }
+
+    let mut v = max;
+    for (i, b) in buf.iter().enumerate().skip(1).take(4) {
+        v += ((b & 0x7F) as usize) << (7 * (i - 1));
+        if b & 0x80 == 0 {
+            return Some((v, i + 1));
+        }
+    }
+
+    None
+}
+
+/// HPACK string literal at the start of `buf`, and its length
+fn hpack_read_string(buf: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let (len, n) = match hpack_read_int(buf, 7) {
+        Some(v) => v,
+        None => return None,
+    };
+    if buf.len() < n + len {
+        return None;
+    }
+
+    let s = &buf[n..n + len];
+    if buf[0] & 0x80 == 0 {
+        return Some((s.to_vec(), n + len));
+    }
+
+    huffman_decode(s).map(|s| (s, n + len))
+}
+
+/// String from its HPACK Huffman code, `None` if it is invalid
+fn huffman_decode(buf: &[u8]) -> Option<Vec<u8>> {
+    // the code is canonical, so it can be walked with just the lengths
+    let mut count = [0_u32; 31];
+    for l in HUFFMAN_LENGTHS.iter() {
+        count[*l as usize] += 1;
+    }
+    let mut symbols: Vec<usize> = (0..HUFFMAN_LENGTHS.len()).collect();
+    symbols.sort_by_key(|s| HUFFMAN_LENGTHS[*s]);
+
+    let mut out = Vec::new();
+    let mut bits = buf
+        .iter()
+        .flat_map(|b| (0..8).rev().map(move |i| b >> i & 1));
+    loop {
+        // first code of each length, and its index in `symbols`
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        let mut ones = true;
+        let mut symbol = None;
+
+        for (len, n) in count.iter().enumerate().skip(1) {
+            let bit = match bits.next() {
+                Some(b) => b as u32,
+                // padding is up to 7 most significant bits of EOS, all ones
+                None if len <= 8 && ones => return Some(out),
+                None => return None,
+            };
+            ones &= bit == 1;
+
+            code |= bit;
+            if code < first + n {
+                symbol = Some(symbols[index + (code - first) as usize]);
+                break;
+            }
+            index += *n as usize;
+            first = (first + n) << 1;
+            code <<= 1;
+        }
+
+        match symbol {
+            // EOS
+            Some(256) | None => return None,
+            Some(s) => out.push(s as u8),
+        }
+    }
+}
+
+/// A header field, name and value
+type Field = (Vec<u8>, Vec<u8>);
+
+/// HPACK decoding context of a connection
+struct Hpack {
+    /// dynamic table, newest first
+    table: VecDeque<Field>,
+    /// of the dynamic table, as HPACK counts it
+    size: usize,
+    max_size: usize,
+}
+
+impl Hpack {
+    fn new() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_HEADER_TABLE,
+        }
+    }
+
+    fn entry(&self, index: usize) -> Result<Field, &'static str> {
+        if index == 0 {
+            return Err("header field index 0");
+        }
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Ok((name.to_vec(), value.to_vec()));
+        }
+
+        self.table
+            .get(index - STATIC_TABLE.len() - 1)
+            .cloned()
+            .ok_or("header field index out of the table")
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let (name, value) = self.table.pop_back().unwrap();
+            self.size -= name.len() + value.len() + 32;
+        }
+    }
+
+    /// Header fields of one complete header block
+    fn decode(&mut self, mut buf: &[u8]) -> Result<Vec<Field>, &'static str> {
+        let truncated = "truncated header block";
+        let mut fields = Vec::new();
+
+        while !buf.is_empty() {
+            let b = buf[0];
+
+            if b & 0x80 != 0 {
+                let (index, n) = try!(hpack_read_int(buf, 7).ok_or(truncated));
+                fields.push(try!(self.entry(index)));
+                buf = &buf[n..];
+            } else if b & 0xE0 == 0x20 {
+                let (size, n) = try!(hpack_read_int(buf, 5).ok_or(truncated));
+                if size > DEFAULT_HEADER_TABLE {
+                    return Err("header table size above the limit");
+                }
+                self.max_size = size;
+                self.evict();
+                buf = &buf[n..];
+            } else {
+                // literal, with incremental indexing or without
+                let indexing = b & 0x40 != 0;
+                let (index, n) =
+                    try!(hpack_read_int(buf, if indexing { 6 } else { 4 }).ok_or(truncated));
+                buf = &buf[n..];
+
+                let name = if index == 0 {
+                    let (name, n) = try!(hpack_read_string(buf).ok_or(truncated));
+                    buf = &buf[n..];
+                    name
+                } else {
+                    try!(self.entry(index)).0
+                };
+                let (value, n) = try!(hpack_read_string(buf).ok_or(truncated));
+                buf = &buf[n..];
+
+                if indexing {
+                    self.size += name.len() + value.len() + 32;
+                    self.table.push_front((name.clone(), value.clone()));
+                    self.evict();
+                }
+                fields.push((name, value));
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+/// Hands writes to the thread of a connection
+struct Queue {
+    frames: SyncSender<Vec<u8>>,
+    /// to drop the client once it falls behind
+    stream: TcpStream,
+}
+
+impl Write for Queue {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.frames.try_send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            Err(TrySendError::Full(_)) => {
+                self.stream.shutdown(Shutdown::Both).ok();
+                Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "client fell behind",
+                ))
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "write failed"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Payload of a DATA or HEADERS frame without its padding
+fn unpadded(flags: u8, payload: &[u8]) -> &[u8] {
+    if flags & PADDED == 0 || payload.is_empty() {
+        return payload;
+    }
+
+    let padding = payload[0] as usize;
+    &payload[1..payload.len() - padding.min(payload.len() - 1)]
+}
+
+/// One call to `Subscribe`
+struct Call {
+    /// `None` until the whole request was received
+    sub: Option<Subscription>,
+    request: Vec<u8>,
+    /// flow control window, in bytes
+    window: i64,
+}
+
+/// State of one connection, shared by the thread reading its frames and
+/// the protocol writing updates
+struct Conn<W: Write> {
+    out: W,
+    /// connection flow control window, in bytes
+    window: i64,
+    initial_window: i64,
+    max_frame: usize,
+    /// by stream ID
+    calls: BTreeMap<u32, Call>,
+    /// highest stream ID of a call taken
+    last_stream: u32,
+    hpack: Hpack,
+    /// header block waiting for CONTINUATION frames, with its stream ID and
+    /// the flags of its HEADERS frame
+    continued: Option<(u32, u8, Vec<u8>)>,
+    closed: bool,
+}
+
+impl<W: Write> Conn<W> {
+    fn new(out: W) -> Self {
+        let mut c = Self {
+            out,
+            window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame: DEFAULT_MAX_FRAME,
+            calls: BTreeMap::new(),
+            last_stream: 0,
+            hpack: Hpack::new(),
+            continued: None,
+            closed: false,
+        };
+        // the defaults are fine otherwise
+        c.write(&frame(SETTINGS, 0, 0, &settings()));
+
+        c
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        if !self.closed && self.out.write_all(buf).is_err() {
+            self.closed = true;
+        }
+    }
+
+    /// Take in one frame from the client, `false` once it is going away
+    fn receive(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> bool {
+        match kind {
+            HEADERS => {
+                let mut block = unpadded(flags, payload);
+                if flags & PRIORITY != 0 {
+                    block = &block[5.min(block.len())..];
+                }
+
+                if flags & END_HEADERS != 0 {
+                    return self.headers(stream, flags, block);
+                }
+                self.continued = Some((stream, flags, block.to_vec()));
+            }
+            CONTINUATION => {
+                let (s, f, mut block) = match self.continued.take() {
+                    Some(c) => c,
+                    None => return false,
+                };
+                block.extend_from_slice(payload);
+                if s != stream || block.len() > MAX_HEADERS {
+                    return false;
+                }
+
+                if flags & END_HEADERS != 0 {
+                    return self.headers(s, f, &block);
+                }
+                self.continued = Some((s, f, block));
+            }
+            DATA => {
+                let data = unpadded(flags, payload);
+                if let Some(c) = self.calls.get_mut(&stream) {
+                    c.request.extend_from_slice(data);
+                }
+                if self
+                    .calls
+                    .get(&stream)
+                    .map_or(false, |c| c.request.len() > MAX_REQUEST + 5)
+                {
+                    debug!("reset gRPC call with a request above {} bytes", MAX_REQUEST);
+                    self.reset(stream, ENHANCE_YOUR_CALM);
+                } else if flags & END_STREAM != 0 {
+                    self.start(stream);
+                }
+            }
+            RST_STREAM => {
+                self.calls.remove(&stream);
+            }
+            SETTINGS if flags & ACK == 0 => {
+                for s in payload.chunks(6).filter(|s| s.len() == 6) {
+                    let value = be32(&s[2..]);
+                    match (s[0] as u16) << 8 | s[1] as u16 {
+                        INITIAL_WINDOW_SIZE if value > MAX_WINDOW => {
+                            return self.go_away(FLOW_CONTROL_ERROR);
+                        }
+                        INITIAL_WINDOW_SIZE => {
+                            let delta = value as i64 - self.initial_window;
+                            for c in self.calls.values_mut() {
+                                c.window += delta;
+                            }
+                            self.initial_window = value as i64;
+                        }
+                        MAX_FRAME_SIZE => {
+                            let value = value as usize;
+                            if value < DEFAULT_MAX_FRAME || value > MAX_MAX_FRAME {
+                                return self.go_away(PROTOCOL_ERROR);
+                            }
+                            self.max_frame = value;
+                        }
+                        _ => {}
+                    }
+                }
+                self.write(&frame(SETTINGS, ACK, 0, &[]));
+            }
+            PING if flags & ACK == 0 => self.write(&frame(PING, ACK, 0, payload)),
+            GOAWAY => return false,
+            WINDOW_UPDATE if payload.len() == 4 => {
+                let increment = (be32(payload) & 0x7FFF_FFFF) as i64;
+                if stream == 0 {
+                    self.window += increment;
+                } else if let Some(c) = self.calls.get_mut(&stream) {
+                    c.window += increment;
+                }
+            }
+            // priorities, continued headers and the like do not matter
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Take in the complete header block of stream `stream`, `false` if it
+    /// cannot be decoded
+    fn headers(&mut self, stream: u32, flags: u8, block: &[u8]) -> bool {
+        let fields = match self.hpack.decode(block) {
+            Ok(f) => f,
+            Err(e) => {
+                debug!("invalid gRPC request headers: {}", e);
+                return false;
+            }
+        };
+
+        if !self.calls.contains_key(&stream) {
+            let path = fields.iter().find(|f| f.0 == b":path").map(|f| &f.1[..]);
+            if path != Some(PATH) {
+                debug!(
+                    "rejected gRPC call to {}",
+                    String::from_utf8_lossy(path.unwrap_or(b""))
+                );
+                self.reject(stream, STATUS_UNIMPLEMENTED, "unknown method");
+                return true;
+            }
+
+            if self.calls.len() >= MAX_CALLS {
+                debug!("refused gRPC call, {} already ongoing", MAX_CALLS);
+                self.reset(stream, REFUSED_STREAM);
+                return true;
+            }
+
+            let window = self.initial_window;
+            self.last_stream = self.last_stream.max(stream);
+            self.calls.insert(
+                stream,
+                Call {
+                    sub: None,
+                    request: Vec::new(),
+                    window,
+                },
+            );
+        }
+        if flags & END_STREAM != 0 {
+            self.start(stream);
+        }
+
+        true
+    }
+
+    /// End call `stream` right away with `status`
+    fn reject(&mut self, stream: u32, status: u8, message: &str) {
+        self.calls.remove(&stream);
+
+        let mut headers = RESPONSE_HEADERS.to_vec();
+        hpack_literal(&mut headers, "grpc-status", &status.to_string());
+        hpack_literal(&mut headers, "grpc-message", message);
+        self.write(&frame(HEADERS, END_HEADERS | END_STREAM, stream, &headers));
+    }
+
+    /// End call `stream` right away with an HTTP/2 `error`
+    fn reset(&mut self, stream: u32, error: u32) {
+        self.calls.remove(&stream);
+        self.write(&frame(RST_STREAM, 0, stream, &be32_bytes(error)));
+    }
+
+    /// Tell the client the connection is closing because of `error`,
+    /// always `false` so it can be returned from `receive`
+    fn go_away(&mut self, error: u32) -> bool {
+        let mut payload = be32_bytes(self.last_stream).to_vec();
+        payload.extend_from_slice(&be32_bytes(error));
+        self.write(&frame(GOAWAY, 0, 0, &payload));
+
+        false
+    }
+
+    /// Answer call `stream` now that its request is complete
+    fn start(&mut self, stream: u32) {
+        let sub = match self.calls.get(&stream) {
+            Some(c) if c.sub.is_none() => parse_request(&c.request),
+            _ => return,
+        };
+
+        match sub {
+            Ok(sub) => {
+                self.calls.get_mut(&stream).unwrap().sub = Some(sub);
+                self.write(&frame(HEADERS, END_HEADERS, stream, RESPONSE_HEADERS));
+            }
+            Err(e) => {
+                debug!("rejected gRPC call: {}", e);
+                self.reject(stream, STATUS_INVALID_ARGUMENT, &e);
+            }
+        }
+    }
+
+    /// Send every update each call subscribed to and has a window for
+    fn send(&mut self, updates: &[Update]) {
+        let mut out = Vec::new();
+
+        for (id, c) in &mut self.calls {
+            let sub = match c.sub {
+                Some(ref s) => s,
+                None => continue,
+            };
+
+            for u in updates.iter().filter(|u| sub.accepts(u)) {
+                let len = u.message.len() as i64;
+                if len > self.window || len > c.window {
+                    continue;
+                }
+
+                self.window -= len;
+                c.window -= len;
+                for chunk in u.message.chunks(self.max_frame) {
+                    out.extend_from_slice(&frame(DATA, 0, *id, chunk));
+                }
+            }
+        }
+
+        if !out.is_empty() {
+            self.write(&out);
+        }
+    }
+}
+
+/// A `Subscription` in the single length-prefixed message of a request
+fn parse_request(buf: &[u8]) -> Result<Subscription, String> {
+    if buf.len() > MAX_REQUEST + 5 {
+        return Err("request too large".to_string());
+    }
+    if buf.len() < 5 || buf[0] != 0 || be32(&buf[1..]) as usize != buf.len() - 5 {
+        return Err("expected one uncompressed Subscription".to_string());
+    }
+
+    Subscription::parse(&buf[5..])
+}
+
+/// Protocol Buffers varint at the start of `buf`, and its length
+fn read_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut v = 0;
+    for (i, b) in buf.iter().enumerate().take(10) {
+        v |= ((b & 0x7F) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((v, i + 1));
+        }
+    }
+
+    None
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(0x80 | (v & 0x7F) as u8);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Subscription {
+    /// kinds of updates, all of them if empty
+    kinds: Vec<u64>,
+    /// in NM
+    range: Option<f32>,
+}
+
+impl Subscription {
+    fn parse(mut buf: &[u8]) -> Result<Self, String> {
+        let mut sub = Subscription::default();
+        let truncated = || "truncated Subscription".to_string();
+
+        while !buf.is_empty() {
+            let (key, n) = try!(read_varint(buf).ok_or_else(truncated));
+            buf = &buf[n..];
+
+            match (key >> 3, key & 0x7) {
+                (1, 0) => {
+                    let (kind, n) = try!(read_varint(buf).ok_or_else(truncated));
+                    sub.kinds.push(kind);
+                    buf = &buf[n..];
+                }
+                // packed
+                (1, 2) => {
+                    let (len, n) = try!(read_varint(buf).ok_or_else(truncated));
+                    if buf.len() < n + len as usize {
+                        return Err(truncated());
+                    }
+                    let mut packed = &buf[n..n + len as usize];
+                    while !packed.is_empty() {
+                        let (kind, n) = try!(read_varint(packed).ok_or_else(truncated));
+                        sub.kinds.push(kind);
+                        packed = &packed[n..];
+                    }
+                    buf = &buf[n + len as usize..];
+                }
+                (2, 5) if buf.len() >= 4 => {
+                    let bits = (buf[3] as u32) << 24
+                        | (buf[2] as u32) << 16
+                        | (buf[1] as u32) << 8
+                        | buf[0] as u32;
+                    sub.range = Some(f32::from_bits(bits));
+                    buf = &buf[4..];
+                }
+                (field, wire) => {
+                    return Err(format!("unexpected field {} of wire type {}", field, wire))
+                }
+            }
+        }
+
+        if let Some(k) = sub.kinds.iter().find(|k| **k < OWNSHIP || **k > STATS) {
+            return Err(format!("unknown kind {}", k));
+        }
+        if sub.range.map_or(false, |r| r.is_nan() || r <= 0.0) {
+            return Err("range must be a positive number".to_string());
+        }
+
+        Ok(sub)
+    }
+
+    fn accepts(&self, u: &Update) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&u.kind))
+            && (u.kind != TRAFFIC
+                || self
+                    .range
+                    .map_or(true, |r| u.range.map_or(false, |t| t <= r)))
+    }
+}
+
+/// An `Update` ready to be sent, length-prefixed
+struct Update {
+    kind: u64,
+    /// of traffic, in NM
+    range: Option<f32>,
+    message: Vec<u8>,
+}
+
+/// Protocol Buffers encoding of one message. Fields set to `None` are left
+/// out.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn key(&mut self, field: u32, wire: u64) {
+        put_varint(&mut self.0, (field as u64) << 3 | wire);
+    }
+
+    fn uint<T: Into<Option<u64>>>(&mut self, field: u32, v: T) {
+        if let Some(v) = v.into() {
+            self.key(field, 0);
+            put_varint(&mut self.0, v);
+        }
+    }
+
+    fn sint<T: Into<Option<i32>>>(&mut self, field: u32, v: T) {
+        if let Some(v) = v.into() {
+            self.key(field, 0);
+            put_varint(&mut self.0, ((v << 1) ^ (v >> 31)) as u32 as u64);
+        }
+    }
+
+    fn boolean<T: Into<Option<bool>>>(&mut self, field: u32, v: T) {
+        self.uint(field, v.into().map(|b| b as u64));
+    }
+
+    fn float<T: Into<Option<f32>>>(&mut self, field: u32, v: T) {
+        if let Some(v) = v.into() {
+            self.key(field, 5);
+            let bits = v.to_bits();
+            self.0.extend_from_slice(&[
+                bits as u8,
+                (bits >> 8) as u8,
+                (bits >> 16) as u8,
+                (bits >> 24) as u8,
+            ]);
+        }
+    }
+
+    fn bytes(&mut self, field: u32, v: &[u8]) {
+        self.key(field, 2);
+        put_varint(&mut self.0, v.len() as u64);
+        self.0.extend_from_slice(v);
+    }
+}
+
+fn band(b: TrafficSource) -> u64 {
+    match b {
+        TrafficSource::UAT => 1,
+        TrafficSource::ES => 2,
+    }
+}
+
+fn ownship(o: &Ownship) -> Message {
+    let mut m = Message::default();
+    m.boolean(1, o.valid);
+    m.float(2, o.lat);
+    m.float(3, o.lon);
+    m.sint(4, o.msl_altitude);
+    m.sint(5, o.hae_altitude);
+    m.sint(6, o.pressure_altitude);
+    m.float(7, o.gs);
+    m.float(8, o.true_track);
+    m.float(9, o.magnetic_track);
+    m.sint(10, o.vs);
+    m.sint(11, o.gnss_vs);
+    m.uint(12, o.nic as u64);
+    m.uint(13, o.nacp as u64);
+    m.float(14, o.fix_age);
+    m.float(15, o.baro_age);
+
+    m
+}
+
+fn target(t: &Target) -> Message {
+    let mut m = Message::default();
+    m.uint(1, t.addr.0 as u64);
+    m.uint(2, band(t.source));
+    if let Some(ref c) = t.callsign {
+        m.bytes(3, c.as_bytes());
+    }
+    m.float(4, t.lat_lon.map(|l| (l.0).0));
+    m.float(5, t.lat_lon.map(|l| (l.0).1));
+    m.sint(6, t.altitude.map(|a| a.0));
+    m.uint(7, t.heading.map(|h| h.0 as u64));
+    m.uint(8, t.speed.map(|s| s.0 as u64));
+    m.sint(9, t.vs.map(|v| v.0 as i32));
+    m.uint(10, t.squawk.map(|s| s as u64));
+    m.boolean(11, t.on_ground);
+    m.uint(12, t.nic.map(|n| n as u64));
+    m.uint(13, t.nacp.map(|n| n as u64));
+    m.uint(14, t.nacv.map(|n| n as u64));
+    m.uint(15, t.sil.map(|n| n as u64));
+    m.boolean(16, t.ident.is_some());
+    m.float(17, t.range);
+    m.float(18, t.bearing);
+    m.boolean(19, t.capability.map(|c| c.cdti));
+    m.boolean(20, t.capability.map(|c| c.tcas));
+
+    m
+}
+
+fn gnss(g: &GNSS) -> Message {
+    let mut m = Message::default();
+    m.uint(
+        1,
+        match g.quality {
+            FixQuality::TwoDim => 1,
+            FixQuality::ThreeDim => 2,
+            FixQuality::SBAS => 3,
+            FixQuality::RTKFloat => 4,
+            FixQuality::RTKFixed => 5,
+            FixQuality::Unknown => 6,
+        },
+    );
+    m.uint(2, g.num_sv as u64);
+    m.uint(3, g.h_accuracy.map(|a| a as u64));
+    m.uint(4, g.v_accuracy.map(|a| a as u64));
+    m.float(5, g.dop.as_ref().map(|d| d.pdop));
+    m.float(6, g.dop.as_ref().map(|d| d.hdop));
+    m.float(7, g.dop.as_ref().map(|d| d.vdop));
+
+    m
+}
+
+fn stats(s: &DecodeStats) -> Message {
+    let mut m = Message::default();
+    m.uint(1, band(s.band));
+    m.uint(2, s.interval);
+    m.uint(3, s.downlink_frames as u64);
+    m.uint(4, s.uplink_frames as u64);
+    m.uint(5, s.corrected_frames as u64);
+    m.uint(6, s.corrected_errors as u64);
+    m.float(7, s.noise_floor);
+    m.boolean(8, s.peer);
+
+    m
+}
+
+/// `Update` carrying report `r`, if it is of a kind served
+fn update(r: &Report) -> Option<Update> {
+    let (kind, range, m) = match *r {
+        Report::Ownship(ref o) => (OWNSHIP, None, ownship(o)),
+        Report::Traffic(ref t) => (TRAFFIC, t.range, target(t)),
+        Report::GNSS(ref g) => (GNSS_STATUS, None, gnss(g)),
+        Report::DecodeStats(ref s) => (STATS, None, stats(s)),
+        _ => return None,
+    };
+
+    let mut u = Message::default();
+    u.bytes(kind as u32, &m.0);
+
+    let len = u.0.len();
+    let mut message = vec![
+        0,
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ];
+    message.extend_from_slice(&u.0);
+
+    Some(Update {
+        kind,
+        range,
+        message,
+    })
+}
+
+/// HPACK static table, by index from 1
+const STATIC_TABLE: &[(&[u8], &[u8])] = &[
+    (b":authority", b""),
+    (b":method", b"GET"),
+    (b":method", b"POST"),
+    (b":path", b"/"),
+    (b":path", b"/index.html"),
+    (b":scheme", b"http"),
+    (b":scheme", b"https"),
+    (b":status", b"200"),
+    (b":status", b"204"),
+    (b":status", b"206"),
+    (b":status", b"304"),
+    (b":status", b"400"),
+    (b":status", b"404"),
+    (b":status", b"500"),
+    (b"accept-charset", b""),
+    (b"accept-encoding", b"gzip, deflate"),
+    (b"accept-language", b""),
+    (b"accept-ranges", b""),
+    (b"accept", b""),
+    (b"access-control-allow-origin", b""),
+    (b"age", b""),
+    (b"allow", b""),
+    (b"authorization", b""),
+    (b"cache-control", b""),
+    (b"content-disposition", b""),
+    (b"content-encoding", b""),
+    (b"content-language", b""),
+    (b"content-length", b""),
+    (b"content-location", b""),
+    (b"content-range", b""),
+    (b"content-type", b""),
+    (b"cookie", b""),
+    (b"date", b""),
+    (b"etag", b""),
+    (b"expect", b""),
+    (b"expires", b""),
+    (b"from", b""),
+    (b"host", b""),
+    (b"if-match", b""),
+    (b"if-modified-since", b""),
+    (b"if-none-match", b""),
+    (b"if-range", b""),
+    (b"if-unmodified-since", b""),
+    (b"last-modified", b""),
+    (b"link", b""),
+    (b"location", b""),
+    (b"max-forwards", b""),
+    (b"proxy-authenticate", b""),
+    (b"proxy-authorization", b""),
+    (b"range", b""),
+    (b"referer", b""),
+    (b"refresh", b""),
+    (b"retry-after", b""),
+    (b"server", b""),
+    (b"set-cookie", b""),
+    (b"strict-transport-security", b""),
+    (b"transfer-encoding", b""),
+    (b"user-agent", b""),
+    (b"vary", b""),
+    (b"via", b""),
+    (b"www-authenticate", b""),
+];
+
+/// Code length of every HPACK Huffman symbol, EOS last
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::traffic::{AddressType, AltitudeType};
+    use std::time::Instant;
+
+    /// `Subscribe` request headers as a client encodes them, `:path` Huffman
+    /// coded and indexed
+    const SUBSCRIBE: &[u8] = b"\x83\x86\x44\x93\x62\xb3\x24\xe9\x5e\x13\x59\xac\xc4\x4d\xb6\
+                                \x15\x8d\xd6\xc6\x82\x58\x68\xcb\x41\x86\xa0\xe4\x1d\x13\x9d\
+                                \x09\x5f\x8b\x1d\x75\xd0\x62\x0d\x26\x3d\x4c\x4d\x65\x64\x40\
+                                \x82\x49\x7f\x86\x4d\x83\x35\x05\xb1\x1f";
+    /// The same once more, from the dynamic table
+    const SUBSCRIBE_INDEXED: &[u8] = b"\x83\x86\xc1\xc0\xbf\xbe";
+
+    fn traffic(range: f32) -> Update {
+        let mut t = Target::new(
+            (0xA00001, AddressType::ADSBICAO),
+            Instant::now(),
+            TrafficSource::ES,
+            None,
+        );
+        t.callsign = Some("N12345".to_string());
+        t.altitude = Some((-1500, AltitudeType::Baro, Instant::now()));
+        t.range = Some(range);
+
+        update(&Report::Traffic(Arc::new(t))).unwrap()
+    }
+
+    #[test]
+    fn test_update() {
+        let u = traffic(1.0);
+        assert_eq!(u.kind, TRAFFIC);
+        assert_eq!(
+            u.message,
+            b"\x00\x00\x00\x00\x1d\x12\x1b\x08\x81\x80\x80\x05\x10\x02\x1a\x06N12345\
+              \x30\xb7\x17\x80\x01\x00\x8d\x01\x00\x00\x80\x3f"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_subscription() {
+        assert_eq!(Subscription::parse(b"").unwrap(), Subscription::default());
+        // packed, then not
+        assert_eq!(
+            Subscription::parse(b"\x0a\x02\x01\x02\x08\x03\x15\x00\x00\x20\x41").unwrap(),
+            Subscription {
+                kinds: vec![OWNSHIP, TRAFFIC, GNSS_STATUS],
+                range: Some(10.0),
+            }
+        );
+
+        let sub = Subscription::parse(b"\x15\x00\x00\x20\x41").unwrap();
+        assert!(sub.accepts(&traffic(5.0)));
+        assert!(!sub.accepts(&traffic(15.0)));
+
+        assert!(Subscription::parse(b"\x08\x09").is_err());
+        assert!(Subscription::parse(b"\x0a\x05\x01").is_err());
+        assert!(Subscription::parse(b"\x15\x00\x00\x20\xc1").is_err());
+        assert!(Subscription::parse(b"\x18\x01").is_err());
+
+        assert!(parse_request(b"\x00\x00\x00\x00\x00").is_ok());
+        assert!(parse_request(b"\x01\x00\x00\x00\x00").is_err());
+        assert!(parse_request(b"\x00\x00\x00\x00\x02\x08").is_err());
+    }
+
+    #[test]
+    fn test_conn() {
+        let mut c = Conn::new(Vec::new());
+        assert_eq!(c.out, frame(SETTINGS, 0, 0, &settings()));
+        c.out.clear();
+
+        // room for one update only
+        assert!(c.receive(SETTINGS, 0, 0, b"\x00\x04\x00\x00\x00\x30"));
+        assert_eq!(c.out, frame(SETTINGS, ACK, 0, &[]));
+        c.out.clear();
+
+        assert!(c.receive(HEADERS, END_HEADERS, 1, SUBSCRIBE));
+        assert!(c.receive(DATA, END_STREAM, 1, b"\x00\x00\x00\x00\x00"));
+        assert_eq!(c.out, frame(HEADERS, END_HEADERS, 1, RESPONSE_HEADERS));
+        c.out.clear();
+
+        c.send(&[traffic(1.0), traffic(2.0)]);
+        assert_eq!(c.out, frame(DATA, 0, 1, &traffic(1.0).message));
+        c.out.clear();
+
+        assert!(c.receive(WINDOW_UPDATE, 0, 1, b"\x00\x00\x00\x30"));
+        c.send(&[traffic(2.0)]);
+        assert_eq!(c.out, frame(DATA, 0, 1, &traffic(2.0).message));
+        c.out.clear();
+
+        assert!(c.receive(PING, 0, 0, b"12345678"));
+        assert_eq!(c.out, frame(PING, ACK, 0, b"12345678"));
+        c.out.clear();
+
+        // trailers only
+        assert!(c.receive(HEADERS, END_HEADERS, 3, SUBSCRIBE_INDEXED));
+        assert!(c.receive(DATA, END_STREAM, 3, b"\x00\x00\x00\x00\x02\x08\x09"));
+        assert!(c.out.ends_with(b"grpc-message\x0eunknown kind 9"));
+        assert!(!c.calls.contains_key(&3));
+        c.out.clear();
+
+        // other methods, with the request then ignored
+        let health = b"\x83\x44\x1c/grpc.health.v1.Health/Check";
+        assert!(c.receive(HEADERS, END_HEADERS, 5, health));
+        assert!(c
+            .out
+            .ends_with(b"grpc-status\x0212\x00\x0cgrpc-message\x0eunknown method"));
+        assert!(!c.calls.contains_key(&5));
+        c.out.clear();
+        assert!(c.receive(DATA, END_STREAM, 5, b"\x00\x00\x00\x00\x00"));
+        assert!(c.out.is_empty());
+
+        assert!(c.receive(RST_STREAM, 0, 1, b"\x00\x00\x00\x08"));
+        assert!(c.calls.is_empty());
+        assert!(!c.receive(GOAWAY, 0, 0, b"\x00\x00\x00\x00\x00\x00\x00\x00"));
+    }
+
+    #[test]
+    fn test_headers() {
+        assert_eq!(
+            huffman_decode(
+                b"\x62\xb3\x24\xe9\x5e\x13\x59\xac\xc4\x4d\xb6\x15\x8d\xd6\xc6\x82\x58\x68\xcb"
+            ),
+            Some(PATH.to_vec())
+        );
+        // padding longer than 7 bits, then not all ones
+        assert!(huffman_decode(b"\xff\xff").is_none());
+        assert!(huffman_decode(b"\x60").is_none());
+
+        let mut h = Hpack::new();
+        let fields = h.decode(SUBSCRIBE).unwrap();
+        assert_eq!(fields[2], (b":path".to_vec(), PATH.to_vec()));
+        assert_eq!(h.table.len(), 4);
+        assert_eq!(h.decode(SUBSCRIBE_INDEXED).unwrap(), fields);
+
+        // evicted as the table shrinks
+        assert_eq!(h.decode(b"\x20").unwrap(), vec![]);
+        assert!(h.table.is_empty());
+        assert!(h.decode(SUBSCRIBE_INDEXED).is_err());
+        assert!(h.decode(&SUBSCRIBE[..10]).is_err());
+
+        // continued, and with priority
+        let mut c = Conn::new(Vec::new());
+        let mut block = vec![0, 0, 0, 0, 0];
+        block.extend_from_slice(&SUBSCRIBE[..10]);
+        assert!(c.receive(HEADERS, PRIORITY, 1, &block));
+        assert!(c.calls.is_empty());
+        assert!(c.receive(CONTINUATION, END_HEADERS, 1, &SUBSCRIBE[10..]));
+        assert!(c.calls.contains_key(&1));
+        assert!(!c.receive(CONTINUATION, END_HEADERS, 1, SUBSCRIBE));
+    }
+
+    #[test]
+    fn test_limits() {
+        let goaway = |error| {
+            let mut payload = vec![0, 0, 0, 0];
+            payload.extend_from_slice(&be32_bytes(error));
+            frame(GOAWAY, 0, 0, &payload)
+        };
+
+        for size in &[0_u32, 16_383, 16_777_216] {
+            let mut c = Conn::new(Vec::new());
+            c.out.clear();
+            let mut s = vec![0, MAX_FRAME_SIZE as u8];
+            s.extend_from_slice(&be32_bytes(*size));
+            assert!(!c.receive(SETTINGS, 0, 0, &s));
+            assert_eq!(c.out, goaway(PROTOCOL_ERROR));
+        }
+
+        let mut c = Conn::new(Vec::new());
+        c.out.clear();
+        assert!(c.receive(SETTINGS, 0, 0, b"\x00\x05\x00\xff\xff\xff"));
+        assert_eq!(c.max_frame, MAX_MAX_FRAME);
+        assert!(!c.receive(SETTINGS, 0, 0, b"\x00\x04\x80\x00\x00\x00"));
+        assert!(c.out.ends_with(&goaway(FLOW_CONTROL_ERROR)));
+
+        // requests are not buffered past the largest one taken
+        let mut c = Conn::new(Vec::new());
+        assert!(c.receive(HEADERS, END_HEADERS, 1, SUBSCRIBE));
+        assert!(c.receive(DATA, 0, 1, &[0; MAX_REQUEST]));
+        assert!(c.calls.contains_key(&1));
+        c.out.clear();
+        assert!(c.receive(DATA, 0, 1, &[0; 6]));
+        assert!(!c.calls.contains_key(&1));
+        assert_eq!(
+            c.out,
+            frame(RST_STREAM, 0, 1, &be32_bytes(ENHANCE_YOUR_CALM))
+        );
+
+        // calls beyond the advertised limit are refused
+        let mut c = Conn::new(Vec::new());
+        assert!(c.out.ends_with(b"\x00\x03\x00\x00\x00\x08"));
+        assert!(c.receive(HEADERS, END_HEADERS, 1, SUBSCRIBE));
+        for i in 1..MAX_CALLS as u32 {
+            assert!(c.receive(HEADERS, END_HEADERS, 2 * i + 1, SUBSCRIBE_INDEXED));
+        }
+        assert_eq!(c.calls.len(), MAX_CALLS);
+        c.out.clear();
+        assert!(c.receive(HEADERS, END_HEADERS, 99, SUBSCRIBE_INDEXED));
+        assert_eq!(c.out, frame(RST_STREAM, 0, 99, &be32_bytes(REFUSED_STREAM)));
+        assert_eq!(c.calls.len(), MAX_CALLS);
+        assert!(!c.receive(GOAWAY, 0, 0, b"\x00\x00\x00\x00\x00\x00\x00\x00"));
+    }
+
+    #[test]
+    fn test_decoders() {
+        assert_eq!(hpack_read_int(b"\x1f\x9a\x0a", 5), Some((1337, 3)));
+        assert_eq!(hpack_read_int(b"\x1f\x9a", 5), None);
+        assert_eq!(hpack_read_int(b"\x7f\xff\xff\xff\xff\x01", 7), None);
+        assert_eq!(hpack_read_string(b"\x05abc"), None);
+        assert_eq!(hpack_read_string(b"\x7f\xff\xff\xff\x0f"), None);
+        assert_eq!(unpadded(PADDED, b"\xffab"), b"");
+        assert_eq!(unpadded(PADDED, b"\x01ab"), b"a");
+
+        let mut h = Hpack::new();
+        assert!(h.decode(b"\x80").is_err());
+        assert!(h.decode(b"\xff\x00").is_err());
+        assert!(h.decode(b"\x3f\xe2\x1f").is_err());
+        // literal with a name index beyond both tables
+        assert!(h.decode(b"\x0f\x30\x00").is_err());
+        // entries larger than the table are not kept
+        let mut big = b"\x40\x01a\x7f\x81\x20".to_vec();
+        big.extend_from_slice(&[b'b'; 4096 + 128]);
+        assert_eq!(h.decode(&big).unwrap().len(), 1);
+        assert!(h.table.is_empty());
+        assert_eq!(h.size, 0);
+
+        // no input may panic a connection, random or a mangled real one
+        let mut seed = 0x2545_f491_u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for round in 0..2000 {
+            let mut c = Conn::new(Vec::new());
+            for _ in 0..8 {
+                let len = next() as usize % 64;
+                let mut payload: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+                if round % 2 == 0 {
+                    payload = SUBSCRIBE.to_vec();
+                    let i = next() as usize % payload.len();
+                    payload[i] = next() as u8;
+                }
+                let kind = next() as u8 % 10;
+                let flags = next() as u8;
+                let stream = next() % 8;
+                if !c.receive(kind, flags, stream, &payload) {
+                    break;
+                }
+                c.send(&[traffic(1.0)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_queue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (tx, rx) = sync_channel(1);
+        let mut c = Conn::new(Queue {
+            frames: tx,
+            stream: listener.accept().unwrap().0,
+        });
+
+        // SETTINGS fill the queue already
+        c.write(&frame(PING, ACK, 0, b"12345678"));
+        assert!(c.closed);
+        assert_eq!(rx.recv().unwrap(), frame(SETTINGS, 0, 0, &settings()));
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
@@ -15,22 +15,63 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use pitot::handle::Pushable;
+use pitot::situation::Situation;
 use processor::Report;
 use std::iter::Chain;
 use std::slice::Iter;
+use std::time::Instant;
 
 type ChainedIter<'a> = Chain<Iter<'a, Report>, Iter<'a, Report>>;
 
+pub mod encounters;
+pub mod feed;
+#[cfg(feature = "gdl90")]
 pub mod gdl90;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod recorder;
+pub mod sbs;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(feature = "websocket")]
 pub mod websocket;
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Payload {
     pub queueable: bool,
     pub payload: Vec<u8>,
+    /// What the payload carries and when the newest data in it was received,
+    /// for measuring latency. `None` for payloads not measured, or data that
+    /// was already sent before.
+    pub received: Option<(Class, Instant)>,
+    /// ID the pipeline trace gave the sensor message the payload carries,
+    /// see `pitot::trace`
+    pub trace: Option<u64>,
+}
+
+/// Kinds of payloads latency is measured for
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Class {
+    Traffic = 0,
+    Uplink = 1,
+    Ownship = 2,
 }
 
 pub trait Protocol {
     /// Deliver event `e` to this processor
     fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter);
+
+    /// Deliver the state of the world after this iteration, for protocols
+    /// that need more than the reports of the iteration, e.g. the nearest
+    /// target. Called right after `run`.
+    fn run_situation(&mut self, _handle: &mut Pushable<Payload>, _situation: &Situation) {}
+
+    /// Called when this protocol is paused at runtime, it is not run again until
+    /// resumed
+    fn pause(&mut self) {}
+
+    /// Called when this protocol is resumed after having been paused
+    fn resume(&mut self) {}
 }
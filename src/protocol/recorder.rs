@@ -0,0 +1,121 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Records every report into a binary log, see `recording` for the format.
+//! Each run of Pitot starts a new `pitot-<n>.plog` in the configured
+//! directory.
+
+use super::*;
+use recording::{create_numbered, LogWriter};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use utils::secs_f32;
+
+pub struct Recorder {
+    /// `None` once writing failed
+    log: Option<LogWriter<BufWriter<File>>>,
+    path: PathBuf,
+    start: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn new(dir: &str) -> Option<Box<Protocol>> {
+        let (path, file) = match create_numbered(Path::new(dir), "pitot", "plog") {
+            Ok(f) => f,
+            Err(e) => {
+                error!("unable to start recording into {}: {}", dir, e);
+                return None;
+            }
+        };
+
+        match LogWriter::new(BufWriter::new(file)) {
+            Ok(log) => {
+                info!("recording reports to {}", path.display());
+
+                Some(Box::new(Self {
+                    log: Some(log),
+                    path,
+                    start: None,
+                }))
+            }
+            Err(e) => {
+                error!("unable to write {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+impl Protocol for Recorder {
+    fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter) {
+        let clock = handle.get_clock();
+        let time = (secs_f32(clock - *self.start.get_or_insert(clock)) * 1000.0) as u32;
+
+        let res = match self.log {
+            Some(ref mut log) => i
+                .map(|r| log.write(time, r))
+                .collect::<io::Result<()>>()
+                .and_then(|_| log.flush()),
+            None => return,
+        };
+
+        if let Err(e) = res {
+            error!(
+                "unable to write {}, recording stopped: {}",
+                self.path.display(),
+                e
+            );
+            self.log = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::events::Event;
+    use recording::LogReader;
+    use std::fs;
+    use testing::{run_protocol, temp_dir, MockHandle};
+
+    #[test]
+    fn test_recorder() {
+        let dir = temp_dir("recorder");
+        let mut h = MockHandle::new(10);
+
+        {
+            let mut r = Recorder::new(dir.to_str().unwrap()).unwrap();
+            run_protocol(&mut *r, &mut h, &[Report::Event(Event::GNSSLost)]);
+            h.advance_secs(2);
+            run_protocol(&mut *r, &mut h, &[Report::Event(Event::Undervoltage)]);
+        }
+
+        // the next run gets a new file
+        Recorder::new(dir.to_str().unwrap()).unwrap();
+
+        let log = File::open(dir.join("pitot-0.plog")).unwrap();
+        let times: Vec<u32> = LogReader::new(log)
+            .unwrap()
+            .map(|r| r.unwrap().time)
+            .collect();
+        assert_eq!(times, vec![0, 2000]);
+        assert!(dir.join("pitot-1.plog").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Traffic in the BaseStation (SBS) text format on TCP port 30003, which
+//! most feeder clients, virtual radar and logging software read. Lines are
+//! only written for targets with news, once per second.
+
+use super::feed::Feed;
+use super::*;
+use chrono::prelude::*;
+use config::SquawkKind;
+use processor::traffic::{AddressType, AltitudeType, HeadingType, SpeedType, Target};
+
+const SBS_PORT: u16 = 30003;
+
+// fields following the time logged in a MSG line
+const CALLSIGN: usize = 0;
+const ALTITUDE: usize = 1;
+const GROUND_SPEED: usize = 2;
+const TRACK: usize = 3;
+const LAT: usize = 4;
+const LON: usize = 5;
+const VERTICAL_RATE: usize = 6;
+const SQUAWK: usize = 7;
+const ALERT: usize = 8;
+const EMERGENCY: usize = 9;
+const SPI: usize = 10;
+const ON_GROUND: usize = 11;
+const FIELDS: usize = 12;
+
+pub struct SBS {
+    feed: Feed,
+}
+
+impl SBS {
+    pub fn new() -> Option<Box<Protocol>> {
+        Feed::bind("SBS", SBS_PORT).map(|feed| Box::new(SBS { feed }) as Box<Protocol>)
+    }
+}
+
+impl Protocol for SBS {
+    fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter) {
+        self.feed.accept();
+        if !self.feed.has_clients() {
+            return;
+        }
+
+        let utc = handle.get_utc();
+        let mut out = String::new();
+
+        for e in i {
+            if let Report::Traffic(ref t) = *e {
+                for m in messages(t, &utc) {
+                    out.push_str(&m);
+                    out.push_str("\r\n");
+                }
+            }
+        }
+
+        if !out.is_empty() {
+            self.feed.send(out.as_bytes());
+        }
+    }
+}
+
+/// SBS writes true as `-1`
+fn flag(f: bool) -> String {
+    if f { "-1" } else { "0" }.to_string()
+}
+
+/// Identification (1), airborne position (3), velocity (4) and
+/// surveillance (6) messages for what is known of `t`
+fn messages(t: &Target, utc: &DateTime<UTC>) -> Vec<String> {
+    // as dump1090 does, non-ICAO addresses are marked
+    let hex = match t.addr.1 {
+        AddressType::ADSBICAO | AddressType::ADSRICAO | AddressType::TISBICAO => {
+            format!("{:06X}", t.addr.0)
+        }
+        _ => format!("~{:06X}", t.addr.0),
+    };
+    let time = utc.format("%Y/%m/%d,%H:%M:%S%.3f").to_string();
+    let line = |typ: u8, fields: &[String]| {
+        format!(
+            "MSG,{},1,1,{},1,{},{},{}",
+            typ,
+            hex,
+            time,
+            time,
+            fields.join(",")
+        )
+    };
+
+    // pressure altitude
+    let altitude = match t.altitude {
+        Some((alt, AltitudeType::Baro, _)) => Some(alt),
+        Some((alt, AltitudeType::GNSS, _)) => t.gnss_delta.map(|d| alt - d),
+        None => None,
+    };
+    let mut status = vec![String::new(); FIELDS];
+    status[ALERT] = flag(false);
+    status[EMERGENCY] = flag(t.squawk_kind == Some(SquawkKind::Emergency));
+    status[SPI] = flag(t.ident.is_some());
+    status[ON_GROUND] = t.on_ground.map_or(String::new(), flag);
+    status[ALTITUDE] = altitude.map_or(String::new(), |a| a.to_string());
+
+    let mut lines = Vec::new();
+
+    if let Some(ref cs) = t.callsign {
+        let mut f = vec![String::new(); FIELDS];
+        f[CALLSIGN] = cs.clone();
+        lines.push(line(1, &f));
+    }
+
+    if let Some(((lat, lon), _)) = t.lat_lon {
+        let mut f = status.clone();
+        f[LAT] = format!("{:.5}", lat);
+        f[LON] = format!("{:.5}", lon);
+        lines.push(line(3, &f));
+    }
+
+    let gs = match t.speed {
+        Some((s, SpeedType::GS, _)) => Some(s),
+        _ => None,
+    };
+    let track = match t.heading {
+        Some((h, HeadingType::True, _)) => Some(h),
+        _ => None,
+    };
+    if gs.is_some() || track.is_some() || t.vs.is_some() {
+        let mut f = vec![String::new(); FIELDS];
+        f[GROUND_SPEED] = gs.map_or(String::new(), |s| s.to_string());
+        f[TRACK] = track.map_or(String::new(), |h| h.to_string());
+        f[VERTICAL_RATE] = t.vs.map_or(String::new(), |v| v.0.to_string());
+        lines.push(line(4, &f));
+    }
+
+    if let Some(sq) = t.squawk {
+        let mut f = status;
+        f[SQUAWK] = format!("{:04}", sq);
+        lines.push(line(6, &f));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::traffic::TrafficSource;
+    use std::time::Instant;
+
+    #[test]
+    fn test_messages() {
+        let clock = Instant::now();
+        let utc = UTC.ymd(2018, 7, 1).and_hms_milli(12, 30, 5, 250);
+        let mut t = Target::new(
+            (0xA1B2C3, AddressType::ADSBICAO),
+            clock,
+            TrafficSource::ES,
+            Some("N12345".to_string()),
+        );
+
+        t.lat_lon = Some(((37.5, -122.25), clock));
+        t.altitude = Some((3500, AltitudeType::GNSS, clock));
+        t.gnss_delta = Some(200);
+        t.speed = Some((120, SpeedType::GS, clock));
+        t.heading = Some((90, HeadingType::Mag, clock));
+        t.squawk = Some(7700);
+        t.squawk_kind = Some(SquawkKind::Emergency);
+        t.on_ground = Some(false);
+
+        let m = messages(&t, &utc);
+        assert_eq!(
+            m,
+            vec![
+                "MSG,1,1,1,A1B2C3,1,2018/07/01,12:30:05.250,2018/07/01,12:30:05.250,N12345,,,,,,,,,,,",
+                "MSG,3,1,1,A1B2C3,1,2018/07/01,12:30:05.250,2018/07/01,12:30:05.250,,3300,,,37.50000,-122.25000,,,0,-1,0,0",
+                "MSG,4,1,1,A1B2C3,1,2018/07/01,12:30:05.250,2018/07/01,12:30:05.250,,,120,,,,,,,,,",
+                "MSG,6,1,1,A1B2C3,1,2018/07/01,12:30:05.250,2018/07/01,12:30:05.250,,3300,,,,,,7700,0,-1,0,0",
+            ]
+        );
+        for l in &m {
+            assert_eq!(l.split(',').count(), 22);
+        }
+
+        // nothing to tell about an anonymous target yet
+        let t = Target::new(
+            (0x123, AddressType::ADSBOther),
+            clock,
+            TrafficSource::UAT,
+            None,
+        );
+        assert!(messages(&t, &utc).is_empty());
+
+        let mut t = Target::new(
+            (0x123, AddressType::ADSBOther),
+            clock,
+            TrafficSource::UAT,
+            None,
+        );
+        t.vs = Some((-500, clock));
+        assert!(messages(&t, &utc)[0].starts_with("MSG,4,1,1,~000123,"));
+    }
+}
@@ -0,0 +1,144 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runs a user supplied Lua script against every report. The script defines
+//! `on_report(report)`, which receives reports as tables shaped like the
+//! WebSocket JSON, and can call back into Pitot through the `pitot` table:
+//!
+//! * `pitot.send(data)` sends `data` (e.g. a custom NMEA sentence) to all
+//!   clients through the transports
+//! * `pitot.alert(message)` logs `message` as a warning
+//!
+//! Scripts run inside the main loop and must return quickly.
+
+use super::*;
+use rlua::{self, Function, Lua, Table};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+pub struct Script {
+    lua: Lua,
+    name: String,
+    /// data the script asked to send since the last run
+    outbox: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Script {
+    pub fn new(path: &str) -> Option<Box<Protocol>> {
+        let mut src = String::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+            error!("unable to read script {}: {}", path, e);
+            return None;
+        }
+
+        let lua = Lua::new();
+        let outbox = Arc::new(Mutex::new(Vec::new()));
+
+        if let Err(e) = Script::expose_api(&lua, outbox.clone()) {
+            error!("unable to set up script {}: {}", path, e);
+            return None;
+        }
+
+        if let Err(e) = lua.exec::<()>(&src, Some(path)) {
+            error!("script {} failed to load: {}", path, e);
+            return None;
+        }
+
+        info!("loaded script {}", path);
+
+        Some(Box::new(Self {
+            lua,
+            name: path.to_string(),
+            outbox,
+        }))
+    }
+
+    fn expose_api(lua: &Lua, outbox: Arc<Mutex<Vec<Vec<u8>>>>) -> rlua::Result<()> {
+        let api = try!(lua.create_table());
+
+        try!(api.set(
+            "send",
+            try!(lua.create_function(move |_, data: rlua::String| {
+                outbox.lock().unwrap().push(data.as_bytes().to_vec());
+                Ok(())
+            })),
+        ));
+        try!(api.set(
+            "alert",
+            try!(lua.create_function(|_, msg: String| {
+                warn!("script alert: {}", msg);
+                Ok(())
+            })),
+        ));
+
+        lua.globals().set("pitot", api)
+    }
+}
+
+/// Converts a JSON value into the equivalent Lua value, arrays become
+/// sequences starting at 1
+fn to_lua<'lua>(lua: &'lua Lua, v: &Value) -> rlua::Result<rlua::Value<'lua>> {
+    Ok(match *v {
+        Value::Null => rlua::Value::Nil,
+        Value::Bool(b) => rlua::Value::Boolean(b),
+        Value::Number(ref n) => match n.as_i64() {
+            Some(i) => rlua::Value::Integer(i),
+            None => rlua::Value::Number(n.as_f64().unwrap_or(0.0)),
+        },
+        Value::String(ref s) => rlua::Value::String(try!(lua.create_string(s))),
+        Value::Array(ref a) => {
+            let t = try!(lua.create_table());
+            for (i, e) in a.iter().enumerate() {
+                try!(t.set(i + 1, try!(to_lua(lua, e))));
+            }
+            rlua::Value::Table(t)
+        }
+        Value::Object(ref o) => {
+            let t: Table = try!(lua.create_table());
+            for (k, e) in o {
+                try!(t.set(k.as_str(), try!(to_lua(lua, e))));
+            }
+            rlua::Value::Table(t)
+        }
+    })
+}
+
+impl Protocol for Script {
+    fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter) {
+        if let Ok(on_report) = self.lua.globals().get::<_, Function>("on_report") {
+            for r in i {
+                if let Some(js) = r.to_json() {
+                    let res = to_lua(&self.lua, &js).and_then(|t| on_report.call::<_, ()>(t));
+
+                    if let Err(e) = res {
+                        warn!("script {} failed: {}", self.name, e);
+                    }
+                }
+            }
+        }
+
+        for payload in self.outbox.lock().unwrap().drain(..) {
+            handle.push_data(Payload {
+                queueable: false,
+                payload,
+                received: None,
+                trace: None,
+            });
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! POSTs events as JSON to user configured URLs. Requests are made with
+//! `curl` from a separate thread, events that can not be delivered (e.g.
+//! because there is no internet connection) are dropped.
+
+use super::*;
+use config::Webhook as Hook;
+use std::io::{self, Write};
+use std::process::{self, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{spawn, JoinHandle};
+
+// give up on a request after this many seconds
+const TIMEOUT_SECS: &str = "10";
+
+pub struct Webhook {
+    hooks: Vec<Hook>,
+    tx: Sender<(String, String)>,
+    _handle: JoinHandle<()>,
+}
+
+impl Webhook {
+    pub fn new(hooks: Vec<Hook>) -> Box<Self> {
+        let (tx, rx) = channel::<(String, String)>();
+
+        let handle = spawn(move || {
+            for (url, body) in rx.iter() {
+                if let Err(e) = post(&url, &body) {
+                    warn!("unable to deliver event to {}: {}", url, e);
+                }
+            }
+        });
+
+        Box::new(Self {
+            hooks,
+            tx,
+            _handle: handle,
+        })
+    }
+}
+
+fn post(url: &str, body: &str) -> io::Result<()> {
+    let mut child = try!(
+        process::Command::new("curl")
+            .args(&["-fsS", "-m", TIMEOUT_SECS, "-X", "POST"])
+            .args(&["-H", "Content-Type: application/json"])
+            .args(&["--data-binary", "@-", url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+    );
+
+    try!(child.stdin.take().unwrap().write_all(body.as_bytes()));
+
+    let status = try!(child.wait());
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, status.to_string()))
+    }
+}
+
+impl Protocol for Webhook {
+    fn run(&mut self, _handle: &mut Pushable<Payload>, i: ChainedIter) {
+        for r in i {
+            match *r {
+                Report::Event(ref e) => {
+                    let body = r.to_json().unwrap().to_string();
+
+                    for h in self.hooks
+                        .iter()
+                        .filter(|h| h.events.is_empty() || h.events.iter().any(|n| n == e.name()))
+                    {
+                        self.tx.send((h.url.clone(), body.clone())).unwrap();
+                    }
+                }
+                Report::Config(ref c) => self.hooks = c.webhooks.clone(),
+                _ => {}
+            }
+        }
+    }
+}
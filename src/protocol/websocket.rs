@@ -14,27 +14,829 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+//! Sends reports as JSON to WebSocket clients and takes commands from them.
+//!
+//! Traffic is sent as deltas: a `TrafficSnapshot` of every known target when
+//! a client connects, then `TrafficAdd`, `TrafficUpdate` and `TrafficRemove`
+//! messages for targets that appear, change or disappear. Deltas carry a
+//! sequence number so clients can spot a gap and ask for a new snapshot.
+//!
+//! Clients may narrow down what they receive in the query string of the URL
+//! they connect to, e.g. `ws://192.168.0.1:9001/?types=Ownship,Traffic&range=10&rate=1`
+//! only sends ownship and traffic within 10 NM, each target at most once per
+//! second. Sequence numbers are kept per client, so filtered out targets do
+//! not show up as gaps.
+//!
+//! Clients can also ask for everything known at once by sending
+//! `{"type": "GetSituation"}`, which is answered right away with a
+//! `Situation` message, see `pitot::situation`.
+//!
+//! Web based EFBs can get the GDL90 stream sent to UDP clients in binary
+//! messages, one per GDL90 message, by adding `gdl90` to the query string
+//! or asking for the `gdl90` subprotocol. The stream is sent by a transport
+//! sharing the connections, see `WebSocket::gdl90`.
+//!
+//! Clients that can not use WebSockets, e.g. scripts using curl, can get the
+//! same messages as Server-Sent Events over plain HTTP, see
+//! `WebSocket::serve_events`. They get a `Situation` message first, as they
+//! can not ask for one, and may use the same query string filters.
+//!
+//! The same HTTP server exports the traffic table, or its history, once as
+//! `/traffic.json` or `/traffic.csv`, see `export`, and answers
+//! `/cabin.json` with the latest `Cabin` message and `/antenna.json` with
+//! the daily antenna summaries of the last 90 days.
+
 use super::*;
-use processor::Report::{Ownship, GNSS};
-use serde_json;
+use pitot::handle::Handle;
+use pitot::situation::SharedSituation;
+use processor::antenna;
+use processor::Report::{
+    Antenna, Cabin, Config, DecodeStats, Event, Navigation, Ownship, SkyView, Traffic, WarmStart,
+    E6B, GNSS,
+};
+use sensor::command::Command;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use storage::Storage;
+use transport::{self, ClientStatus, SharedStatus, Transport};
+use units::Units;
 use ws;
+use ws::util::Token;
+
+/// Targets are reported every second while fresh, one not reported for
+/// this long is gone
+const TRAFFIC_REMOVE_SECS: u64 = 3;
+/// GDL90 clients, the loopback check, packing and latency are sent this
+/// often, in Hz
+const CLIENTS_FREQ: u32 = 1;
+/// Subprotocol of clients only receiving the GDL90 stream
+const GDL90_PROTOCOL: &str = "gdl90";
+/// Server-Sent Events clients must send their request, and take the
+/// response head and traffic snapshot, within this long, in ms. Later
+/// messages are dropped along with the client once the socket buffer is full.
+const EVENTS_TIMEOUT_MS: u64 = 2000;
+/// Largest request head taken from Server-Sent Events clients
+const EVENTS_MAX_REQUEST: usize = 8192;
+/// HTTP requests answered at the same time, each in its own thread, more
+/// connections are closed right away
+const EVENTS_MAX_PENDING: usize = 16;
+
+/// Message types clients can subscribe to, `Traffic` covers the deltas
+const TYPES: [&str; 15] = [
+    "Ownship",
+    "GNSS",
+    "SkyView",
+    "DecodeStats",
+    "E6B",
+    "Navigation",
+    "Traffic",
+    "Clients",
+    "Loopback",
+    "Packing",
+    "WarmStart",
+    "Antenna",
+    "Cabin",
+    "Latency",
+    "Event",
+];
 
 pub struct WebSocket {
-    ws_broadcaster: ws::Sender,
     _handle: JoinHandle<()>,
+    shared: Arc<Mutex<Shared>>,
+    transport: SharedStatus,
+    situation: SharedSituation,
+    clients_counter: u32,
+}
+
+/// State shared between the protocol and the WebSocket thread
+struct Shared {
+    traffic: TrafficTable,
+    clients: BTreeMap<Token, Subscriber>,
+    /// Server-Sent Events clients
+    events: Vec<Subscriber>,
+    units: Units,
+    /// handle clock of the last run, snapshots sent from client threads
+    /// use it so later deltas compare against the same clock
+    clock: Instant,
+}
+
+impl Shared {
+    /// Send `js` to every client subscribed to it
+    fn broadcast(&self, js: &Value) {
+        let msg = js.to_string();
+
+        for c in self
+            .clients
+            .values()
+            .chain(&self.events)
+            .filter(|c| c.view.filter.accepts(js))
+        {
+            c.out.send(&msg).unwrap();
+        }
+    }
+}
+
+/// Where the messages of one client go
+enum Out {
+    WebSocket(ws::Sender),
+    /// Server-Sent Events on a plain HTTP response, non-blocking so a slow
+    /// client can not hold up the others. Closed once writing fails or
+    /// would block.
+    Events(TcpStream, Cell<bool>),
+}
+
+impl Out {
+    fn send(&self, msg: &str) -> ws::Result<()> {
+        match *self {
+            Out::WebSocket(ref out) => out.send(msg),
+            Out::Events(ref stream, ref closed) => {
+                if !closed.get() && write_event(stream, msg).is_err() {
+                    closed.set(true);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Keep an events client from timing out while there is nothing it
+    /// subscribed to, and notice when it is gone
+    fn keep_alive(&self) {
+        if let Out::Events(ref stream, ref closed) = *self {
+            if !closed.get() && (&*stream).write_all(b":\n\n").is_err() {
+                closed.set(true);
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match *self {
+            Out::WebSocket(_) => false,
+            Out::Events(_, ref closed) => closed.get(),
+        }
+    }
+}
+
+/// Answer the request of an events client, and add it to the clients
+/// messages are sent to
+fn accept_events(
+    stream: TcpStream,
+    shared: &Arc<Mutex<Shared>>,
+    situation: &SharedSituation,
+    storage: &Storage,
+) -> io::Result<()> {
+    let timeout = Some(Duration::from_millis(EVENTS_TIMEOUT_MS));
+    try!(stream.set_read_timeout(timeout));
+    try!(stream.set_write_timeout(timeout));
+
+    let reader = BufReader::new((&stream).take(EVENTS_MAX_REQUEST as u64));
+    let resource = match read_request(reader) {
+        Ok(r) => r,
+        Err(e) => return reject(&stream, &e),
+    };
+
+    let path = resource.split('?').next().unwrap();
+    if path == "/traffic.json" || path == "/traffic.csv" {
+        let units = shared.lock().unwrap().units;
+        return export(&stream, &resource, units, situation);
+    }
+    if path == "/cabin.json" {
+        let cabin = situation.lock().unwrap().cabin().cloned();
+        let body = cabin.map_or(Value::Null, |c| Report::Cabin(c).to_json().unwrap());
+        return respond(&stream, "application/json", &body.to_string());
+    }
+    if path == "/antenna.json" {
+        let today = situation.lock().unwrap().antenna().cloned();
+        let mut js = Map::new();
+        js.insert("type".to_string(), "AntennaHistory".into());
+        js.insert(
+            "days".to_string(),
+            serde_json::to_value(antenna::history(storage, today.as_ref())).unwrap(),
+        );
+        return respond(&stream, "application/json", &Value::Object(js).to_string());
+    }
+
+    let filter = match Filter::parse(&resource) {
+        Ok(ref f) if f.gdl90 => Err("gdl90 is only sent over WebSocket".to_string()),
+        r => r,
+    };
+    let filter = match filter {
+        Ok(f) => f,
+        Err(e) => return reject(&stream, &e),
+    };
+
+    try!((&stream).write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+              Access-Control-Allow-Origin: *\r\n\r\n"
+    ));
+
+    let units = shared.lock().unwrap().units;
+    let js = situation.lock().unwrap().to_json(units);
+    try!(write_event(&stream, &js.to_string()));
+
+    let mut view = View::new(filter);
+
+    // written blocking as it may not fit the socket buffer, and without
+    // holding up the others. Traffic changing meanwhile is sent as deltas.
+    if view.filter.wants("Traffic") {
+        let snapshot = {
+            let shared = shared.lock().unwrap();
+            view.snapshot(&shared.traffic, shared.clock)
+        };
+        try!(write_event(&stream, &snapshot));
+    }
+
+    try!(stream.set_nonblocking(true));
+    shared.lock().unwrap().events.push(Subscriber {
+        out: Out::Events(stream, Cell::new(false)),
+        view,
+    });
+
+    Ok(())
+}
+
+/// Answer a plain HTTP request with 400 and the reason `e`
+fn reject(stream: &TcpStream, e: &str) -> io::Result<()> {
+    warn!("HTTP client rejected: {}", e);
+    write!(
+        &*stream,
+        "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        e.len(),
+        e
+    )
+}
+
+/// Answer a request for `/traffic.json` or `/traffic.csv` with the traffic
+/// table, or every traffic report of the last minutes with
+/// `?history=<minutes>`. The body is written as it is formatted and ends
+/// when the connection is closed. `X-History-Shortened-To` says how many
+/// seconds of history are left when the oldest reports asked for had to be
+/// dropped already.
+fn export(
+    stream: &TcpStream,
+    resource: &str,
+    units: Units,
+    situation: &SharedSituation,
+) -> io::Result<()> {
+    // formatted after the lock is released, not to hold up the main loop
+    let export = {
+        let situation = situation.lock().unwrap();
+        parse_history(resource, situation.history_len()).map(|h| situation.export(h))
+    };
+    let export = match export {
+        Ok(e) => e,
+        Err(e) => return reject(stream, &e),
+    };
+    let now = SystemTime::now();
+    let csv = resource.starts_with("/traffic.csv");
+
+    let mut w = BufWriter::new(stream);
+    try!(write!(
+        w,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nAccess-Control-Allow-Origin: *\r\n",
+        if csv { "text/csv" } else { "application/json" }
+    ));
+    if let Some(secs) = export.shortened_to() {
+        try!(write!(w, "X-History-Shortened-To: {}\r\n", secs));
+    }
+    try!(w.write_all(b"Connection: close\r\n\r\n"));
+
+    if csv {
+        try!(export.write_csv(&mut w, now));
+    } else {
+        try!(export.write_json(&mut w, units, now));
+    }
+
+    w.flush()
+}
+
+/// Answer a plain HTTP request with `body` and close it
+fn respond(stream: &TcpStream, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        &*stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// History asked for in the query string of an export, no longer than
+/// `kept`
+fn parse_history(resource: &str, kept: Option<Duration>) -> Result<Option<Duration>, String> {
+    let query = match resource.find('?') {
+        Some(i) => &resource[i + 1..],
+        None => return Ok(None),
+    };
+
+    let mut history = None;
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next().unwrap(), kv.next().unwrap_or("")) {
+            ("history", value) => {
+                let kept = match kept {
+                    Some(k) => k.as_secs() / 60,
+                    None => return Err("no history is kept, see traffic_history".to_string()),
+                };
+                match value.parse::<u64>() {
+                    Ok(m) if m > 0 && m <= kept => history = Some(Duration::from_secs(m * 60)),
+                    _ => {
+                        return Err(format!(
+                            "history must be between 1 and {} minutes, got {:?}",
+                            kept, value
+                        ))
+                    }
+                }
+            }
+            (key, _) => return Err(format!("unknown parameter {}", key)),
+        }
+    }
+
+    Ok(history)
+}
+
+/// Resource requested in the HTTP request head read from `r`, only `GET`
+/// is served
+fn read_request<R: BufRead>(r: R) -> Result<String, String> {
+    let mut lines = r.lines();
+
+    let resource = match lines.next() {
+        Some(Ok(line)) => {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("GET"), Some(resource)) => resource.to_string(),
+                _ => return Err(format!("unsupported request {:?}", line.trim())),
+            }
+        }
+        _ => return Err("no request".to_string()),
+    };
+
+    // the headers are of no interest
+    for line in lines {
+        match line {
+            Ok(ref l) if l.trim().is_empty() => return Ok(resource),
+            Ok(_) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    Err("incomplete request".to_string())
+}
+
+/// Write `msg` as one event, with every line as data
+fn write_event<W: Write>(mut w: W, msg: &str) -> io::Result<()> {
+    let mut event = String::with_capacity(msg.len() + 8);
+    for line in msg.lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+
+    w.write_all(event.as_bytes())
+}
+
+struct Target {
+    js: Map<String, Value>,
+    /// when it was last reported
+    seen: Instant,
+    /// bumped on every change
+    version: u64,
+}
+
+/// Every known target, as sent to clients
+#[derive(Default)]
+struct TrafficTable {
+    /// version of the last change
+    version: u64,
+    targets: BTreeMap<u64, Target>,
+}
+
+impl TrafficTable {
+    /// Target `js` was reported at `clock`
+    fn update(&mut self, mut js: Map<String, Value>, clock: Instant) {
+        js.remove("type");
+        let addr = match js.get("addr").and_then(|a| a.as_u64()) {
+            Some(a) => a,
+            None => return,
+        };
+
+        if let Some(t) = self.targets.get_mut(&addr) {
+            t.seen = clock;
+            if t.js == js {
+                return;
+            }
+        }
+
+        self.version += 1;
+        self.targets.insert(
+            addr,
+            Target {
+                js,
+                seen: clock,
+                version: self.version,
+            },
+        );
+    }
+
+    /// Forget targets no longer reported
+    fn expire(&mut self, clock: Instant) {
+        let gone: Vec<u64> = self
+            .targets
+            .iter()
+            .filter(|&(_, t)| clock - t.seen >= Duration::from_secs(TRAFFIC_REMOVE_SECS))
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        for addr in gone {
+            self.targets.remove(&addr);
+        }
+    }
+}
+
+/// What a client subscribed to when connecting
+#[derive(Debug, Default, PartialEq)]
+struct Filter {
+    /// message types to send, all if `None`
+    types: Option<Vec<String>>,
+    /// only send traffic this close, in NM
+    range: Option<f64>,
+    /// minimum time between updates of one target
+    interval: Option<Duration>,
+    /// also send the GDL90 stream
+    gdl90: bool,
+}
+
+impl Filter {
+    /// Parse the query string of `resource`, the path requested when
+    /// connecting
+    fn parse(resource: &str) -> Result<Self, String> {
+        let mut filter = Filter::default();
+
+        let query = match resource.find('?') {
+            Some(i) => &resource[i + 1..],
+            None => return Ok(filter),
+        };
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let mut kv = pair.splitn(2, '=');
+            let key = kv.next().unwrap();
+            let value = kv.next().unwrap_or("");
+
+            match key {
+                "types" => {
+                    let types: Vec<String> = value
+                        .split(',')
+                        .filter(|t| !t.is_empty())
+                        .map(|t| t.to_string())
+                        .collect();
+
+                    if let Some(t) = types.iter().find(|t| !TYPES.contains(&t.as_str())) {
+                        return Err(format!("unknown message type {}", t));
+                    }
+
+                    filter.types = Some(types);
+                }
+                "range" => filter.range = Some(try!(positive(key, value))),
+                "rate" => {
+                    let rate = try!(positive(key, value));
+                    filter.interval = Some(Duration::from_millis((1000.0 / rate) as u64));
+                }
+                "gdl90" => {
+                    filter.gdl90 = match value {
+                        "" | "true" => true,
+                        "false" => false,
+                        _ => return Err(format!("gdl90 must be true or false, got {:?}", value)),
+                    }
+                }
+                _ => return Err(format!("unknown filter {}", key)),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    fn wants(&self, kind: &str) -> bool {
+        self.types
+            .as_ref()
+            .map_or(true, |t| t.iter().any(|t| t == kind))
+    }
+
+    /// Whether target `js` is close enough, targets at an unknown range are
+    /// not when a range is set
+    fn in_range(&self, js: &Map<String, Value>) -> bool {
+        self.range.map_or(true, |max| {
+            js.get("range")
+                .and_then(|r| r.as_f64())
+                .map_or(false, |r| r <= max)
+        })
+    }
+
+    /// Whether report `js` should be sent
+    fn accepts(&self, js: &Value) -> bool {
+        let kind = js["type"].as_str().unwrap_or("");
+
+        self.wants(kind)
+            && match *js {
+                Value::Object(ref o) if kind == "Traffic" => self.in_range(o),
+                _ => true,
+            }
+    }
+}
+
+fn positive(key: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(v) if v > 0.0 => Ok(v),
+        _ => Err(format!(
+            "{} must be a positive number, got {:?}",
+            key, value
+        )),
+    }
+}
+
+/// `v` as a message of type `kind`
+fn tagged<T: Serialize>(kind: &str, v: &T) -> Value {
+    let mut js = match serde_json::to_value(v).unwrap() {
+        Value::Object(o) => o,
+        _ => unreachable!(),
+    };
+    js.insert("type".to_string(), kind.into());
+
+    Value::Object(js)
+}
+
+/// `Clients` message listing the GDL90 clients in `table`
+fn clients_json(table: &BTreeMap<Ipv4Addr, ClientStatus>) -> Value {
+    let clients: Vec<Value> = table
+        .iter()
+        .map(|(ip, c)| {
+            let mut js = match serde_json::to_value(c).unwrap() {
+                Value::Object(o) => o,
+                _ => unreachable!(),
+            };
+            js.insert("ip".to_string(), ip.to_string().into());
+
+            Value::Object(js)
+        })
+        .collect();
+
+    let mut js = Map::new();
+    js.insert("type".to_string(), "Clients".into());
+    js.insert("clients".to_string(), clients.into());
+
+    Value::Object(js)
+}
+
+/// Message `kind` about one target, numbered after `seq`
+fn delta(seq: &mut u64, kind: &str, mut js: Map<String, Value>) -> String {
+    *seq += 1;
+    js.insert("type".to_string(), kind.into());
+    js.insert("seq".to_string(), (*seq).into());
+
+    Value::Object(js).to_string()
+}
+
+/// Traffic as seen by one client
+struct View {
+    filter: Filter,
+    /// sequence number of the last delta
+    seq: u64,
+    /// targets the client knows about, with the version and when it was sent
+    sent: BTreeMap<u64, (u64, Instant)>,
+}
+
+impl View {
+    fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            seq: 0,
+            sent: BTreeMap::new(),
+        }
+    }
+
+    /// Deltas bringing the client up to date with `table`
+    fn sync(&mut self, table: &TrafficTable, clock: Instant) -> Vec<String> {
+        let View {
+            ref filter,
+            ref mut seq,
+            ref mut sent,
+        } = *self;
+        let mut deltas = Vec::new();
+
+        let hidden: Vec<u64> = sent
+            .keys()
+            .filter(|a| {
+                table
+                    .targets
+                    .get(a)
+                    .map_or(true, |t| !filter.in_range(&t.js))
+            })
+            .cloned()
+            .collect();
+
+        for addr in hidden {
+            sent.remove(&addr);
+
+            let mut js = Map::new();
+            js.insert("addr".to_string(), addr.into());
+            deltas.push(delta(seq, "TrafficRemove", js));
+        }
+
+        for (addr, t) in table.targets.iter().filter(|t| filter.in_range(&t.1.js)) {
+            let kind = match sent.get(addr) {
+                None => "TrafficAdd",
+                Some(&(version, _)) if version == t.version => continue,
+                // sent again once the interval is over
+                Some(&(_, at)) if filter.interval.map_or(false, |i| clock - at < i) => continue,
+                Some(_) => "TrafficUpdate",
+            };
+
+            sent.insert(*addr, (t.version, clock));
+            deltas.push(delta(seq, kind, t.js.clone()));
+        }
+
+        deltas
+    }
+
+    /// Every target the client wants, deltas up to `seq` are included
+    fn snapshot(&mut self, table: &TrafficTable, clock: Instant) -> String {
+        let View {
+            ref filter,
+            ref mut sent,
+            ..
+        } = *self;
+        sent.clear();
+
+        let mut targets = Vec::new();
+        for (addr, t) in table.targets.iter().filter(|t| filter.in_range(&t.1.js)) {
+            sent.insert(*addr, (t.version, clock));
+            targets.push(Value::Object(t.js.clone()));
+        }
+
+        let mut js = Map::new();
+        js.insert("type".to_string(), "TrafficSnapshot".into());
+        js.insert("seq".to_string(), self.seq.into());
+        js.insert("targets".to_string(), targets.into());
+
+        Value::Object(js).to_string()
+    }
+}
+
+struct Subscriber {
+    out: Out,
+    view: View,
+}
+
+impl Subscriber {
+    fn send_snapshot(&mut self, table: &TrafficTable, clock: Instant) -> ws::Result<()> {
+        if !self.view.filter.wants("Traffic") {
+            return Ok(());
+        }
+
+        let snapshot = self.view.snapshot(table, clock);
+        self.out.send(&snapshot)
+    }
+}
+
+/// One connected client
+struct Client {
+    out: ws::Sender,
+    commands: Sender<Command>,
+    shared: Arc<Mutex<Shared>>,
+    situation: SharedSituation,
+}
+
+impl ws::Handler for Client {
+    fn on_request(&mut self, req: &ws::Request) -> ws::Result<ws::Response> {
+        let mut res = try!(ws::Response::from_request(req));
+        if try!(req.protocols()).contains(&GDL90_PROTOCOL) {
+            res.set_protocol(GDL90_PROTOCOL);
+        }
+
+        Ok(res)
+    }
+
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        let mut filter = match Filter::parse(shake.request.resource()) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("WebSocket client rejected: {}", e);
+                return self.out.close_with_reason(ws::CloseCode::Policy, e);
+            }
+        };
+
+        // the subprotocol is GDL90 only, unless messages were asked for
+        if try!(shake.request.protocols()).contains(&GDL90_PROTOCOL) {
+            filter.gdl90 = true;
+            if filter.types.is_none() {
+                filter.types = Some(Vec::new());
+            }
+        }
+
+        let mut sub = Subscriber {
+            out: Out::WebSocket(self.out.clone()),
+            view: View::new(filter),
+        };
+
+        let mut shared = self.shared.lock().unwrap();
+        try!(sub.send_snapshot(&shared.traffic, shared.clock));
+        shared.clients.insert(self.out.token(), sub);
+
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        self.shared
+            .lock()
+            .unwrap()
+            .clients
+            .remove(&self.out.token());
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        match msg {
+            ws::Message::Text(ref t) => {
+                // answered right away, it is about this client only
+                let js = serde_json::from_str::<Value>(t).ok();
+                match js.as_ref().and_then(|j| j["type"].as_str()) {
+                    Some("TrafficResync") => {
+                        let mut shared = self.shared.lock().unwrap();
+                        let Shared {
+                            ref traffic,
+                            ref mut clients,
+                            clock,
+                            ..
+                        } = *shared;
+
+                        return match clients.get_mut(&self.out.token()) {
+                            Some(c) => c.send_snapshot(traffic, clock),
+                            None => Ok(()),
+                        };
+                    }
+                    Some("GetSituation") => {
+                        let units = self.shared.lock().unwrap().units;
+                        let situation = self.situation.lock().unwrap().to_json(units);
+
+                        return self.out.send(situation.to_string());
+                    }
+                    _ => {}
+                }
+
+                match serde_json::from_str::<Command>(t) {
+                    Ok(c) => self.commands.send(c).unwrap(),
+                    Err(e) => warn!("unable to parse command from WebSocket: {}", e),
+                }
+            }
+            ws::Message::Binary(bundle) => self.commands.send(Command::Update { bundle }).unwrap(),
+        }
+
+        Ok(())
+    }
 }
 
 impl WebSocket {
-    pub fn new(addr: String) -> Box<Self> {
+    /// Text messages received from clients are parsed as `Command` and
+    /// forwarded to `commands`, binary messages are treated as update bundles.
+    /// Measurements are also sent in `units`. The GDL90 clients, loopback
+    /// check and packing in `transport` are sent once per second, `situation`
+    /// when a client asks for it.
+    pub fn new(
+        addr: String,
+        commands: Sender<Command>,
+        units: Units,
+        transport: SharedStatus,
+        situation: SharedSituation,
+    ) -> Box<Self> {
+        let shared = Arc::new(Mutex::new(Shared {
+            traffic: TrafficTable::default(),
+            clients: BTreeMap::new(),
+            events: Vec::new(),
+            units,
+            // the traffic table stays empty until the first run
+            clock: Instant::now(),
+        }));
+
         // spawn WS thread
 
-        let socket = ws::WebSocket::new(|_| {
-            move |_| panic!("This server cannot receive messages, it only sends them.")
+        let state = shared.clone();
+        let client_situation = situation.clone();
+        let socket = ws::WebSocket::new(move |out| Client {
+            out,
+            commands: commands.clone(),
+            shared: state.clone(),
+            situation: client_situation.clone(),
         }).expect("Unable to create WebSocket");
 
-        let ws_broadcaster = socket.broadcaster();
-
         let handle = spawn(move || {
             socket.listen(addr).expect("Unable to run WebSocket.");
         });
@@ -42,29 +844,394 @@ impl WebSocket {
 
         Box::new(Self {
             _handle: handle,
-            ws_broadcaster,
+            shared,
+            transport,
+            situation,
+            clients_counter: 0,
+        })
+    }
+
+    /// Send `js` to every client subscribed to it
+    pub fn broadcast(&self, js: &Value) {
+        self.shared.lock().unwrap().broadcast(js);
+    }
+
+    /// Serve the same messages as Server-Sent Events to HTTP clients
+    /// connecting to `addr`, e.g. `curl -N http://192.168.0.1:9002/?types=Traffic`.
+    /// The antenna history is read from `storage`.
+    pub fn serve_events(&self, addr: &str, storage: Storage) {
+        let listener = match TcpListener::bind(addr) {
+            Ok(l) => l,
+            Err(e) => {
+                warn!("unable to serve events on {}: {}", addr, e);
+                return;
+            }
+        };
+
+        let shared = self.shared.clone();
+        let situation = self.situation.clone();
+        let pending = Arc::new(AtomicUsize::new(0));
+        spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        debug!("unable to accept events client: {}", e);
+                        continue;
+                    }
+                };
+
+                if pending.load(Ordering::SeqCst) >= EVENTS_MAX_PENDING {
+                    warn!("too many HTTP requests at once, connection closed");
+                    continue;
+                }
+
+                // waiting for the request must not hold up other clients
+                let shared = shared.clone();
+                let situation = situation.clone();
+                let storage = storage.clone();
+                let pending = pending.clone();
+                pending.fetch_add(1, Ordering::SeqCst);
+                spawn(move || {
+                    if let Err(e) = accept_events(stream, &shared, &situation, &storage) {
+                        debug!("events client failed: {}", e);
+                    }
+                    pending.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        debug!("spawned events thread");
+    }
+
+    /// Transport sending the payload stream, i.e. GDL90, to the clients
+    /// that asked for it
+    pub fn gdl90(&self) -> Box<Transport> {
+        Box::new(GDL90Stream {
+            shared: self.shared.clone(),
         })
     }
 }
 
+pub struct GDL90Stream {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Transport for GDL90Stream {
+    fn run(&mut self, _handle: &mut Handle, i: transport::ChainedIter) {
+        let shared = self.shared.lock().unwrap();
+        let clients: Vec<&Subscriber> = shared
+            .clients
+            .values()
+            .filter(|c| c.view.filter.gdl90)
+            .collect();
+        if clients.is_empty() {
+            return;
+        }
+
+        for p in i {
+            for c in &clients {
+                if let Out::WebSocket(ref out) = c.out {
+                    out.send(ws::Message::Binary(p.payload.clone())).unwrap();
+                }
+            }
+        }
+    }
+}
+
 impl Protocol for WebSocket {
-    fn run(&mut self, _handle: &mut Pushable<Payload>, i: ChainedIter) {
+    fn run(&mut self, handle: &mut Pushable<Payload>, i: ChainedIter) {
+        let clock = handle.get_clock();
+        // held while sending, so a client connecting meanwhile does not get
+        // a snapshot older than the deltas it receives
+        let mut shared = self.shared.lock().unwrap();
+        shared.clock = clock;
+
         for r in i {
             match *r {
-                Ownship(ref o) => {
-                    let mut js = serde_json::to_value(o).unwrap();
-                    js["type"] = "Ownship".into();
-
-                    self.ws_broadcaster.send(js.to_string()).unwrap();
+                Ownship(_) | GNSS(_) | SkyView(_) | DecodeStats(_) | E6B(_) | Navigation(_)
+                | WarmStart(_) | Antenna(_) | Cabin(_) | Event(_) => {
+                    let js = r.to_json_in(shared.units).unwrap();
+                    shared.broadcast(&js);
                 }
-                GNSS(ref g) => {
-                    let mut js = serde_json::to_value(g).unwrap();
-                    js["type"] = "GNSS".into();
-
-                    self.ws_broadcaster.send(js.to_string()).unwrap();
+                Traffic(_) => {
+                    if let Some(Value::Object(js)) = r.to_json_in(shared.units) {
+                        shared.traffic.update(js, clock);
+                    }
                 }
+                Config(ref c) => shared.units = c.units,
                 _ => {}
             }
         }
+
+        run_every!(CLIENTS_FREQ, self.clients_counter, handle, {
+            let transport = self.transport.lock().unwrap();
+
+            shared.broadcast(&clients_json(&transport.clients));
+            if let Some(ref l) = transport.loopback {
+                shared.broadcast(&tagged("Loopback", l));
+            }
+            if let Some(ref p) = transport.packing {
+                shared.broadcast(&tagged("Packing", p));
+            }
+            if let Some(ref l) = transport.latency {
+                shared.broadcast(&tagged("Latency", l));
+            }
+            for c in &shared.events {
+                c.out.keep_alive();
+            }
+        });
+
+        let Shared {
+            ref mut traffic,
+            ref mut clients,
+            ref mut events,
+            ..
+        } = *shared;
+        traffic.expire(clock);
+
+        for c in clients
+            .values_mut()
+            .chain(events.iter_mut())
+            .filter(|c| c.view.filter.wants("Traffic"))
+        {
+            for delta in c.view.sync(traffic, clock) {
+                c.out.send(&delta).unwrap();
+            }
+        }
+
+        events.retain(|c| !c.out.is_closed());
+        self.transport.lock().unwrap().web_clients = clients.len() + events.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::warmstart;
+    use std::sync::mpsc::channel;
+    use testing::{run_protocol, MockHandle};
+    use transport::{ClientState, Profile, SendErrors, SharedStatus, Transition};
+
+    fn target(js: &str) -> Map<String, Value> {
+        match serde_json::from_str(js).unwrap() {
+            Value::Object(o) => o,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_traffic_deltas() {
+        let mut t = TrafficTable::default();
+        let mut v = View::new(Filter::default());
+        let start = Instant::now();
+
+        t.update(target(r#"{"addr": 1, "altitude": 1000}"#), start);
+        assert_eq!(
+            v.sync(&t, start),
+            vec![r#"{"addr":1,"altitude":1000,"seq":1,"type":"TrafficAdd"}"#.to_string()]
+        );
+
+        // unchanged targets are not sent again
+        let later = start + Duration::from_secs(2);
+        t.update(target(r#"{"addr": 1, "altitude": 1000}"#), later);
+        assert!(v.sync(&t, later).is_empty());
+
+        t.update(target(r#"{"addr": 2, "altitude": 2000}"#), start);
+        t.update(target(r#"{"addr": 1, "altitude": 1100}"#), later);
+        let deltas = v.sync(&t, later);
+        assert!(deltas[0].contains(r#""seq":2,"type":"TrafficUpdate""#));
+        assert!(deltas[1].contains(r#""seq":3,"type":"TrafficAdd""#));
+
+        assert_eq!(
+            v.snapshot(&t, later),
+            r#"{"seq":3,"targets":[{"addr":1,"altitude":1100},{"addr":2,"altitude":2000}],"type":"TrafficSnapshot"}"#
+        );
+
+        // 2 was last reported at start
+        t.expire(start + Duration::from_secs(TRAFFIC_REMOVE_SECS));
+        assert_eq!(t.targets.len(), 1);
+        assert_eq!(
+            v.sync(&t, later),
+            vec![r#"{"addr":2,"seq":4,"type":"TrafficRemove"}"#.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(Filter::parse("/").unwrap(), Filter::default());
+        assert_eq!(
+            Filter::parse("/?types=Ownship,Traffic&range=10&rate=2").unwrap(),
+            Filter {
+                types: Some(vec!["Ownship".to_string(), "Traffic".to_string()]),
+                range: Some(10.0),
+                interval: Some(Duration::from_millis(500)),
+                gdl90: false,
+            }
+        );
+        assert!(Filter::parse("/?gdl90").unwrap().gdl90);
+        assert!(Filter::parse("/?types=Ownship&gdl90=true").unwrap().gdl90);
+        assert!(!Filter::parse("/?gdl90=false").unwrap().gdl90);
+
+        assert!(Filter::parse("/?types=FISB").is_err());
+        assert!(Filter::parse("/?gdl90=1").is_err());
+        assert!(Filter::parse("/?range=-1").is_err());
+        assert!(Filter::parse("/?rate=fast").is_err());
+        assert!(Filter::parse("/?altitude=1000").is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!(
+            read_request(&b"GET /?types=Traffic HTTP/1.1\r\nHost: pitot\r\n\r\n"[..]).unwrap(),
+            "/?types=Traffic"
+        );
+        assert!(read_request(&b"POST / HTTP/1.1\r\n\r\n"[..]).is_err());
+        assert!(read_request(&b"GET / HTTP/1.1\r\nHost: pitot\r\n"[..]).is_err());
+        assert!(read_request(&b""[..]).is_err());
+
+        let mut out = Vec::new();
+        write_event(&mut out, "{\"type\":\"Ownship\"}").unwrap();
+        write_event(&mut out, "a\nb").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "data: {\"type\":\"Ownship\"}\n\ndata: a\ndata: b\n\n"
+        );
+    }
+
+    #[test]
+    fn test_slow_events_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // never reads
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = listener.accept().unwrap().0;
+        stream.set_nonblocking(true).unwrap();
+
+        let out = Out::Events(stream, Cell::new(false));
+        let msg = "x".repeat(64 * 1024);
+
+        // dropped once the socket buffer is full instead of blocking
+        let mut sent = 0;
+        while !out.is_closed() {
+            out.send(&msg).unwrap();
+            sent += 1;
+            assert!(sent < 10000);
+        }
+    }
+
+    #[test]
+    fn test_broadcast_warm_start() {
+        let mut w = WebSocket::new(
+            "127.0.0.1:0".to_string(),
+            channel().0,
+            Units::Aviation,
+            SharedStatus::default(),
+            SharedSituation::default(),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        w.shared.lock().unwrap().events.push(Subscriber {
+            out: Out::Events(listener.accept().unwrap().0, Cell::new(false)),
+            view: View::new(Filter::parse("/?types=WarmStart").unwrap()),
+        });
+
+        let mut h = MockHandle::new(10);
+        run_protocol(
+            &mut *w,
+            &mut h,
+            &[Report::WarmStart(warmstart::State::default())],
+        );
+
+        let mut buf = [0_u8; 512];
+        let n = client.read(&mut buf).unwrap();
+        let event = String::from_utf8_lossy(&buf[..n]);
+        assert!(event.starts_with("data: {"));
+        assert!(event.contains("\"type\":\"WarmStart\""));
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let kept = Some(Duration::from_secs(600));
+        assert_eq!(parse_history("/traffic.csv", None), Ok(None));
+        assert_eq!(
+            parse_history("/traffic.csv?history=5", kept),
+            Ok(Some(Duration::from_secs(300)))
+        );
+
+        assert!(parse_history("/traffic.csv?history=5", None).is_err());
+        assert!(parse_history("/traffic.csv?history=11", kept).is_err());
+        assert!(parse_history("/traffic.csv?history=0", kept).is_err());
+        assert!(parse_history("/traffic.csv?types=Traffic", kept).is_err());
+    }
+
+    #[test]
+    fn test_clients_json() {
+        let mut table = BTreeMap::new();
+        table.insert(
+            Ipv4Addr::new(192, 168, 10, 5),
+            ClientStatus {
+                app: Some("ForeFlight".to_string()),
+                profile: Profile::ForeFlight,
+                ports: vec![4000],
+                active: true,
+                in_app: false,
+                connected: 1514764800,
+                last_seen: 0.5,
+                rtt: Some(4.5),
+                bytes_sent: 1472,
+                replays: 0,
+                history: vec![Transition {
+                    time: 1514764800,
+                    state: ClientState::Background,
+                }],
+                send_errors: SendErrors {
+                    refused: 3,
+                    ..SendErrors::default()
+                },
+                dropped: None,
+            },
+        );
+
+        assert_eq!(
+            clients_json(&table).to_string(),
+            r#"{"clients":[{"active":true,"app":"ForeFlight","bytes_sent":1472,"connected":1514764800,"dropped":null,"history":[{"state":"background","time":1514764800}],"in_app":false,"ip":"192.168.10.5","last_seen":0.5,"ports":[4000],"profile":"foreflight","replays":0,"rtt":4.5,"send_errors":{"other":0,"refused":3,"unreachable":0,"would_block":0}}],"type":"Clients"}"#
+        );
+    }
+
+    #[test]
+    fn test_traffic_filters() {
+        let mut t = TrafficTable::default();
+        let mut v = View::new(Filter::parse("/?types=Traffic&range=10&rate=1").unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        t.update(target(r#"{"addr": 1, "range": 5.0}"#), start);
+        t.update(target(r#"{"addr": 2, "range": 12.0}"#), start);
+        t.update(target(r#"{"addr": 3, "range": null}"#), start);
+        assert_eq!(
+            v.sync(&t, start),
+            vec![r#"{"addr":1,"range":5.0,"seq":1,"type":"TrafficAdd"}"#.to_string()]
+        );
+
+        // held back until a second after the last update
+        t.update(target(r#"{"addr": 1, "range": 5.5}"#), at(500));
+        assert!(v.sync(&t, at(500)).is_empty());
+        let deltas = v.sync(&t, at(1000));
+        assert!(deltas[0].contains(r#""range":5.5,"seq":2,"type":"TrafficUpdate""#));
+
+        // leaving the range removes it, per client sequence has no gaps
+        t.update(target(r#"{"addr": 1, "range": 10.5}"#), at(1100));
+        assert_eq!(
+            v.sync(&t, at(1100)),
+            vec![r#"{"addr":1,"seq":3,"type":"TrafficRemove"}"#.to_string()]
+        );
+
+        let f = Filter::parse("/?types=Traffic,GNSS&range=10").unwrap();
+        assert!(f.accepts(&serde_json::from_str(r#"{"type": "GNSS"}"#).unwrap()));
+        assert!(!f.accepts(&serde_json::from_str(r#"{"type": "SkyView"}"#).unwrap()));
+        assert!(!f.accepts(&serde_json::from_str(r#"{"type": "Traffic", "range": 11}"#).unwrap()));
     }
 }
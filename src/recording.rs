@@ -0,0 +1,367 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Binary log of reports for later analysis.
+//!
+//! A log starts with the 8 byte magic `PITOTLOG` followed by a one byte format
+//! version. Every report is then written as one frame:
+//!
+//! | bytes | content                                              |
+//! | ----- | ---------------------------------------------------- |
+//! | 4     | milliseconds since the recording started, LE         |
+//! | 1     | report type, index into `TYPES`                      |
+//! | 2     | payload length, LE                                   |
+//! | n     | payload, the WebSocket JSON message without `type`   |
+//!
+//! Frames are self-delimiting, so a log cut short by a power loss is
+//! readable up to its last complete frame.
+//!
+//! Also home of helpers shared by everything else recording to files.
+
+use processor::Report;
+use serde_json::{self, Map, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC: &[u8; 8] = b"PITOTLOG";
+pub const VERSION: u8 = 1;
+/// Report types in the order of their type byte, append only
+const TYPES: [&str; 11] = [
+    "Ownship",
+    "Traffic",
+    "GNSS",
+    "SkyView",
+    "DecodeStats",
+    "E6B",
+    "Navigation",
+    "Event",
+    "WarmStart",
+    "Antenna",
+    "Cabin",
+];
+
+/// One report read back from a log
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// milliseconds since the recording started
+    pub time: u32,
+    pub kind: &'static str,
+    pub value: Map<String, Value>,
+}
+
+pub struct LogWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> LogWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        try!(inner.write_all(MAGIC));
+        try!(inner.write_all(&[VERSION]));
+
+        Ok(LogWriter { inner })
+    }
+
+    /// Append `r`, reports not sent over WebSocket are skipped, as are
+    /// reports of a type missing from `TYPES`
+    pub fn write(&mut self, time: u32, r: &Report) -> io::Result<()> {
+        let mut js = match r.to_json() {
+            Some(Value::Object(o)) => o,
+            _ => return Ok(()),
+        };
+
+        let kind = match TYPES.iter().position(|k| *k == r.topic()) {
+            Some(k) => k,
+            None => {
+                warn!("{} reports can not be recorded, skipped", r.topic());
+                return Ok(());
+            }
+        };
+        js.remove("type");
+
+        let payload = serde_json::to_vec(&js).unwrap();
+        if payload.len() > u16::max_value() as usize {
+            warn!("{} report too large to record, skipped", TYPES[kind]);
+            return Ok(());
+        }
+
+        let mut frame = Vec::with_capacity(7 + payload.len());
+        frame.extend_from_slice(&[
+            time as u8,
+            (time >> 8) as u8,
+            (time >> 16) as u8,
+            (time >> 24) as u8,
+            kind as u8,
+            payload.len() as u8,
+            (payload.len() >> 8) as u8,
+        ]);
+        frame.extend_from_slice(&payload);
+
+        self.inner.write_all(&frame)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct LogReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> LogReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0_u8; 9];
+        try!(inner.read_exact(&mut header));
+
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a Pitot log",
+            ));
+        }
+
+        if header[8] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported log version {}", header[8]),
+            ));
+        }
+
+        Ok(LogReader { inner })
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0_u8; 7];
+        match self.inner.read_exact(&mut header) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let time = header[0] as u32
+            | (header[1] as u32) << 8
+            | (header[2] as u32) << 16
+            | (header[3] as u32) << 24;
+        let len = header[5] as usize | (header[6] as usize) << 8;
+
+        let mut payload = vec![0; len];
+        match self.inner.read_exact(&mut payload) {
+            Ok(_) => {}
+            // truncated last frame
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let kind = match TYPES.get(header[4] as usize) {
+            Some(k) => *k,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown report type {}", header[4]),
+                ))
+            }
+        };
+
+        match serde_json::from_slice(&payload) {
+            Ok(Value::Object(value)) => Ok(Some(Record { time, kind, value })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "corrupted frame",
+            )),
+        }
+    }
+}
+
+impl<R: Read> Iterator for LogReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_frame() {
+            Ok(Some(r)) => Some(Ok(r)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl Record {
+    /// The WebSocket JSON message with an added `time` field
+    pub fn to_json(&self) -> Value {
+        let mut js = self.value.clone();
+        js.insert("type".to_string(), self.kind.into());
+        js.insert("time".to_string(), self.time.into());
+
+        Value::Object(js)
+    }
+
+    /// Values of `columns` as one CSV line. Strings are quoted, nested values
+    /// are written as quoted JSON and missing values are left empty.
+    pub fn to_csv(&self, columns: &[String]) -> String {
+        let mut fields = vec![self.time.to_string()];
+
+        for c in columns {
+            fields.push(match self.value.get(c) {
+                None | Some(&Value::Null) => String::new(),
+                Some(&Value::String(ref s)) => csv_quote(s),
+                Some(v @ &Value::Array(_)) | Some(v @ &Value::Object(_)) => {
+                    csv_quote(&v.to_string())
+                }
+                Some(v) => v.to_string(),
+            });
+        }
+
+        fields.join(",")
+    }
+}
+
+/// Create the first `<stem>-<n>.<extension>` in `dir` that does not exist
+/// yet, so every run of Pitot records into a new file
+pub fn create_numbered(dir: &Path, stem: &str, extension: &str) -> io::Result<(PathBuf, File)> {
+    try!(fs::create_dir_all(dir));
+
+    let mut n = 0;
+    loop {
+        let path = dir.join(format!("{}-{}.{}", stem, n, extension));
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(f) => return Ok((path, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn csv_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::antenna::Day;
+    use processor::cabin::Cabin;
+    use processor::e6b::E6B;
+    use processor::events::Event;
+    use processor::gnss::{SkyView, GNSS};
+    use processor::navigation::Navigation;
+    use processor::ownship::Ownship;
+    use processor::traffic::{AddressType, Target, TrafficSource};
+    use processor::warmstart::State;
+    use sensor::sdr::DecodeStats;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use storage::Storage;
+    use testing::{run_processor, MockHandle};
+
+    fn log() -> Vec<u8> {
+        let mut w = LogWriter::new(Vec::new()).unwrap();
+        w.write(100, &Report::Event(Event::GNSSLost)).unwrap();
+        w.write(
+            70000,
+            &Report::Event(Event::Takeoff {
+                lat: 37.5,
+                lon: -122.0,
+            }),
+        ).unwrap();
+        w.inner
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let buf = log();
+        assert_eq!(&buf[..9], b"PITOTLOG\x01");
+
+        let records: Vec<Record> = LogReader::new(&buf[..])
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].time, 100);
+        assert_eq!(records[0].kind, "Event");
+        assert_eq!(
+            records[1].to_json().to_string(),
+            r#"{"event":"Takeoff","lat":37.5,"lon":-122.0,"time":70000,"type":"Event"}"#
+        );
+
+        // a frame cut short by a power loss is dropped
+        assert_eq!(LogReader::new(&buf[..buf.len() - 3]).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_every_report() {
+        let mut h = MockHandle::new(1);
+        let mut navigation = Navigation::new(Storage::disabled());
+        run_processor(&mut *navigation, &mut h, &[]);
+
+        let mut reports = h.take();
+        reports.extend(vec![
+            Report::Ownship(Ownship::default()),
+            Report::Traffic(Arc::new(Target::new(
+                (0xA00001, AddressType::ADSBICAO),
+                Instant::now(),
+                TrafficSource::UAT,
+                None,
+            ))),
+            Report::GNSS(GNSS::new()),
+            Report::SkyView(SkyView::new(&[])),
+            Report::DecodeStats(DecodeStats::new(TrafficSource::UAT)),
+            Report::E6B(E6B::default()),
+            Report::Event(Event::GNSSLost),
+            Report::WarmStart(State::default()),
+            Report::Antenna(Day::default()),
+            Report::Cabin(Cabin {
+                sensor: "BME280",
+                pressure: 1013.2,
+                temperature: None,
+                humidity: None,
+                dew_point: None,
+            }),
+        ]);
+
+        // one of each type
+        assert_eq!(reports.len(), TYPES.len());
+
+        let mut w = LogWriter::new(Vec::new()).unwrap();
+        for r in &reports {
+            assert!(r.to_json().is_some());
+            w.write(0, r).unwrap();
+        }
+
+        let kinds: Vec<&str> = LogReader::new(&w.inner[..])
+            .unwrap()
+            .map(|r| r.unwrap().kind)
+            .collect();
+        let topics: Vec<&str> = reports.iter().map(|r| r.topic()).collect();
+        assert_eq!(kinds, topics);
+    }
+
+    #[test]
+    fn test_invalid_log() {
+        assert!(LogReader::new(&b"PITOTLOG\x02"[..]).is_err());
+        assert!(LogReader::new(&b"{\"type\": 1}"[..]).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let buf = log();
+        let r = LogReader::new(&buf[..]).unwrap().nth(1).unwrap().unwrap();
+        let columns = vec!["event".to_string(), "lat".to_string(), "addr".to_string()];
+
+        assert_eq!(r.to_csv(&columns), "70000,\"Takeoff\",37.5,");
+    }
+}
@@ -0,0 +1,92 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#[cfg(all(target_os = "linux", feature = "airspeed-ms4525do"))]
+pub mod ms4525do;
+
+use super::barometer::pressure_altitude;
+
+const SEA_LEVEL_DENSITY: f32 = 1.225; // kg/m^3, ISA standard
+const SEA_LEVEL_PRESSURE: f32 = 1013.25; // hPa, ISA standard
+const SEA_LEVEL_TEMPERATURE: f32 = 288.15; // K, ISA standard
+const LAPSE_RATE: f32 = 0.0019812; // K/ft, ISA standard
+const MPS_TO_KTS: f32 = 1.943844;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct AirspeedData {
+    /// Identifies the sensor this reading came from
+    pub sensor_id: &'static str,
+    /// Differential (pitot - static) pressure in Pa
+    pub differential_pressure: f32,
+    /// Sensor temperature in Celsius, if available
+    pub temperature: Option<f32>,
+    /// Indicated airspeed in kts
+    pub ias: f32,
+}
+
+impl AirspeedData {
+    pub fn new(
+        sensor_id: &'static str,
+        differential_pressure: f32,
+        temperature: Option<f32>,
+    ) -> Self {
+        AirspeedData {
+            sensor_id,
+            differential_pressure,
+            temperature,
+            ias: ias(differential_pressure),
+        }
+    }
+}
+
+/// Given differential pressure in Pa, return indicated airspeed in kts
+pub fn ias(differential_pressure: f32) -> f32 {
+    if differential_pressure <= 0_f32 {
+        return 0_f32;
+    }
+
+    (2_f32 * differential_pressure / SEA_LEVEL_DENSITY).sqrt() * MPS_TO_KTS
+}
+
+/// Given indicated airspeed in kts and static pressure in hPa, return true
+/// airspeed in kts. Outside air temperature is assumed to be ISA standard
+/// as the cabin temperature says nothing about it.
+pub fn tas(ias: f32, static_pressure: f32) -> f32 {
+    let temperature =
+        SEA_LEVEL_TEMPERATURE - LAPSE_RATE * pressure_altitude(static_pressure) as f32;
+    let density_ratio =
+        (static_pressure / SEA_LEVEL_PRESSURE) / (temperature / SEA_LEVEL_TEMPERATURE);
+
+    ias / density_ratio.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ias() {
+        assert_eq!(ias(0_f32), 0_f32);
+        assert_eq!(ias(-10_f32), 0_f32);
+        assert!((ias(1000_f32) - 78.54).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_tas() {
+        assert!((tas(100_f32, 1013.25) - 100_f32).abs() < 0.01);
+        assert!((tas(100_f32, 696.81) - 116.37).abs() < 0.1);
+    }
+}
@@ -0,0 +1,129 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Driver for the TE MS4525DO differential pressure sensor (1 psi, type A)
+
+use super::AirspeedData;
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::LinuxI2CDevice;
+use pitot::handle::Pushable;
+use sensor::i2c::{attempt, Retry};
+use sensor::{Sensor, SensorData};
+
+const MS4525DO_I2C_ADDR: u16 = 0x28;
+const I2C_DEV: &'static str = "/dev/i2c-1";
+const SENSOR_ID: &'static str = "MS4525DO";
+const P_MIN: f32 = -1.0; // psi
+const P_MAX: f32 = 1.0; // psi
+const PSI_TO_PA: f32 = 6894.757;
+
+pub struct MS4525DOProvider {
+    /// `None` after a failed read, until reopened
+    dev: Option<LinuxI2CDevice>,
+    retry: Retry,
+}
+
+impl MS4525DOProvider {
+    pub fn new() -> Option<Box<Sensor>> {
+        let mut dev = match LinuxI2CDevice::new(I2C_DEV, MS4525DO_I2C_ADDR) {
+            Ok(d) => d,
+            Err(_) => {
+                info!("MS4525DO not found!");
+                return None;
+            }
+        };
+
+        let mut buf = [0_u8; 4];
+        if dev.read(&mut buf).is_err() || parse_reading(&buf).is_none() {
+            info!("MS4525DO not found!");
+            return None;
+        }
+
+        info!("MS4525DO initialization successful");
+
+        Some(Box::new(Self {
+            dev: Some(dev),
+            retry: Retry::new(SENSOR_ID),
+        }))
+    }
+}
+
+/// Convert raw sensor output to differential pressure in Pa and temperature
+/// in Celsius. Returns `None` if the sensor reports a fault.
+fn parse_reading(buf: &[u8; 4]) -> Option<(f32, f32)> {
+    // 0 = normal, 2 = stale data, 3 = fault
+    if buf[0] >> 6 == 3 {
+        return None;
+    }
+
+    let raw_p = ((buf[0] & 0x3F) as u16) << 8 | buf[1] as u16;
+    let raw_t = (buf[2] as u16) << 3 | (buf[3] >> 5) as u16;
+
+    // see datasheet p. 4, output type A (10% to 90%)
+    let psi = (raw_p as f32 - 0.1 * 16383_f32) * (P_MAX - P_MIN) / (0.8 * 16383_f32) + P_MIN;
+    let temperature = raw_t as f32 * 200_f32 / 2047_f32 - 50_f32;
+
+    Some((psi * PSI_TO_PA, temperature))
+}
+
+impl Sensor for MS4525DOProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        if !self.retry.ready(h.get_clock()) {
+            return;
+        }
+
+        if self.dev.is_none() {
+            self.dev = LinuxI2CDevice::new(I2C_DEV, MS4525DO_I2C_ADDR).ok();
+        }
+
+        let mut buf = [0_u8; 4];
+        let read = match self.dev {
+            Some(ref mut d) => attempt(|| d.read(&mut buf)).map_err(|e| e.to_string()),
+            None => Err("unable to reopen".to_string()),
+        };
+
+        if let Err(e) = read {
+            self.dev = None;
+            self.retry.failed(h, &e);
+            return;
+        }
+        self.retry.succeeded(h);
+
+        match parse_reading(&buf) {
+            Some((dp, t)) => h.push_data(SensorData::Airspeed(AirspeedData::new(
+                SENSOR_ID,
+                dp,
+                Some(t),
+            ))),
+            None => warn!("MS4525DO reported a fault"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reading() {
+        // zero differential pressure at 8192 counts, ~25 C
+        let (dp, t) = parse_reading(&[0x20, 0x00, 0x60, 0x00]).unwrap();
+        assert!(dp.abs() < 1_f32);
+        assert!((t - 25.03).abs() < 0.1);
+
+        assert_eq!(parse_reading(&[0xE0, 0x00, 0x60, 0x00]), None);
+    }
+}
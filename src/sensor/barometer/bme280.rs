@@ -0,0 +1,258 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Driver for the Bosch BME280 pressure, temperature and humidity sensor.
+//! Temperature and humidity are oversampled only once, so the sensor heats
+//! itself up as little as possible.
+
+use super::BaroData;
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+use pitot::handle::Pushable;
+use sensor::i2c::{attempt, Retry};
+use sensor::{Sensor, SensorData};
+
+const BME280_I2C_ADDR: u16 = 0x76;
+const I2C_DEV: &str = "/dev/i2c-1";
+const SENSOR_ID: &str = "BME280";
+const CHIP_ID: u8 = 0x60;
+
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_CALIB_TP: u8 = 0x88;
+const REG_CALIB_H1: u8 = 0xA1;
+const REG_CALIB_H: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+
+// humidity oversampling x1
+const CTRL_HUM: u8 = 0x01;
+// temperature x1, pressure x4, normal mode
+const CTRL_MEAS: u8 = 0x2F;
+// 62.5 ms standby, IIR filter coefficient 16
+const CONFIG: u8 = 0x30;
+
+/// Trimming parameters of a sensor, see datasheet section 4.2.2
+#[derive(Debug, PartialEq)]
+struct Calibration {
+    t: [f64; 3],
+    p: [f64; 9],
+    h: [f64; 6],
+}
+
+fn u16_le(b: &[u8]) -> f64 {
+    (b[0] as u16 | (b[1] as u16) << 8) as f64
+}
+
+fn i16_le(b: &[u8]) -> f64 {
+    (b[0] as u16 | (b[1] as u16) << 8) as i16 as f64
+}
+
+/// Parse the trimming parameters, `tp` from 0x88, `h1` from 0xA1 and `h`
+/// from 0xE1
+fn parse_calibration(tp: &[u8; 24], h1: u8, h: &[u8; 7]) -> Calibration {
+    let mut p = [u16_le(&tp[6..]); 9];
+    for (i, v) in p.iter_mut().enumerate().skip(1) {
+        *v = i16_le(&tp[6 + i * 2..]);
+    }
+
+    // H4 and H5 are 12 bit signed values sharing a nibble of 0xE5
+    let h4 = ((h[3] as i8 as i16) << 4 | (h[4] & 0x0F) as i16) as f64;
+    let h5 = ((h[5] as i8 as i16) << 4 | (h[4] >> 4) as i16) as f64;
+
+    Calibration {
+        t: [u16_le(&tp[0..]), i16_le(&tp[2..]), i16_le(&tp[4..])],
+        p,
+        h: [
+            h1 as f64,
+            i16_le(&h[0..]),
+            h[2] as f64,
+            h4,
+            h5,
+            h[6] as i8 as f64,
+        ],
+    }
+}
+
+/// Convert the 8 data registers from 0xF7 to pressure in hPa, temperature in
+/// Celsius and relative humidity in percent, with the floating point
+/// formulas of datasheet section 8.1
+fn compensate(c: &Calibration, data: &[u8; 8]) -> (f32, f32, f32) {
+    let adc_p = ((data[0] as u32) << 12 | (data[1] as u32) << 4 | (data[2] >> 4) as u32) as f64;
+    let adc_t = ((data[3] as u32) << 12 | (data[4] as u32) << 4 | (data[5] >> 4) as u32) as f64;
+    let adc_h = ((data[6] as u32) << 8 | data[7] as u32) as f64;
+
+    let (t, p, h) = (&c.t, &c.p, &c.h);
+
+    let var1 = (adc_t / 16384.0 - t[0] / 1024.0) * t[1];
+    let var2 = (adc_t / 131072.0 - t[0] / 8192.0).powi(2) * t[2];
+    let t_fine = var1 + var2;
+    let temperature = t_fine / 5120.0;
+
+    let var1 = t_fine / 2.0 - 64000.0;
+    let var2 = var1 * var1 * p[5] / 32768.0 + var1 * p[4] * 2.0;
+    let var2 = var2 / 4.0 + p[3] * 65536.0;
+    let var1 = (p[2] * var1 * var1 / 524288.0 + p[1] * var1) / 524288.0;
+    let var1 = (1.0 + var1 / 32768.0) * p[0];
+    let pressure = if var1 == 0.0 {
+        0.0 // avoid division by zero
+    } else {
+        let pa = (1048576.0 - adc_p - var2 / 4096.0) * 6250.0 / var1;
+        pa + (p[8] * pa * pa / 2147483648.0 + pa * p[7] / 32768.0 + p[6]) / 16.0
+    };
+
+    let var_h = t_fine - 76800.0;
+    let var_h = (adc_h - (h[3] * 64.0 + h[4] / 16384.0 * var_h))
+        * (h[1] / 65536.0 * (1.0 + h[5] / 67108864.0 * var_h * (1.0 + h[2] / 67108864.0 * var_h)));
+    let humidity = var_h * (1.0 - h[0] * var_h / 524288.0);
+    // out of range values are noise close to the limits
+    let humidity = if humidity < 0.0 {
+        0.0
+    } else if humidity > 100.0 {
+        100.0
+    } else {
+        humidity
+    };
+
+    (
+        (pressure / 100.0) as f32,
+        temperature as f32,
+        humidity as f32,
+    )
+}
+
+fn read_registers(dev: &mut LinuxI2CDevice, reg: u8, buf: &mut [u8]) -> Result<(), LinuxI2CError> {
+    try!(dev.write(&[reg]));
+    dev.read(buf)
+}
+
+/// Open the sensor, read its trimming parameters and start measuring
+fn open() -> Result<(LinuxI2CDevice, Calibration), String> {
+    let mut dev = try!(LinuxI2CDevice::new(I2C_DEV, BME280_I2C_ADDR).map_err(|e| e.to_string()));
+
+    let mut id = [0_u8];
+    try!(read_registers(&mut dev, REG_CHIP_ID, &mut id).map_err(|e| e.to_string()));
+    if id[0] != CHIP_ID {
+        return Err(format!("unexpected chip ID {:#04X}", id[0]));
+    }
+
+    let mut tp = [0_u8; 24];
+    let mut h1 = [0_u8];
+    let mut h = [0_u8; 7];
+    try!(read_registers(&mut dev, REG_CALIB_TP, &mut tp).map_err(|e| e.to_string()));
+    try!(read_registers(&mut dev, REG_CALIB_H1, &mut h1).map_err(|e| e.to_string()));
+    try!(read_registers(&mut dev, REG_CALIB_H, &mut h).map_err(|e| e.to_string()));
+
+    // humidity settings only take effect after writing ctrl_meas
+    for &(reg, value) in &[
+        (REG_CTRL_HUM, CTRL_HUM),
+        (REG_CONFIG, CONFIG),
+        (REG_CTRL_MEAS, CTRL_MEAS),
+    ] {
+        try!(dev.write(&[reg, value]).map_err(|e| e.to_string()));
+    }
+
+    Ok((dev, parse_calibration(&tp, h1[0], &h)))
+}
+
+pub struct BME280Provider {
+    /// `None` after a failed read, until reopened
+    dev: Option<(LinuxI2CDevice, Calibration)>,
+    retry: Retry,
+}
+
+impl BME280Provider {
+    pub fn new() -> Option<Box<Sensor>> {
+        match open() {
+            Ok(d) => {
+                info!("BME280 initialization successful");
+
+                Some(Box::new(Self {
+                    dev: Some(d),
+                    retry: Retry::new(SENSOR_ID),
+                }))
+            }
+            Err(e) => {
+                info!("BME280 not found: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Sensor for BME280Provider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        if !self.retry.ready(h.get_clock()) {
+            return;
+        }
+
+        // the bus may have been reset, the sensor then needs to be set up again
+        if self.dev.is_none() {
+            self.dev = open().ok();
+        }
+
+        let mut data = [0_u8; 8];
+        let read = match self.dev {
+            Some((ref mut d, _)) => {
+                attempt(|| read_registers(d, REG_DATA, &mut data)).map_err(|e| e.to_string())
+            }
+            None => Err("unable to reopen".to_string()),
+        };
+
+        if let Err(e) = read {
+            self.dev = None;
+            self.retry.failed(h, &e);
+            return;
+        }
+        self.retry.succeeded(h);
+
+        let (pressure, temperature, humidity) = compensate(&self.dev.as_ref().unwrap().1, &data);
+
+        let mut b = BaroData::new(SENSOR_ID, pressure, Some(temperature));
+        b.humidity = Some(humidity);
+        h.push_data(SensorData::Baro(b));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compensate() {
+        // trimming parameters and readings of the example in the BMP280
+        // datasheet, which uses the same formulas
+        let c = parse_calibration(
+            &[
+                0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC, 0x7D, 0x8E, 0x43, 0xD6, 0xD0, 0x0B, 0x27, 0x0B,
+                0x8C, 0x00, 0xF9, 0xFF, 0x8C, 0x3C, 0xF8, 0xC6, 0x70, 0x17,
+            ],
+            75,
+            &[0x6A, 0x01, 0x00, 0x13, 0x29, 0x03, 0x1E],
+        );
+        assert_eq!(c.t, [27504.0, 26435.0, -1000.0]);
+        assert_eq!(c.p[5], -7.0);
+        assert_eq!(c.h, [75.0, 362.0, 0.0, 313.0, 50.0, 30.0]);
+
+        let (p, t, h) = compensate(&c, &[0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x6D, 0x60]);
+        assert!((p - 1006.5327).abs() < 0.01);
+        assert!((t - 25.08).abs() < 0.01);
+        // the datasheet has no humidity example, 44909 / 1024 %RH is what
+        // the 32 bit integer compensation of its section 4.2.3 gives
+        assert!((h - 44909.0 / 1024.0).abs() < 0.01);
+    }
+}
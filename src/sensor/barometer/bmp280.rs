@@ -14,48 +14,104 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use i2cdev::core::I2CDevice;
 use i2cdev::linux::LinuxI2CDevice;
 use i2cdev_bmp280::*;
-use i2csensors::Barometer;
+use i2csensors::{Barometer, Thermometer};
+use super::BaroData;
 use pitot::handle::Pushable;
+use sensor::i2c::{attempt, Retry};
 use sensor::{Sensor, SensorData};
 
 const BMP280_I2C_ADDR: u16 = 0x76;
 const I2C_DEV: &'static str = "/dev/i2c-1";
-const SEA_LEVEL_QNH: f32 = 101.325;
+const SENSOR_ID: &'static str = "BMP280";
+const REG_CHIP_ID: u8 = 0xD0;
+/// Chip ID of the BME280, which shares the address and the pressure
+/// registers but not all of the setup
+const BME280_CHIP_ID: u8 = 0x60;
 
 pub struct BMP280BaroProvider {
-    bmp280: BMP280<LinuxI2CDevice>,
+    /// `None` after a failed read, until reopened
+    bmp280: Option<BMP280<LinuxI2CDevice>>,
+    retry: Retry,
+}
+
+/// Open and configure the sensor
+fn open() -> Result<BMP280<LinuxI2CDevice>, String> {
+    let mut i2c_device =
+        try!(LinuxI2CDevice::new(I2C_DEV, BMP280_I2C_ADDR).map_err(|e| e.to_string()));
+
+    let mut id = [0u8];
+    let read = i2c_device
+        .write(&[REG_CHIP_ID])
+        .and_then(|_| i2c_device.read(&mut id));
+    if read.is_ok() && id[0] == BME280_CHIP_ID {
+        return Err("found a BME280, build with the baro-bme280 feature to use it".to_string());
+    }
+
+    let settings = BMP280Settings {
+        compensation: BMP280CompensationAlgorithm::B64,
+        t_sb: BMP280Timing::ms0_5,
+        iir_filter_coeff: BMP280FilterCoefficient::Medium,
+        osrs_t: BMP280TemperatureOversampling::x1,
+        osrs_p: BMP280PressureOversampling::StandardResolution,
+        power_mode: BMP280PowerMode::NormalMode,
+    };
+
+    BMP280::new(i2c_device, settings).map_err(|e| e.to_string())
 }
 
 impl BMP280BaroProvider {
     pub fn new() -> Option<Box<Sensor>> {
-        let i2c_device = LinuxI2CDevice::new(I2C_DEV, BMP280_I2C_ADDR).unwrap();
-
-        let settings = BMP280Settings {
-            compensation: BMP280CompensationAlgorithm::B64,
-            t_sb: BMP280Timing::ms0_5,
-            iir_filter_coeff: BMP280FilterCoefficient::Medium,
-            osrs_t: BMP280TemperatureOversampling::x1,
-            osrs_p: BMP280PressureOversampling::StandardResolution,
-            power_mode: BMP280PowerMode::NormalMode,
-        };
-
-        if let Ok(b) = BMP280::new(i2c_device, settings) {
-            Some(Box::new(Self { bmp280: b }))
-        } else {
-            info!("BMP280 not found!");
-            None
+        match open() {
+            Ok(b) => Some(Box::new(Self {
+                bmp280: Some(b),
+                retry: Retry::new(SENSOR_ID),
+            })),
+            Err(e) => {
+                info!("BMP280 not found: {}", e);
+                None
+            }
         }
     }
 }
 
 impl Sensor for BMP280BaroProvider {
     fn run(&mut self, h: &mut Pushable<SensorData>) {
-        let pressure = self.bmp280.pressure_kpa().unwrap();
+        if !self.retry.ready(h.get_clock()) {
+            return;
+        }
+
+        // the bus may have been reset, the sensor then needs to be set up again
+        if self.bmp280.is_none() {
+            self.bmp280 = open().ok();
+        }
 
-        let altitude = 145366.45 * (1_f32 - (pressure / SEA_LEVEL_QNH).powf(0.190284));
+        let pressure = match self.bmp280 {
+            Some(ref mut b) => attempt(|| b.pressure_kpa()).map_err(|e| e.to_string()),
+            None => Err("unable to reopen".to_string()),
+        };
 
-        h.push_data(SensorData::Baro(altitude.round() as i32))
+        match pressure {
+            Ok(p) => {
+                self.retry.succeeded(h);
+
+                let temperature = self
+                    .bmp280
+                    .as_mut()
+                    .and_then(|b| b.temperature_celsius().ok());
+
+                h.push_data(SensorData::Baro(BaroData::new(
+                    SENSOR_ID,
+                    p * 10_f32, // to hPa
+                    temperature,
+                )))
+            }
+            Err(e) => {
+                self.bmp280 = None;
+                self.retry.failed(h, &e);
+            }
+        }
     }
 }
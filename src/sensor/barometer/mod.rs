@@ -14,4 +14,59 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+#[cfg(all(target_os = "linux", feature = "baro-bme280"))]
+pub mod bme280;
+#[cfg(all(target_os = "linux", feature = "baro-bmp280"))]
 pub mod bmp280;
+
+// both drivers would open the sensor at I2C address 0x76
+#[cfg(all(feature = "baro-bme280", feature = "baro-bmp280"))]
+compile_error!(
+    "baro-bme280 and baro-bmp280 can not be enabled together, \
+     build with --no-default-features and only one of them"
+);
+
+const SEA_LEVEL_PRESSURE: f32 = 1013.25; // hPa, ISA standard
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct BaroData {
+    /// Identifies the sensor this reading came from
+    pub sensor_id: &'static str,
+    /// Static pressure in hPa
+    pub pressure: f32,
+    /// Sensor temperature in Celsius, if available
+    pub temperature: Option<f32>,
+    /// Relative humidity in percent, if available
+    pub humidity: Option<f32>,
+    /// Pressure altitude in ft
+    pub pressure_altitude: i32,
+}
+
+impl BaroData {
+    pub fn new(sensor_id: &'static str, pressure: f32, temperature: Option<f32>) -> Self {
+        BaroData {
+            sensor_id,
+            pressure,
+            temperature,
+            humidity: None,
+            pressure_altitude: pressure_altitude(pressure),
+        }
+    }
+}
+
+/// Given static pressure in hPa, return pressure altitude in ft
+pub fn pressure_altitude(pressure: f32) -> i32 {
+    (145366.45 * (1_f32 - (pressure / SEA_LEVEL_PRESSURE).powf(0.190284))).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressure_altitude() {
+        assert_eq!(pressure_altitude(1013.25), 0);
+        assert_eq!(pressure_altitude(843.07), 4998);
+        assert_eq!(pressure_altitude(696.81), 9996);
+    }
+}
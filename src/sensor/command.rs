@@ -0,0 +1,115 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Commands sent to us by clients (e.g. through the WebSocket), fed into
+//! the pipeline as if they came from a sensor.
+
+use super::*;
+use config::AlertZone;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Command {
+    /// Replace the active route, waypoints are `[lat, lon]` in degrees
+    Route { waypoints: Vec<(f32, f32)> },
+    /// Replace the alert zones until the config file changes or Pitot
+    /// restarts
+    AlertZones { zones: Vec<AlertZone> },
+    /// Reboot the system, `token` must match the configured admin token
+    Reboot { token: String },
+    /// Power off the system, `token` must match the configured admin token
+    PowerOff { token: String },
+    /// Change the log level of `module` (all modules if unset) to `level`,
+    /// `token` must match the configured admin token
+    LogLevel {
+        token: String,
+        module: Option<String>,
+        level: String,
+    },
+    /// Pause the linked pipeline component named `component`, `token` must
+    /// match the configured admin token
+    Pause { token: String, component: String },
+    /// Resume a paused pipeline component, `token` must match the
+    /// configured admin token
+    Resume { token: String, component: String },
+    /// Signed update bundle, uploaded as a binary message
+    #[serde(skip_deserializing)]
+    Update { bundle: Vec<u8> },
+}
+
+pub struct CommandProvider {
+    rx: Receiver<Command>,
+}
+
+impl CommandProvider {
+    /// Returns the sensor along with the sending end clients should use
+    /// to submit commands
+    pub fn new() -> (Box<Sensor>, Sender<Command>) {
+        let (tx, rx) = channel();
+
+        (Box::new(CommandProvider { rx }), tx)
+    }
+}
+
+impl Sensor for CommandProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        for c in self.rx.try_iter() {
+            h.push_data(SensorData::Command(c));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_parse_command() {
+        let c: Command =
+            serde_json::from_str(r#"{"type": "Route", "waypoints": [[37.5, -122.25], [38, -121]]}"#)
+                .unwrap();
+        assert_eq!(
+            c,
+            Command::Route {
+                waypoints: vec![(37.5, -122.25), (38.0, -121.0)],
+            }
+        );
+
+        let c: Command = serde_json::from_str(r#"{"type": "Reboot", "token": "secret"}"#).unwrap();
+        assert_eq!(
+            c,
+            Command::Reboot {
+                token: "secret".to_string(),
+            }
+        );
+
+        let c: Command =
+            serde_json::from_str(r#"{"type": "Pause", "token": "secret", "component": "uat"}"#)
+                .unwrap();
+        assert_eq!(
+            c,
+            Command::Pause {
+                token: "secret".to_string(),
+                component: "uat".to_string(),
+            }
+        );
+
+        assert!(serde_json::from_str::<Command>(r#"{"type": "PowerOff"}"#).is_err());
+        assert!(serde_json::from_str::<Command>(r#"{"type": "Foo"}"#).is_err());
+    }
+}
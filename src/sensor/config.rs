@@ -0,0 +1,221 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Watches the config file and feeds changed configs into the pipeline,
+//! components pick up the settings they care about without a restart.
+
+use super::*;
+use config::Config;
+#[cfg(target_os = "linux")]
+use inotify::{watch_mask, Inotify};
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "linux"))]
+use std::time::SystemTime;
+
+pub struct ConfigProvider {
+    watcher: Watcher,
+    path: PathBuf,
+    current: Config,
+}
+
+impl ConfigProvider {
+    pub fn new(current: Config) -> Option<Box<Sensor>> {
+        let path = PathBuf::from(Config::path());
+        let watcher = match Watcher::new(&path) {
+            Some(w) => w,
+            None => return None,
+        };
+
+        Some(Box::new(Self {
+            watcher,
+            path,
+            current,
+        }))
+    }
+
+    fn reload(&mut self, h: &mut Pushable<SensorData>) {
+        let parsed = match File::open(&self.path) {
+            Ok(f) => Config::parse(f),
+            Err(e) => {
+                warn!("unable to open config file {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+
+        let config = match parsed {
+            Ok(c) => c,
+            Err(errors) => {
+                for e in errors {
+                    error!("config file {}: {}", self.path.display(), e);
+                }
+                error!("keeping the current config");
+                return;
+            }
+        };
+
+        if config == self.current {
+            return;
+        }
+
+        info!("config file changed, applying");
+
+        if config.data_dir != self.current.data_dir {
+            warn!("data_dir change will take effect after restart");
+        }
+
+        if config.extensions != self.current.extensions {
+            warn!("extensions change will take effect after restart");
+        }
+
+        if config.peer != self.current.peer {
+            warn!("peer change will take effect after restart");
+        }
+
+        if config.scripts != self.current.scripts {
+            warn!("scripts change will take effect after restart");
+        }
+
+        if config.gdl90_ports != self.current.gdl90_ports
+            || config.gdl90_client_ports != self.current.gdl90_client_ports
+        {
+            warn!("GDL90 port change will take effect after restart");
+        }
+
+        if config.gdl90_loopback != self.current.gdl90_loopback
+            || config.gdl90_max_datagram != self.current.gdl90_max_datagram
+            || config.gdl90_coalesce_ms != self.current.gdl90_coalesce_ms
+        {
+            warn!("GDL90 transport change will take effect after restart");
+        }
+
+        if config.ground_station != self.current.ground_station {
+            warn!("ground_station change will take effect after restart");
+        }
+
+        if config.uat_raw_log != self.current.uat_raw_log
+            || config.uat_raw_port != self.current.uat_raw_port
+        {
+            warn!("raw UAT output change will take effect after restart");
+        }
+
+        if config.region != self.current.region {
+            warn!("region change of the SBAS used will take effect after restart");
+        }
+
+        self.current = config.clone();
+        h.push_data(SensorData::Config(config));
+    }
+}
+
+impl Sensor for ConfigProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        if self.watcher.changed() {
+            self.reload(h);
+        }
+    }
+}
+
+/// Watches the directory of the config file for changes with inotify
+#[cfg(target_os = "linux")]
+struct Watcher {
+    inotify: Inotify,
+    file_name: OsString,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn new(path: &Path) -> Option<Self> {
+        let file_name = match path.file_name() {
+            Some(f) => f.to_os_string(),
+            None => return None,
+        };
+
+        // watch the directory, editors often replace the file instead of
+        // writing to it
+        let dir = path.parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| Path::new(".").to_path_buf());
+
+        let mut inotify = match Inotify::init() {
+            Ok(i) => i,
+            Err(e) => {
+                warn!("unable to watch config file: {}", e);
+                return None;
+            }
+        };
+
+        if let Err(e) = inotify.add_watch(
+            &dir,
+            watch_mask::CLOSE_WRITE | watch_mask::CREATE | watch_mask::MOVED_TO,
+        ) {
+            warn!("unable to watch {}: {}", dir.display(), e);
+            return None;
+        }
+
+        Some(Self { inotify, file_name })
+    }
+
+    fn changed(&mut self) -> bool {
+        let mut buffer = [0; 512];
+
+        match self.inotify.read_events(&mut buffer) {
+            Ok(events) => events.into_iter().any(|e| e.name == self.file_name.as_os_str()),
+            Err(e) => {
+                warn!("error while reading inotify events: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Without inotify, fall back to checking the modification time of the
+/// config file every run.
+#[cfg(not(target_os = "linux"))]
+struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    fn new(path: &Path) -> Option<Self> {
+        let mut w = Self {
+            path: path.to_path_buf(),
+            modified: None,
+        };
+        w.modified = w.modified();
+
+        Some(w)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.path.metadata().and_then(|m| m.modified()).ok()
+    }
+
+    fn changed(&mut self) -> bool {
+        let modified = self.modified();
+
+        if modified != self.modified {
+            self.modified = modified;
+            return modified.is_some();
+        }
+
+        false
+    }
+}
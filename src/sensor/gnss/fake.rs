@@ -18,17 +18,19 @@ use super::*;
 use pitot::handle::Pushable;
 use sensor::{Sensor, SensorData};
 
-struct FakeGNSSProvider {}
+/// Reports a fixed position, for running the pipeline without a receiver
+pub struct FakeGNSSProvider {}
 
 impl Sensor for FakeGNSSProvider {
     fn run(&mut self, h: &mut Pushable<SensorData>) {
         let fix = SensorData::GNSS(GNSSData::TimeFix {
             time: Some(UTC.ymd(2014, 7, 8).and_hms(9, 10, 11)),
             fix: Some(Fix {
-                lat_lon: ((12345_f32, 12345_f32), Some(1000)),
+                lat_lon: ((37.6189_f32, -122.375_f32), Some(1000)),
                 height_msl: (1000, Some(500)),
                 height_ellipsoid: (900, Some(500)),
                 gs: (10000, Some(100)),
+                vs: (0, Some(100)),
                 true_course: (123_f32, Some(2_f32)),
                 quality: FixQuality::ThreeDim,
                 num_sv: 4,
@@ -41,7 +43,7 @@ impl Sensor for FakeGNSSProvider {
 }
 
 impl FakeGNSSProvider {
-    fn new() -> Option<Box<Self>> {
+    pub fn new() -> Option<Box<Self>> {
         Some(Box::new(FakeGNSSProvider {}))
     }
 }
@@ -49,36 +51,34 @@ impl FakeGNSSProvider {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use pitot::handle::{BasicHandle, PushableHandle};
-    use std::collections::VecDeque;
+    use testing::MockHandle;
 
     #[test]
     fn test_fake_gnss_provider() {
         let mut p = FakeGNSSProvider::new().unwrap();
-        let mut q = VecDeque::<SensorData>::new();
+        let mut h = MockHandle::new(10);
 
-        for i in 0..2 {
-            {
-                let mut b = BasicHandle::new(10);
-                let mut h = PushableHandle::new(&mut b, &mut q);
-                p.run(&mut h);
-            }
+        for _ in 0..2 {
+            p.run(&mut h);
 
             assert_eq!(
-                q[i],
-                SensorData::GNSS(GNSSData::TimeFix {
-                    time: Some(UTC.ymd(2014, 7, 8).and_hms(9, 10, 11)),
-                    fix: Some(Fix {
-                        lat_lon: ((12345_f32, 12345_f32), Some(1000)),
-                        height_msl: (1000, Some(500)),
-                        height_ellipsoid: (900, Some(500)),
-                        gs: (10000, Some(100)),
-                        true_course: (123_f32, Some(2_f32)),
-                        quality: FixQuality::ThreeDim,
-                        num_sv: 4,
-                        mag_dec: Some((10_f32, Some(4_f32))),
+                h.take(),
+                vec![
+                    SensorData::GNSS(GNSSData::TimeFix {
+                        time: Some(UTC.ymd(2014, 7, 8).and_hms(9, 10, 11)),
+                        fix: Some(Fix {
+                            lat_lon: ((37.6189_f32, -122.375_f32), Some(1000)),
+                            height_msl: (1000, Some(500)),
+                            height_ellipsoid: (900, Some(500)),
+                            gs: (10000, Some(100)),
+                            vs: (0, Some(100)),
+                            true_course: (123_f32, Some(2_f32)),
+                            quality: FixQuality::ThreeDim,
+                            num_sv: 4,
+                            mag_dec: Some((10_f32, Some(4_f32))),
+                        }),
                     }),
-                })
+                ]
             );
         }
     }
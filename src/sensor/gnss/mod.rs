@@ -15,11 +15,16 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use chrono::prelude::*;
+use std::time::Instant;
 
-mod fake;
+pub mod fake;
+pub mod pps;
+#[cfg(feature = "gnss-ublox")]
+pub mod rtcm;
+#[cfg(feature = "gnss-ublox")]
 pub mod ublox;
 
-#[derive(PartialEq, Debug, Copy, Clone, Serialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Serialize)]
 pub enum Constellation {
     GPS,
     SBAS,
@@ -33,6 +38,10 @@ pub enum FixQuality {
     TwoDim,
     ThreeDim,
     SBAS,
+    /// RTK with carrier phase ambiguities not yet resolved
+    RTKFloat,
+    /// RTK with carrier phase ambiguities resolved
+    RTKFixed,
     Unknown,
 }
 
@@ -57,32 +66,73 @@ pub struct Fix {
     pub height_ellipsoid: Reading<i32, u32>,
     /// Ground speed and accuracy in millimeters per second
     pub gs: Reading<u32, u32>,
+    /// Vertical speed (positive up) and accuracy in millimeters per second
+    pub vs: Reading<i32, u32>,
     /// True course and accuracy in degrees
     pub true_course: Reading<f32, f32>,
     /// Magnetic declination in degrees, if unknown, use 0
     pub mag_dec: OptionalReading<f32, f32>,
 }
 
+/// Frequency band of a signal
 #[derive(PartialEq, Debug, Copy, Clone, Serialize)]
+pub enum Band {
+    /// GPS/QZSS L1C/A, SBAS L1 and GLONASS L1OF
+    L1,
+    /// GPS/QZSS L2C and GLONASS L2OF
+    L2,
+    /// GPS/QZSS L5
+    L5,
+    E1,
+    E5a,
+    E5b,
+    Unknown,
+}
+
+/// One signal tracked from a satellite, only reported by multi-band
+/// receivers
+#[derive(PartialEq, Debug, Copy, Clone, Serialize)]
+pub struct Signal {
+    pub band: Band,
+    /// Signal strength in dbHz
+    pub snr: u8,
+    /// Used for navigation?
+    pub in_solution: bool,
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
 pub struct SVStatus {
     /// Constellation this satellite belongs
-    system: Constellation,
+    pub system: Constellation,
     /// SVid inside system (not PRN)
-    sv_id: u8,
+    pub sv_id: u8,
     /// Signal strength in dbHz
-    signal: Option<u8>,
+    pub signal: Option<u8>,
     /// Elevation in degrees
-    elevation: Option<i8>,
+    pub elevation: Option<i8>,
     /// Azimuth in degrees
-    azimuth: Option<u16>,
+    pub azimuth: Option<u16>,
     /// Is this satellite healthy?
-    healthy: Option<bool>,
+    pub healthy: Option<bool>,
     /// Signal acquired?
-    acquired: bool,
+    pub acquired: bool,
     /// In solution?
-    in_solution: bool,
+    pub in_solution: bool,
     /// SBAS corrections applies to this SV?
-    sbas_in_use: Option<bool>,
+    pub sbas_in_use: Option<bool>,
+    /// Signals tracked per band, empty if the receiver does not report them
+    pub signals: Vec<Signal>,
+}
+
+/// Dilution of precision, unitless
+#[derive(PartialEq, Debug, Copy, Clone, Serialize)]
+pub struct DOP {
+    /// Position DOP
+    pub pdop: f32,
+    /// Horizontal DOP
+    pub hdop: f32,
+    /// Vertical DOP
+    pub vdop: f32,
 }
 
 #[derive(PartialEq, Debug)]
@@ -96,4 +146,28 @@ pub enum GNSSData {
     },
     /// Satellite status report
     SatelliteInfo(Vec<SVStatus>),
+    /// Signals tracked by a multi-band receiver, along with the satellite
+    /// they belong to
+    SignalInfo(Vec<(Constellation, u8, Signal)>),
+    /// Dilution of precision of the current solution
+    DOP(DOP),
+    /// RF front end status, for spotting a degrading antenna or jamming
+    RFStatus {
+        /// Noise level as measured by the GNSS core
+        noise: u16,
+        /// AGC monitor in percent of its full range
+        agc: f32,
+        /// CW jamming indicator, 0 (none) to 255 (strong)
+        jamming: u8,
+    },
+    /// Upcoming leap second as broadcast by the satellites, `change` is 0
+    /// when none is announced
+    LeapSecond {
+        /// Seconds added (or removed, if negative) to UTC
+        change: i8,
+        /// Seconds until the leap second happens
+        in_secs: i32,
+    },
+    /// Start of a UTC second as marked by the receiver's time pulse
+    TimePulse(Instant),
 }
@@ -0,0 +1,139 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reads the time pulse of the GNSS receiver, which marks the start of
+//! every UTC second, through the kernel PPS subsystem (e.g. `pps-gpio`).
+//!
+//! The kernel stamps each pulse with the system clock, that stamp is turned
+//! into monotonic time by comparing it with the wall and monotonic time of
+//! the current iteration, both read from the same system clock.
+
+use super::*;
+use chrono::Duration;
+use pitot::handle::Pushable;
+use sensor::{Sensor, SensorData};
+use std::fs::File;
+use std::io::Read;
+
+const ASSERT_PATH: &str = "/sys/class/pps/pps0/assert";
+// pulses seen later than this, e.g. because the clock was stepped, are
+// ignored, in seconds
+const MAX_PULSE_AGE: i64 = 1;
+
+pub struct PPSProvider {
+    /// sequence number of the last pulse
+    seq: Option<u64>,
+}
+
+impl PPSProvider {
+    pub fn new() -> Option<Box<Sensor>> {
+        if read_assert().is_none() {
+            info!("PPS not found!");
+            return None;
+        }
+
+        info!("PPS initialization successful");
+        Some(Box::new(PPSProvider { seq: None }))
+    }
+
+    fn pulse(&mut self, h: &mut Pushable<SensorData>, assert: (DateTime<UTC>, u64)) {
+        let (stamp, seq) = assert;
+        if self.seq == Some(seq) {
+            return;
+        }
+        self.seq = Some(seq);
+
+        let age = h.get_utc().signed_duration_since(stamp);
+        if age < Duration::zero() || age >= Duration::seconds(MAX_PULSE_AGE) {
+            return;
+        }
+
+        let clock = h.get_clock() - age.to_std().unwrap();
+        h.push_data(SensorData::GNSS(GNSSData::TimePulse(clock)));
+    }
+}
+
+/// Time stamp and sequence number of the last pulse
+fn read_assert() -> Option<(DateTime<UTC>, u64)> {
+    let mut s = String::new();
+
+    File::open(ASSERT_PATH)
+        .and_then(|mut f| f.read_to_string(&mut s))
+        .ok()
+        .and_then(|_| parse_assert(&s))
+}
+
+/// Parses e.g. `1530486667.000000331#1234`
+fn parse_assert(s: &str) -> Option<(DateTime<UTC>, u64)> {
+    let parts: Vec<&str> = s.trim().split(|c| c == '.' || c == '#').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    match (parts[0].parse(), parts[1].parse(), parts[2].parse()) {
+        // no pulse seen yet
+        (Ok(0), _, _) => None,
+        (Ok(secs), Ok(nanos), Ok(seq)) => UTC.timestamp_opt(secs, nanos).single().map(|t| (t, seq)),
+        _ => None,
+    }
+}
+
+impl Sensor for PPSProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        if let Some(assert) = read_assert() {
+            self.pulse(h, assert);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Handle;
+    use std::time::Duration as StdDuration;
+    use testing::MockHandle;
+
+    #[test]
+    fn test_parse_assert() {
+        assert_eq!(
+            parse_assert("1530486667.000000331#1234\n"),
+            Some((UTC.timestamp(1530486667, 331), 1234))
+        );
+        assert_eq!(parse_assert("0.000000000#0\n"), None);
+        assert_eq!(parse_assert("garbage"), None);
+        assert_eq!(parse_assert("1530486667.2000000000#1234\n"), None);
+        assert_eq!(parse_assert("9223372036854775807.000000000#1234\n"), None);
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut p = PPSProvider { seq: None };
+        let mut h = MockHandle::new(10);
+        let utc = h.get_utc();
+
+        p.pulse(&mut h, (utc - Duration::milliseconds(40), 1));
+        let pulse = h.get_clock() - StdDuration::from_millis(40);
+        assert_eq!(h.take(), vec![SensorData::GNSS(GNSSData::TimePulse(pulse))]);
+
+        // same pulse
+        p.pulse(&mut h, (utc - Duration::milliseconds(40), 1));
+        assert!(h.take().is_empty());
+
+        // too old to be trusted
+        p.pulse(&mut h, (utc - Duration::seconds(5), 2));
+        assert!(h.take().is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! RTCM 3 corrections for RTK capable receivers, from an NTRIP caster or a
+//! serial port. Corrections are received on a separate thread and handed
+//! over untouched, the receiver does the rest. A lost source is reconnected
+//! to until it comes back.
+
+use config::Rtcm;
+use serial::{self, BaudRate, SerialPort};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+const RECONNECT_SECS: u64 = 10;
+/// casters send corrections at least once per second, a silent connection is
+/// considered lost after this
+const TIMEOUT_SECS: u64 = 30;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Start receiving corrections from `source`, the thread exits once the
+/// returned receiver is dropped
+pub fn start(source: &Rtcm) -> Receiver<Vec<u8>> {
+    let (tx, rx) = channel();
+    let source = source.clone();
+
+    spawn(move || loop {
+        let res = match source {
+            Rtcm::Ntrip {
+                ref host,
+                port,
+                ref mountpoint,
+                ref username,
+                ref password,
+            } => ntrip(host, port, mountpoint, username, password, &tx),
+            Rtcm::Serial { ref path, baud } => passthrough(path, baud, &tx),
+        };
+
+        match res {
+            Ok(_) => return,
+            Err(e) => warn!(
+                "RTCM source lost: {}, retrying in {} seconds",
+                e, RECONNECT_SECS
+            ),
+        }
+
+        sleep(Duration::from_secs(RECONNECT_SECS));
+    });
+
+    rx
+}
+
+/// Forward everything read from `r` to `tx`. Returns `Ok` once the receiving
+/// end is gone.
+fn forward<R: Read>(r: &mut R, tx: &Sender<Vec<u8>>) -> io::Result<()> {
+    let mut buf = [0_u8; 1024];
+
+    loop {
+        match r.read(&mut buf) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed",
+                ))
+            }
+            Ok(n) => {
+                if tx.send(buf[..n].to_vec()).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn ntrip(
+    host: &str,
+    port: u16,
+    mountpoint: &str,
+    username: &Option<String>,
+    password: &Option<String>,
+    tx: &Sender<Vec<u8>>,
+) -> io::Result<()> {
+    let addr = match try!((host, port).to_socket_addrs()).next() {
+        Some(a) => a,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not resolve", host),
+            ))
+        }
+    };
+
+    let mut stream = try!(TcpStream::connect_timeout(
+        &addr,
+        Duration::from_secs(TIMEOUT_SECS)
+    ));
+    try!(stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS))));
+
+    let mut request = format!(
+        "GET /{} HTTP/1.0\r\nHost: {}\r\nUser-Agent: NTRIP Pitot\r\n",
+        mountpoint, host
+    );
+    if let Some(ref u) = *username {
+        let credentials = format!("{}:{}", u, password.as_ref().map_or("", |p| p.as_str()));
+        request.push_str(&format!(
+            "Authorization: Basic {}\r\n",
+            base64(credentials.as_bytes())
+        ));
+    }
+    request.push_str("\r\n");
+    try!(stream.write_all(request.as_bytes()));
+
+    let mut headers = match check_response(&try!(read_line(&mut stream))) {
+        Ok(h) => h,
+        Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+    };
+    while headers {
+        headers = !try!(read_line(&mut stream)).is_empty();
+    }
+
+    info!("receiving RTCM corrections from {}/{}", host, mountpoint);
+
+    forward(&mut stream, tx)
+}
+
+fn passthrough(path: &str, baud: usize, tx: &Sender<Vec<u8>>) -> io::Result<()> {
+    let mut port = try!(serial::open(path));
+    try!(port.reconfigure(&|settings| {
+        try!(settings.set_baud_rate(BaudRate::from_speed(baud)));
+        settings.set_char_size(serial::Bits8);
+        settings.set_parity(serial::ParityNone);
+        settings.set_stop_bits(serial::Stop1);
+        settings.set_flow_control(serial::FlowNone);
+        Ok(())
+    }));
+    try!(port.set_timeout(Duration::from_secs(TIMEOUT_SECS)));
+
+    info!("receiving RTCM corrections on {}", path);
+
+    forward(&mut port, tx)
+}
+
+/// Read one line of the response header, without the line ending
+fn read_line<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut b = [0_u8];
+
+    while line.len() < 1024 {
+        try!(r.read_exact(&mut b));
+        match b[0] {
+            b'\n' => break,
+            b'\r' => {}
+            c => line.push(c),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Check the status line of a caster response. NTRIP 1 casters answer with
+/// `ICY 200 OK` followed directly by data, NTRIP 2 casters with a regular HTTP
+/// response. Returns whether headers follow.
+fn check_response(status: &str) -> Result<bool, String> {
+    if status.starts_with("ICY 200") {
+        Ok(false)
+    } else if status.starts_with("HTTP/") && status.split(' ').nth(1) == Some("200") {
+        Ok(true)
+    } else if status.starts_with("SOURCETABLE") {
+        Err("mountpoint not found on caster".to_string())
+    } else {
+        Err(format!("caster refused connection: {}", status))
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    let mut out = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for chunk in input.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - i * 6) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"a"), "YQ==");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert_eq!(check_response("ICY 200 OK"), Ok(false));
+        assert_eq!(check_response("HTTP/1.1 200 OK"), Ok(true));
+        assert!(check_response("SOURCETABLE 200 OK").is_err());
+        assert!(check_response("HTTP/1.1 401 Unauthorized").is_err());
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut r = &b"ICY 200 OK\r\n\xD3\x00"[..];
+
+        assert_eq!(read_line(&mut r).unwrap(), "ICY 200 OK");
+        assert_eq!(r, b"\xD3\x00");
+    }
+}
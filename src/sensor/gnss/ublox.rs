@@ -15,24 +15,49 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::*;
+use config::{Region, Rtcm};
 use nom::{le_i16, le_i32, le_i8, le_u16, le_u32, le_u8, shift, ErrorKind, IResult};
 use pitot::handle::Pushable;
+use recording::create_numbered;
 use sensor::{Sensor, SensorData};
 use serial::{self, BaudRate, SerialPort, SystemPort};
-use std::io::{self, Read, Write};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
 use std::num::Wrapping;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
-use std::{str, thread, time};
 
 const SERIAL_PATH: [&str; 1] = ["/dev/ttyAMA0"];
 const BAUD_RATE: BaudRate = BaudRate::Baud38400;
+/// resend a request after this many unrelated messages or read timeouts
+const RESEND_AFTER: u32 = 5;
+/// give up on a request after sending it this many times
+const MAX_SENDS: u32 = 3;
+/// accuracy of the last known position handed to the receiver at startup in
+/// cm, as the aircraft may have been moved since
+const POSITION_AIDING_ACCURACY: u32 = 10_000_000;
+/// time between solutions in ms, normally and while saving power
+const RATE_MS: u16 = 100;
+const LOW_RATE_MS: u16 = 1000;
 
 pub struct UbloxGNSSProvider {
     comm: UBXCommunicator,
+    /// RXM-RAWX and RXM-SFRBX messages are written here as received
+    raw_log: Option<BufWriter<File>>,
+    /// RTCM corrections to pass on to the receiver
+    corrections: Option<Receiver<Vec<u8>>>,
+    /// set while saving power, see `processor::powersave`
+    low_rate: Arc<AtomicBool>,
+    /// whether the receiver was last set to `LOW_RATE_MS`
+    at_low_rate: bool,
 }
 
 #[derive(Debug, PartialEq)]
-struct UBXPacket<'a> {
+pub struct UBXPacket<'a> {
     class: u8,
     id: u8,
     payload: &'a [u8],
@@ -47,6 +72,8 @@ enum ProtocolError {
 #[derive(Debug)]
 enum Error {
     NAK,
+    /// no ACK/NAK or answer to a poll after all resends
+    NoResponse,
     Protocol(ProtocolError),
     Io(io::Error),
 }
@@ -141,21 +168,38 @@ impl UBXCommunicator {
         }
     }
 
+    fn send(&mut self, packet: &UBXPacket) -> io::Result<()> {
+        try!(self.serial.write_all(&packet.to_wire()));
+        self.serial.flush()
+    }
+
+    /// Count a response that was not the one we are waiting for, returns
+    /// whether the request should be sent again
+    fn missed(n: &mut u32, resend: bool) -> Result<bool, Error> {
+        *n += 1;
+
+        if *n >= RESEND_AFTER * MAX_SENDS {
+            return Err(Error::NoResponse);
+        }
+
+        Ok(resend || *n % RESEND_AFTER == 0)
+    }
+
     /// Write `packet` to wire, wait for ACK/NAK responses if class id is CFG
     fn write(&mut self, packet: &UBXPacket) -> Result<(), Error> {
-        try!(
-            self.serial
-                .write_all(&packet.to_wire())
-                .and_then(|_| self.serial.flush())
-        );
+        try!(self.send(packet));
+
+        // only wait for response if class is CFG and not reconfiguring ports
+        // observation is that when CFG-PRT is sent, sometimes we do not even get
+        // an ACK/NAK back, thus waiting on it is not really safe to do
+        if packet.class != 0x06 || packet.id == 0x00 || packet.payload.is_empty() {
+            return Ok(());
+        }
 
         let mut n = 0;
 
-        while packet.class == 0x06 && packet.id != 0x00 && packet.payload.len() > 0 {
-            // only wait for response if class is CFG and not reconfiguring ports
-            // observation is that when CFG-PRT is sent, sometimes we do not even get
-            // an ACK/NAK back, thus waiting on it is not really safe to do
-            if match self.next() {
+        loop {
+            let resend = match self.next() {
                 Ok(UBXPacket {
                     class: 0x05,
                     id: 0x01,
@@ -172,37 +216,69 @@ impl UBXCommunicator {
                 {
                     return Err(Error::NAK)
                 }
-                Ok(_) => {
-                    // likely a bad send when switching baudrate
-                    n += 1;
-                    n % 5 == 0
-                }
+                // likely a bad send when switching baudrate
+                Ok(_) => try!(Self::missed(&mut n, false)),
                 Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => {
-                    // likely a bad send when switching baudrate
-                    n += 1;
-                    n % 5 == 0
+                    try!(Self::missed(&mut n, false))
                 }
-                Err(Error::Protocol(ProtocolError::Parse(_))) => true,
+                Err(Error::Protocol(ProtocolError::Parse(_))) => try!(Self::missed(&mut n, true)),
                 Err(e) => return Err(e),
-            } {
+            };
+
+            if resend {
                 // try send again
                 debug!("parse error, resending");
                 // wait for port to stabilize
-                thread::sleep(time::Duration::from_millis(100));
-                try!(
-                    self.serial
-                        .write_all(&packet.to_wire())
-                        .and_then(|_| self.serial.flush())
-                );
+                thread::sleep(Duration::from_millis(100));
+                try!(self.send(packet));
             }
         }
+    }
+
+    /// Poll the message `class`/`id` and return its payload, resending the
+    /// poll the same way `write` does
+    fn poll(&mut self, class: u8, id: u8) -> Result<Vec<u8>, Error> {
+        let packet = UBXPacket::new(class, id, &[]);
+        try!(self.send(&packet));
+
+        let mut n = 0;
+
+        loop {
+            let resend = match self.next() {
+                Ok(UBXPacket {
+                    class: c,
+                    id: i,
+                    payload,
+                }) if c == class && i == id =>
+                {
+                    return Ok(payload.to_vec())
+                }
+                Ok(UBXPacket {
+                    class: 0x05,
+                    id: 0x00,
+                    payload,
+                }) if payload == &[class, id] =>
+                {
+                    return Err(Error::NAK)
+                }
+                Ok(_) => try!(Self::missed(&mut n, false)),
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut => {
+                    try!(Self::missed(&mut n, false))
+                }
+                Err(Error::Protocol(ProtocolError::Parse(_))) => try!(Self::missed(&mut n, true)),
+                Err(e) => return Err(e),
+            };
 
-        Ok(())
+            if resend {
+                debug!("no answer to poll, resending");
+                try!(self.send(&packet));
+            }
+        }
     }
 }
 
 named!(
-    parse_ubx_message<UBXPacket>,
+    pub parse_ubx_message<UBXPacket>,
     map_res!(
         do_parse!(
             take_until_and_consume!(&[0xB5_u8, 0x62][..])
@@ -219,7 +295,7 @@ named!(
 );
 
 named!(
-    parse_ubx_nav_pvt<GNSSData>, // see p. 291
+    pub parse_ubx_nav_pvt<GNSSData>, // see p. 291
     map!(
         do_parse!(
             take!(4) >> // skip iTOW
@@ -244,15 +320,20 @@ named!(
             vertical_accuracy: le_u32 >>
             take!(4) >> // skip NED north velocity
             take!(4) >> // skip NED east velocity
-            take!(4) >> // skip NED down velocity
+            vel_d: le_i32 >>
             gs: le_i32 >>
             hdg: le_i32 >>
             gs_accuracy: le_u32 >>
             hdg_accuracy: le_u32 >>
             take!(2) >> // skip pDOP
             take!(6) >> // skip reserved
-            take!(4) >> // skip headVeh
-            mag_dec: le_i16 >> mag_dec_accuracy: le_u16
+            // protocol 14 and later, before that the message ends here
+            mag: opt!(complete!(do_parse!(
+                take!(4) >> // skip headVeh
+                mag_dec: le_i16 >>
+                mag_dec_accuracy: le_u16 >>
+                (mag_dec, mag_dec_accuracy)
+            )))
                 >> (
                     year,
                     month,
@@ -260,7 +341,12 @@ named!(
                     hour,
                     min,
                     sec,
-                    time_valid,
+                    // validMag is reserved before protocol 14
+                    if mag.is_some() {
+                        time_valid
+                    } else {
+                        time_valid & !0x08
+                    },
                     fix_type,
                     fix_status,
                     num_sv,
@@ -270,12 +356,13 @@ named!(
                     height_msl,
                     horizontal_accuracy,
                     vertical_accuracy,
+                    vel_d,
                     gs,
                     hdg,
                     gs_accuracy,
                     hdg_accuracy,
-                    mag_dec,
-                    mag_dec_accuracy
+                    mag.map_or(0, |m| m.0),
+                    mag.map_or(0, |m| m.1)
                 )
         ),
         fix_from_pvt
@@ -283,7 +370,7 @@ named!(
 );
 
 named!(
-    parse_ubx_nav_sat<GNSSData>, // see p. 296
+    pub parse_ubx_nav_sat<GNSSData>, // see p. 296
     map!(
         do_parse!(
             take!(4) >> // skip iTOW
@@ -307,17 +394,208 @@ named!(
     )
 );
 
+named!(
+    pub parse_ubx_nav_dop<GNSSData>, // see p. 283
+    do_parse!(
+        take!(4) >> // skip iTOW
+        take!(2) >> // skip gDOP
+        pdop: le_u16 >>
+        take!(2) >> // skip tDOP
+        vdop: le_u16 >>
+        hdop: le_u16 >>
+        take!(4) >> // skip nDOP and eDOP
+        (GNSSData::DOP(DOP {
+            pdop: pdop as f32 * 0.01,
+            hdop: hdop as f32 * 0.01,
+            vdop: vdop as f32 * 0.01,
+        }))
+    )
+);
+
+named!(
+    pub parse_ubx_nav_sig<GNSSData>, // see p. 94 of the F9 HPG interface description
+    map!(
+        do_parse!(
+            take!(4) >> // skip iTOW
+            tag!([0x00]) >> // version = 0
+            num_sigs: le_u8 >>
+            take!(2) >> // skip reserved
+            signals: count!(
+                        map!(do_parse!(
+                            gnss_id: le_u8 >>
+                            sv_id: le_u8 >>
+                            sig_id: le_u8 >>
+                            take!(3) >> // skip freqId and prRes
+                            cno: le_u8 >>
+                            take!(3) >> // skip qualityInd, corrSource and ionoModel
+                            flags: le_u16 >>
+                            take!(4) >> // skip reserved
+                            (gnss_id, sv_id, sig_id, cno, flags)
+                        ), signal_from_protocol)
+            , num_sigs as usize) >> (signals)
+        ),
+        GNSSData::SignalInfo
+    )
+);
+
+named!(
+    pub parse_ubx_mon_hw<GNSSData>, // u-blox M8 layout
+    do_parse!(
+        take!(16) >> // skip pins
+        noise: le_u16 >>
+        agc: le_u16 >>
+        take!(25) >> // skip antenna and pin status, virtual pins
+        jamming: le_u8 >>
+        take!(14) >>
+        (GNSSData::RFStatus {
+            noise,
+            agc: agc as f32 / 8191.0 * 100.0,
+            jamming,
+        })
+    )
+);
+
+named!(
+    pub parse_ubx_nav_timels<Option<GNSSData>>, // see p. 317
+    do_parse!(
+        take!(11) >> // skip iTOW, version, reserved and current leap second
+        change: le_i8 >>
+        in_secs: le_i32 >>
+        take!(7) >> // skip date of leap second and reserved
+        valid: le_u8 >>
+        (if valid & 0x02 != 0 {
+            // validTimeToLsEvent
+            Some(GNSSData::LeapSecond { change, in_secs })
+        } else {
+            None
+        })
+    )
+);
+
+/// Output of a NAV message parser, `None` if the message is malformed so
+/// that garbage on the serial line can not take down the receiver
+fn parsed<T>(name: &str, res: IResult<&[u8], T>) -> Option<T> {
+    match res {
+        IResult::Done(rem, o) => {
+            if !rem.is_empty() {
+                trace!("{} has {} trailing bytes", name, rem.len());
+            }
+
+            Some(o)
+        }
+        IResult::Error(e) => {
+            debug!("malformed {}: {:?}", name, e);
+            None
+        }
+        IResult::Incomplete(_) => {
+            debug!("truncated {}", name);
+            None
+        }
+    }
+}
+
+fn constellation(gnss_id: u8) -> Constellation {
+    match gnss_id {
+        0 => Constellation::GPS,
+        1 => Constellation::SBAS,
+        2 => Constellation::Galileo,
+        6 => Constellation::GLONASS,
+        _ => Constellation::Unknown,
+    }
+}
+
+/// CFG-SBAS scanmode2 and scanmode1 searching only `prns`
+fn sbas_scanmode(prns: &[u8]) -> (u8, u32) {
+    let mut scanmode2 = 0;
+    let mut scanmode1 = 0;
+
+    for prn in prns {
+        match *prn {
+            120...151 => scanmode1 |= 1 << (prn - 120),
+            152...158 => scanmode2 |= 1 << (prn - 152),
+            _ => {}
+        }
+    }
+
+    (scanmode2, scanmode1)
+}
+
+/// What the module reported in MON-VER
+#[derive(Debug, Default, PartialEq)]
+struct Version {
+    /// protocol version, e.g. 18.00 for u-blox M8, `None` if unknown
+    protocol: Option<f32>,
+    /// supported GNSS, e.g. "GPS", "GLO", "GAL"
+    gnss: Vec<String>,
+}
+
+impl Version {
+    /// ROM BASE 2.01 (75331)FWVER=SPG 3.01PROTVER=18.00FIS=0xEF4015 (200030)
+    /// GPS;GLO;GAL;BDSSBAS;IMES;QZSS
+    /// where each extension is a 30 byte NUL padded string after the 30 byte
+    /// swVersion and 10 byte hwVersion
+    fn parse(payload: &[u8]) -> Version {
+        let mut v = Version::default();
+
+        for ext in payload.get(40..).unwrap_or(&[]).chunks(30) {
+            let ext = String::from_utf8_lossy(ext);
+            let ext = ext.trim_end_matches('\0');
+
+            // older firmware separates with a space instead of `=`
+            let mut kv = ext.splitn(2, |c| c == '=' || c == ' ');
+            match (kv.next(), kv.next()) {
+                (Some("PROTVER"), Some(protocol)) => v.protocol = protocol.parse().ok(),
+                _ if ext.contains(';') || ext == "GPS" => {
+                    v.gnss.extend(ext.split(';').map(String::from))
+                }
+                _ => {}
+            }
+        }
+
+        v
+    }
+
+    /// Whether the module speaks at least `protocol`, assumed when unknown
+    /// so that the module gets to NAK what it does not support
+    fn at_least(&self, protocol: f32) -> bool {
+        self.protocol.map_or(true, |p| p >= protocol)
+    }
+
+    fn supports(&self, gnss: &str) -> bool {
+        self.gnss.iter().any(|g| g == gnss)
+    }
+}
+
+fn signal_from_protocol(data: (u8, u8, u8, u8, u16)) -> (Constellation, u8, Signal) {
+    let (gnss_id, sv_id, sig_id, cno, flags) = data;
+
+    let band = match (gnss_id, sig_id) {
+        (0, 0) | (1, 0) | (5, 0) | (5, 1) | (6, 0) => Band::L1,
+        (0, 3) | (0, 4) | (5, 4) | (5, 5) | (6, 2) => Band::L2,
+        (0, 6) | (0, 7) | (5, 8) | (5, 9) => Band::L5,
+        (2, 0) | (2, 1) => Band::E1,
+        (2, 3) | (2, 4) => Band::E5a,
+        (2, 5) | (2, 6) => Band::E5b,
+        _ => Band::Unknown,
+    };
+
+    (
+        constellation(gnss_id),
+        sv_id,
+        Signal {
+            band,
+            snr: cno,
+            // prUsed, crUsed or doUsed
+            in_solution: flags & 0x38 != 0,
+        },
+    )
+}
+
 fn svinfo_from_protocol(data: (u8, u8, u8, i8, i16, u32)) -> SVStatus {
     let (gnss_id, sv_id, signal, elev, azim, flags) = data;
 
     SVStatus {
-        system: match gnss_id {
-            0 => Constellation::GPS,
-            1 => Constellation::SBAS,
-            2 => Constellation::Galileo,
-            6 => Constellation::GLONASS,
-            _ => Constellation::Unknown,
-        },
+        system: constellation(gnss_id),
         sv_id,
         signal: Some(signal),
         elevation: Some(elev),
@@ -326,6 +604,7 @@ fn svinfo_from_protocol(data: (u8, u8, u8, i8, i16, u32)) -> SVStatus {
         acquired: flags & 0x07 >= 2,
         in_solution: flags & 0x08 != 0,
         sbas_in_use: Some(flags & 0x10000 != 0),
+        signals: Vec::new(),
     }
 }
 
@@ -353,6 +632,7 @@ fn fix_from_pvt(
         u32,
         i32,
         i32,
+        i32,
         u32,
         u32,
         i16,
@@ -376,6 +656,7 @@ fn fix_from_pvt(
         height_msl,
         horizontal_accuracy,
         vertical_accuracy,
+        vel_d,
         gs,
         hdg,
         gs_accuracy,
@@ -385,13 +666,18 @@ fn fix_from_pvt(
     ) = data;
 
     GNSSData::TimeFix {
-        time: if time_valid & 0x07 != 0 {
-            // validDate || validTime || fullyResolved
-            Some(UTC.ymd(year as i32, month as u32, day as u32).and_hms(
-                hour as u32,
-                min as u32,
-                sec as u32,
-            ))
+        time: if time_valid & 0x03 == 0x03 {
+            // validDate && validTime, but still check the values
+            UTC.ymd_opt(year as i32, month as u32, day as u32)
+                .single()
+                .and_then(|date| {
+                    if sec == 60 {
+                        // leap second
+                        date.and_hms_milli_opt(hour as u32, min as u32, 59, 1000)
+                    } else {
+                        date.and_hms_opt(hour as u32, min as u32, sec as u32)
+                    }
+                })
         } else {
             // time is unreliable
             None
@@ -405,8 +691,15 @@ fn fix_from_pvt(
                 height_msl: (height_msl, Some(vertical_accuracy)),
                 height_ellipsoid: (height_ellipsoid, Some(vertical_accuracy)),
                 gs: (gs as u32, Some(gs_accuracy)),
+                // sAcc is the accuracy of the 3D speed
+                vs: (0_i32.saturating_sub(vel_d), Some(gs_accuracy)),
                 true_course: (hdg as f32 * 1.0e-5, Some(hdg_accuracy as f32 * 1.0e-5)),
-                quality: if fix_status & 0x02 != 0 {
+                // carrSoln, diffSoln is also set when RTK is used
+                quality: if fix_status >> 6 == 2 {
+                    FixQuality::RTKFixed
+                } else if fix_status >> 6 == 1 {
+                    FixQuality::RTKFloat
+                } else if fix_status & 0x02 != 0 {
                     FixQuality::SBAS
                 } else {
                     match fix_type {
@@ -487,6 +780,26 @@ impl<'a> UBXPacket<'a> {
 
 impl Sensor for UbloxGNSSProvider {
     fn run(&mut self, h: &mut Pushable<SensorData>) {
+        let mut raw_log_failed = false;
+
+        let low_rate = self.low_rate.load(Ordering::Relaxed);
+        if low_rate != self.at_low_rate {
+            // not waiting for the ACK, the port is non-blocking by now
+            let payload = cfg_rate(if low_rate { LOW_RATE_MS } else { RATE_MS });
+            match self.comm.send(&UBXPacket::new(0x06, 0x08, &payload)) {
+                Ok(_) => self.at_low_rate = low_rate,
+                Err(e) => warn!("could not change update rate: {}", e),
+            }
+        }
+
+        if let Some(ref rx) = self.corrections {
+            for c in rx.try_iter() {
+                if let Err(e) = self.comm.serial.write_all(&c) {
+                    debug!("unable to pass on RTCM corrections: {}", e);
+                }
+            }
+        }
+
         loop {
             match self.comm.next() {
                 Ok(UBXPacket {
@@ -495,10 +808,10 @@ impl Sensor for UbloxGNSSProvider {
                     payload,
                 }) => {
                     // PVT
-                    let (rem, pvt) = parse_ubx_nav_pvt(payload).unwrap();
-                    debug_assert!(rem.len() == 0);
-                    trace!("got PVT");
-                    h.push_data(SensorData::GNSS(pvt))
+                    if let Some(pvt) = parsed("NAV-PVT", parse_ubx_nav_pvt(payload)) {
+                        trace!("got PVT");
+                        h.push_data(SensorData::GNSS(pvt))
+                    }
                 }
                 Ok(UBXPacket {
                     class: 0x01,
@@ -506,10 +819,66 @@ impl Sensor for UbloxGNSSProvider {
                     payload,
                 }) => {
                     // SAT
-                    let (rem, sat) = parse_ubx_nav_sat(payload).unwrap();
-                    debug_assert!(rem.len() == 0);
-                    trace!("got SAT");
-                    h.push_data(SensorData::GNSS(sat))
+                    if let Some(sat) = parsed("NAV-SAT", parse_ubx_nav_sat(payload)) {
+                        trace!("got SAT");
+                        h.push_data(SensorData::GNSS(sat))
+                    }
+                }
+                Ok(UBXPacket {
+                    class: 0x01,
+                    id: 0x04,
+                    payload,
+                }) => {
+                    // DOP
+                    if let Some(dop) = parsed("NAV-DOP", parse_ubx_nav_dop(payload)) {
+                        trace!("got DOP");
+                        h.push_data(SensorData::GNSS(dop))
+                    }
+                }
+                Ok(UBXPacket {
+                    class: 0x01,
+                    id: 0x43,
+                    payload,
+                }) => {
+                    // SIG
+                    if let Some(sig) = parsed("NAV-SIG", parse_ubx_nav_sig(payload)) {
+                        trace!("got SIG");
+                        h.push_data(SensorData::GNSS(sig))
+                    }
+                }
+                Ok(UBXPacket {
+                    class: 0x01,
+                    id: 0x26,
+                    payload,
+                }) => {
+                    // TIMELS
+                    if let Some(ls) = parsed("NAV-TIMELS", parse_ubx_nav_timels(payload)) {
+                        trace!("got TIMELS");
+                        if let Some(ls) = ls {
+                            h.push_data(SensorData::GNSS(ls))
+                        }
+                    }
+                }
+                Ok(UBXPacket {
+                    class: 0x0A,
+                    id: 0x09,
+                    payload,
+                }) => {
+                    // HW
+                    if let Some(hw) = parsed("MON-HW", parse_ubx_mon_hw(payload)) {
+                        trace!("got HW");
+                        h.push_data(SensorData::GNSS(hw))
+                    }
+                }
+                Ok(ref packet @ UBXPacket { class: 0x02, .. }) => {
+                    // RAWX and SFRBX, only enabled when logging them
+                    if let Some(ref mut w) = self.raw_log {
+                        if let Err(e) = w.write_all(&packet.to_wire()) {
+                            error!("unable to log raw measurements: {}", e);
+                            raw_log_failed = true;
+                            break;
+                        }
+                    }
                 }
                 Err(Error::Io(e)) => {
                     if e.kind() == io::ErrorKind::TimedOut {
@@ -522,32 +891,55 @@ impl Sensor for UbloxGNSSProvider {
                 _ => break,
             }
         }
+
+        if let Some(e) = self.raw_log.as_mut().and_then(|w| w.flush().err()) {
+            error!("unable to log raw measurements: {}", e);
+            raw_log_failed = true;
+        }
+
+        if raw_log_failed {
+            warn!("raw measurement logging stopped");
+            self.raw_log = None;
+        }
     }
 }
 
 impl UbloxGNSSProvider {
-    pub fn new() -> Option<Box<Sensor>> {
+    /// With `raw_log`, raw measurements are also logged into that directory
+    /// for post-processing. With `rtcm`, corrections from that source are
+    /// passed on to the receiver. Only the SBAS of `region` is used.
+    /// `position`, the last known position and height above ellipsoid in mm,
+    /// is handed to the receiver for a faster first fix. While `low_rate` is
+    /// set, fixes are only computed once per second.
+    pub fn new(
+        raw_log: Option<&str>,
+        rtcm: Option<&Rtcm>,
+        region: Region,
+        position: Option<((f32, f32), i32)>,
+        low_rate: Arc<AtomicBool>,
+    ) -> Option<Box<Sensor>> {
         for p in &SERIAL_PATH {
             info!("trying port {}", p);
             if let Ok(mut p) = serial::open(p) {
                 p.set_timeout(Duration::from_secs(1)).unwrap();
                 let mut p = UBXCommunicator::new(p, 1024);
 
-                p.serial
-                    .reconfigure(&|settings| {
-                        try!(settings.set_baud_rate(BaudRate::Baud9600));
-                        settings.set_char_size(serial::Bits8);
-                        settings.set_parity(serial::ParityNone);
-                        settings.set_stop_bits(serial::Stop1);
-                        settings.set_flow_control(serial::FlowNone);
-                        Ok(())
-                    })
-                    .expect("could not configure baud rate");
+                if let Err(e) = p.serial.reconfigure(&|settings| {
+                    try!(settings.set_baud_rate(BaudRate::Baud9600));
+                    settings.set_char_size(serial::Bits8);
+                    settings.set_parity(serial::ParityNone);
+                    settings.set_stop_bits(serial::Stop1);
+                    settings.set_flow_control(serial::FlowNone);
+                    Ok(())
+                }) {
+                    error!("could not configure baud rate: {}", e);
+                    return None;
+                }
 
                 // configure port
                 // first, set port baud rate
 
-                let payload = &[
+                let payload = &mut [
                     0x01, // portID
                     0x00, // reserved1
                     0x00,
@@ -569,6 +961,9 @@ impl UbloxGNSSProvider {
                     0x00,
                     0x00, // flags, padding
                 ];
+                if rtcm.is_some() {
+                    payload[12] |= 0x20; // RTCM3 in
+                }
                 let packet = UBXPacket::new(0x06, 0x00, payload);
                 if let Err(e) = p.write(&packet) {
                     info!(
@@ -580,26 +975,22 @@ impl UbloxGNSSProvider {
 
                 // see https://github.com/dcuddeback/serial-rs/issues/43
                 // sleep 50ms to let RPi finishes transmitting
-                thread::sleep(time::Duration::from_millis(50));
+                thread::sleep(Duration::from_millis(50));
 
-                p.serial
-                    .reconfigure(&|settings| {
-                        try!(settings.set_baud_rate(BAUD_RATE));
-                        Ok(())
-                    })
-                    .expect("could not configure baud rate");
+                if let Err(e) = p.serial.reconfigure(&|settings| {
+                    try!(settings.set_baud_rate(BAUD_RATE));
+                    Ok(())
+                }) {
+                    error!("could not configure baud rate: {}", e);
+                    return None;
+                }
 
                 // next, set update rate
-                let payload = &[
-                    0x64,
-                    0x00, // measRate = 100ms
-                    0x01,
-                    0x00,
-                    0x01,
-                    0x00, // navRate = 1, timeRef = 1 (GPS)
-                ];
-                let packet = UBXPacket::new(0x06, 0x08, payload);
-                p.write(&packet).expect("could not configure update rate");
+                let payload = cfg_rate(RATE_MS);
+                let packet = UBXPacket::new(0x06, 0x08, &payload);
+                if let Err(e) = p.write(&packet) {
+                    warn!("could not configure update rate: {:?}", e);
+                }
 
                 // nav engine settings
                 let payload = &mut [0; 36];
@@ -608,49 +999,38 @@ impl UbloxGNSSProvider {
                 payload[2] = 0x07; // dyn = airborne with <2g acceleration
                 payload[3] = 0x02; // fixMode = 3D only
                 let packet = UBXPacket::new(0x06, 0x24, payload);
-                p.write(&packet).expect("could not configure update rate");
-
-                // determine if Galileo is supported
-                let galileo_supported;
-                let packet = UBXPacket::new(0x0A, 0x04, &[]);
-                p.write(&packet).expect("could not pull version");
-                loop {
-                    match p.next() {
-                        Ok(UBXPacket {
-                            class: 0x0A,
-                            id: 0x04,
-                            payload,
-                        }) => {
-                            info!(
-                                "ublox GPS detected, version string: {}",
-                                str::from_utf8(payload).unwrap()
-                            );
-                            // ROM BASE 2.01 (75331)FWVER=SPG 3.01PROTVER=18.00FIS=0xEF4015 (200030)
-                            // GPS;GLO;GAL;BDSSBAS;IMES;QZSS
-                            galileo_supported =
-                                str::from_utf8(&payload[40..]).unwrap().contains(";GAL;");
-
-                            break;
-                        }
-                        _ => {}
-                    }
+                if let Err(e) = p.write(&packet) {
+                    warn!("could not configure nav engine: {:?}", e);
                 }
 
-                let packet = UBXPacket::new(0x06, 0x3E, &[]);
-                p.write(&packet).expect("could not pull GNSS configuration");
-                loop {
-                    match p.next() {
-                        Ok(UBXPacket {
-                            class: 0x06,
-                            id: 0x3E,
-                            payload,
-                        }) => {
-                            info!("hardware tracking channels available: {}", payload[1]);
-                            break;
-                        }
-                        _ => {}
+                let version = match p.poll(0x0A, 0x04) {
+                    Ok(payload) => {
+                        info!(
+                            "ublox GPS detected, version string: {}",
+                            String::from_utf8_lossy(&payload)
+                        );
+                        Version::parse(&payload)
                     }
-                }
+                    Err(e) => {
+                        warn!("could not read ublox version, assuming defaults: {:?}", e);
+                        Version::default()
+                    }
+                };
+
+                // CFG-GNSS appeared with u-blox 7
+                let gnss_configurable = version.at_least(14.0)
+                    && match p.poll(0x06, 0x3E) {
+                        Ok(payload) => {
+                            if let Some(ch) = payload.get(1) {
+                                info!("hardware tracking channels available: {}", ch);
+                            }
+                            true
+                        }
+                        Err(e) => {
+                            info!("GNSS configuration is not available: {:?}", e);
+                            false
+                        }
+                    };
 
                 let payload = &mut [
                     // see p. 164
@@ -716,21 +1096,38 @@ impl UbloxGNSSProvider {
                     0x00, // Glonass = 8-14
                 ];
 
-                if galileo_supported {
+                if version.supports("GAL") {
                     payload[24] = 0x01;
                     info!("chip supports Galileo");
                 }
 
                 let packet = UBXPacket::new(0x06, 0x3E, payload);
-                p.write(&packet).expect("could not configure GNSS");
+                if gnss_configurable {
+                    if let Err(e) = p.write(&packet) {
+                        warn!("could not configure GNSS: {:?}", e);
+                    }
+                }
 
                 // SBAS cfg
-                // enabled = true, usage = all, maxSBAS = 3, search all PRNs
-                let payload = &[0x01, 0x07, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00];
+                // enabled = true, usage = all, maxSBAS = 3, search regional PRNs
+                let (scanmode2, scanmode1) = sbas_scanmode(region.sbas_prns());
+                let payload = &[
+                    0x01,
+                    0x07,
+                    0x03,
+                    scanmode2,
+                    scanmode1 as u8,
+                    (scanmode1 >> 8) as u8,
+                    (scanmode1 >> 16) as u8,
+                    (scanmode1 >> 24) as u8,
+                ];
                 let packet = UBXPacket::new(0x06, 0x16, payload);
-                p.write(&packet).expect("could not configure SBAS");
+                if let Err(e) = p.write(&packet) {
+                    warn!("could not configure SBAS: {:?}", e);
+                }
 
-                // next, enable message (per 1 solution)
+                // next, enable message (per 1 solution), everything else is
+                // derived from it
                 let payload = &[
                     0x01,
                     0x07, // NAV-PVT
@@ -742,26 +1139,62 @@ impl UbloxGNSSProvider {
                     0x00, // DDC, UART1, res, USB, I2C, res
                 ];
                 let packet = UBXPacket::new(0x06, 0x01, payload);
-                p.write(&packet).expect("could not enable PVT message");
+                if let Err(e) = p.write(&packet) {
+                    warn!(
+                        "could not enable PVT message, Ublox module is disabled: {:?}",
+                        e
+                    );
+                    return None;
+                }
 
-                // next, enable SAT (satellite status reporting per 10 solution)
-                let payload = &[
-                    0x01,
-                    0x35, // NAV-SAT
-                    0x00,
-                    0x0A,
-                    0x00,
-                    0x00,
-                    0x00,
-                    0x00, // DDC, UART1, res, USB, I2C, res
-                ];
-                let packet = UBXPacket::new(0x06, 0x01, payload);
-                p.write(&packet).expect("could not enable SAT message");
+                // next, enable the optional messages the protocol version
+                // has, SAT and DOP for satellite status, SIG only on
+                // multi-band receivers, TIMELS for leap second announcements
+                // and HW for antenna diagnostics
+                for &(class, id, rate, protocol, what) in &[
+                    (0x01, 0x35, 0x0A, 15.0, "satellite status"),         // NAV-SAT
+                    (0x01, 0x04, 0x0A, 0.0, "dilution of precision"),     // NAV-DOP
+                    (0x01, 0x43, 0x0A, 27.0, "per signal status"),        // NAV-SIG
+                    (0x01, 0x26, 0x64, 0.0, "leap second announcements"), // NAV-TIMELS
+                    (0x0A, 0x09, 0x0A, 15.0, "RF front end status"),      // MON-HW
+                ] {
+                    if !version.at_least(protocol) {
+                        info!("{} is not available before protocol {:.2}", what, protocol);
+                        continue;
+                    }
+
+                    // DDC, UART1, res, USB, I2C, res
+                    let payload = &[class, id, 0x00, rate, 0x00, 0x00, 0x00, 0x00];
+                    let packet = UBXPacket::new(0x06, 0x01, payload);
+                    if let Err(e) = p.write(&packet) {
+                        info!("{} is not available: {:?}", what, e);
+                    }
+                }
+
+                // MGA-INI-POS_LLH appeared with u-blox M8
+                if let Some((lat_lon, height)) = position {
+                    if version.at_least(15.0) {
+                        let payload = position_aiding(lat_lon, height);
+                        let packet = UBXPacket::new(0x13, 0x40, &payload);
+                        match p.write(&packet) {
+                            Ok(_) => info!("warm starting from last known position"),
+                            Err(e) => info!("could not hand over last known position: {:?}", e),
+                        }
+                    }
+                }
+
+                let raw_log = raw_log.and_then(|dir| enable_raw_measurements(&mut p, dir));
 
                 // make non-blocking
                 p.serial.set_timeout(Duration::from_secs(0)).unwrap();
 
-                return Some(Box::new(UbloxGNSSProvider { comm: p }));
+                return Some(Box::new(UbloxGNSSProvider {
+                    comm: p,
+                    raw_log,
+                    corrections: rtcm.map(super::rtcm::start),
+                    low_rate,
+                    at_low_rate: false,
+                }));
             }
         }
 
@@ -771,6 +1204,67 @@ impl UbloxGNSSProvider {
     }
 }
 
+/// CFG-RATE payload, a solution every `ms` with GPS time as reference
+fn cfg_rate(ms: u16) -> [u8; 6] {
+    [ms as u8, (ms >> 8) as u8, 0x01, 0x00, 0x01, 0x00]
+}
+
+/// Enable RXM-RAWX (per 10 solution, to stay within the port bandwidth) and
+/// RXM-SFRBX and open the file in `dir` they are logged into. Returns `None`
+/// if the firmware does not support raw measurements or the file could not be
+/// created.
+fn enable_raw_measurements(p: &mut UBXCommunicator, dir: &str) -> Option<BufWriter<File>> {
+    let (path, file) = match create_numbered(Path::new(dir), "raw", "ubx") {
+        Ok(f) => f,
+        Err(e) => {
+            error!("unable to create raw measurement log in {}: {}", dir, e);
+            return None;
+        }
+    };
+
+    for &(id, rate, name) in &[(0x15, 0x0A, "RXM-RAWX"), (0x13, 0x01, "RXM-SFRBX")] {
+        // DDC, UART1, res, USB, I2C, res
+        let payload = &[0x02, id, 0x00, rate, 0x00, 0x00, 0x00, 0x00];
+        let packet = UBXPacket::new(0x06, 0x01, payload);
+
+        match p.write(&packet) {
+            Ok(_) => {}
+            Err(Error::NAK) => {
+                warn!(
+                    "{} is not supported by this firmware, raw measurements are not logged",
+                    name
+                );
+                return None;
+            }
+            Err(e) => {
+                error!("could not enable {} message: {:?}", name, e);
+                return None;
+            }
+        }
+    }
+
+    info!("logging raw measurements to {}", path.display());
+
+    Some(BufWriter::new(file))
+}
+
+/// MGA-INI-POS_LLH payload for the receiver to start from `lat_lon` and
+/// `height` above ellipsoid in mm
+fn position_aiding(lat_lon: (f32, f32), height: i32) -> Vec<u8> {
+    let mut payload = vec![0x01, 0x00, 0x00, 0x00]; // type, version, reserved
+
+    for &v in &[
+        (f64::from(lat_lon.0) * 1e7).round() as i32 as u32,
+        (f64::from(lat_lon.1) * 1e7).round() as i32 as u32,
+        (height / 10) as u32,
+        POSITION_AIDING_ACCURACY,
+    ] {
+        payload.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+    }
+
+    payload
+}
+
 /// Given a slice containing the correct range for calculating the checksum,
 /// calculate and returns it.
 fn make_ubx_checksum(buf: &[u8]) -> (u8, u8) {
@@ -816,6 +1310,150 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_malformed_nav() {
+        let mut payload = [
+            148, 99, 86, 7, 225, 7, 5, 22, 10, 11, 24, 55, 60, 3, 0, 0, 88, 166, 244, 5, 3, 0, 6,
+            6, 28, 27, 253, 182, 131, 185, 113, 22, 117, 202, 255, 255, 175, 63, 0, 0, 45, 71, 1,
+            0, 91, 36, 7, 0, 150, 253, 255, 255, 47, 1, 0, 0, 117, 0, 0, 0, 176, 2, 0, 0, 0, 0, 0,
+            0, 79, 15, 0, 0, 128, 168, 18, 1, 105, 3, 0, 0, 248, 74, 35, 0, 0, 0, 0, 0, 0, 0, 246,
+            255,
+        ];
+
+        // flagged valid, but month 13 and hour 24
+        for &(i, v) in &[(6, 13), (8, 24)] {
+            let mut payload = payload;
+            payload[i] = v;
+            match parse_ubx_nav_pvt(&payload) {
+                IResult::Done(_, GNSSData::TimeFix { time, fix }) => {
+                    assert_eq!(time, None);
+                    assert!(fix.is_some());
+                }
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+
+        // velD can not be negated
+        payload[56..60].clone_from_slice(&[0x00, 0x00, 0x00, 0x80]);
+        match parse_ubx_nav_pvt(&payload) {
+            IResult::Done(_, GNSSData::TimeFix { fix: Some(f), .. }) => {
+                assert_eq!(f.vs.0, i32::max_value())
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        assert!(parsed("NAV-PVT", parse_ubx_nav_pvt(&payload)).is_some());
+        assert!(parsed("NAV-PVT", parse_ubx_nav_pvt(&payload[..50])).is_none());
+
+        // newer firmware appending fields
+        let mut longer = payload.to_vec();
+        longer.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            parsed("NAV-PVT", parse_ubx_nav_pvt(&longer)),
+            parsed("NAV-PVT", parse_ubx_nav_pvt(&payload))
+        );
+
+        // protocol 13 and earlier, no magnetic declination even if validMag
+        payload[11] |= 0x08;
+        match parse_ubx_nav_pvt(&payload[..84]) {
+            IResult::Done(rem, GNSSData::TimeFix { fix: Some(f), .. }) => {
+                assert!(rem.is_empty());
+                assert_eq!(f.mag_dec, None);
+                assert_eq!(f.num_sv, 6);
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+        // version 2
+        assert!(parsed("NAV-SAT", parse_ubx_nav_sat(&[0, 0, 0, 0, 2, 0, 0, 0])).is_none());
+    }
+
+    #[test]
+    fn test_sbas_scanmode() {
+        assert_eq!(sbas_scanmode(Region::US.sbas_prns()), (0, 0x0004_A800));
+        assert_eq!(sbas_scanmode(Region::Australia.sbas_prns()), (0, 0x0000_0004));
+        assert_eq!(sbas_scanmode(&[152, 158]), (0x41, 0));
+    }
+
+    #[test]
+    fn test_mon_hw() {
+        let mut payload = [0_u8; 60];
+        payload[16] = 0x52; // noisePerMS = 82
+        payload[18] = 0xFF;
+        payload[19] = 0x0F; // agcCnt = 4095
+        payload[45] = 12; // jamInd
+
+        match parse_ubx_mon_hw(&payload) {
+            IResult::Done(
+                rem,
+                GNSSData::RFStatus {
+                    noise,
+                    agc,
+                    jamming,
+                },
+            ) => {
+                assert!(rem.is_empty());
+                assert_eq!(noise, 82);
+                assert!((agc - 50.0).abs() < 0.01);
+                assert_eq!(jamming, 12);
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        assert!(parsed("MON-HW", parse_ubx_mon_hw(&payload[..40])).is_none());
+    }
+
+    #[test]
+    fn test_position_aiding() {
+        assert_eq!(
+            position_aiding((37.5, -122.25), -30_005),
+            vec![
+                0x01, 0x00, 0x00, 0x00, // type, version, reserved
+                0xC0, 0x0B, 0x5A, 0x16, // lat = 375000000
+                0x60, 0x21, 0x22, 0xB7, // lon = -1222500000
+                0x48, 0xF4, 0xFF, 0xFF, // alt = -3000 cm, rounded towards zero
+                0x80, 0x96, 0x98, 0x00, // posAcc = 100 km
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version() {
+        fn mon_ver(ext: &[&str]) -> Vec<u8> {
+            let mut payload = vec![0; 40];
+            payload[..13].copy_from_slice(b"ROM CORE 3.01");
+            for e in ext {
+                let mut e = e.as_bytes().to_vec();
+                e.resize(30, 0);
+                payload.extend(e);
+            }
+            payload
+        }
+
+        let v = Version::parse(&mon_ver(&[
+            "FWVER=SPG 3.01",
+            "PROTVER=18.00",
+            "GPS;GLO;GAL;BDS",
+            "SBAS;IMES;QZSS",
+        ]));
+        assert_eq!(v.protocol, Some(18.0));
+        assert!(v.supports("GAL"));
+        assert!(v.supports("QZSS"));
+        assert!(!v.at_least(27.0));
+        assert!(v.at_least(15.0));
+
+        // u-blox 7 separates with a space and lists no GNSS
+        let v = Version::parse(&mon_ver(&["PROTVER 14.00", "GPS;SBAS;GLO;QZSS"]));
+        assert_eq!(v.protocol, Some(14.0));
+        assert!(!v.supports("GAL"));
+        assert!(!v.at_least(15.0));
+
+        // nothing known, everything gets tried
+        let v = Version::parse(&[0; 40]);
+        assert_eq!(v, Version::default());
+        assert!(v.at_least(27.0));
+        assert_eq!(Version::parse(&[]), Version::default());
+    }
+
     #[test]
     fn test_ubx_parser() {
         let msg = [0xB5, 0x62, 0x0A, 0x04, 0x00, 0x00, 0x0E, 0x34, 0x00];
@@ -915,6 +1553,7 @@ mod tests {
                         height_msl: (16303, Some(468059)),
                         height_ellipsoid: (-13707, Some(468059)),
                         gs: (688, Some(3919)),
+                        vs: (-117, Some(3919)),
                         true_course: (0_f32, Some(180_f32)),
                         quality: FixQuality::ThreeDim,
                         num_sv: 6,
@@ -943,6 +1582,7 @@ mod tests {
                         height_msl: (16303, Some(468059)),
                         height_ellipsoid: (-13707, Some(468059)),
                         gs: (688, Some(3919)),
+                        vs: (-117, Some(3919)),
                         true_course: (0_f32, Some(180_f32)),
                         quality: FixQuality::SBAS,
                         num_sv: 6,
@@ -952,6 +1592,23 @@ mod tests {
             )
         );
 
+        // same as above, but with RTK fixed carrier phase solution
+        let mut payload = payload;
+        payload[21] = 0x83;
+        match parse_ubx_nav_pvt(&payload) {
+            IResult::Done(_, GNSSData::TimeFix { fix: Some(f), .. }) => {
+                assert_eq!(f.quality, FixQuality::RTKFixed)
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+        payload[21] = 0x43;
+        match parse_ubx_nav_pvt(&payload) {
+            IResult::Done(_, GNSSData::TimeFix { fix: Some(f), .. }) => {
+                assert_eq!(f.quality, FixQuality::RTKFloat)
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
         let payload = [
             36, 209, 62, 8, 1, 3, 0, 0, 0, 2, 0, 0, 70, 1, 0, 0, 17, 18, 0, 0, 6, 14, 0, 8, 219, 0,
             0, 0, 17, 18, 0, 0, 6, 88, 12, 33, 20, 0, 0, 0, 44, 0, 1, 0,
@@ -971,6 +1628,7 @@ mod tests {
                         acquired: false,
                         in_solution: false,
                         sbas_in_use: Some(false),
+                        signals: vec![],
                     },
                     SVStatus {
                         system: Constellation::GLONASS,
@@ -982,6 +1640,7 @@ mod tests {
                         acquired: false,
                         in_solution: false,
                         sbas_in_use: Some(false),
+                        signals: vec![],
                     },
                     SVStatus {
                         system: Constellation::GLONASS,
@@ -993,9 +1652,72 @@ mod tests {
                         acquired: true,
                         in_solution: true,
                         sbas_in_use: Some(true),
+                        signals: vec![],
                     },
                 ])
             )
         );
+
+        let payload = [
+            36, 209, 62, 8, 200, 0, 156, 0, 100, 0, 120, 0, 99, 0, 70, 0, 75, 0,
+        ];
+        assert_eq!(
+            parse_ubx_nav_dop(&payload),
+            IResult::Done(
+                &[][..],
+                GNSSData::DOP(DOP {
+                    pdop: 156_f32 * 0.01,
+                    hdop: 99_f32 * 0.01,
+                    vdop: 120_f32 * 0.01,
+                })
+            )
+        );
+
+        // Galileo E1C used in solution and E5aI tracked only
+        let payload = [
+            36, 209, 62, 8, 0, 2, 0, 0, 2, 12, 0, 0, 0, 0, 40, 7, 0, 0, 0x29, 0, 0, 0, 0, 0, 2,
+            12, 3, 0, 0, 0, 35, 4, 0, 0, 0x01, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            parse_ubx_nav_sig(&payload),
+            IResult::Done(
+                &[][..],
+                GNSSData::SignalInfo(vec![
+                    (
+                        Constellation::Galileo,
+                        12,
+                        Signal {
+                            band: Band::E1,
+                            snr: 40,
+                            in_solution: true,
+                        },
+                    ),
+                    (
+                        Constellation::Galileo,
+                        12,
+                        Signal {
+                            band: Band::E5a,
+                            snr: 35,
+                            in_solution: false,
+                        },
+                    ),
+                ])
+            )
+        );
+
+        // leap second announced in one hour
+        let payload = [
+            36, 209, 62, 8, 0, 0, 0, 0, 2, 18, 2, 1, 0x10, 0x0E, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x03,
+        ];
+        assert_eq!(
+            parse_ubx_nav_timels(&payload),
+            IResult::Done(
+                &[][..],
+                Some(GNSSData::LeapSecond {
+                    change: 1,
+                    in_secs: 3600,
+                })
+            )
+        );
     }
 }
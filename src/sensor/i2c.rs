@@ -0,0 +1,159 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retry policy of the I2C sensors. Cheap wiring in aircraft vibrates loose
+//! regularly, so a failed transfer is retried right away a few times. When
+//! that does not help, the sensor is reported degraded, its device is reopened
+//! and accessed again with exponential backoff until it answers.
+
+use pitot::handle::Pushable;
+use sensor::SensorData;
+use std::cmp;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+// transfers tried in a row before giving up for this iteration
+const ATTEMPTS: u32 = 3;
+// backoff after the first failed iteration, doubled after each one after
+const MIN_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 10_000;
+
+/// Run `f` until it succeeds, at most `ATTEMPTS` times
+pub fn attempt<T, E, F>(mut f: F) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+{
+    let mut res = f();
+
+    for _ in 1..ATTEMPTS {
+        if res.is_ok() {
+            break;
+        }
+        res = f();
+    }
+
+    res
+}
+
+pub struct Retry {
+    sensor_id: &'static str,
+    /// Iterations in a row the sensor could not be read
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Retry {
+    pub fn new(sensor_id: &'static str) -> Self {
+        Retry {
+            sensor_id,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// Whether the sensor should be accessed at `clock`, `false` while
+    /// backing off
+    pub fn ready(&self, clock: Instant) -> bool {
+        self.retry_at.map_or(true, |i| clock >= i)
+    }
+
+    pub fn succeeded(&mut self, h: &mut Pushable<SensorData>) {
+        if self.failures > 0 {
+            info!(
+                "{} recovered after {} failed reads",
+                self.sensor_id, self.failures
+            );
+            h.push_data(SensorData::Degraded(self.sensor_id, false));
+        }
+
+        self.failures = 0;
+        self.retry_at = None;
+    }
+
+    /// Record a failed iteration, the caller should reopen the device before
+    /// accessing it again
+    pub fn failed(&mut self, h: &mut Pushable<SensorData>, e: &Display) {
+        if self.failures == 0 {
+            warn!("{} read error: {}, sensor degraded", self.sensor_id, e);
+            h.push_data(SensorData::Degraded(self.sensor_id, true));
+        } else {
+            debug!("{} read error: {}", self.sensor_id, e);
+        }
+
+        self.failures = self.failures.saturating_add(1);
+
+        let backoff = cmp::min(
+            MIN_BACKOFF_MS << cmp::min(self.failures - 1, 16),
+            MAX_BACKOFF_MS,
+        );
+        self.retry_at = Some(h.get_clock() + Duration::from_millis(backoff));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pitot::handle::Handle;
+    use testing::MockHandle;
+
+    #[test]
+    fn test_attempt() {
+        let mut n = 0;
+        assert_eq!(
+            attempt(|| {
+                n += 1;
+                if n < ATTEMPTS {
+                    Err(n)
+                } else {
+                    Ok(n)
+                }
+            }),
+            Ok(ATTEMPTS)
+        );
+
+        n = 0;
+        assert_eq!(
+            attempt(|| {
+                n += 1;
+                Err::<(), u32>(n)
+            }),
+            Err(ATTEMPTS)
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut r = Retry::new("test");
+        let mut h = MockHandle::new(10);
+        let start = h.get_clock();
+
+        r.failed(&mut h, &"NACK");
+        assert_eq!(h.take(), vec![SensorData::Degraded("test", true)]);
+        assert!(!r.ready(start));
+        assert!(r.ready(start + Duration::from_millis(MIN_BACKOFF_MS)));
+
+        for _ in 0..20 {
+            r.failed(&mut h, &"NACK");
+        }
+        assert!(h.take().is_empty());
+        assert!(!r.ready(start + Duration::from_millis(MAX_BACKOFF_MS - 1)));
+        assert!(r.ready(start + Duration::from_millis(MAX_BACKOFF_MS)));
+
+        r.succeeded(&mut h);
+        assert_eq!(h.take(), vec![SensorData::Degraded("test", false)]);
+        assert!(r.ready(start));
+    }
+}
@@ -14,13 +14,25 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod airspeed;
 pub mod barometer;
+pub mod command;
+pub mod config;
 pub mod gnss;
+pub mod i2c;
+#[cfg(feature = "gdl90")]
+pub mod pcap;
+pub mod peer;
+pub mod power;
 pub mod sdr;
 
-use self::sdr::TrafficData;
+use self::airspeed::AirspeedData;
+use self::barometer::BaroData;
+use self::command::Command;
+use self::sdr::{DecodeStats, TrafficData};
 
 use self::gnss::GNSSData;
+use config::Config;
 use pitot::handle::Pushable;
 use processor::fisb::FISBData;
 
@@ -30,11 +42,31 @@ pub enum SensorData {
     GNSS(GNSSData),
     Traffic(TrafficData),
     FISB(FISBData),
-    Baro(i32),
+    /// Decode quality of a receiver
+    DecodeStats(DecodeStats),
+    Baro(BaroData),
+    Airspeed(AirspeedData),
+    /// True heading in degrees, from an AHRS or magnetometer
+    Heading(f32),
+    Command(Command),
+    /// Whether the supply voltage is currently below the safe threshold
+    Undervoltage(bool),
+    /// Whether the sensor with this ID is failing to be read, e.g. because
+    /// of I2C bus errors
+    Degraded(&'static str, bool),
+    /// Config file was changed
+    Config(Config),
 }
 
 /// A type for representing a sensor
 pub trait Sensor {
     /// Run the provider, may or may not yield any result
     fn run(&mut self, h: &mut Pushable<SensorData>);
+
+    /// Called when this sensor is paused at runtime, it is not run again until
+    /// resumed
+    fn pause(&mut self) {}
+
+    /// Called when this sensor is resumed after having been paused
+    fn resume(&mut self) {}
 }
@@ -0,0 +1,305 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Feeds traffic and uplink frames found in a pcap capture of GDL 90 UDP
+//! traffic from another receiver into the pipeline, at the pace they were
+//! captured, so its output can be compared against what Pitot sends.
+//!
+//! Only classic pcap files (not pcapng) with IPv4 over Ethernet, Linux
+//! cooked, BSD loopback or raw IP link types are supported.
+
+use super::*;
+use processor::fisb::FISBData;
+use protocol::gdl90::{parse_stream, parse_traffic};
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+// message ID and time of reception precede the uplink payload
+const UPLINK_HEADER_LEN: usize = 4;
+const UPLINK_PAYLOAD_LEN: usize = 432;
+
+pub struct PcapProvider {
+    /// UDP payloads along with when they were captured, relative to the
+    /// first packet
+    packets: Vec<(Duration, Vec<u8>)>,
+    next: usize,
+    start: Option<Instant>,
+}
+
+impl PcapProvider {
+    pub fn new(path: &str) -> Option<Box<Sensor>> {
+        let mut capture = Vec::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut capture)) {
+            error!("unable to read {}: {}", path, e);
+            return None;
+        }
+
+        match udp_payloads(&capture) {
+            Ok(packets) => {
+                info!("replaying {} UDP packets from {}", packets.len(), path);
+
+                Some(Box::new(Self {
+                    packets,
+                    next: 0,
+                    start: None,
+                }))
+            }
+            Err(e) => {
+                error!("unable to parse {}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+impl Sensor for PcapProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        let clock = h.get_clock();
+        let elapsed = clock - *self.start.get_or_insert(clock);
+
+        while let Some(&(at, ref payload)) = self.packets.get(self.next) {
+            if at > elapsed {
+                break;
+            }
+
+            self.next += 1;
+
+            for msg in parse_stream(payload).into_iter().filter_map(|m| m.ok()) {
+                match msg[0] {
+                    0x07 if msg.len() >= UPLINK_HEADER_LEN + UPLINK_PAYLOAD_LEN => {
+                        h.push_data(SensorData::FISB(FISBData {
+                            payload: msg[UPLINK_HEADER_LEN..UPLINK_HEADER_LEN + UPLINK_PAYLOAD_LEN]
+                                .to_vec(),
+                            received: Some(Instant::now()),
+                        }))
+                    }
+                    0x14 => {
+                        if let Some(t) = parse_traffic(&msg) {
+                            h.push_data(SensorData::Traffic(TrafficData {
+                                received: Some(Instant::now()),
+                                trace: None,
+                                ..t
+                            }));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if self.next == self.packets.len() {
+                info!("end of capture reached");
+            }
+        }
+    }
+}
+
+fn read_u16(b: &[u8]) -> u16 {
+    (b[0] as u16) << 8 | b[1] as u16
+}
+
+/// Payloads of all IPv4 UDP packets in a classic pcap file
+fn udp_payloads(capture: &[u8]) -> Result<Vec<(Duration, Vec<u8>)>, String> {
+    if capture.len() < 24 {
+        return Err("file too short".to_string());
+    }
+
+    let raw =
+        |b: &[u8]| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32;
+    let (swap, nanos) = match raw(&capture[..4]) {
+        0xA1B2C3D4 => (false, false),
+        0xD4C3B2A1 => (true, false),
+        0xA1B23C4D => (false, true),
+        0x4D3CB2A1 => (true, true),
+        _ => return Err("not a pcap file".to_string()),
+    };
+    let read_u32 = |b: &[u8]| if swap { raw(b).swap_bytes() } else { raw(b) };
+
+    let linktype = read_u32(&capture[20..24]);
+    let mut packets = Vec::new();
+    let mut first = None;
+    let mut pos = 24;
+
+    while pos + 16 <= capture.len() {
+        let secs = read_u32(&capture[pos..]) as u64;
+        let frac = read_u32(&capture[pos + 4..]);
+        let len = read_u32(&capture[pos + 8..]) as usize;
+        pos += 16;
+
+        if pos + len > capture.len() {
+            warn!("last packet of the capture is truncated");
+            break;
+        }
+
+        let frame = &capture[pos..pos + len];
+        pos += len;
+
+        if frac >= if nanos { 1_000_000_000 } else { 1_000_000 } {
+            warn!("skipping packet with invalid timestamp fraction {}", frac);
+            continue;
+        }
+
+        let at = Duration::new(secs, if nanos { frac } else { frac * 1000 });
+        let first = *first.get_or_insert(at);
+
+        let ip = match linktype {
+            LINKTYPE_NULL if len >= 4 => &frame[4..],
+            LINKTYPE_ETHERNET if len >= 14 => match read_u16(&frame[12..]) {
+                ETHERTYPE_IPV4 => &frame[14..],
+                ETHERTYPE_VLAN if len >= 18 && read_u16(&frame[16..]) == ETHERTYPE_IPV4 => {
+                    &frame[18..]
+                }
+                _ => continue,
+            },
+            LINKTYPE_RAW => frame,
+            LINKTYPE_LINUX_SLL if len >= 16 && read_u16(&frame[14..]) == ETHERTYPE_IPV4 => {
+                &frame[16..]
+            }
+            LINKTYPE_NULL | LINKTYPE_ETHERNET | LINKTYPE_RAW | LINKTYPE_LINUX_SLL => continue,
+            t => return Err(format!("unsupported link type {}", t)),
+        };
+
+        if ip.len() < 20 || ip[0] >> 4 != 4 || ip[9] != IPPROTO_UDP {
+            continue;
+        }
+
+        let header_len = (ip[0] & 0x0F) as usize * 4;
+        let total_len = (read_u16(&ip[2..]) as usize).min(ip.len());
+        if header_len + 8 > total_len {
+            continue;
+        }
+
+        packets.push((
+            if at > first {
+                at - first
+            } else {
+                Duration::new(0, 0)
+            },
+            ip[header_len + 8..total_len].to_vec(),
+        ));
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::gdl90::GDL90;
+    use testing::MockHandle;
+
+    /// A capture of two Ethernet frames with a GDL 90 traffic report and a
+    /// heartbeat sent over UDP one second apart
+    fn capture() -> Vec<u8> {
+        let traffic = [
+            0x7E, 0x14, 0x00, 0xA1, 0xB2, 0xC3, 0x1A, 0xD8, 0x3F, 0xA8, 0xDE, 0xAF, 0x21, 0x79,
+            0x79, 0x04, 0x2F, 0xF0, 0x57, 0x03, 'e' as u8, 'a' as u8, 'T' as u8, 'E' as u8,
+            'S' as u8, 'T' as u8, '1' as u8, '2' as u8, 0x00, 0xEA, 0xC4, 0x7E,
+        ];
+        let heartbeat =
+            GDL90::prepare_payload(&mut [0x00, 0x81, 0x41, 0xDB, 0xD0, 0x08, 0x02, 0, 0]);
+
+        let mut pcap = vec![
+            0xD4, 0xC3, 0xB2, 0xA1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0, 0, 1, 0, 0,
+            0,
+        ];
+
+        for (i, payload) in [&traffic[..], &heartbeat[..]].iter().enumerate() {
+            let udp_len = 8 + payload.len();
+            let ip_len = 20 + udp_len;
+            let len = (14 + ip_len) as u8;
+
+            pcap.extend_from_slice(&[
+                100 + i as u8,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                len,
+                0,
+                0,
+                0,
+                len,
+                0,
+                0,
+                0,
+            ]);
+            // Ethernet
+            pcap.extend_from_slice(&[0; 12]);
+            pcap.extend_from_slice(&[0x08, 0x00]);
+            // IPv4
+            pcap.extend_from_slice(&[0x45, 0, 0, ip_len as u8, 0, 0, 0, 0, 64, IPPROTO_UDP, 0, 0]);
+            pcap.extend_from_slice(&[192, 168, 10, 1, 192, 168, 10, 255]);
+            // UDP
+            pcap.extend_from_slice(&[0x0F, 0xA0, 0x0F, 0xA0, 0, udp_len as u8, 0, 0]);
+            pcap.extend_from_slice(payload);
+        }
+
+        pcap
+    }
+
+    #[test]
+    fn test_udp_payloads() {
+        let packets = udp_payloads(&capture()).unwrap();
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].0, Duration::new(0, 0));
+        assert_eq!(packets[0].1.len(), 32);
+        assert_eq!(packets[1].0, Duration::new(1, 0));
+        assert_eq!(packets[1].1[1], 0x00);
+
+        assert!(udp_payloads(b"not a capture").is_err());
+
+        // corrupt microseconds of the first packet
+        let mut corrupt = capture();
+        corrupt[28..32].copy_from_slice(&[0xFF; 4]);
+        let packets = udp_payloads(&corrupt).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].1[1], 0x00);
+    }
+
+    #[test]
+    fn test_pcap_provider() {
+        let mut p = PcapProvider {
+            packets: udp_payloads(&capture()).unwrap(),
+            next: 0,
+            start: None,
+        };
+        let mut h = MockHandle::new(10);
+
+        p.run(&mut h);
+        match h.take().pop() {
+            Some(SensorData::Traffic(t)) => assert_eq!(t.addr.0, 0xA1B2C3),
+            d => panic!("unexpected {:?}", d),
+        }
+
+        // the heartbeat is not passed on
+        h.advance_secs(1);
+        p.run(&mut h);
+        assert!(h.take().is_empty());
+        assert_eq!(p.next, 2);
+    }
+}
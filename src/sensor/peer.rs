@@ -0,0 +1,404 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pairing of two units over the LAN, e.g. one per band or one per antenna.
+//! The forwarder sends what its receivers decoded to the aggregator, which
+//! feeds it into its own pipeline as if it was received locally. The traffic
+//! processor drops the updates both units received.
+//!
+//! A connection starts with the 8 byte magic `PITOTNET` followed by a one
+//! byte protocol version, then carries one frame per item:
+//!
+//! | bytes | content                                                   |
+//! | ----- | --------------------------------------------------------- |
+//! | 1     | item type, see `TRAFFIC` and below                        |
+//! | 2     | payload length, LE                                        |
+//! | n     | payload, JSON for traffic and statistics, raw FIS-B frames |
+//!
+//! Times of reception do not mean anything across units, so the latency of
+//! forwarded items is not measured. Items are dropped on either side rather
+//! than queued without bound while the other end falls behind.
+//!
+//! Forwarders are not authenticated, anything that can reach the aggregator
+//! can inject traffic unless it only accepts the address of its forwarder.
+
+use super::*;
+use serde_json;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+const MAGIC: &[u8; 8] = b"PITOTNET";
+const VERSION: u8 = 1;
+const TRAFFIC: u8 = 0;
+const UPLINK: u8 = 1;
+// 2 was raw UAT downlink frames, which now travel along with their traffic
+const DECODE_STATS: u8 = 3;
+const RECONNECT_SECS: u64 = 10;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+/// Items waiting to be sent or fed into the pipeline, dropped beyond
+const QUEUE_LEN: usize = 1024;
+
+/// Frame forwarding `e`, `None` for data that is not forwarded
+pub fn encode(e: &SensorData) -> Option<Vec<u8>> {
+    let (kind, payload) = match *e {
+        SensorData::Traffic(ref t) => (TRAFFIC, serde_json::to_vec(t).unwrap()),
+        SensorData::FISB(ref f) => (UPLINK, f.payload.clone()),
+        SensorData::DecodeStats(ref s) => (DECODE_STATS, serde_json::to_vec(s).unwrap()),
+        _ => return None,
+    };
+
+    let mut frame = Vec::with_capacity(3 + payload.len());
+    frame.extend_from_slice(&[kind, payload.len() as u8, (payload.len() >> 8) as u8]);
+    frame.extend_from_slice(&payload);
+
+    Some(frame)
+}
+
+/// Read the next frame from `r`, `Ok(None)` for frames of types this
+/// version does not know
+pub fn decode<R: Read>(r: &mut R) -> io::Result<Option<SensorData>> {
+    let mut header = [0_u8; 3];
+    try!(r.read_exact(&mut header));
+
+    let mut payload = vec![0_u8; header[1] as usize | (header[2] as usize) << 8];
+    try!(r.read_exact(&mut payload));
+
+    let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    Ok(Some(match header[0] {
+        TRAFFIC => SensorData::Traffic(try!(serde_json::from_slice(&payload).map_err(invalid))),
+        UPLINK => SensorData::FISB(FISBData {
+            payload,
+            received: None,
+        }),
+        DECODE_STATS => {
+            let mut stats: DecodeStats = try!(serde_json::from_slice(&payload).map_err(invalid));
+            stats.peer = true;
+            SensorData::DecodeStats(stats)
+        }
+        _ => return Ok(None),
+    }))
+}
+
+/// Start forwarding the frames sent to the returned sender to the
+/// aggregator at `host`. Frames are dropped while it can not be reached,
+/// senders should not wait for room either. The thread exits once the
+/// sender is dropped.
+pub fn forward(host: &str, port: u16) -> SyncSender<Vec<u8>> {
+    let (tx, rx) = sync_channel(QUEUE_LEN);
+    let host = host.to_string();
+
+    spawn(move || loop {
+        match send_all(&host, port, &rx) {
+            Ok(_) => return,
+            Err(e) => warn!(
+                "aggregator {}:{} lost: {}, retrying in {} seconds",
+                host, port, e, RECONNECT_SECS
+            ),
+        }
+
+        sleep(Duration::from_secs(RECONNECT_SECS));
+    });
+
+    tx
+}
+
+/// Send every frame received on `rx` to the aggregator. Returns `Ok` once
+/// the sending end is gone.
+fn send_all(host: &str, port: u16, rx: &Receiver<Vec<u8>>) -> io::Result<()> {
+    let addr = match try!((host, port).to_socket_addrs()).next() {
+        Some(a) => a,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not resolve", host),
+            ))
+        }
+    };
+
+    let mut stream = try!(TcpStream::connect_timeout(
+        &addr,
+        Duration::from_secs(CONNECT_TIMEOUT_SECS)
+    ));
+    try!(stream.set_nodelay(true));
+    try!(stream.write_all(MAGIC));
+    try!(stream.write_all(&[VERSION]));
+
+    info!("forwarding to aggregator {}", addr);
+
+    // queued while disconnected, too old to be of use
+    rx.try_iter().count();
+
+    for frame in rx.iter() {
+        try!(stream.write_all(&frame));
+    }
+
+    Ok(())
+}
+
+pub struct PeerProvider {
+    rx: Receiver<SensorData>,
+}
+
+impl PeerProvider {
+    /// Accept forwarders on `port`, only from `forwarder` if set
+    pub fn new(port: u16, forwarder: Option<Ipv4Addr>) -> Option<Box<Sensor>> {
+        let listener = match TcpListener::bind(("0.0.0.0", port)) {
+            Ok(l) => l,
+            Err(e) => {
+                error!("unable to listen for forwarders on port {}: {}", port, e);
+                return None;
+            }
+        };
+
+        info!("accepting forwarders on port {}", port);
+
+        let (tx, rx) = sync_channel(QUEUE_LEN);
+
+        spawn(move || {
+            for s in listener.incoming() {
+                match s {
+                    Ok(s) => {
+                        if let Some(ip) = forwarder {
+                            match s.peer_addr() {
+                                Ok(a) if a.ip() == IpAddr::V4(ip) => {}
+                                Ok(a) => {
+                                    warn!("refusing forwarder {}, only {} is accepted", a, ip);
+                                    continue;
+                                }
+                                Err(e) => {
+                                    warn!("unable to accept forwarder: {}", e);
+                                    continue;
+                                }
+                            }
+                        }
+
+                        let tx = tx.clone();
+                        spawn(move || receive(s, &tx));
+                    }
+                    Err(e) => warn!("unable to accept forwarder: {}", e),
+                }
+            }
+        });
+
+        Some(Box::new(PeerProvider { rx }))
+    }
+}
+
+/// Feed the frames a forwarder sends into `tx` until it disconnects
+fn receive(mut s: TcpStream, tx: &SyncSender<SensorData>) {
+    let peer = match s.peer_addr() {
+        Ok(a) => a.to_string(),
+        Err(_) => "forwarder".to_string(),
+    };
+
+    let mut header = [0_u8; 9];
+    if s.read_exact(&mut header).is_err() || &header[..8] != MAGIC || header[8] != VERSION {
+        warn!("{} is not a compatible forwarder, disconnecting", peer);
+        return;
+    }
+
+    info!("forwarder {} connected", peer);
+
+    loop {
+        match decode(&mut s) {
+            Ok(Some(e)) => match tx.try_send(e) {
+                Ok(_) => {}
+                Err(TrySendError::Full(_)) => debug!("pipeline behind, dropping item of {}", peer),
+                Err(TrySendError::Disconnected(_)) => return,
+            },
+            Ok(None) => {}
+            Err(e) => {
+                info!("forwarder {} disconnected: {}", peer, e);
+                return;
+            }
+        }
+    }
+}
+
+impl Sensor for PeerProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        for e in self.rx.try_iter() {
+            h.push_data(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, Peer};
+    use pitot::situation::Situation;
+    use processor::forwarder::Forwarder;
+    use processor::receiver::Receiver;
+    use processor::traffic::{AddressType, AltitudeType, Traffic, TrafficSource};
+    use processor::Report;
+    use std::io::Cursor;
+    use std::time::Instant;
+    use testing::{run_processor, MockHandle};
+
+    fn traffic_data() -> TrafficData {
+        TrafficData {
+            addr: (0xA1B2C3, AddressType::ADSBICAO),
+            altitude: Some((3500, AltitudeType::Baro)),
+            gnss_delta: None,
+            heading: None,
+            speed: None,
+            vs: Some(-500),
+            squawk: Some(1200),
+            callsign: Some("N12345".to_string()),
+            category: None,
+            lat_lon: Some((37.5, -122.25)),
+            nic: Some(8),
+            nacp: None,
+            nacv: None,
+            sil: None,
+            ident: None,
+            capability: None,
+            on_ground: Some(false),
+            source: TrafficSource::ES,
+            received: Some(Instant::now()),
+            trace: None,
+            raw: None,
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut traffic = traffic_data();
+        traffic.raw = Some(vec![0x08; 18]);
+        let uplink = vec![0x5A_u8; 432];
+
+        let mut stream = Vec::new();
+        for e in &[
+            SensorData::Traffic(traffic.clone()),
+            SensorData::FISB(FISBData {
+                payload: uplink.clone(),
+                received: Some(Instant::now()),
+            }),
+            SensorData::Undervoltage(true),
+        ] {
+            if let Some(f) = encode(e) {
+                stream.extend_from_slice(&f);
+            }
+        }
+        // from a later version
+        stream.extend_from_slice(&[0xFF, 2, 0, 1, 2]);
+
+        let mut r = Cursor::new(stream);
+        traffic.received = None;
+        assert_eq!(decode(&mut r).unwrap(), Some(SensorData::Traffic(traffic)));
+        assert_eq!(
+            decode(&mut r).unwrap(),
+            Some(SensorData::FISB(FISBData {
+                payload: uplink,
+                received: None,
+            }))
+        );
+        assert_eq!(decode(&mut r).unwrap(), None);
+        assert!(decode(&mut r).is_err());
+    }
+
+    #[test]
+    fn test_forwarder_to_aggregator() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut provider = PeerProvider::new(port, Some(Ipv4Addr::new(127, 0, 0, 1))).unwrap();
+        let mut forwarder = Forwarder::new("127.0.0.1", port);
+
+        let stats = DecodeStats::new(TrafficSource::ES);
+        let both = [
+            SensorData::Traffic(traffic_data()),
+            SensorData::DecodeStats(stats.clone()),
+        ];
+
+        // what is forwarded before the connection is up is dropped, so keep
+        // forwarding until both arrive
+        let (mut traffic, mut peer_stats) = (None, None);
+        let mut fh = MockHandle::new(10);
+        let mut ph = MockHandle::new(10);
+        for _ in 0..500 {
+            run_processor(&mut *forwarder, &mut fh, &both);
+            sleep(Duration::from_millis(10));
+            provider.run(&mut ph);
+
+            for e in ph.take() {
+                match e {
+                    SensorData::Traffic(t) => traffic = Some(t),
+                    SensorData::DecodeStats(s) => peer_stats = Some(s),
+                    _ => {}
+                }
+            }
+            if traffic.is_some() && peer_stats.is_some() {
+                break;
+            }
+        }
+        let peer_stats = peer_stats.unwrap();
+        assert!(peer_stats.peer);
+
+        // the aggregator received the same frame itself, and a newer one
+        // before the copy arrived
+        let mut c = Config::default();
+        c.peer = Some(Peer::Aggregator {
+            port,
+            forwarder: None,
+        });
+        let mut t = Traffic::new(&c);
+        let mut h = MockHandle::new(1);
+        let mut newer = traffic_data();
+        newer.lat_lon = Some((37.51, -122.25));
+        run_processor(
+            &mut t,
+            &mut h,
+            &[
+                SensorData::Traffic(traffic_data()),
+                SensorData::Traffic(newer),
+                SensorData::Traffic(traffic.unwrap()),
+            ],
+        );
+        let positions: Vec<(f32, f32)> = h
+            .take()
+            .into_iter()
+            .filter_map(|r| match r {
+                Report::Traffic(t) => t.lat_lon.map(|l| l.0),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(positions, vec![(37.51, -122.25)]);
+
+        // statistics of both units are kept apart
+        let mut r = Receiver::new();
+        run_processor(
+            &mut *r,
+            &mut h,
+            &[
+                SensorData::DecodeStats(stats),
+                SensorData::DecodeStats(peer_stats),
+            ],
+        );
+        let mut s = Situation::default();
+        s.update(h.take().iter(), Instant::now());
+        assert_eq!(s.stats().len(), 2);
+        assert_eq!(s.stats()[0].peer, false);
+        assert_eq!(s.stats()[1].peer, true);
+    }
+}
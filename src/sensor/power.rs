@@ -0,0 +1,80 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Monitors the Raspberry Pi supply voltage through the `rpi_volt` hwmon
+//! driver, which raises an alarm when the firmware detects undervoltage.
+
+use super::*;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+
+const HWMON_DIR: &'static str = "/sys/class/hwmon";
+const HWMON_NAME: &'static str = "rpi_volt";
+const POLL_FREQ: u16 = 1;
+
+pub struct UndervoltageProvider {
+    alarm: PathBuf,
+    counter: u32,
+}
+
+impl UndervoltageProvider {
+    pub fn new() -> Option<Box<Sensor>> {
+        let entries = match fs::read_dir(HWMON_DIR) {
+            Ok(e) => e,
+            Err(_) => {
+                info!("rpi_volt not found!");
+                return None;
+            }
+        };
+
+        for e in entries.filter_map(|e| e.ok()) {
+            let path = e.path();
+
+            if read_trimmed(&path.join("name")).as_ref().map(|s| s.as_str()) == Some(HWMON_NAME) {
+                info!("rpi_volt initialization successful");
+
+                return Some(Box::new(Self {
+                    alarm: path.join("in0_lcrit_alarm"),
+                    counter: 0,
+                }));
+            }
+        }
+
+        info!("rpi_volt not found!");
+        None
+    }
+}
+
+fn read_trimmed(path: &PathBuf) -> Option<String> {
+    let mut s = String::new();
+
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut s))
+        .ok()
+        .map(|_| s.trim().to_string())
+}
+
+impl Sensor for UndervoltageProvider {
+    fn run(&mut self, h: &mut Pushable<SensorData>) {
+        run_every!(POLL_FREQ, self.counter, h, {
+            match read_trimmed(&self.alarm) {
+                Some(ref s) => h.push_data(SensorData::Undervoltage(s == "1")),
+                None => warn!("unable to read {}", self.alarm.display()),
+            }
+        });
+    }
+}
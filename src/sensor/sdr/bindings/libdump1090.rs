@@ -15,6 +15,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::super::*;
+use processor::traffic::category_from_es;
 use std::collections::VecDeque;
 use std::os::raw::c_void;
 use std::slice::from_raw_parts;
@@ -31,6 +32,11 @@ const SPEED_IS_GS: u8 = 1;
 const SPEED_IS_IAS: u8 = 2;
 const SPEED_IS_TAS: u8 = 3;
 
+const DF_ES: u8 = 17;
+const DF_ES_NON_TRANSPONDER: u8 = 18;
+const TC_OPERATIONAL_STATUS: u8 = 31;
+const ADS_B_VERSION_2: u8 = 2;
+
 #[derive(Debug)]
 #[repr(C)]
 struct TrafficT {
@@ -64,10 +70,16 @@ struct TrafficT {
     pos_valid: u8,
     nacp_valid: u8,
     airground_valid: u8,
+
+    /// the Mode S frame all of the above was decoded from
+    msg: *const u8,
+    msg_len: u32,
 }
 
 pub struct Dump1090 {
     parsed: VecDeque<TrafficData>,
+    /// raw Mode S frames, 7 or 14 bytes each
+    frames: VecDeque<Vec<u8>>,
 }
 
 #[link(name = "dump1090")]
@@ -85,6 +97,7 @@ impl Dump1090 {
         // now
         let me = Box::new(Self {
             parsed: VecDeque::new(),
+            frames: VecDeque::new(),
         });
 
         unsafe {
@@ -104,6 +117,10 @@ impl Dump1090 {
         &mut self.parsed
     }
 
+    pub fn frames_as_mut_ref(&mut self) -> &mut VecDeque<Vec<u8>> {
+        &mut self.frames
+    }
+
     fn push_message(&mut self, msg: TrafficData) {
         trace!("got a Mode S message: {:?}", msg);
         self.parsed.push_back(msg);
@@ -112,30 +129,109 @@ impl Dump1090 {
 
 unsafe impl Send for Dump1090 {}
 
+/// Map the address type libdump1090 derived from the downlink format and, for
+/// DF18, the control field (DO-260B 2.2.3.2.1):
+///
+/// * DF17 and DF18 CF=0 (non-transponder devices) are ADS-B with an ICAO address
+/// * DF18 CF=1 is ADS-B with an anonymous or ground vehicle address
+/// * DF18 CF=2 and 3 (fine and coarse TIS-B) carry an ICAO address or, with the
+///   IMF bit set, a TIS-B track file number
+/// * DF18 CF=5 is TIS-B with a non-ICAO address
+/// * DF18 CF=6 is ADS-R, the IMF bit telling ICAO and non-ICAO addresses apart
+///
+/// This runs inside a C callback, so values unknown to this version are never
+/// allowed to panic.
+fn address_type(raw: u8) -> AddressType {
+    match raw {
+        ADDR_TYPE_ADS_B_ICAO => AddressType::ADSBICAO,
+        ADDR_TYPE_ADS_B_OTHER => AddressType::ADSBOther,
+        ADDR_TYPE_ADS_R_ICAO => AddressType::ADSRICAO,
+        ADDR_TYPE_ADS_R_OTHER => AddressType::ADSROther,
+        ADDR_TYPE_TIS_B_ICAO => AddressType::TISBICAO,
+        ADDR_TYPE_TIS_B_OTHER => AddressType::TISBOther,
+        ADDR_TYPE_UNKNOWN => AddressType::Unknown,
+        _ => {
+            debug!("unknown libdump1090 address type {}", raw);
+            AddressType::Unknown
+        }
+    }
+}
+
+/// Capability class and operational mode of a DO-260B (version 2) operational
+/// status message (type code 31, DO-260B 2.2.3.2.7.2), read from the raw
+/// extended squitter `frame`. Yields the capability codes and whether IDENT
+/// is active, which only airborne targets report.
+///
+/// Earlier versions lay out the capability class differently and are left
+/// alone, as are TIS-B frames, which describe a radar track rather than the
+/// capabilities of the target.
+fn operational_status(frame: &[u8]) -> Option<(Capability, Option<bool>)> {
+    if frame.len() != 14 {
+        return None;
+    }
+
+    match (frame[0] >> 3, frame[0] & 0x07) {
+        (DF_ES, _)
+        | (DF_ES_NON_TRANSPONDER, 0)
+        | (DF_ES_NON_TRANSPONDER, 1)
+        | (DF_ES_NON_TRANSPONDER, 6) => {}
+        _ => return None,
+    }
+
+    let me = &frame[4..11];
+    // ME bit `n` as numbered by DO-260B, starting from 1
+    let bit = |n: usize| me[(n - 1) / 8] & (0x80 >> ((n - 1) % 8)) != 0;
+
+    if me[0] >> 3 != TC_OPERATIONAL_STATUS || (me[5] >> 5) & 0x07 != ADS_B_VERSION_2 {
+        return None;
+    }
+
+    match me[0] & 0x07 {
+        // airborne: TCAS operational (11), 1090ES IN (12), UAT IN (19) and
+        // IDENT switch active (28)
+        0 => Some((
+            Capability {
+                cdti: bit(12) || bit(19),
+                tcas: bit(11),
+            },
+            Some(bit(28)),
+        )),
+        // surface: 1090ES IN (12) and UAT IN (16), surface targets have no TCAS
+        1 => Some((
+            Capability {
+                cdti: bit(12) || bit(16),
+                tcas: false,
+            },
+            None,
+        )),
+        _ => None,
+    }
+}
+
+/// Every message decoded comes with the Mode S frame it was decoded from,
+/// which is kept for the raw frame outputs even when it carried no traffic.
 extern "C" fn callback(inst: *mut c_void, traffic: *const TrafficT) {
     let inst = inst as *mut Dump1090;
 
     unsafe {
         let traffic = &*traffic;
+        let frame = match traffic.msg_len {
+            7 | 14 if !traffic.msg.is_null() => {
+                let frame = from_raw_parts(traffic.msg, traffic.msg_len as usize);
+                (*inst).frames.push_back(frame.to_vec());
+                Some(frame)
+            }
+            _ => None,
+        };
+        let status = frame.and_then(operational_status);
+
         if traffic.addr == 0 {
             // this happens sometimes, just ignore
             return;
         }
 
         let msg = TrafficData {
-            addr: (
-                traffic.addr,
-                match traffic.addr_type {
-                    ADDR_TYPE_ADS_B_ICAO => AddressType::ADSBICAO,
-                    ADDR_TYPE_ADS_B_OTHER => AddressType::ADSBOther,
-                    ADDR_TYPE_ADS_R_ICAO => AddressType::ADSRICAO,
-                    ADDR_TYPE_ADS_R_OTHER => AddressType::ADSROther,
-                    ADDR_TYPE_TIS_B_ICAO => AddressType::TISBICAO,
-                    ADDR_TYPE_TIS_B_OTHER => AddressType::TISBOther,
-                    ADDR_TYPE_UNKNOWN => AddressType::Unknown,
-                    _ => unreachable!(),
-                },
-            ),
+            addr: (traffic.addr, address_type(traffic.addr_type)),
             altitude: match traffic.altitude_valid {
                 1 => Some((
                     traffic.altitude,
@@ -209,20 +305,18 @@ extern "C" fn callback(inst: *mut c_void, traffic: *const TrafficT) {
                 _ => None,
             },
             category: match traffic.category_valid {
-                1 => {
-                    let mut ct = 0_u8;
-
-                    ct += (((traffic.category as u8 & 0xF0) >> 4) - 0x0A) * 8;
-                    ct += traffic.category as u8 & 0x0F;
-
-                    Some(ct)
-                }
+                1 => Some(category_from_es(traffic.category as u8)),
                 _ => None,
             },
             lat_lon: match traffic.pos_valid {
                 1 => Some((traffic.lat as f32, traffic.lon as f32)),
                 _ => None,
             },
+            // NIC and NACp are interpreted by libdump1090, which does not report the
+            // ADS-B version (DO-260/260A/260B) of the target or the NIC supplement
+            // bits, so they can not be corrected for version differences here.
+            // Doing so needs `TrafficT` to grow version and supplement fields in
+            // libdump1090 first.
             nic: match traffic.pos_valid {
                 1 => Some(traffic.nic as u8),
                 _ => None,
@@ -231,13 +325,106 @@ extern "C" fn callback(inst: *mut c_void, traffic: *const TrafficT) {
                 1 => Some(traffic.nacp as u8),
                 _ => None,
             },
+            // not exposed by libdump1090
+            nacv: None,
+            sil: None,
+            ident: status.and_then(|s| s.1),
+            capability: status.map(|s| s.0),
             on_ground: match traffic.airground_valid {
                 1 => Some(traffic.on_ground == 1),
                 _ => None,
             },
             source: TrafficSource::ES,
+            received: Some(Instant::now()),
+            trace: None,
+            raw: None,
         };
 
         (*inst).push_message(msg);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_type() {
+        assert_eq!(address_type(1), AddressType::ADSBICAO);
+        assert_eq!(address_type(2), AddressType::ADSBOther);
+        assert_eq!(address_type(3), AddressType::ADSRICAO);
+        assert_eq!(address_type(4), AddressType::ADSROther);
+        assert_eq!(address_type(5), AddressType::TISBICAO);
+        assert_eq!(address_type(6), AddressType::TISBOther);
+        assert_eq!(address_type(7), AddressType::Unknown);
+        assert_eq!(address_type(0), AddressType::Unknown);
+        assert_eq!(address_type(42), AddressType::Unknown);
+    }
+
+    #[test]
+    fn test_operational_status() {
+        // DF17, airborne version 2 with TCAS operational, 1090ES IN and IDENT
+        let mut frame = [
+            0x8D, 0xA1, 0xB2, 0xC3, 0xF8, 0x30, 0x00, 0x10, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            operational_status(&frame),
+            Some((
+                Capability {
+                    cdti: true,
+                    tcas: true,
+                },
+                Some(true)
+            ))
+        );
+
+        // UAT IN only
+        frame[5] = 0x00;
+        frame[6] = 0x20;
+        frame[7] = 0x00;
+        assert_eq!(
+            operational_status(&frame),
+            Some((
+                Capability {
+                    cdti: true,
+                    tcas: false,
+                },
+                Some(false)
+            ))
+        );
+
+        // surface, UAT IN
+        frame[4] = 0xF9;
+        frame[5] = 0x01;
+        frame[6] = 0x00;
+        assert_eq!(
+            operational_status(&frame),
+            Some((
+                Capability {
+                    cdti: true,
+                    tcas: false,
+                },
+                None
+            ))
+        );
+
+        // version 1 is laid out differently
+        frame[9] = 0x20;
+        assert_eq!(operational_status(&frame), None);
+        frame[9] = 0x40;
+
+        // TIS-B (DF18 CF=2)
+        frame[0] = 0x92;
+        assert_eq!(operational_status(&frame), None);
+
+        // ADS-R (DF18 CF=6)
+        frame[0] = 0x96;
+        assert!(operational_status(&frame).is_some());
+
+        // not an operational status message (airborne position)
+        frame[0] = 0x8D;
+        frame[4] = 0x58;
+        assert_eq!(operational_status(&frame), None);
+        assert_eq!(operational_status(&frame[..7]), None);
+    }
+}
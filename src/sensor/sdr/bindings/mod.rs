@@ -14,6 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+#[cfg(feature = "es")]
 pub mod libdump1090;
+#[cfg(feature = "uat")]
 pub mod libdump978;
+#[cfg(any(feature = "es", feature = "uat"))]
 pub mod librtlsdr;
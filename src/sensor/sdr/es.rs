@@ -16,25 +16,71 @@
 
 use super::bindings::libdump1090::Dump1090;
 use super::bindings::librtlsdr::{get_device_count, get_device_info, Device, HWInfo};
+use super::queue::{queue, QueueReceiver};
 use super::*;
 use pitot::handle::Pushable;
+use protocol::feed::Feed;
 use sensor::{Sensor, SensorData};
 use std::io::{self, Read};
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::{spawn, JoinHandle};
 
 const TUNER_GAIN: i32 = 480;
 const SAMPLE_RATE: i32 = 2400000;
 const CENTER_FREQ: u32 = 1090000000;
 const RTL_SDR_BUF_SIZE: usize = 16 * 16384;
+const BEAST_PORT: u16 = 30005;
+const BEAST_ESCAPE: u8 = 0x1A;
+
+/// Received frames in the binary Beast format on TCP port 30005, which
+/// feeder clients and MLAT software read from dump1090. Each frame is sent
+/// as `0x1a`, the type (`'2'` for 56 bit and `'3'` for 112 bit frames), a
+/// 6 byte 12 MHz timestamp, the signal level and the frame itself, with
+/// every `0x1a` after the type doubled. libdump1090 reports neither the
+/// time of arrival nor the signal level, so both are sent as zero.
+pub struct Beast {
+    feed: Feed,
+}
+
+impl Beast {
+    pub fn new() -> Option<Self> {
+        Feed::bind("Beast", BEAST_PORT).map(|feed| Self { feed })
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.feed.accept();
+        if self.feed.has_clients() && !data.is_empty() {
+            self.feed.send(data);
+        }
+    }
+}
+
+/// Append Mode S `frame` to `out` in the Beast format, see `Beast`
+fn beast_frame(frame: &[u8], out: &mut Vec<u8>) {
+    out.push(BEAST_ESCAPE);
+    out.push(if frame.len() == 7 { b'2' } else { b'3' });
+
+    // timestamp and signal level
+    out.extend_from_slice(&[0; 7]);
+    for &b in frame {
+        out.push(b);
+        if b == BEAST_ESCAPE {
+            out.push(b);
+        }
+    }
+}
 
 pub struct ES {
     _handle: JoinHandle<()>,
-    rx: Receiver<TrafficData>,
+    rx: QueueReceiver<SensorData>,
+    /// receiving stops while set
+    paused: Arc<AtomicBool>,
 }
 
 impl ES {
-    pub fn new() -> Option<Self> {
+    /// With `beast`, every frame received is also written there
+    pub fn new(mut beast: Option<Beast>) -> Option<Self> {
         for i in 0..get_device_count() {
             if let Some(HWInfo { serial: ref s, .. }) = get_device_info(i) {
                 if !s.contains("1090") {
@@ -57,14 +103,23 @@ impl ES {
 
                 let mut dump1090 = Dump1090::new();
 
-                let (tx, rx) = channel();
+                let paused = Arc::new(AtomicBool::new(false));
+                let thread_paused = paused.clone();
+
+                let (tx, rx) = queue("1090ES");
 
                 // this thread is responsible for reading the SDR device and fed
                 // dump1090
                 let handle = spawn(move || {
                     let mut buf = vec![0; RTL_SDR_BUF_SIZE];
+                    let mut stats = DecodeStats::new(TrafficSource::ES);
 
                     loop {
+                        if wait_resumed(&thread_paused) {
+                            dev.reset_buffer().ok();
+                            stats = DecodeStats::new(TrafficSource::ES);
+                        }
+
                         match dev.read(&mut buf[..]) {
                             Ok(n) => {
                                 trace!("ES read {} bytes", n);
@@ -75,11 +130,29 @@ impl ES {
                                 // process new data
                                 let mut acc = 0_usize;
                                 while let Some(item) = dump1090.parsed_as_mut_ref().pop_front() {
-                                    tx.send(item).unwrap();
+                                    tx.send(SensorData::Traffic(item));
                                     acc += 1;
                                 }
 
                                 debug!("dump1090 returned {} messages", acc);
+
+                                let mut raw = Vec::new();
+                                while let Some(f) = dump1090.frames_as_mut_ref().pop_front() {
+                                    if beast.is_some() {
+                                        beast_frame(&f, &mut raw);
+                                    }
+                                }
+                                if let Some(ref mut b) = beast {
+                                    b.write(&raw);
+                                }
+
+                                stats.downlink_frames += acc as u32;
+                                if stats.due() {
+                                    stats.noise_floor = noise_floor(&buf[..n]);
+                                }
+                                if let Some(s) = stats.take() {
+                                    tx.send(SensorData::DecodeStats(s));
+                                }
                             }
                             Err(e) => {
                                 if e.kind() == io::ErrorKind::TimedOut {
@@ -95,6 +168,7 @@ impl ES {
                 return Some(ES {
                     _handle: handle,
                     rx,
+                    paused,
                 });
             }
         }
@@ -105,10 +179,63 @@ impl ES {
     }
 }
 
+/// Decode a capture of raw 8 bit I/Q samples taken at 1090 MHz and the ES
+/// sample rate (e.g. `rtl_sdr -f 1090000000 -s 2400000`), handing decoded
+/// traffic to `send` the same way live reception does
+pub fn decode_capture<R: Read>(mut capture: R, send: &mut FnMut(SensorData)) -> io::Result<()> {
+    let mut dump1090 = Dump1090::new();
+    let mut buf = vec![0; RTL_SDR_BUF_SIZE];
+
+    loop {
+        let n = try!(capture.read(&mut buf[..]));
+        if n == 0 {
+            return Ok(());
+        }
+
+        dump1090.process_data(&buf[..n]);
+
+        while let Some(item) = dump1090.parsed_as_mut_ref().pop_front() {
+            send(SensorData::Traffic(item));
+        }
+        dump1090.frames_as_mut_ref().clear();
+    }
+}
+
 impl Sensor for ES {
     fn run(&mut self, h: &mut Pushable<SensorData>) {
-        for u in self.rx.try_iter() {
-            h.push_data(SensorData::Traffic(u));
+        for d in self.rx.try_iter() {
+            h.push_data(d);
         }
     }
+
+    fn pause(&mut self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&mut self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beast_frame() {
+        let mut out = Vec::new();
+        beast_frame(&[0x5D, 0xA1, 0xB2, 0xC3, 0x1A, 0x00, 0x01], &mut out);
+        assert_eq!(
+            out,
+            vec![0x1A, b'2', 0, 0, 0, 0, 0, 0, 0, 0x5D, 0xA1, 0xB2, 0xC3, 0x1A, 0x1A, 0x00, 0x01]
+        );
+
+        out.clear();
+        let long = [
+            0x8D, 0x48, 0x40, 0xD6, 0x20, 0x2C, 0xC3, 0x71, 0xC3, 0x2C, 0xE0, 0x57, 0x60, 0x98,
+        ];
+        beast_frame(&long, &mut out);
+        assert_eq!(&out[..9], &[0x1A, b'3', 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&out[9..], &long[..]);
+    }
 }
@@ -15,12 +15,31 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod bindings;
+#[cfg(feature = "es")]
 pub mod es;
+#[cfg(any(feature = "es", feature = "uat"))]
+mod queue;
+#[cfg(any(feature = "es", feature = "uat"))]
+pub mod scan;
+#[cfg(feature = "uat")]
 pub mod uat;
 
 use processor::traffic::{AddressType, AltitudeType, HeadingType, SpeedType, TrafficSource};
+use std::mem;
+#[cfg(any(feature = "es", feature = "uat"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-#[derive(Debug, PartialEq)]
+// how often decode statistics are sent, in seconds
+const STATS_INTERVAL_SECS: u64 = 10;
+// how often a paused receiver thread checks whether it was resumed
+#[cfg(any(feature = "es", feature = "uat"))]
+const PAUSE_POLL_MS: u64 = 500;
+// samples per block the noise floor is measured over
+#[cfg(any(feature = "es", feature = "uat"))]
+const NOISE_BLOCK: usize = 256;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct TrafficData {
     pub addr: (u32, AddressType),
     pub altitude: Option<(i32, AltitudeType)>,
@@ -30,10 +49,185 @@ pub struct TrafficData {
     pub vs: Option<i16>,
     pub squawk: Option<u16>,
     pub callsign: Option<String>,
+    /// Emitter category in the DO-282B encoding GDL90 also uses
     pub category: Option<u8>,
     pub lat_lon: Option<(f32, f32)>,
     pub nic: Option<u8>,
     pub nacp: Option<u8>,
+    /// Navigation accuracy category for velocity
+    pub nacv: Option<u8>,
+    /// Source integrity level
+    pub sil: Option<u8>,
+    /// Pilot pressed IDENT
+    pub ident: Option<bool>,
+    /// Equipment the target reports having
+    pub capability: Option<Capability>,
     pub on_ground: Option<bool>,
     pub source: TrafficSource,
+    /// When the frame was decoded, for measuring latency
+    #[serde(skip)]
+    pub received: Option<Instant>,
+    /// ID the pipeline trace gave the message this came in, see
+    /// `pitot::trace`
+    #[serde(skip)]
+    pub trace: Option<u64>,
+    /// The error corrected UAT ADS-B payload this was decoded from, for
+    /// clients decoding UAT themselves
+    pub raw: Option<Vec<u8>>,
+}
+
+/// Capability codes of the UAT mode status (DO-282B 2.2.4.5.4.12) or the
+/// 1090 ES operational status (DO-260B 2.2.3.2.7.2), which tell whether a
+/// target can see us back
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Capability {
+    /// Has a cockpit display of traffic information, i.e. receives ADS-B
+    pub cdti: bool,
+    /// TCAS (ACAS) is installed and operational
+    pub tcas: bool,
+}
+
+/// Decode quality of one receiver over the last few seconds. libdump978 and
+/// libdump1090 only report frames they decoded successfully, so frames failing
+/// error correction or CRC checks are not counted.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DecodeStats {
+    pub band: TrafficSource,
+    /// Length of the interval in seconds
+    pub interval: u64,
+    pub downlink_frames: u32,
+    pub uplink_frames: u32,
+    /// Frames that needed Reed-Solomon error correction, UAT only
+    pub corrected_frames: u32,
+    /// Bytes fixed by Reed-Solomon error correction, UAT only
+    pub corrected_errors: u32,
+    /// Noise floor in dBFS, measured at the end of the interval
+    pub noise_floor: Option<f32>,
+    /// Forwarded by a paired unit, see `sensor::peer`, rather than measured
+    /// by a receiver of this one
+    #[serde(default)]
+    pub peer: bool,
+    #[serde(skip, default = "Instant::now")]
+    since: Instant,
+}
+
+impl DecodeStats {
+    pub fn new(band: TrafficSource) -> Self {
+        Self {
+            band,
+            interval: 0,
+            downlink_frames: 0,
+            uplink_frames: 0,
+            corrected_frames: 0,
+            corrected_errors: 0,
+            noise_floor: None,
+            peer: false,
+            since: Instant::now(),
+        }
+    }
+
+    /// Whether the statistics cover `STATS_INTERVAL_SECS`, i.e. the next
+    /// `take` returns them
+    pub fn due(&self) -> bool {
+        self.since.elapsed().as_secs() >= STATS_INTERVAL_SECS
+    }
+
+    /// Statistics collected so far once they cover `STATS_INTERVAL_SECS`,
+    /// counting starts over afterwards
+    pub fn take(&mut self) -> Option<Self> {
+        if !self.due() {
+            return None;
+        }
+
+        let mut stats = DecodeStats::new(self.band);
+        mem::swap(self, &mut stats);
+        stats.interval = stats.since.elapsed().as_secs();
+
+        Some(stats)
+    }
+}
+
+/// Noise floor of interleaved unsigned 8 bit I/Q `samples` in dBFS, the
+/// median power of short blocks so that bursts of traffic do not raise it.
+/// `None` without a single full block.
+#[cfg(any(feature = "es", feature = "uat"))]
+fn noise_floor(samples: &[u8]) -> Option<f32> {
+    let mut power: Vec<f32> = samples
+        .chunks(NOISE_BLOCK * 2)
+        .filter(|b| b.len() == NOISE_BLOCK * 2)
+        .map(|b| {
+            let sum: f32 = b
+                .iter()
+                .map(|&s| (s as f32 - 127.5) / 127.5)
+                .map(|v| v * v)
+                .sum();
+
+            // a full scale complex tone has a power of 1
+            sum / NOISE_BLOCK as f32
+        })
+        .collect();
+
+    if power.is_empty() {
+        return None;
+    }
+
+    power.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    Some(10.0 * power[power.len() / 2].max(1e-20).log10())
+}
+
+/// Block the receiver thread while `paused` is set, so samples are neither
+/// read nor demodulated. Returns whether it was paused, samples buffered by
+/// the device since are stale then.
+#[cfg(any(feature = "es", feature = "uat"))]
+fn wait_resumed(paused: &AtomicBool) -> bool {
+    let mut was_paused = false;
+
+    while paused.load(Ordering::Relaxed) {
+        was_paused = true;
+        ::std::thread::sleep(::std::time::Duration::from_millis(PAUSE_POLL_MS));
+    }
+
+    was_paused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_decode_stats_interval() {
+        let mut stats = DecodeStats::new(TrafficSource::UAT);
+        stats.downlink_frames = 5;
+        assert_eq!(stats.take(), None);
+
+        stats.since -= Duration::from_secs(STATS_INTERVAL_SECS);
+        let taken = stats.take().unwrap();
+        assert_eq!(taken.downlink_frames, 5);
+        assert_eq!(taken.interval, STATS_INTERVAL_SECS);
+
+        // counting starts over
+        assert_eq!(stats.downlink_frames, 0);
+        assert_eq!(stats.take(), None);
+    }
+
+    #[cfg(any(feature = "es", feature = "uat"))]
+    #[test]
+    fn test_noise_floor() {
+        assert_eq!(noise_floor(&[127; NOISE_BLOCK]), None);
+
+        // I and Q alternating at +-0.1 full scale
+        let mut samples: Vec<u8> = (0..NOISE_BLOCK * 2 * 9)
+            .map(|i| if i % 3 == 0 { 140 } else { 115 })
+            .collect();
+        let floor = noise_floor(&samples).unwrap();
+        assert!((floor - -17.0).abs() < 0.5, "{}", floor);
+
+        // a burst through a few blocks does not move it
+        for s in &mut samples[..NOISE_BLOCK * 2 * 4] {
+            *s = 255;
+        }
+        assert!((noise_floor(&samples).unwrap() - floor).abs() < 0.01);
+    }
 }
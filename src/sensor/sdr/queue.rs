@@ -0,0 +1,102 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bounded queue an SDR thread hands decoded messages to the main loop through.
+
+use crossbeam_channel::{self, Receiver, Sender, TryIter, TrySendError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// messages buffered between an SDR thread and the main loop, several seconds
+// worth in busy airspace
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Creates a queue, `name` identifies it in log messages
+pub fn queue<T>(name: &'static str) -> (QueueSender<T>, QueueReceiver<T>) {
+    with_capacity(name, QUEUE_CAPACITY)
+}
+
+fn with_capacity<T>(name: &'static str, cap: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (tx, rx) = crossbeam_channel::bounded(cap);
+    let dropped = Arc::new(AtomicUsize::new(0));
+
+    (
+        QueueSender {
+            tx,
+            dropped: dropped.clone(),
+        },
+        QueueReceiver { rx, dropped, name },
+    )
+}
+
+pub struct QueueSender<T> {
+    tx: Sender<T>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<T> QueueSender<T> {
+    /// Never blocks the SDR thread, the message is dropped and counted
+    /// instead when the main loop has fallen behind.
+    pub fn send(&self, msg: T) {
+        match self.tx.try_send(msg) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("queue receiver is gone"),
+        }
+    }
+}
+
+pub struct QueueReceiver<T> {
+    rx: Receiver<T>,
+    dropped: Arc<AtomicUsize>,
+    name: &'static str,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Messages queued so far, warns about any that were dropped since the
+    /// last call
+    pub fn try_iter(&self) -> TryIter<T> {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("{} queue full, dropped {} messages", self.name, dropped);
+        }
+
+        self.rx.try_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_drops_when_full() {
+        let (tx, rx) = with_capacity("test", 2);
+
+        tx.send(1);
+        tx.send(2);
+        tx.send(3);
+        assert_eq!(rx.dropped.load(Ordering::Relaxed), 1);
+
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(rx.dropped.load(Ordering::Relaxed), 0);
+
+        tx.send(4);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![4]);
+    }
+}
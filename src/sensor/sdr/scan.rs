@@ -0,0 +1,251 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Maintenance mode sweeping an SDR across a frequency range and measuring the
+//! noise floor and strongest signal of each step, for finding interference
+//! sources in an installation. Runs instead of the normal receivers, as the
+//! SDRs can not be shared.
+
+use super::bindings::librtlsdr::{get_device_count, get_device_info, Device};
+use std::f32::consts::PI;
+use std::io::Read;
+
+const SAMPLE_RATE: i32 = 2400000;
+const TUNER_GAIN: i32 = 480;
+const FFT_SIZE: usize = 1024;
+// FFT blocks averaged for every step
+const FFT_AVERAGE: usize = 64;
+// the outer bins are attenuated by the anti-aliasing filter, ignore them
+const USABLE_FRACTION: f32 = 0.8;
+// samples read and thrown away after retuning while the tuner settles
+const SETTLE_BYTES: usize = 16 * 16384;
+
+/// Measurements of one step of the sweep, powers are in dB relative to full scale
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ScanStep {
+    /// Center frequency of the step in Hz
+    pub frequency: u32,
+    /// Median power of all bins
+    pub noise_floor: f32,
+    /// Strongest bin, likely a spur or interference if well above the noise floor
+    pub peak: f32,
+    /// Frequency of the strongest bin in Hz
+    pub peak_frequency: u32,
+}
+
+/// Sweep from `start` to `end` Hz with the SDR at `index`, calling `report`
+/// after every step
+pub fn scan<F>(index: u32, start: u32, end: u32, mut report: F) -> Result<(), String>
+where
+    F: FnMut(ScanStep),
+{
+    if index >= get_device_count() {
+        return Err(format!("no RTL-SDR device #{}", index));
+    }
+
+    if let Some(info) = get_device_info(index) {
+        info!(
+            "scanning with {} {} ({})",
+            info.manufact, info.product, info.serial
+        );
+    }
+
+    let mut dev = try!(Device::new(index).map_err(|e| format!("unable to open SDR: {:?}", e)));
+    try!(dev
+        .set_tuner_gain_mode(true)
+        .and_then(|d| d.set_tuner_gain(TUNER_GAIN))
+        .and_then(|d| d.set_sample_rate(SAMPLE_RATE))
+        .map_err(|e| format!("unable to configure SDR: {:?}", e)));
+
+    let step = (SAMPLE_RATE as f32 * USABLE_FRACTION) as u32;
+    let mut buf = vec![0_u8; FFT_SIZE * 2 * FFT_AVERAGE];
+    let mut settle = vec![0_u8; SETTLE_BYTES];
+    let mut frequency = start + step / 2;
+
+    while frequency - step / 2 < end {
+        try!(dev
+            .set_center_freq(frequency)
+            .and_then(|d| d.reset_buffer())
+            .map_err(|e| format!("unable to tune to {} Hz: {:?}", frequency, e)));
+
+        try!(read_exact(&mut dev, &mut settle));
+        try!(read_exact(&mut dev, &mut buf));
+
+        report(measure(&power_spectrum(&buf), frequency));
+        frequency += step;
+    }
+
+    Ok(())
+}
+
+fn read_exact<R: Read>(dev: &mut R, buf: &mut [u8]) -> Result<(), String> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match try!(dev
+            .read(&mut buf[read..])
+            .map_err(|e| format!("SDR read error: {}", e)))
+        {
+            0 => return Err("SDR stopped returning samples".to_string()),
+            n => read += n,
+        }
+    }
+
+    Ok(())
+}
+
+/// Average power of every bin in dBFS, from interleaved unsigned 8 bit I/Q
+/// samples, DC in the middle
+fn power_spectrum(samples: &[u8]) -> Vec<f32> {
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+        .collect();
+    let blocks = samples.len() / (FFT_SIZE * 2);
+    let mut power = vec![0_f32; FFT_SIZE];
+
+    for block in samples.chunks(FFT_SIZE * 2).take(blocks) {
+        let mut re: Vec<f32> = Vec::with_capacity(FFT_SIZE);
+        let mut im: Vec<f32> = Vec::with_capacity(FFT_SIZE);
+
+        for (i, iq) in block.chunks(2).enumerate() {
+            re.push((iq[0] as f32 - 127.5) / 127.5 * window[i]);
+            im.push((iq[1] as f32 - 127.5) / 127.5 * window[i]);
+        }
+
+        fft(&mut re, &mut im);
+
+        for (i, p) in power.iter_mut().enumerate() {
+            // swap halves so negative frequencies come first
+            let bin = (i + FFT_SIZE / 2) % FFT_SIZE;
+            *p += re[bin] * re[bin] + im[bin] * im[bin];
+        }
+    }
+
+    // a full scale tone through the Hann window has a power of (N / 2)^2
+    let full_scale = (FFT_SIZE as f32 / 2.0).powi(2);
+    power
+        .iter()
+        .map(|p| 10.0 * (p / blocks as f32 / full_scale).max(1e-20).log10())
+        .collect()
+}
+
+/// Noise floor and peak of the usable part of `spectrum` centered on `frequency`
+fn measure(spectrum: &[f32], frequency: u32) -> ScanStep {
+    let skip = (spectrum.len() as f32 * (1.0 - USABLE_FRACTION) / 2.0) as usize;
+    let usable = &spectrum[skip..spectrum.len() - skip];
+
+    let mut sorted = usable.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let (peak_bin, peak) =
+        usable.iter().enumerate().fold(
+            (0, ::std::f32::MIN),
+            |m, (i, &p)| if p > m.1 { (i, p) } else { m },
+        );
+
+    let bin_width = SAMPLE_RATE as f64 / spectrum.len() as f64;
+    let offset = (peak_bin + skip) as f64 - spectrum.len() as f64 / 2.0;
+
+    ScanStep {
+        frequency,
+        noise_floor: sorted[sorted.len() / 2],
+        peak,
+        peak_frequency: (frequency as f64 + offset * bin_width).round() as u32,
+    }
+}
+
+/// In place iterative radix-2 FFT, `re.len()` must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+
+        let mut start = 0;
+        while start < n {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+
+                let tr = re[b] * wr - im[b] * wi;
+                let ti = re[b] * wi + im[b] * wr;
+
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+
+            start += len;
+        }
+
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_exact() {
+        let mut buf = [0_u8; 4];
+        assert!(read_exact(&mut &[1_u8, 2, 3, 4, 5][..], &mut buf).is_ok());
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        // no samples must not spin forever
+        assert!(read_exact(&mut &[1_u8][..], &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_tone_spectrum() {
+        // full scale complex tone 100 bins above the center
+        let mut samples = Vec::with_capacity(FFT_SIZE * 2 * 4);
+        for i in 0..FFT_SIZE * 4 {
+            let phase = 2.0 * PI * 100.0 * i as f32 / FFT_SIZE as f32;
+            samples.push((127.5 + 127.0 * phase.cos()).round() as u8);
+            samples.push((127.5 + 127.0 * phase.sin()).round() as u8);
+        }
+
+        let spectrum = power_spectrum(&samples);
+        let step = measure(&spectrum, 978000000);
+
+        let bin_width = SAMPLE_RATE as f64 / FFT_SIZE as f64;
+        assert_eq!(
+            step.peak_frequency,
+            (978000000.0 + 100.0 * bin_width).round() as u32
+        );
+        assert!(step.peak > -1.0 && step.peak < 0.5);
+        assert!(step.noise_floor < step.peak - 40.0);
+    }
+}
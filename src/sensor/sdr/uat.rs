@@ -16,15 +16,24 @@
 
 use super::bindings::libdump978::{Dump978, Frame, FrameType, Move};
 use super::bindings::librtlsdr::{get_device_count, get_device_info, Device, HWInfo};
+use super::queue::{queue, QueueReceiver};
 use super::*;
 use nom::shift;
 use pitot::handle::Pushable;
 use processor::fisb::FISBData;
+use processor::traffic::category_from_uat;
+use protocol::feed::Feed;
+use recording::create_numbered;
 use sensor::{Sensor, SensorData};
 use std::f32::consts::PI;
-use std::io::{self, Read};
-use std::sync::mpsc::{channel, Receiver};
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::{spawn, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const TUNER_GAIN: i32 = 480;
 const SAMPLE_RATE: i32 = 2083334;
@@ -33,17 +42,168 @@ const TUNER_FREQ: u32 = 28800000;
 const CENTER_FREQ: u32 = 978000000;
 const BANDWIDTH: u32 = 1000000;
 const RTL_SDR_BUF_SIZE: usize = 16 * 16384;
+// number of reads that fit in the sample buffer before it needs compacting
+const SAMPLE_BUF_READS: usize = 4;
+// payload size of basic and long ADS-B frames
+const BASIC_PAYLOAD_SIZE: usize = 18;
+const LONG_PAYLOAD_SIZE: usize = 34;
 const CALLSIGN_BASE40: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ  ..";
 const LAT_LON_RESOLUTION: f32 = 360.0 / 16777216_f32; // 2^24, see p. 19
 const TRACK_RESOLUTION: f32 = 360.0 / 512.0;
 
+/// Holds samples waiting to be demodulated. Samples libdump978 could not
+/// consume yet are left in place and only moved back to the front when there
+/// is no room left for the next read, instead of after every read.
+struct SampleBuffer {
+    buf: Vec<u8>,
+    read_size: usize,
+    /// start of unconsumed samples
+    start: usize,
+    /// end of unconsumed samples
+    end: usize,
+}
+
+impl SampleBuffer {
+    fn new(read_size: usize, reads: usize) -> Self {
+        Self {
+            buf: vec![0; read_size * reads],
+            read_size,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Space for the next read
+    fn writable(&mut self) -> &mut [u8] {
+        if self.buf.len() - self.end < self.read_size {
+            trace!("compacting {} unconsumed UAT samples", self.end - self.start);
+
+            shift(&mut self.buf, self.start, self.end);
+            self.end -= self.start;
+            self.start = 0;
+        }
+
+        &mut self.buf[self.end..self.end + self.read_size]
+    }
+
+    /// `n` bytes have been read into `writable()`
+    fn commit(&mut self, n: usize) {
+        self.end += n;
+    }
+
+    /// Samples not consumed yet
+    fn readable(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.end]
+    }
+
+    /// Keep only `start..end` of the last `readable()`, everything else
+    /// has been consumed
+    fn retain(&mut self, start: usize, end: usize) {
+        if start == end {
+            self.start = 0;
+            self.end = 0;
+        } else {
+            self.end = self.start + end;
+            self.start += start;
+        }
+    }
+}
+
+/// Received frames in the text format of dump978, for the tools reading
+/// its output. Each frame is a line of its payload in hex, prefixed with
+/// `+` for uplinks and `-` for downlinks, followed by the number of bytes
+/// error correction fixed (`rs`, left out when none) and the UNIX time of
+/// reception (`t`), e.g. `-0b28c...;rs=2;t=1514764800.123;`
+pub struct RawLog {
+    /// `None` once writing failed
+    file: Option<(PathBuf, BufWriter<File>)>,
+    feed: Option<Feed>,
+}
+
+impl RawLog {
+    /// Log into a new `uat-<n>.txt` in `dir` and serve on TCP `port`,
+    /// `None` when neither is set up
+    pub fn new(dir: Option<&str>, port: Option<u16>) -> Option<Self> {
+        let file = dir.and_then(|dir| match create_numbered(Path::new(dir), "uat", "txt") {
+            Ok((path, file)) => {
+                info!("logging raw UAT frames to {}", path.display());
+                Some((path, BufWriter::new(file)))
+            }
+            Err(e) => {
+                error!("unable to create raw UAT log in {}: {}", dir, e);
+                None
+            }
+        });
+        let feed = port.and_then(|port| Feed::bind("raw UAT", port));
+
+        if file.is_none() && feed.is_none() {
+            return None;
+        }
+
+        Some(Self { file, feed })
+    }
+
+    fn write(&mut self, lines: &str) {
+        if let Some(ref mut f) = self.feed {
+            f.accept();
+            if f.has_clients() && !lines.is_empty() {
+                f.send(lines.as_bytes());
+            }
+        }
+
+        let res = match self.file {
+            Some((_, ref mut w)) if !lines.is_empty() => {
+                w.write_all(lines.as_bytes()).and_then(|_| w.flush())
+            }
+            _ => return,
+        };
+
+        if let Err(e) = res {
+            error!(
+                "unable to write {}, raw UAT logging stopped: {}",
+                self.file.as_ref().unwrap().0.display(),
+                e
+            );
+            self.file = None;
+        }
+    }
+}
+
+/// Line of `f` in the dump978 format, see `RawLog`
+fn raw_line(f: &Frame, time: SystemTime) -> String {
+    let mut line = String::with_capacity(2 * f.payload.len() + 32);
+
+    line.push(match f.frame_type {
+        FrameType::GroundUplink => '+',
+        FrameType::ADSBShort | FrameType::ADSBLong => '-',
+    });
+    for b in &f.payload {
+        write!(line, "{:02x}", b).unwrap();
+    }
+    line.push(';');
+
+    if f.rs_error > 0 {
+        write!(line, "rs={};", f.rs_error).unwrap();
+    }
+    if let Ok(t) = time.duration_since(UNIX_EPOCH) {
+        let ms = t.subsec_nanos() / 1_000_000;
+        write!(line, "t={}.{:03};", t.as_secs(), ms).unwrap();
+    }
+    line.push('\n');
+
+    line
+}
+
 pub struct UAT {
     _handle: JoinHandle<()>,
-    rx: Receiver<Frame>,
+    rx: QueueReceiver<SensorData>,
+    /// receiving stops while set
+    paused: Arc<AtomicBool>,
 }
 
 impl UAT {
-    pub fn new() -> Option<Self> {
+    /// With `raw`, every frame received is also written there
+    pub fn new(mut raw: Option<RawLog>) -> Option<Self> {
         for i in 0..get_device_count() {
             if let Some(HWInfo { serial: ref s, .. }) = get_device_info(i) {
                 if !s.contains("978") {
@@ -70,39 +230,57 @@ impl UAT {
 
                 let mut dump978 = Dump978::new();
 
-                let (tx, rx) = channel();
+                let paused = Arc::new(AtomicBool::new(false));
+                let thread_paused = paused.clone();
+
+                let (tx, rx) = queue("UAT");
 
                 // this thread is responsible for reading the SDR device and fed
                 // dump978
                 let handle = spawn(move || {
-                    let mut buf = vec![0; RTL_SDR_BUF_SIZE * 2];
-                    let mut len = 0_usize;
+                    let mut buf = SampleBuffer::new(RTL_SDR_BUF_SIZE, SAMPLE_BUF_READS);
+                    let mut stats = DecodeStats::new(TrafficSource::UAT);
 
                     loop {
-                        let read_end = len + RTL_SDR_BUF_SIZE;
+                        if wait_resumed(&thread_paused) {
+                            dev.reset_buffer().ok();
+                            stats = DecodeStats::new(TrafficSource::UAT);
+                        }
 
-                        match dev.read(&mut buf[len..read_end]) {
+                        match dev.read(buf.writable()) {
                             Ok(n) => {
                                 trace!("UAT read {} bytes", n);
-                                len += n;
+                                buf.commit(n);
 
-                                // feed libdump978
-                                let Move { start, end } = dump978.process_data(&mut buf[..len]);
-                                if start != end {
-                                    shift(&mut buf, start, end);
-                                    len = end - start;
-                                } else {
-                                    len = 0;
+                                if stats.due() {
+                                    // before libdump978 gets the samples
+                                    stats.noise_floor = noise_floor(buf.readable());
                                 }
 
+                                // feed libdump978
+                                let Move { start, end } = dump978.process_data(buf.readable());
+                                buf.retain(start, end);
+
                                 // process new data
                                 let mut acc = 0_usize;
+                                let mut lines = String::new();
+                                let now = SystemTime::now();
                                 while let Some(item) = dump978.parsed_as_mut_ref().pop_front() {
-                                    tx.send(item).unwrap();
+                                    if raw.is_some() {
+                                        lines.push_str(&raw_line(&item, now));
+                                    }
+                                    parse_frame(item, &mut stats, &mut |d| tx.send(d));
                                     acc += 1;
                                 }
+                                if let Some(ref mut r) = raw {
+                                    r.write(&lines);
+                                }
 
                                 debug!("dump978 returned {} frames", acc);
+
+                                if let Some(s) = stats.take() {
+                                    tx.send(SensorData::DecodeStats(s));
+                                }
                             }
                             Err(e) => {
                                 if e.kind() == io::ErrorKind::TimedOut {
@@ -118,6 +296,7 @@ impl UAT {
                 return Some(UAT {
                     _handle: handle,
                     rx,
+                    paused,
                 });
             }
         }
@@ -128,7 +307,29 @@ impl UAT {
     }
 }
 
-fn parse_adsb_downlink(buf: &[u8]) -> TrafficData {
+/// Decodes an ADS-B payload, payloads shorter than their payload type
+/// requires are rejected
+pub fn parse_adsb_downlink(buf: &[u8]) -> Result<TrafficData, String> {
+    if buf.is_empty() {
+        return Err("empty ADS-B payload".to_string());
+    }
+
+    // only payload type 0 fits in a basic frame, all others are long
+    let payload_type = (buf[0] & 0xF8) >> 3;
+    let size = if payload_type == 0 {
+        BASIC_PAYLOAD_SIZE
+    } else {
+        LONG_PAYLOAD_SIZE
+    };
+
+    if buf.len() < size {
+        return Err(format!(
+            "{} byte ADS-B payload too short for payload type {}",
+            buf.len(),
+            payload_type
+        ));
+    }
+
     let mut trfc = TrafficData {
         addr: (
             ((buf[1] as u32) << 16) | ((buf[2] as u32) << 8) | buf[3] as u32,
@@ -137,6 +338,8 @@ fn parse_adsb_downlink(buf: &[u8]) -> TrafficData {
                 1 => AddressType::ADSBOther,
                 2 => AddressType::TISBICAO,
                 3 => AddressType::TISBOther,
+                4 => AddressType::SurfaceVehicle,
+                5 => AddressType::FixedBeacon,
                 6 => AddressType::ADSRICAO,
                 _ => AddressType::Unknown,
             },
@@ -152,58 +355,83 @@ fn parse_adsb_downlink(buf: &[u8]) -> TrafficData {
         lat_lon: None,
         nic: None,
         nacp: None,
+        nacv: None,
+        sil: None,
+        ident: None,
+        capability: None,
         on_ground: None,
         source: TrafficSource::UAT,
+        received: None,
+        trace: None,
+        raw: None,
     };
 
-    let payload_type = (buf[0] & 0xF8) >> 3;
-
     if payload_type == 1 || payload_type == 3 {
-        let b40 = (buf[17] as u16) << 8 | buf[18] as u16;
-        trfc.category = Some((b40 / 1600) as u8);
-
-        if (buf[26] >> 1) & 0x01 == 1 {
-            // callsign ID = 1
-            let mut callsign = String::with_capacity(8);
-            let alphabet = CALLSIGN_BASE40.as_bytes();
-
-            callsign.push(alphabet[(b40 % 1600 / 40) as usize] as char);
-            callsign.push(alphabet[(b40 % 40) as usize] as char);
+        mode_status(buf, &mut trfc);
+    }
 
-            let b40 = (buf[19] as u16) << 8 | buf[20] as u16;
-            callsign.push(alphabet[(b40 / 1600) as usize] as char);
-            callsign.push(alphabet[(b40 % 1600 / 40) as usize] as char);
-            callsign.push(alphabet[(b40 % 40) as usize] as char);
+    // sent in all types of payload
+    state_vector(buf, &mut trfc);
 
-            let b40 = (buf[21] as u16) << 8 | buf[22] as u16;
-            callsign.push(alphabet[(b40 / 1600) as usize] as char);
-            callsign.push(alphabet[(b40 % 1600 / 40) as usize] as char);
-            callsign.push(alphabet[(b40 % 40) as usize] as char);
+    Ok(trfc)
+}
 
+/// Decodes the mode status element of a long `buf`
+fn mode_status(buf: &[u8], trfc: &mut TrafficData) {
+    let b40 = (buf[17] as u16) << 8 | buf[18] as u16;
+    let b40_2 = (buf[19] as u16) << 8 | buf[20] as u16;
+    let b40_3 = (buf[21] as u16) << 8 | buf[22] as u16;
+    trfc.category = Some(category_from_uat((b40 / 1600) as u8));
+
+    if (buf[26] >> 1) & 0x01 == 1 {
+        // callsign ID = 1
+        let alphabet = CALLSIGN_BASE40.as_bytes();
+
+        // `None` if a character is not in the alphabet
+        let callsign: Option<String> = [
+            b40 % 1600 / 40,
+            b40 % 40,
+            b40_2 / 1600,
+            b40_2 % 1600 / 40,
+            b40_2 % 40,
+            b40_3 / 1600,
+            b40_3 % 1600 / 40,
+            b40_3 % 40,
+        ]
+        .iter()
+        .map(|c| alphabet.get(*c as usize).map(|a| *a as char))
+        .collect();
+
+        if let Some(callsign) = callsign {
             let trimmed = callsign.trim();
             if trimmed.len() > 0 {
                 trfc.callsign = Some(trimmed.into());
             }
-        } else if (buf[23] >> 2) & 0x07 >= 2 {
-            // uat_version >= 2
-            let mut squawk = 0;
-
-            squawk += b40 % 1600 / 40 * 1000;
-            squawk += b40 % 40 * 100;
-
-            let b40 = (buf[19] as u16) << 8 | buf[20] as u16;
-            squawk += b40 / 1600 * 10;
-            squawk += b40 % 1600 / 40;
-
-            trfc.squawk = Some(squawk);
         }
+    } else if (buf[23] >> 2) & 0x07 >= 2 {
+        // uat_version >= 2
+        let digits = [b40 % 1600 / 40, b40 % 40, b40_2 / 1600, b40_2 % 1600 / 40];
 
-        trfc.nacp = Some((buf[25] >> 4) & 0x0F);
-        // emergency status currently not extracted
-    } // type == 1 | 2
+        // squawk digits are octal
+        if digits.iter().all(|d| *d < 8) {
+            trfc.squawk = Some(digits.iter().fold(0, |squawk, d| squawk * 10 + d));
+        }
+    }
 
-    // parse SV (sent in all types of payload)
+    trfc.sil = Some(buf[23] & 0x03);
+    trfc.nacp = Some((buf[25] >> 4) & 0x0F);
+    trfc.nacv = Some((buf[25] >> 1) & 0x07);
+    trfc.ident = Some(buf[26] & 0x10 != 0);
+    trfc.capability = Some(Capability {
+        cdti: buf[26] & 0x80 != 0,
+        tcas: buf[26] & 0x40 != 0,
+    });
+    // emergency status currently not extracted
+}
 
+/// Decodes the state vector, after the mode status so that ADS-R can be told
+/// apart from TIS-B
+fn state_vector(buf: &[u8], trfc: &mut TrafficData) {
     trfc.nic = Some(buf[11] & 0x0F);
 
     if trfc.addr.1 == AddressType::TISBICAO {
@@ -215,6 +443,12 @@ fn parse_adsb_downlink(buf: &[u8]) -> TrafficData {
         }
     }
 
+    // TIS-B targets are tracked by radar, the capability codes are not theirs
+    match trfc.addr.1 {
+        AddressType::TISBICAO | AddressType::TISBOther => trfc.capability = None,
+        _ => {}
+    }
+
     let raw_lat = (buf[4] as u32) << 15 | (buf[5] as u32) << 7 | buf[6] as u32 >> 1;
     let raw_lon = ((buf[6] & 0x01) as u32) << 23
         | (buf[7] as u32) << 15
@@ -302,7 +536,7 @@ fn parse_adsb_downlink(buf: &[u8]) -> TrafficData {
             trfc.on_ground = Some(true);
 
             let raw_gs = ((buf[12] & 0x1F) as u16) << 6 | (buf[13] & 0xFC) as u16 >> 2;
-            if raw_gs != 0 {
+            if raw_gs & 0x3FF != 0 {
                 trfc.speed = Some(((raw_gs & 0x3FF) - 1, SpeedType::GS));
             }
 
@@ -320,30 +554,137 @@ fn parse_adsb_downlink(buf: &[u8]) -> TrafficData {
         }
         st => warn!("unknown A/C status: {}", st),
     }
+}
+
+/// Decodes a frame on the SDR thread so the main loop only has to forward it.
+/// ADS-B frames keep their raw payload along with the decoded traffic.
+fn parse_frame(u: Frame, stats: &mut DecodeStats, send: &mut FnMut(SensorData)) {
+    trace!("UAT: {:?}", u);
+
+    if u.rs_error > 0 {
+        stats.corrected_frames += 1;
+        stats.corrected_errors += u.rs_error;
+    }
+
+    let received = Some(Instant::now());
+
+    match u.frame_type {
+        FrameType::GroundUplink => {
+            stats.uplink_frames += 1;
+            send(SensorData::FISB(FISBData {
+                payload: u.payload,
+                received,
+            }))
+        }
+        FrameType::ADSBShort | FrameType::ADSBLong => {
+            stats.downlink_frames += 1;
+            match parse_adsb_downlink(&u.payload) {
+                Ok(t) => send(SensorData::Traffic(TrafficData {
+                    received,
+                    raw: Some(u.payload),
+                    ..t
+                })),
+                Err(e) => debug!("UAT: {}", e),
+            }
+        }
+    }
+}
+
+/// Decode a capture of raw 8 bit I/Q samples taken at 978 MHz and the UAT
+/// sample rate (e.g. `rtl_sdr -f 978000000 -s 2083334`), handing everything
+/// decoded to `send` the same way live reception does
+pub fn decode_capture<R: Read>(mut capture: R, send: &mut FnMut(SensorData)) -> io::Result<()> {
+    let mut dump978 = Dump978::new();
+    let mut buf = SampleBuffer::new(RTL_SDR_BUF_SIZE, SAMPLE_BUF_READS);
+    let mut stats = DecodeStats::new(TrafficSource::UAT);
+
+    loop {
+        let n = try!(capture.read(buf.writable()));
+        if n == 0 {
+            return Ok(());
+        }
+        buf.commit(n);
+
+        let Move { start, end } = dump978.process_data(buf.readable());
+        buf.retain(start, end);
 
-    return trfc;
+        while let Some(item) = dump978.parsed_as_mut_ref().pop_front() {
+            parse_frame(item, &mut stats, send);
+        }
+    }
 }
 
 impl Sensor for UAT {
     fn run(&mut self, h: &mut Pushable<SensorData>) {
-        for u in self.rx.try_iter() {
-            trace!("UAT: {:?}", u);
-
-            match u.frame_type {
-                FrameType::GroundUplink => {
-                    h.push_data(SensorData::FISB(FISBData { payload: u.payload }))
-                }
-                FrameType::ADSBShort | FrameType::ADSBLong => {
-                    h.push_data(SensorData::Traffic(parse_adsb_downlink(&u.payload)))
-                }
-            }
+        for d in self.rx.try_iter() {
+            h.push_data(d);
         }
     }
+
+    fn pause(&mut self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&mut self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sample_buffer() {
+        let mut b = SampleBuffer::new(4, 2);
+
+        b.writable().copy_from_slice(&[1, 2, 3, 4]);
+        b.commit(4);
+        assert_eq!(b.readable(), &[1, 2, 3, 4]);
+
+        // last two samples are left over
+        b.retain(2, 4);
+        assert_eq!(b.readable(), &[3, 4]);
+
+        // short read
+        b.writable()[..2].copy_from_slice(&[5, 6]);
+        b.commit(2);
+        assert_eq!(b.readable(), &[3, 4, 5, 6]);
+        b.retain(1, 4);
+
+        // no room for another read without compacting
+        assert_eq!(b.writable().len(), 4);
+        assert_eq!(b.start, 0);
+        b.writable().copy_from_slice(&[7, 8, 9, 10]);
+        b.commit(4);
+        assert_eq!(b.readable(), &[4, 5, 6, 7, 8, 9, 10]);
+
+        b.retain(7, 7);
+        assert!(b.readable().is_empty());
+        assert_eq!(b.end, 0);
+    }
+
+    #[test]
+    fn test_raw_line() {
+        let time = UNIX_EPOCH + Duration::from_millis(1514764800123);
+        let downlink = Frame {
+            frame_type: FrameType::ADSBShort,
+            payload: vec![0x0B, 0x28, 0xC0],
+            rs_error: 2,
+        };
+        assert_eq!(
+            raw_line(&downlink, time),
+            "-0b28c0;rs=2;t=1514764800.123;\n"
+        );
+
+        let uplink = Frame {
+            frame_type: FrameType::GroundUplink,
+            payload: vec![0xFF, 0x00],
+            rs_error: 0,
+        };
+        assert_eq!(raw_line(&uplink, time), "+ff00;t=1514764800.123;\n");
+    }
 
     #[test]
     fn test_parse_adsb_downlink() {
@@ -364,10 +705,17 @@ mod tests {
             lat_lon: Some((37.456383, -122.17355)),
             nic: Some(6),
             nacp: Some(8),
+            nacv: Some(1),
+            sil: Some(2),
+            ident: Some(false),
+            capability: None,
             on_ground: Some(false),
             source: TrafficSource::UAT,
+            received: None,
+            trace: None,
+            raw: None,
         };
-        assert_eq!(parse_adsb_downlink(&payload), exp);
+        assert_eq!(parse_adsb_downlink(&payload), Ok(exp));
 
         let payload = [
             8, 166, 98, 159, 46, 182, 45, 99, 174, 214, 28, 42, 0, 30, 44, 128, 24, 9, 229, 187,
@@ -386,10 +734,20 @@ mod tests {
             lat_lon: Some((32.844100, -109.91043)),
             nic: Some(10),
             nacp: Some(10),
+            nacv: Some(0),
+            sil: Some(2),
+            ident: Some(false),
+            capability: Some(Capability {
+                cdti: true,
+                tcas: false,
+            }),
             on_ground: Some(false),
             source: TrafficSource::UAT,
+            received: None,
+            trace: None,
+            raw: None,
         };
-        assert_eq!(parse_adsb_downlink(&payload), exp);
+        assert_eq!(parse_adsb_downlink(&payload), Ok(exp));
 
         let payload = [
             8, 165, 16, 171, 63, 198, 127, 123, 20, 102, 6, 169, 16, 168, 61, 160, 40, 6, 229, 19,
@@ -408,10 +766,20 @@ mod tests {
             lat_lon: Some((44.842050, -93.459595)),
             nic: Some(9),
             nacp: Some(10),
+            nacv: Some(2),
+            sil: Some(3),
+            ident: Some(false),
+            capability: Some(Capability {
+                cdti: true,
+                tcas: true,
+            }),
             on_ground: Some(false),
             source: TrafficSource::UAT,
+            received: None,
+            trace: None,
+            raw: None,
         };
-        assert_eq!(parse_adsb_downlink(&payload), exp);
+        assert_eq!(parse_adsb_downlink(&payload), Ok(exp));
 
         let payload = [
             10, 163, 166, 85, 63, 125, 231, 123, 194, 150, 7, 32, 1, 170, 10, 64, 223, 9, 219, 19,
@@ -430,9 +798,62 @@ mod tests {
             lat_lon: Some((44.642665, -92.98117)),
             nic: Some(0),
             nacp: Some(9),
+            nacv: Some(0),
+            sil: Some(0),
+            ident: Some(false),
+            capability: None,
             on_ground: Some(false),
             source: TrafficSource::UAT,
+            received: None,
+            trace: None,
+            raw: None,
         };
-        assert_eq!(parse_adsb_downlink(&payload), exp);
+        assert_eq!(parse_adsb_downlink(&payload), Ok(exp));
+    }
+
+    #[test]
+    fn test_parse_malformed_downlink() {
+        assert!(parse_adsb_downlink(&[]).is_err());
+        // basic payload
+        assert!(parse_adsb_downlink(&[0x00; BASIC_PAYLOAD_SIZE - 1]).is_err());
+        assert!(parse_adsb_downlink(&[0x00; BASIC_PAYLOAD_SIZE]).is_ok());
+
+        let mut payload = [
+            8, 166, 98, 159, 46, 182, 45, 99, 174, 214, 28, 42, 0, 30, 44, 128, 24, 9, 229, 187,
+            168, 230, 196, 6, 120, 160, 130, 0, 0, 28, 96, 0, 0, 0,
+        ];
+
+        // long payload cut down to a basic one
+        assert_eq!(
+            parse_adsb_downlink(&payload[..BASIC_PAYLOAD_SIZE]),
+            Err("18 byte ADS-B payload too short for payload type 1".to_string())
+        );
+        assert!(parse_adsb_downlink(&payload[..LONG_PAYLOAD_SIZE - 1]).is_err());
+
+        // call sign character past the end of the alphabet
+        payload[19] = 0xFF;
+        payload[20] = 0xFF;
+        let t = parse_adsb_downlink(&payload).unwrap();
+        assert_eq!((t.callsign, t.category), (None, Some(1)));
+
+        // UAT version 2 sending squawk 2507 instead of a call sign
+        payload[17..21].clone_from_slice(&[0x06, 0x95, 0x01, 0x18]);
+        payload[23] = 0x0A;
+        payload[26] &= !0x02;
+        assert_eq!(parse_adsb_downlink(&payload).unwrap().squawk, Some(2507));
+        // digit that is not octal
+        payload[20] = 0x40;
+        assert_eq!(parse_adsb_downlink(&payload).unwrap().squawk, None);
+
+        // on ground, ground speed with only the unused bit set
+        payload[12] = 0x90;
+        payload[13] = 0x00;
+        let t = parse_adsb_downlink(&payload).unwrap();
+        assert_eq!((t.on_ground, t.speed), (Some(true), None));
+
+        // garbled, but long enough
+        for b in 0..=0xFF_u8 {
+            assert!(parse_adsb_downlink(&[b; LONG_PAYLOAD_SIZE]).is_ok());
+        }
     }
 }
@@ -0,0 +1,162 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persistent storage for state that should survive a restart.
+//!
+//! Pitot is expected to run with a read-only root file system, so everything
+//! is written into a single configurable data directory. If that directory is
+//! not writable, persistence is disabled and every `save` becomes a no-op.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct Storage {
+    dir: Option<PathBuf>,
+}
+
+impl Storage {
+    pub fn new(dir: &str) -> Self {
+        let dir = PathBuf::from(dir);
+
+        match fs::create_dir_all(&dir).and_then(|_| write_atomic(&dir, ".probe", &[])) {
+            Ok(_) => {
+                info!("persisting data in {}", dir.display());
+                Storage { dir: Some(dir) }
+            }
+            Err(e) => {
+                warn!(
+                    "data directory {} is not writable: {}, persistence disabled",
+                    dir.display(),
+                    e
+                );
+                Storage::disabled()
+            }
+        }
+    }
+
+    /// Storage that never persists anything
+    pub fn disabled() -> Self {
+        Storage { dir: None }
+    }
+
+    /// Read back what was last saved under `name`
+    pub fn load(&self, name: &str) -> Option<Vec<u8>> {
+        let dir = match self.dir {
+            Some(ref d) => d,
+            None => return None,
+        };
+
+        let mut buf = Vec::new();
+        match File::open(dir.join(name)).and_then(|mut f| f.read_to_end(&mut buf)) {
+            Ok(_) => Some(buf),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("unable to read {} from storage: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Forget what was saved under `name`
+    pub fn remove(&self, name: &str) {
+        if let Some(ref dir) = self.dir {
+            match fs::remove_file(dir.join(name)) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("unable to remove {} from storage: {}", name, e)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Location of `name` inside the data directory, `None` if persistence
+    /// is disabled
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|d| d.join(name))
+    }
+
+    /// Append `line` to the log kept under `name`
+    pub fn append(&self, name: &str, line: &str) {
+        if let Some(ref dir) = self.dir {
+            let res = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(name))
+                .and_then(|mut f| {
+                    try!(writeln!(f, "{}", line));
+                    f.sync_all()
+                });
+
+            if let Err(e) = res {
+                warn!("unable to append to {} in storage: {}", name, e);
+            }
+        }
+    }
+
+    /// Save `data` under `name`, replacing the previous content atomically
+    /// so a power loss never leaves a partially written file behind
+    pub fn save(&self, name: &str, data: &[u8]) {
+        if let Some(ref dir) = self.dir {
+            if let Err(e) = write_atomic(dir, name, data) {
+                warn!("unable to write {} to storage: {}", name, e);
+            }
+        }
+    }
+}
+
+fn write_atomic(dir: &PathBuf, name: &str, data: &[u8]) -> io::Result<()> {
+    let tmp = dir.join(format!("{}.tmp", name));
+
+    {
+        let mut f = try!(File::create(&tmp));
+        try!(f.write_all(data));
+        try!(f.sync_all());
+    }
+
+    fs::rename(&tmp, dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::temp_dir;
+
+    #[test]
+    fn test_storage() {
+        let dir = temp_dir("storage");
+        let s = Storage::new(dir.to_str().unwrap());
+
+        assert_eq!(s.load("foo"), None);
+        s.save("foo", b"bar");
+        assert_eq!(s.load("foo"), Some(b"bar".to_vec()));
+        s.save("foo", b"baz");
+        assert_eq!(s.load("foo"), Some(b"baz".to_vec()));
+        s.remove("foo");
+        assert_eq!(s.load("foo"), None);
+
+        s.append("log", "one");
+        s.append("log", "two");
+        assert_eq!(s.load("log"), Some(b"one\ntwo\n".to_vec()));
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let s = Storage::disabled();
+        s.save("foo", b"bar");
+        assert_eq!(s.load("foo"), None);
+    }
+}
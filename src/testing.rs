@@ -0,0 +1,107 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Helpers for unit testing a single stage of the pipeline.
+
+use chrono::prelude::*;
+use chrono::Duration as UTCDuration;
+use pitot::handle::{Handle, Pushable};
+use processor::{Processor, Report};
+use protocol::{Payload, Protocol};
+use sensor::SensorData;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, fs, mem, process};
+
+/// A handle whose clock only moves when told to, capturing everything
+/// pushed into it
+pub struct MockHandle<D> {
+    utc: DateTime<UTC>,
+    clock: Instant,
+    freq: u16,
+    pushed: Vec<D>,
+}
+
+impl<D> MockHandle<D> {
+    pub fn new(freq: u16) -> Self {
+        Self {
+            utc: UTC::now(),
+            clock: Instant::now(),
+            freq,
+            pushed: Vec::new(),
+        }
+    }
+
+    /// Move both the monotonic and the wall clock forward by `d`
+    pub fn advance(&mut self, d: Duration) {
+        self.clock += d;
+        self.utc = self.utc + UTCDuration::from_std(d).unwrap();
+    }
+
+    pub fn advance_secs(&mut self, secs: u64) {
+        self.advance(Duration::from_secs(secs));
+    }
+
+    /// Everything pushed since the last call
+    pub fn take(&mut self) -> Vec<D> {
+        mem::replace(&mut self.pushed, Vec::new())
+    }
+}
+
+impl<D> Handle for MockHandle<D> {
+    fn get_utc(&self) -> DateTime<UTC> {
+        self.utc
+    }
+
+    fn get_clock(&self) -> Instant {
+        self.clock
+    }
+
+    fn get_frequency(&self) -> u16 {
+        self.freq
+    }
+}
+
+impl<D> Pushable<D> for MockHandle<D> {
+    fn push_data(&mut self, d: D) {
+        self.pushed.push(d);
+    }
+}
+
+/// Run `p` once with `input` as this iteration's sensor data
+pub fn run_processor(p: &mut Processor, h: &mut MockHandle<Report>, input: &[SensorData]) {
+    p.run(h, input.iter().chain([].iter()));
+}
+
+/// Hand `p` the reports `input` of the processors it was linked after
+pub fn run_reports(p: &mut Processor, h: &mut MockHandle<Report>, input: &[Report]) {
+    let reports: Vec<&Report> = input.iter().collect();
+    p.run_reports(h, &reports);
+}
+
+/// Run `p` once with `input` as this iteration's reports
+pub fn run_protocol(p: &mut Protocol, h: &mut MockHandle<Payload>, input: &[Report]) {
+    p.run(h, input.iter().chain([].iter()));
+}
+
+/// Empty directory for test `name` to keep its files in, also unique to
+/// the process so test runs in parallel do not share it
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("pitot-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    dir
+}
@@ -0,0 +1,139 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Time from receiving data, e.g. decoding a frame on the SDR thread, to
+//! sending the payload generated from it over the network
+
+use protocol::Class;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+// latest samples of each class percentiles are taken over
+const WINDOW: usize = 1000;
+
+/// Latency percentiles of one class over the latest `WINDOW` payloads
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentiles {
+    /// Median in ms
+    pub p50: f32,
+    /// 95th percentile in ms
+    pub p95: f32,
+    /// Payloads measured since Pitot started
+    pub samples: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyStatus {
+    /// `None` until a payload of the class was sent
+    pub traffic: Option<Percentiles>,
+    pub uplink: Option<Percentiles>,
+    pub ownship: Option<Percentiles>,
+}
+
+#[derive(Debug, Default)]
+pub struct Latency {
+    /// in ms, indexed by `Class`
+    window: [VecDeque<f32>; 3],
+    samples: [u64; 3],
+}
+
+impl Latency {
+    /// Payload of `class` with data `received` was sent at `now`
+    pub fn record(&mut self, class: Class, received: Instant, now: Instant) {
+        let window = &mut self.window[class as usize];
+
+        if window.len() == WINDOW {
+            window.pop_front();
+        }
+        let d = now.duration_since(received);
+        window.push_back(d.as_secs() as f32 * 1000.0 + d.subsec_nanos() as f32 / 1e6);
+        self.samples[class as usize] += 1;
+    }
+
+    pub fn status(&self) -> LatencyStatus {
+        LatencyStatus {
+            traffic: self.percentiles(Class::Traffic),
+            uplink: self.percentiles(Class::Uplink),
+            ownship: self.percentiles(Class::Ownship),
+        }
+    }
+
+    fn percentiles(&self, class: Class) -> Option<Percentiles> {
+        let mut sorted: Vec<f32> = self.window[class as usize].iter().cloned().collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        // nearest rank
+        let rank = |p: f32| sorted[((p * sorted.len() as f32).ceil() as usize).max(1) - 1];
+
+        Some(Percentiles {
+            p50: rank(0.5),
+            p95: rank(0.95),
+            samples: self.samples[class as usize],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_percentiles() {
+        let mut l = Latency::default();
+        let now = Instant::now();
+
+        assert_eq!(l.status().traffic, None);
+
+        for ms in (1..101).rev() {
+            l.record(Class::Traffic, now, now + Duration::from_millis(ms));
+        }
+        l.record(Class::Ownship, now, now + Duration::from_millis(20));
+
+        let status = l.status();
+        assert_eq!(
+            status.traffic,
+            Some(Percentiles {
+                p50: 50.0,
+                p95: 95.0,
+                samples: 100,
+            })
+        );
+        assert_eq!(status.uplink, None);
+        assert_eq!(status.ownship.unwrap().p95, 20.0);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut l = Latency::default();
+        let now = Instant::now();
+
+        for _ in 0..WINDOW {
+            l.record(Class::Uplink, now, now + Duration::from_secs(1));
+        }
+        for _ in 0..WINDOW {
+            l.record(Class::Uplink, now, now);
+        }
+
+        // only the latest samples count
+        let uplink = l.status().uplink.unwrap();
+        assert_eq!(uplink.p95, 0.0);
+        assert_eq!(uplink.samples, 2 * WINDOW as u64);
+    }
+}
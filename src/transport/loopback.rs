@@ -0,0 +1,216 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! End-to-end check of the GDL90 output. Every datagram sent to all clients
+//! is also sent to a socket on this device, which decodes it like an EFB
+//! would and checks that heartbeats keep arriving once per second and that
+//! every message passes its CRC. A transport that silently stops sending
+//! fails the check.
+
+use super::LoopbackStatus;
+use protocol::gdl90::parse_stream;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+use utils::secs_f32;
+
+/// heartbeats are sent once per second
+const HEARTBEAT_TIMEOUT_SECS: u64 = 3;
+/// fail for this long after a CRC error
+const CRC_ERROR_HOLD_SECS: u64 = 10;
+
+pub struct Loopback {
+    tx: UdpSocket,
+    rx: UdpSocket,
+    /// holds the largest datagram sent to clients
+    buf: Vec<u8>,
+    /// when the last two heartbeats were received
+    heartbeats: (Option<Instant>, Option<Instant>),
+    last_crc_error: Option<Instant>,
+    datagrams_sent: u64,
+    datagrams_received: u64,
+    messages: u64,
+    crc_errors: u64,
+}
+
+impl Loopback {
+    /// Check datagrams of up to `max_datagram` bytes
+    pub fn new(max_datagram: usize) -> io::Result<Self> {
+        let rx = try!(UdpSocket::bind("127.0.0.1:0"));
+        try!(rx.set_nonblocking(true));
+
+        let tx = try!(UdpSocket::bind("127.0.0.1:0"));
+        try!(tx.set_nonblocking(true));
+        try!(tx.connect(try!(rx.local_addr())));
+
+        Ok(Self {
+            tx,
+            rx,
+            buf: vec![0; max_datagram],
+            heartbeats: (None, None),
+            last_crc_error: None,
+            datagrams_sent: 0,
+            datagrams_received: 0,
+            messages: 0,
+            crc_errors: 0,
+        })
+    }
+
+    /// Send `buffer` the way it is sent to clients
+    pub fn send(&mut self, buffer: &[u8]) {
+        match self.tx.send(buffer) {
+            Ok(_) => self.datagrams_sent += 1,
+            Err(e) => debug!("unable to send loopback datagram: {}", e),
+        }
+    }
+
+    /// Check everything received since the last call
+    pub fn receive(&mut self, clock: Instant) {
+        let mut buf = mem::replace(&mut self.buf, Vec::new());
+
+        loop {
+            match self.rx.recv(&mut buf) {
+                Ok(n) => self.check(&buf[..n], clock),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("unable to receive loopback datagram: {}", e);
+                    break;
+                }
+            }
+        }
+
+        self.buf = buf;
+    }
+
+    fn check(&mut self, datagram: &[u8], clock: Instant) {
+        self.datagrams_received += 1;
+
+        for m in parse_stream(datagram) {
+            match m {
+                Ok(ref m) if m[0] == 0x00 => {
+                    self.messages += 1;
+                    self.heartbeats = (Some(clock), self.heartbeats.0);
+                }
+                Ok(_) => self.messages += 1,
+                Err(e) => {
+                    warn!("loopback: {}", e);
+                    self.crc_errors += 1;
+                    self.last_crc_error = Some(clock);
+                }
+            }
+        }
+    }
+
+    pub fn status(&self, clock: Instant) -> LoopbackStatus {
+        let mut problems = Vec::new();
+
+        let heartbeat_age = self.heartbeats.0.map(|h| clock - h);
+        match heartbeat_age {
+            None => problems.push("no heartbeat received".to_string()),
+            Some(age) if age > Duration::from_secs(HEARTBEAT_TIMEOUT_SECS) => problems.push(
+                format!("no heartbeat received for {} seconds", age.as_secs()),
+            ),
+            _ => {}
+        }
+
+        if self.last_crc_error.map_or(false, |e| {
+            clock - e < Duration::from_secs(CRC_ERROR_HOLD_SECS)
+        }) {
+            problems.push("CRC errors".to_string());
+        }
+
+        LoopbackStatus {
+            pass: problems.is_empty(),
+            problems,
+            heartbeat_age: heartbeat_age.map(secs_f32),
+            heartbeat_interval: match self.heartbeats {
+                (Some(last), Some(previous)) => Some(secs_f32(last - previous)),
+                _ => None,
+            },
+            datagrams_sent: self.datagrams_sent,
+            datagrams_received: self.datagrams_received,
+            messages: self.messages,
+            crc_errors: self.crc_errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::gdl90::GDL90;
+
+    fn message(buf: &mut [u8]) -> Vec<u8> {
+        GDL90::prepare_payload(buf)
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut l = Loopback::new(512).unwrap();
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+
+        let status = l.status(start);
+        assert!(!status.pass);
+        assert_eq!(status.problems, vec!["no heartbeat received"]);
+
+        let mut datagram = message(&mut [0x00, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0, 0]);
+        datagram.extend(message(&mut [0x0B, 0x00, 0x10, 0, 0]));
+        l.send(&datagram);
+        l.receive(at(0));
+        l.send(&datagram);
+        l.receive(at(1));
+
+        let status = l.status(at(1));
+        assert!(status.pass);
+        assert_eq!(status.heartbeat_interval, Some(1.0));
+        assert_eq!(status.datagrams_received, 2);
+        assert_eq!(status.messages, 4);
+
+        // corrupted CRC
+        let last = datagram.len() - 2;
+        datagram[last] ^= 0x01;
+        l.check(&datagram, at(2));
+        assert_eq!(l.status(at(2)).problems, vec!["CRC errors"]);
+
+        let status = l.status(at(12));
+        assert!(!status.pass);
+        assert_eq!(
+            status.problems,
+            vec!["no heartbeat received for 10 seconds"]
+        );
+        assert_eq!(status.crc_errors, 1);
+    }
+    #[test]
+    fn test_large_datagram() {
+        let mut l = Loopback::new(65507).unwrap();
+
+        // coalesced far beyond a typical MTU
+        let mut datagram = Vec::new();
+        for _ in 0..1000 {
+            datagram.extend(message(&mut [0x0B, 0x00, 0x10, 0, 0]));
+        }
+        assert!(datagram.len() > 2048);
+
+        l.send(&datagram);
+        l.receive(Instant::now());
+        let status = l.status(Instant::now());
+        assert_eq!(status.datagrams_received, 1);
+        assert_eq!(status.messages, 1000);
+        assert_eq!(status.crc_errors, 0);
+    }
+}
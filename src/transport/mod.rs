@@ -15,15 +15,175 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use pitot::handle::Handle;
+use std::collections::BTreeMap;
 use std::iter::Chain;
+use std::net::Ipv4Addr;
 use std::slice::Iter;
+use std::sync::{Arc, Mutex};
 
-type ChainedIter<'a> = Chain<Iter<'a, Payload>, Iter<'a, Payload>>;
+pub type ChainedIter<'a> = Chain<Iter<'a, Payload>, Iter<'a, Payload>>;
 
+pub mod latency;
+#[cfg(all(target_os = "linux", feature = "gdl90"))]
+pub mod loopback;
+#[cfg(target_os = "linux")]
+pub mod ping;
+#[cfg(target_os = "linux")]
 pub mod udp;
 
+use self::latency::LatencyStatus;
 use protocol::Payload;
 
+/// Kept up to date by the transport for the status API
+pub type SharedStatus = Arc<Mutex<TransportStatus>>;
+
+#[derive(Debug, Default)]
+pub struct TransportStatus {
+    /// GDL90 clients by IP address
+    pub clients: BTreeMap<Ipv4Addr, ClientStatus>,
+    /// `None` unless `gdl90_loopback` is enabled
+    pub loopback: Option<LoopbackStatus>,
+    /// `None` until the transport runs
+    pub packing: Option<PackingStatus>,
+    /// `None` until the transport runs
+    pub latency: Option<LatencyStatus>,
+    /// WebSocket and Server-Sent Events clients, kept up to date by the
+    /// WebSocket protocol
+    pub web_clients: usize,
+}
+
+/// How well payloads sent to every client fill datagrams
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackingStatus {
+    /// largest datagram in bytes, unless a single payload is larger
+    pub max_datagram: usize,
+    pub datagrams: u64,
+    pub bytes: u64,
+    /// average share of `max_datagram` used, 0 to 1
+    pub fill: f32,
+    /// times a datagram that was not full was held to be filled further
+    pub held: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientStatus {
+    /// EFB app detected on the client, if it announced itself
+    pub app: Option<String>,
+    pub profile: Profile,
+    /// UDP ports GDL90 is sent to
+    pub ports: Vec<u16>,
+    /// answering pings
+    pub active: bool,
+    /// not refusing GDL90, so the app is likely in the foreground
+    pub in_app: bool,
+    /// UNIX time the client was first seen
+    pub connected: i64,
+    /// seconds since the client last answered a ping
+    pub last_seen: f32,
+    /// round trip time of the latest ping answered in ms, `None` until one
+    /// is
+    pub rtt: Option<f32>,
+    /// GDL90 bytes sent, over all ports
+    pub bytes_sent: u64,
+    /// times buffered messages were replayed after the app came back
+    pub replays: u32,
+    /// latest state changes, oldest first
+    pub history: Vec<Transition>,
+    /// sends that failed, by why
+    pub send_errors: SendErrors,
+    /// why sending was stopped, `None` unless the client is dropped
+    pub dropped: Option<SendError>,
+}
+
+/// Why sending a datagram to a client failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SendError {
+    /// socket buffers are full
+    WouldBlock,
+    /// ICMP port unreachable, nothing is listening on the port
+    Refused,
+    /// ICMP host or network unreachable, the client is gone
+    Unreachable,
+    Other,
+}
+
+/// Number of failed sends of each kind
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SendErrors {
+    pub would_block: u64,
+    pub refused: u64,
+    pub unreachable: u64,
+    pub other: u64,
+}
+
+impl SendErrors {
+    pub fn count(&mut self, e: SendError) {
+        match e {
+            SendError::WouldBlock => self.would_block += 1,
+            SendError::Refused => self.refused += 1,
+            SendError::Unreachable => self.unreachable += 1,
+            SendError::Other => self.other += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Transition {
+    /// UNIX time of the change
+    pub time: i64,
+    pub state: ClientState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientState {
+    /// not answering pings
+    Asleep,
+    /// reachable, but the app is not receiving
+    Background,
+    InApp,
+    /// sending failed for too long and was stopped
+    Dropped,
+}
+
+/// How GDL90 is sent to a client, picked from the app detected on it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Sent to the configured ports, without the ForeFlight extension
+    /// messages
+    Generic,
+    /// Sent to the port ForeFlight (or an app speaking its protocol) asked
+    /// for, unless the client has ports configured, along with the
+    /// ForeFlight ID and AHRS messages
+    ForeFlight,
+}
+
 pub trait Transport {
     fn run(&mut self, handle: &mut Handle, i: ChainedIter);
+
+    /// Called when this transport is paused at runtime, it is not run again until
+    /// resumed
+    fn pause(&mut self) {}
+
+    /// Called when this transport is resumed after having been paused
+    fn resume(&mut self) {}
+}
+
+/// Outcome of checking the GDL90 output received back on this device
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoopbackStatus {
+    pub pass: bool,
+    /// why the check fails, empty when passing
+    pub problems: Vec<String>,
+    /// seconds since the last heartbeat was received
+    pub heartbeat_age: Option<f32>,
+    /// seconds between the last two heartbeats
+    pub heartbeat_interval: Option<f32>,
+    pub datagrams_sent: u64,
+    pub datagrams_received: u64,
+    /// messages passing the CRC check
+    pub messages: u64,
+    pub crc_errors: u64,
 }
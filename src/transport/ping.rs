@@ -0,0 +1,271 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! ICMP echo over a non-blocking raw socket, shared by every client
+
+use libc::{
+    self, c_int, c_ulong, c_void, sockaddr, sockaddr_in, socklen_t, timeval, AF_INET, IPPROTO_ICMP,
+    SOCK_CLOEXEC, SOCK_NONBLOCK, SOCK_RAW,
+};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// `ioctl` returning when the packet last read was received, from
+/// linux/sockios.h
+const SIOCGSTAMP: c_ulong = 0x8906;
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+/// data carried by every request, and expected back in replies
+const DATA: &[u8] = b"PITOT";
+/// rounds a reply is matched to its request for, older ones have no RTT
+const ROUNDS: usize = 16;
+
+pub struct Pinger {
+    fd: c_int,
+    /// tells our requests apart from those of other programs
+    id: u16,
+    seq: u16,
+    /// when the latest rounds were sent, by sequence number
+    sent: VecDeque<(u16, Instant)>,
+}
+
+/// Reply to one of our requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reply {
+    pub from: Ipv4Addr,
+    pub seq: u16,
+    /// `None` if the request is too old
+    pub rtt: Option<Duration>,
+}
+
+impl Pinger {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                AF_INET,
+                SOCK_RAW | SOCK_NONBLOCK | SOCK_CLOEXEC,
+                IPPROTO_ICMP,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Pinger {
+            fd,
+            id: unsafe { libc::getpid() } as u16,
+            seq: 0,
+            sent: VecDeque::with_capacity(ROUNDS),
+        })
+    }
+
+    /// Requests sent from now on are a new round, with the next sequence
+    /// number
+    pub fn next_round(&mut self) {
+        self.seq = self.seq.wrapping_add(1);
+
+        if self.sent.len() == ROUNDS {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((self.seq, Instant::now()));
+    }
+
+    /// Send a request of the current round to `to`
+    pub fn send(&self, to: Ipv4Addr) -> io::Result<()> {
+        let packet = echo_request(self.id, self.seq);
+
+        let mut addr: sockaddr_in = unsafe { mem::zeroed() };
+        addr.sin_family = AF_INET as _;
+        addr.sin_addr.s_addr = u32::from(to).to_be();
+
+        let n = unsafe {
+            libc::sendto(
+                self.fd,
+                packet.as_ptr() as *const c_void,
+                packet.len(),
+                0,
+                &addr as *const sockaddr_in as *const sockaddr,
+                mem::size_of::<sockaddr_in>() as socklen_t,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Next reply to one of our requests, `None` once there is nothing left
+    /// to read
+    pub fn recv(&mut self) -> io::Result<Option<Reply>> {
+        let mut buf = [0_u8; 128];
+
+        loop {
+            let n = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    ErrorKind::WouldBlock => Ok(None),
+                    ErrorKind::Interrupted => continue,
+                    _ => Err(e),
+                };
+            }
+
+            let (from, id, seq) = match parse_reply(&buf[..n as usize]) {
+                Some(r) => r,
+                None => continue,
+            };
+            if id != self.id {
+                continue;
+            }
+
+            let rtt = match (self.received(), self.sent.iter().find(|s| s.0 == seq)) {
+                (Some(received), Some(&(_, sent))) if received >= sent => Some(received - sent),
+                _ => None,
+            };
+
+            return Ok(Some(Reply { from, seq, rtt }));
+        }
+    }
+
+    /// When the kernel received the packet last read. Its timestamp is on
+    /// the system clock, which the clock processor may step, so only its age
+    /// is taken and moved onto the monotonic clock the requests were sent on.
+    fn received(&self) -> Option<Instant> {
+        let mut tv: timeval = unsafe { mem::zeroed() };
+        if unsafe { libc::ioctl(self.fd, SIOCGSTAMP, &mut tv) } < 0 {
+            return None;
+        }
+
+        let stamp = UNIX_EPOCH + Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+        // the clock was stepped back since the packet arrived, take it as just now
+        let age = SystemTime::now()
+            .duration_since(stamp)
+            .unwrap_or(Duration::from_secs(0));
+
+        Instant::now().checked_sub(age)
+    }
+}
+
+impl Drop for Pinger {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Internet checksum of `data`, RFC 1071
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2).fold(0_u32, |acc, c| {
+        acc + ((c[0] as u32) << 8 | c.get(1).cloned().unwrap_or(0) as u32)
+    });
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn echo_request(id: u16, seq: u16) -> Vec<u8> {
+    let mut packet = vec![
+        ECHO_REQUEST,
+        0,
+        0,
+        0,
+        (id >> 8) as u8,
+        id as u8,
+        (seq >> 8) as u8,
+        seq as u8,
+    ];
+    packet.extend_from_slice(DATA);
+
+    let sum = checksum(&packet);
+    packet[2] = (sum >> 8) as u8;
+    packet[3] = sum as u8;
+
+    packet
+}
+
+/// Source, identifier and sequence number of `packet` if it is an IPv4
+/// datagram carrying an intact echo reply
+fn parse_reply(packet: &[u8]) -> Option<(Ipv4Addr, u16, u16)> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != IPPROTO_ICMP as u8 {
+        return None;
+    }
+
+    let header = (packet[0] & 0x0F) as usize * 4;
+    if packet.len() < header + 8 {
+        return None;
+    }
+
+    let icmp = &packet[header..];
+    if icmp[0] != ECHO_REPLY || icmp[1] != 0 || checksum(icmp) != 0 {
+        return None;
+    }
+
+    Some((
+        Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+        (icmp[4] as u16) << 8 | icmp[5] as u16,
+        (icmp[6] as u16) << 8 | icmp[7] as u16,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo() {
+        let request = echo_request(0x1234, 7);
+        assert_eq!(&request[..8], &[8, 0, 0xED, 0x2B, 0x12, 0x34, 0x00, 0x07]);
+        assert_eq!(checksum(&request), 0);
+
+        // answered by 192.168.10.5, with the same identifier, sequence
+        // number and data
+        let mut reply = vec![
+            0x45, 0, 0, 33, 0, 0, 0x40, 0, 64, 1, 0, 0, 192, 168, 10, 5, 192, 168, 10, 1,
+        ];
+        let mut icmp = request.clone();
+        icmp[0] = ECHO_REPLY;
+        icmp[2] = 0;
+        icmp[3] = 0;
+        let sum = checksum(&icmp);
+        icmp[2] = (sum >> 8) as u8;
+        icmp[3] = sum as u8;
+        reply.extend(icmp);
+
+        assert_eq!(
+            parse_reply(&reply),
+            Some((Ipv4Addr::new(192, 168, 10, 5), 0x1234, 7))
+        );
+
+        // corrupted
+        let mut corrupted = reply.clone();
+        corrupted[25] ^= 0xFF;
+        assert_eq!(parse_reply(&corrupted), None);
+
+        // our own request looped back
+        let mut looped = reply[..20].to_vec();
+        looped.extend(request);
+        assert_eq!(parse_reply(&looped), None);
+
+        assert_eq!(parse_reply(&reply[..24]), None);
+    }
+}
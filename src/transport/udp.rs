@@ -14,67 +14,144 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::latency::Latency;
+use super::ping::Pinger;
 use super::*;
-use icmp::IcmpSocket;
+use chrono::prelude::*;
+use config::Config;
 use inotify::{watch_mask, Inotify};
+use libc::{EHOSTDOWN, EHOSTUNREACH, ENETUNREACH};
 use nom::{be_u32, be_u64, be_u8, IResult};
+use protocol::Class;
+use serde_json::{self, Value};
 use std::collections::VecDeque;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, ErrorKind, Read};
 use std::net::{IpAddr, Ipv4Addr, UdpSocket};
 use std::time::{Duration, Instant};
-use time::{now_utc, Timespec, Tm};
+use utils::secs_f32;
+
+#[cfg(feature = "gdl90")]
+use super::loopback::Loopback;
 
 const LEASE_FILE_PATH: &str = "/tmp/udhcpd.leases";
 const WATCH_PATH: &str = "/tmp";
-const GDL90_PORT: u16 = 4000;
-const UDP_MAX_SIZE: usize = 1472; // maximum UDP payload size without fragmentation in Ethernet environment
+/// ForeFlight and apps speaking its protocol broadcast a JSON announcement
+/// to this port, see: https://www.foreflight.com/connect/spec/
+const DISCOVERY_PORT: u16 = 63093;
 const PAYLOAD_PER_DRAIN: usize = 256; // maximum queueable payload to drain per run
 const INACTIVE_BUFFER_SIZE: usize = 8192; // maximum number of messages to buffer and later reply back to sleeping clients
-const PING_PACKET: [u8; 13] = [
-    0x08,
-    0x00,
-    0x25,
-    0xc9,
-    0xd9,
-    0x9d, // identifier magic
-    0x00,
-    0x00, // sequence number
-    'P' as u8,
-    'I' as u8,
-    'T' as u8,
-    'O' as u8,
-    'T' as u8,
-];
 const PING_FREQ: u32 = 1;
 const DEAD_THRESHOLD: u64 = 15; // if no ping response has been received in this much seconds, consider the client as inactive
 const IN_APP_THRESHOLD: u64 = 30; // if no "connection refused" has been received in this much seconds, consider the client as back to the App
                                   // IN_APP_THRESHOLD should be >= than DEAD_THRESHOLD
 const REPLAY_INTERVAL: u64 = 30; // at mist 1 replay can be delivered to a client in REPLAY_INTERVAL seconds
+const HISTORY_SIZE: usize = 16; // number of state changes kept per client
+/// A port is no longer failing once a send has not failed for this long, in
+/// seconds. Errors from ICMP only show up on the next send, so sends keep
+/// alternating between failing and not while the client is gone.
+const STREAK_GAP: u64 = 5;
+/// Clients whose every port is unreachable for this long are dropped, in
+/// seconds
+const UNREACHABLE_SECS: u64 = 60;
+/// Clients whose every port refuses for this long are dropped, in seconds
+const REFUSED_SECS: u64 = 600;
+/// Clients dropped for refusing are tried again after this long, in seconds,
+/// and dropped again if they still refuse after `PROBE_SECS`
+const RETRY_SECS: u64 = 60;
+const PROBE_SECS: u64 = 10;
 
-struct Client {
+/// One of the ports GDL90 is sent to on a client
+struct Port {
+    port: u16,
     udp_sock: UdpSocket,
-    icmp_sock: IcmpSocket,
+    last_refused: Instant,
+    streak: Option<Streak>,
+}
+
+/// Sends to a port failing the same way without a break
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Streak {
+    kind: SendError,
+    since: Instant,
+    last: Instant,
+}
+
+/// Why and when sending to a client was stopped, and the ports to send to
+/// once it returns
+struct Dropped {
+    reason: SendError,
+    since: Instant,
+    ports: Vec<u16>,
+}
+
+struct Client {
+    ports: Vec<Port>,
+    /// EFB app the client announced
+    app: Option<String>,
+    profile: Profile,
     queue: VecDeque<Payload>,
     active: bool,
     last_reply: Instant,
+    /// round trip time of the latest ping answered
+    rtt: Option<Duration>,
     in_app: bool,
-    last_refused: Instant,
     last_replay: Instant,
+    connected: i64,
+    bytes_sent: u64,
+    replays: u32,
+    history: VecDeque<Transition>,
+    send_errors: SendErrors,
+    /// `None` unless sending was stopped
+    dropped: Option<Dropped>,
+    /// when the client was tried again after being dropped for refusing,
+    /// until a port accepts
+    probing: Option<Instant>,
+}
+
+/// Packs the payloads sent to every client into datagrams
+struct Packer {
+    max_size: usize,
+    /// how long a datagram that is not full may be held to be filled further
+    hold: Option<Duration>,
+    buffer: Vec<u8>,
+    /// when the first payload in `buffer` was added
+    since: Option<Instant>,
+    /// latency of the payloads in `buffer` is measured once it is sent
+    received: Vec<(Class, Instant)>,
+    /// payloads taken out of `buffer` since last asked, to measure
+    sent: Vec<(Class, Instant)>,
+    stats: PackingStatus,
 }
 
 pub struct UDP {
+    config: Config,
+    packer: Packer,
     clients: HashMap<Ipv4Addr, Client>,
+    status: SharedStatus,
+    #[cfg(feature = "gdl90")]
+    loopback: Option<Loopback>,
+    /// receives app announcements, `None` if the port is taken
+    discovery_sock: Option<UdpSocket>,
     inotify: Inotify,
+    pinger: Pinger,
     inactive_buffer: VecDeque<Payload>,
+    latency: Latency,
     ping_counter: u32,
+    /// lease file needs to be read regardless of inotify events
+    stale_clients: bool,
+    /// `written` time stamp of the lease file last read and when it was
+    /// first seen
+    lease_file: Option<(u64, Instant)>,
 }
 
-named_args!(parse_ip_from_lease_file(tm: Timespec, cap: usize)<HashSet<Ipv4Addr>>,
+// Wall clock time the lease file was written at, and each lease with the
+// seconds it had left by then
+named!(pub parse_lease_file<(u64, Vec<(Ipv4Addr, u32)>)>,
        do_parse!(
            written: be_u64 >>
-           ips: fold_many0!(
+           leases: fold_many0!(
                do_parse!(
                    expires: be_u32 >>
                    a: be_u8 >>
@@ -82,15 +159,13 @@ named_args!(parse_ip_from_lease_file(tm: Timespec, cap: usize)<HashSet<Ipv4Addr>
                    c: be_u8 >>
                    d: be_u8 >>
                    take!(6 + 20 + 2) >> // skip mac, hostname and pad
-                   (a, b, c, d, expires)
-               ), HashSet::with_capacity(cap), |mut acc: HashSet<Ipv4Addr>, info: (u8, u8, u8, u8, u32)| {
-                      if tm.sec < (info.4 as u64 + written) as i64 {
-                        acc.insert(Ipv4Addr::new(info.0, info.1, info.2, info.3));
-                      }
+                   (Ipv4Addr::new(a, b, c, d), expires)
+               ), Vec::new(), |mut acc: Vec<_>, lease| {
+                      acc.push(lease);
                       acc
                   }
            ) >>
-        (ips)));
+        ((written, leases))));
 
 impl Transport for UDP {
     fn run(&mut self, handle: &mut Handle, i: ChainedIter) {
@@ -102,40 +177,60 @@ impl Transport for UDP {
 
         for e in events {
             if e.name.to_str().unwrap().contains("udhcpd.leases") {
-                if let Err(e) = self.update_clients_list(handle.get_utc(), handle.get_clock()) {
-                    debug!("unable to update client list: {}", e);
-                }
-
+                self.stale_clients = true;
                 break;
             }
         }
 
-        let mut buffer = Vec::with_capacity(UDP_MAX_SIZE);
+        if self.stale_clients {
+            self.stale_clients = false;
+
+            if let Err(e) = self.update_clients_list(handle.get_utc(), handle.get_clock()) {
+                debug!("unable to update client list: {}", e);
+            }
+        }
 
         for p in i {
             if p.queueable {
-                for (_, c) in self.clients.iter_mut() {
-                    c.queue.push_back(p.clone());
+                // measured once the first client it is queued for sends it,
+                // not once per client
+                let mut received = p.received;
+                for c in self.clients.values_mut().filter(|c| c.dropped.is_none()) {
+                    c.queue.push_back(Payload {
+                        received: received.take(),
+                        ..p.clone()
+                    });
                 }
 
-                self.inactive_buffer.push_front(p.clone());
+                // replays are not measured, and not needed by ground
+                // stations feeding clients that do not sleep
+                if !self.config.ground_station {
+                    self.inactive_buffer.push_front(Payload {
+                        received: None,
+                        ..p.clone()
+                    });
+                }
                 continue;
             }
 
-            if buffer.len() + p.payload.len() > UDP_MAX_SIZE {
-                self.send_to_all_clients(handle.get_clock(), &buffer);
-                buffer.clear();
+            if let Some(datagram) = self.packer.push(&p.payload, p.received, handle.get_clock()) {
+                self.send_to_all_clients(handle.get_clock(), &datagram);
             }
-
-            buffer.extend(p.payload.iter());
         }
 
-        if !buffer.is_empty() {
-            self.send_to_all_clients(handle.get_clock(), &buffer);
+        if let Some(datagram) = self.packer.flush(handle.get_clock()) {
+            self.send_to_all_clients(handle.get_clock(), &datagram);
         }
 
+        let max_size = self.packer.max_size;
+        let mut sent: Vec<_> = self.packer.sent.drain(..).collect();
         for (_, c) in self.clients.iter_mut() {
-            c.drain_queue();
+            c.drain_queue(max_size, handle.get_clock(), &mut sent);
+        }
+
+        let clock = handle.get_clock();
+        for (class, received) in sent {
+            self.latency.record(class, received, clock);
         }
 
         self.inactive_buffer.truncate(INACTIVE_BUFFER_SIZE);
@@ -153,22 +248,50 @@ impl Transport for UDP {
         let mut need_replay = HashSet::new();
 
         for (ip, c) in self.clients.iter_mut() {
-            if (handle.get_clock() - c.last_reply).as_secs() > DEAD_THRESHOLD {
+            let clock = handle.get_clock();
+
+            if let Some(reason) = c.failing(clock) {
+                info!("client {} is {:?} on every port, dropping it", ip, reason);
+                c.drop_ports(reason, clock);
+            } else if c.has_returned(clock) {
+                info!("client {} returned, sending to it again", ip);
+                c.restore(*ip, clock);
+            }
+
+            if (clock - c.last_reply).as_secs() > DEAD_THRESHOLD {
                 c.active = false;
             } else if !c.active {
                 c.active = true;
-                c.last_refused = handle.get_clock();
+                for p in c.ports.iter_mut() {
+                    p.last_refused = clock;
+                }
             }
 
-            if c.active {
-                if (handle.get_clock() - c.last_refused).as_secs() < IN_APP_THRESHOLD {
+            if c.dropped.is_some() {
+                c.in_app = false;
+            } else if c.active {
+                if (handle.get_clock() - c.last_refused()).as_secs() < IN_APP_THRESHOLD {
                     c.in_app = false;
                 } else if !c.in_app {
                     // when iPad is unreachable (sleeping), in_app will appears to be active
                     c.in_app = true;
-                    need_replay.insert(ip.clone());
+                    if !self.config.ground_station {
+                        need_replay.insert(ip.clone());
+                    }
                 }
             }
+
+            let state = c.state();
+            record(&mut c.history, handle.get_utc().timestamp(), state);
+        }
+
+        self.read_announcements(handle.get_clock());
+
+        #[cfg(feature = "gdl90")]
+        {
+            if let Some(ref mut l) = self.loopback {
+                l.receive(handle.get_clock());
+            }
         }
 
         for ip in need_replay {
@@ -179,6 +302,7 @@ impl Transport for UDP {
             }
 
             c.last_replay = handle.get_clock();
+            c.replays += 1;
 
             debug!(
                 "client {} came back online, replaying {} queued messages",
@@ -189,83 +313,220 @@ impl Transport for UDP {
                 c.queue.push_back(p.clone());
             }
         }
+
+        self.update_status(handle.get_clock());
     }
 }
 
 impl UDP {
-    pub fn new() -> Box<Transport> {
+    /// GDL90 is sent to the ports `config` has for each client, or those
+    /// the app on it asked for. Clients are listed in `status`, along with
+    /// the loopback check if `gdl90_loopback` is enabled.
+    pub fn new(config: &Config, status: SharedStatus) -> Box<Transport> {
         let mut inotify = Inotify::init().unwrap();
         inotify
             .add_watch(WATCH_PATH, watch_mask::MODIFY | watch_mask::CREATE)
             .unwrap();
 
-        let mut me = Box::new(UDP {
+        let discovery_sock = match UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)) {
+            Ok(s) => {
+                s.set_nonblocking(true)
+                    .expect("could not set socket to non blocking mode");
+                Some(s)
+            }
+            Err(e) => {
+                warn!(
+                    "unable to listen on UDP port {}: {}, EFB apps will not be detected",
+                    DISCOVERY_PORT, e
+                );
+                None
+            }
+        };
+
+        #[cfg(feature = "gdl90")]
+        let loopback = if config.gdl90_loopback {
+            Loopback::new(config.gdl90_max_datagram)
+                .map_err(|e| error!("unable to set up GDL90 loopback: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        // the lease file is read on the first run, once we know the time
+        Box::new(UDP {
+            config: config.clone(),
+            packer: Packer::new(
+                config.gdl90_max_datagram,
+                match config.gdl90_coalesce_ms {
+                    0 => None,
+                    ms => Some(Duration::from_millis(ms)),
+                },
+            ),
             clients: HashMap::new(),
+            status,
+            #[cfg(feature = "gdl90")]
+            loopback,
+            discovery_sock,
             inotify,
+            pinger: Pinger::new().expect("could not open ICMP socket"),
             inactive_buffer: VecDeque::with_capacity(INACTIVE_BUFFER_SIZE),
+            latency: Latency::default(),
             ping_counter: 0,
-        });
+            stale_clients: true,
+            lease_file: None,
+        })
+    }
+
+    /// Detect the app on clients from the announcements they broadcast, and
+    /// send GDL90 to the port it asks for
+    fn read_announcements(&mut self, clock: Instant) {
+        let mut buf = [0_u8; 512];
+
+        loop {
+            let (n, from) = match self.discovery_sock.as_ref().map(|s| s.recv_from(&mut buf)) {
+                Some(Ok(r)) => r,
+                Some(Err(ref e)) if e.kind() != ErrorKind::WouldBlock => {
+                    debug!("unable to read announcement: {}", e);
+                    return;
+                }
+                _ => return,
+            };
 
-        if let Err(e) = me.update_clients_list(now_utc(), Instant::now()) {
-            debug!("unable to update client list: {}", e);
+            let ip = match from.ip() {
+                IpAddr::V4(ip) => ip,
+                IpAddr::V6(_) => continue,
+            };
+
+            // only clients on our network
+            let c = match self.clients.get_mut(&ip) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let (app, port) = match fingerprint(&buf[..n]) {
+                Some(f) => f,
+                None => continue,
+            };
+
+            if c.dropped.is_some() {
+                info!("client {} announced itself, sending to it again", ip);
+                c.restore(ip, clock);
+            }
+
+            if c.app.as_ref() != Some(&app) {
+                info!("client {} runs {}", ip, app);
+                c.app = Some(app);
+                c.profile = Profile::ForeFlight;
+            }
+
+            // configured ports win
+            if let Some(port) = port {
+                if !self.config.gdl90_client_ports.contains_key(&ip) {
+                    c.switch_port(ip, port, clock);
+                }
+            }
         }
+    }
+
+    fn update_status(&self, clock: Instant) {
+        let mut status = self.status.lock().unwrap();
+
+        #[cfg(feature = "gdl90")]
+        {
+            status.loopback = self.loopback.as_ref().map(|l| l.status(clock));
+        }
+
+        status.packing = Some(self.packer.stats.clone());
+        status.latency = Some(self.latency.status());
 
-        me
+        status.clients.clear();
+        for (ip, c) in &self.clients {
+            status.clients.insert(
+                *ip,
+                ClientStatus {
+                    app: c.app.clone(),
+                    profile: c.profile,
+                    ports: c.ports.iter().map(|p| p.port).collect(),
+                    active: c.active,
+                    in_app: c.in_app,
+                    connected: c.connected,
+                    last_seen: secs_f32(clock - c.last_reply),
+                    rtt: c.rtt.map(|d| secs_f32(d) * 1000.0),
+                    bytes_sent: c.bytes_sent,
+                    replays: c.replays,
+                    history: c.history.iter().cloned().collect(),
+                    send_errors: c.send_errors.clone(),
+                    dropped: c.dropped.as_ref().map(|d| d.reason),
+                },
+            );
+        }
     }
 
     fn read_icmp_responses(&mut self, clock: Instant) {
-        let mut buf = [0_u8; 22];
-
-        for (ip, c) in self.clients.iter_mut() {
-            if let Ok((n, IpAddr::V4(recv_ip))) = c.icmp_sock.recv_from(&mut buf) {
-                if n != buf.len() || &recv_ip != ip {
-                    continue;
+        loop {
+            let reply = match self.pinger.recv() {
+                Ok(Some(r)) => r,
+                Ok(None) => return,
+                Err(e) => {
+                    debug!("unable to read ICMP: {}", e);
+                    return;
                 }
+            };
 
-                if buf[20] == 0 && buf[21] == 0 {
-                    trace!("got ICMP echo reply from {}", ip);
-                    c.last_reply = clock;
+            if let Some(c) = self.clients.get_mut(&reply.from) {
+                trace!("got ICMP echo reply {} from {}", reply.seq, reply.from);
+                c.last_reply = clock;
+                if reply.rtt.is_some() {
+                    c.rtt = reply.rtt;
                 }
             }
         }
     }
 
     fn send_icmp_echo_request_to_all_clients(&mut self) {
-        for (ip, c) in self.clients.iter_mut() {
-            if let Err(e) = c.icmp_sock.send(&PING_PACKET) {
+        self.pinger.next_round();
+
+        for ip in self.clients.keys() {
+            if let Err(e) = self.pinger.send(*ip) {
                 if e.kind() != ErrorKind::WouldBlock {
-                    error!("unable to send ping to {}", ip)
+                    debug!("unable to send ping to {}: {}", ip, e)
                 }
             }
         }
     }
 
     fn send_to_all_clients(&mut self, clock: Instant, buffer: &[u8]) {
-        for (_, c) in self.clients.iter_mut() {
-            if let Err(e) = c.udp_sock.send(buffer) {
-                if e.kind() == ErrorKind::WouldBlock {
-                    warn!("UDP send overwhelming buffers");
-                }
+        #[cfg(feature = "gdl90")]
+        {
+            if let Some(ref mut l) = self.loopback {
+                l.send(buffer);
+            }
+        }
 
-                match e.kind() {
-                    ErrorKind::WouldBlock => warn!("UDP send overwhelming buffers"),
-                    ErrorKind::ConnectionRefused => c.last_refused = clock,
-                    _ => error!("UDP send failed: {}", e),
+        // stripped once for every generic client
+        let mut generic = None;
+        for c in self.clients.values_mut() {
+            match c.profile {
+                Profile::ForeFlight => c.send(buffer, clock),
+                Profile::Generic => {
+                    let stripped = generic.get_or_insert_with(|| strip_foreflight(buffer));
+                    if !stripped.is_empty() {
+                        c.send(stripped, clock);
+                    }
                 }
             }
         }
     }
 
-    fn update_clients_list(&mut self, utc: Tm, clock: Instant) -> io::Result<()> {
+    fn update_clients_list(&mut self, utc: DateTime<UTC>, clock: Instant) -> io::Result<()> {
         let mut buf = Vec::new();
         let mut file = try!(File::open(LEASE_FILE_PATH));
 
         try!(file.read_to_end(&mut buf));
 
         if buf.len() > 0 {
-            if let IResult::Done(_, mut alive) =
-                parse_ip_from_lease_file(&buf[..], utc.to_timespec(), (buf.len() - 8) / 36)
-            {
+            if let IResult::Done(_, (written, leases)) = parse_lease_file(&buf[..]) {
+                let mut alive = alive_leases(&mut self.lease_file, written, &leases, clock);
                 debug!("found client IP(s) {:?} from lease file", alive);
 
                 self.clients.retain(|k, _| {
@@ -281,40 +542,19 @@ impl UDP {
 
                 // here, we are left with IPs that are not in self.clients yet
                 for ip in alive {
-                    let udp_sock = UdpSocket::bind("0.0.0.0:0").expect("can not bind UDP socket");
-                    udp_sock
-                        .set_nonblocking(true)
-                        .expect("could not set socket to non blocking mode");
-                    if let Err(e) = udp_sock.connect((ip, GDL90_PORT)) {
-                        error!("could not connect to client IP: {} (UDP)", e);
+                    let ports = connect(ip, self.config.gdl90_ports_of(&ip), clock);
+                    if ports.is_empty() {
                         continue;
                     }
 
-                    let icmp_sock =
-                        IcmpSocket::connect(ip.into()).expect("could not connect to ICMP socket");
-
-                    icmp_sock
-                        .set_write_timeout(Some(Duration::new(0, 1))) // TODO, fix this once we have real nonblocking mode
-                        .unwrap();
-                    icmp_sock
-                        .set_read_timeout(Some(Duration::new(0, 1))) // TODO, fix this once we have real nonblocking mode
-                        .unwrap();
+                    self.clients
+                        .insert(ip, Client::new(ports, utc.timestamp(), clock));
 
-                    self.clients.insert(
+                    info!(
+                        "new client: {}, sending GDL90 to port(s) {:?}",
                         ip,
-                        Client {
-                            udp_sock,
-                            icmp_sock,
-                            queue: VecDeque::new(),
-                            active: true,
-                            last_reply: clock,
-                            in_app: false,
-                            last_refused: clock,
-                            last_replay: clock,
-                        },
+                        self.config.gdl90_ports_of(&ip)
                     );
-
-                    info!("new client: {}", ip);
                 }
             }
         }
@@ -323,20 +563,414 @@ impl UDP {
     }
 }
 
+/// Addresses of `leases` that have not expired. The lease file only has wall
+/// clock times, and the system clock may have been set from GNSS since it was
+/// written, so leases age from when the file was first seen with `written`.
+fn alive_leases(
+    seen: &mut Option<(u64, Instant)>,
+    written: u64,
+    leases: &[(Ipv4Addr, u32)],
+    clock: Instant,
+) -> HashSet<Ipv4Addr> {
+    let since = match *seen {
+        Some((w, since)) if w == written => since,
+        _ => {
+            *seen = Some((written, clock));
+            clock
+        }
+    };
+    let age = (clock - since).as_secs();
+
+    leases
+        .iter()
+        .filter(|&&(_, expires)| age < expires as u64)
+        .map(|&(ip, _)| ip)
+        .collect()
+}
+
+/// UDP sockets sending to `ports` on `ip`
+fn connect(ip: Ipv4Addr, ports: &[u16], clock: Instant) -> Vec<Port> {
+    let mut connected = Vec::new();
+
+    for port in ports {
+        let udp_sock = UdpSocket::bind("0.0.0.0:0").expect("can not bind UDP socket");
+        udp_sock
+            .set_nonblocking(true)
+            .expect("could not set socket to non blocking mode");
+        if let Err(e) = udp_sock.connect((ip, *port)) {
+            error!("could not connect to client IP: {} (UDP)", e);
+            continue;
+        }
+
+        connected.push(Port {
+            port: *port,
+            udp_sock,
+            last_refused: clock,
+            streak: None,
+        });
+    }
+
+    connected
+}
+
+impl Packer {
+    fn new(max_size: usize, hold: Option<Duration>) -> Self {
+        Self {
+            max_size,
+            hold,
+            buffer: Vec::with_capacity(max_size),
+            since: None,
+            received: Vec::new(),
+            sent: Vec::new(),
+            stats: PackingStatus {
+                max_datagram: max_size,
+                datagrams: 0,
+                bytes: 0,
+                fill: 0.0,
+                held: 0,
+            },
+        }
+    }
+
+    /// Add `payload`, returns the datagram to send if it does not fit.
+    /// Payloads larger than a datagram are sent on their own.
+    fn push(
+        &mut self,
+        payload: &[u8],
+        received: Option<(Class, Instant)>,
+        clock: Instant,
+    ) -> Option<Vec<u8>> {
+        let full = if !self.buffer.is_empty() && self.buffer.len() + payload.len() > self.max_size {
+            Some(self.take())
+        } else {
+            None
+        };
+
+        if self.buffer.is_empty() {
+            self.since = Some(clock);
+        }
+        self.buffer.extend(payload.iter());
+        self.received.extend(received);
+
+        full
+    }
+
+    /// Returns the datagram to send at the end of a run, unless it is held
+    /// to be filled further
+    fn flush(&mut self, clock: Instant) -> Option<Vec<u8>> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        let held = match (self.hold, self.since) {
+            (Some(hold), Some(since)) => clock - since < hold,
+            _ => false,
+        };
+
+        if held {
+            self.stats.held += 1;
+            return None;
+        }
+
+        Some(self.take())
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        let datagram = self.buffer.split_off(0);
+        self.since = None;
+        self.sent.append(&mut self.received);
+
+        self.stats.datagrams += 1;
+        self.stats.bytes += datagram.len() as u64;
+        self.stats.fill =
+            self.stats.bytes as f32 / (self.stats.datagrams * self.max_size as u64) as f32;
+
+        datagram
+    }
+}
+
+/// Why sending failed with `e`
+fn classify(e: &io::Error) -> SendError {
+    match e.kind() {
+        ErrorKind::WouldBlock => return SendError::WouldBlock,
+        ErrorKind::ConnectionRefused => return SendError::Refused,
+        _ => {}
+    }
+
+    match e.raw_os_error() {
+        Some(EHOSTUNREACH) | Some(ENETUNREACH) | Some(EHOSTDOWN) => SendError::Unreachable,
+        _ => SendError::Other,
+    }
+}
+
+/// Add a send failing with `kind` at `clock` to `streak`. Failing another
+/// way, or not for `STREAK_GAP`, starts a new streak.
+fn extend(streak: &mut Option<Streak>, kind: SendError, clock: Instant) {
+    match *streak {
+        Some(ref mut s) if s.kind == kind && clock - s.last < Duration::from_secs(STREAK_GAP) => {
+            s.last = clock;
+        }
+        _ => {
+            *streak = Some(Streak {
+                kind,
+                since: clock,
+                last: clock,
+            })
+        }
+    }
+}
+
+/// The failure `streak` is still ongoing with at `clock`, and since when
+fn ongoing(streak: Option<Streak>, clock: Instant) -> Option<(SendError, Instant)> {
+    streak
+        .filter(|s| clock - s.last < Duration::from_secs(STREAK_GAP))
+        .map(|s| (s.kind, s.since))
+}
+
+/// Add `state` to `history` if it changed
+fn record(history: &mut VecDeque<Transition>, time: i64, state: ClientState) {
+    if history.back().map_or(false, |t| t.state == state) {
+        return;
+    }
+
+    if history.len() == HISTORY_SIZE {
+        history.pop_front();
+    }
+    history.push_back(Transition { time, state });
+}
+
+/// `datagram` without the ForeFlight extension messages (ID 0x65), which
+/// only apps announcing themselves as speaking the ForeFlight protocol get
+fn strip_foreflight(datagram: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(datagram.len());
+    let mut rest = datagram;
+
+    // every message is framed by flag bytes, and the ID is never escaped
+    while let Some(start) = rest.iter().position(|b| *b == 0x7E) {
+        let end = match rest[start + 1..].iter().position(|b| *b == 0x7E) {
+            Some(n) => start + 1 + n,
+            None => break,
+        };
+
+        if rest.get(start + 1) != Some(&0x65) {
+            stripped.extend_from_slice(&rest[start..end + 1]);
+        }
+        rest = &rest[end + 1..];
+    }
+
+    stripped
+}
+
+/// App name and the GDL90 port it listens on, if `data` is an app
+/// announcement such as `{"App": "ForeFlight", "GDL90": {"port": 4000}}`
+fn fingerprint(data: &[u8]) -> Option<(String, Option<u16>)> {
+    let js: Value = match serde_json::from_slice(data) {
+        Ok(js) => js,
+        Err(_) => return None,
+    };
+
+    let port = js["GDL90"]["port"].as_u64().and_then(|p| {
+        if p > 0 && p <= 0xFFFF {
+            Some(p as u16)
+        } else {
+            None
+        }
+    });
+
+    js["App"].as_str().map(|app| (app.to_string(), port))
+}
+
 impl Client {
-    fn drain_queue(&mut self) {
-        let mut buffer = Vec::with_capacity(UDP_MAX_SIZE);
+    /// Client sending to `ports`, connected at UNIX time `connected`
+    fn new(ports: Vec<Port>, connected: i64, clock: Instant) -> Self {
+        Self {
+            ports,
+            app: None,
+            profile: Profile::Generic,
+            queue: VecDeque::new(),
+            active: true,
+            last_reply: clock,
+            rtt: None,
+            in_app: false,
+            last_replay: clock,
+            connected,
+            bytes_sent: 0,
+            replays: 0,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            send_errors: SendErrors::default(),
+            dropped: None,
+            probing: None,
+        }
+    }
+
+    fn state(&self) -> ClientState {
+        if self.dropped.is_some() {
+            ClientState::Dropped
+        } else if !self.active {
+            ClientState::Asleep
+        } else if self.in_app {
+            ClientState::InApp
+        } else {
+            ClientState::Background
+        }
+    }
+
+    /// When sending was last refused, a client counts as refusing while
+    /// every port does
+    fn last_refused(&self) -> Instant {
+        self.ports.iter().map(|p| p.last_refused).min().unwrap()
+    }
+
+    /// Why to drop the client, once sending to every port has been failing
+    /// the same way for long enough
+    fn failing(&mut self, clock: Instant) -> Option<SendError> {
+        if self.dropped.is_some() || self.ports.is_empty() {
+            return None;
+        }
+
+        let streaks: Vec<_> = self
+            .ports
+            .iter()
+            .map(|p| ongoing(p.streak, clock))
+            .collect();
+
+        // a port accepting for a while after being tried again
+        if self
+            .probing
+            .map_or(false, |since| (clock - since).as_secs() >= PROBE_SECS)
+            && streaks.iter().any(|s| s.is_none())
+        {
+            self.probing = None;
+        }
+
+        let mut reason = None;
+        for s in streaks {
+            let (kind, since) = match s {
+                Some(s) => s,
+                None => return None,
+            };
+
+            let secs = match kind {
+                SendError::Unreachable => UNREACHABLE_SECS,
+                SendError::Refused if self.probing.is_some() => PROBE_SECS,
+                SendError::Refused => REFUSED_SECS,
+                _ => return None,
+            };
+            if (clock - since).as_secs() < secs || reason.map_or(false, |r| r != kind) {
+                return None;
+            }
+            reason = Some(kind);
+        }
+
+        reason
+    }
+
+    /// Stop sending, closing the sockets of every port
+    fn drop_ports(&mut self, reason: SendError, clock: Instant) {
+        self.dropped = Some(Dropped {
+            reason,
+            since: clock,
+            ports: self.ports.iter().map(|p| p.port).collect(),
+        });
+        self.ports.clear();
+        self.queue.clear();
+        self.probing = None;
+    }
+
+    /// Whether a dropped client is back: answering pings again after being
+    /// unreachable. Whether an app listens again can only be told by
+    /// sending, so clients dropped for refusing are tried again once in a
+    /// while.
+    fn has_returned(&self, clock: Instant) -> bool {
+        match self.dropped {
+            Some(Dropped {
+                reason: SendError::Unreachable,
+                since,
+                ..
+            }) => self.last_reply > since,
+            Some(Dropped { since, .. }) => (clock - since).as_secs() >= RETRY_SECS,
+            None => false,
+        }
+    }
+
+    /// Send to the ports of a dropped client again
+    fn restore(&mut self, ip: Ipv4Addr, clock: Instant) {
+        let d = match self.dropped.take() {
+            Some(d) => d,
+            None => return,
+        };
+
+        self.ports = connect(ip, &d.ports, clock);
+        if self.ports.is_empty() {
+            self.dropped = Some(Dropped { since: clock, ..d });
+            return;
+        }
+        if d.reason == SendError::Refused {
+            self.probing = Some(clock);
+        }
+    }
+
+    /// Send to `port` only, as the app on the client asked. A client that
+    /// is still dropped is sent to on it once it returns.
+    fn switch_port(&mut self, ip: Ipv4Addr, port: u16, clock: Instant) {
+        if let Some(ref mut d) = self.dropped {
+            d.ports = vec![port];
+            return;
+        }
+
+        if self.ports.len() == 1 && self.ports[0].port == port {
+            return;
+        }
+
+        let ports = connect(ip, &[port], clock);
+        if !ports.is_empty() {
+            info!("client {} asked for GDL90 on port {}", ip, port);
+            self.ports = ports;
+        }
+    }
+
+    /// Send `buffer` to every port, keeping track of why sending fails
+    fn send(&mut self, buffer: &[u8], clock: Instant) {
+        for p in self.ports.iter_mut() {
+            let kind = match p.udp_sock.send(buffer) {
+                Ok(n) => {
+                    self.bytes_sent += n as u64;
+                    continue;
+                }
+                Err(e) => {
+                    trace!("UDP send to port {} failed: {}", p.port, e);
+                    classify(&e)
+                }
+            };
+
+            self.send_errors.count(kind);
+            match kind {
+                SendError::Refused => p.last_refused = clock,
+                SendError::WouldBlock => warn!("UDP send overwhelming buffers"),
+                _ => {}
+            }
+            if kind == SendError::Refused || kind == SendError::Unreachable {
+                extend(&mut p.streak, kind, clock);
+            }
+        }
+    }
+
+    /// Send up to `PAYLOAD_PER_DRAIN` queued payloads, adding those to be
+    /// measured to `sent`
+    fn drain_queue(&mut self, max_size: usize, clock: Instant, sent: &mut Vec<(Class, Instant)>) {
+        let mut buffer = Vec::with_capacity(max_size);
         let to_drain = PAYLOAD_PER_DRAIN.min(self.queue.len());
 
         for _ in 0..to_drain {
             let p = self.queue.pop_front().unwrap();
 
-            if buffer.len() + p.payload.len() > UDP_MAX_SIZE {
-                self.send_payload(&buffer);
+            if !buffer.is_empty() && buffer.len() + p.payload.len() > max_size {
+                self.send(&buffer, clock);
                 buffer.clear();
             }
 
             buffer.extend(p.payload.iter());
+            sent.extend(p.received);
         }
 
         // if buffer is not empty, and we still have space to squeeze, don't waste
@@ -344,22 +978,364 @@ impl Client {
         // otherwise, send the remaining packet
         if !buffer.is_empty() {
             while !self.queue.is_empty()
-                && buffer.len() + self.queue.front().unwrap().payload.len() <= UDP_MAX_SIZE
+                && buffer.len() + self.queue.front().unwrap().payload.len() <= max_size
             {
                 let item = self.queue.pop_front().unwrap(); // this can not fail
 
                 buffer.extend(item.payload.iter());
+                sent.extend(item.received);
             }
 
-            self.send_payload(&buffer);
+            self.send(&buffer, clock);
         }
     }
+}
 
-    fn send_payload(&self, buffer: &[u8]) {
-        if let Err(e) = self.udp_sock.send(buffer) {
-            if e.kind() == ErrorKind::WouldBlock {
-                warn!("UDP send overwhelming buffers");
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint(br#"{"App":"ForeFlight","GDL90":{"port":4000}}"#),
+            Some(("ForeFlight".to_string(), Some(4000)))
+        );
+        assert_eq!(
+            fingerprint(br#"{"App":"Other EFB","GDL90":{"port":70000}}"#),
+            Some(("Other EFB".to_string(), None))
+        );
+        assert_eq!(fingerprint(br#"{"GDL90":{"port":4000}}"#), None);
+        assert_eq!(fingerprint(b"\x7e\x00"), None);
+    }
+
+    #[test]
+    fn test_lease_file() {
+        let lease = |expires: u32, ip: [u8; 4]| {
+            let mut l = vec![
+                (expires >> 24) as u8,
+                (expires >> 16) as u8,
+                (expires >> 8) as u8,
+                expires as u8,
+            ];
+            l.extend_from_slice(&ip);
+            l.extend_from_slice(&[0; 28]);
+            l
+        };
+
+        // written at 1970-01-01T00:16:40Z, before the clock was set
+        let mut file = vec![0, 0, 0, 0, 0, 0, 0x03, 0xE8];
+        file.extend(lease(3600, [192, 168, 10, 10]));
+        file.extend(lease(60, [192, 168, 10, 11]));
+
+        let (written, leases) = match parse_lease_file(&file) {
+            IResult::Done(_, l) => l,
+            r => panic!("unexpected {:?}", r),
+        };
+        assert_eq!(written, 1000);
+        assert_eq!(leases.len(), 2);
+
+        let start = Instant::now();
+        let mut seen = None;
+        let alive = |seen: &mut _, secs| {
+            let mut ips: Vec<_> =
+                alive_leases(seen, written, &leases, start + Duration::from_secs(secs))
+                    .into_iter()
+                    .collect();
+            ips.sort();
+            ips
+        };
+
+        assert_eq!(
+            alive(&mut seen, 0),
+            vec![
+                Ipv4Addr::new(192, 168, 10, 10),
+                Ipv4Addr::new(192, 168, 10, 11),
+            ]
+        );
+        assert_eq!(alive(&mut seen, 60), vec![Ipv4Addr::new(192, 168, 10, 10)]);
+        assert!(alive(&mut seen, 3600).is_empty());
+    }
+
+    #[test]
+    fn test_packer() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut p = Packer::new(10, None);
+        assert_eq!(p.push(&[1; 6], None, at(0)), None);
+        assert_eq!(p.push(&[2; 6], None, at(0)), Some(vec![1; 6]));
+        // larger than a datagram, on its own
+        assert_eq!(p.push(&[3; 12], None, at(0)), Some(vec![2; 6]));
+        assert_eq!(p.flush(at(0)), Some(vec![3; 12]));
+        assert_eq!(p.flush(at(0)), None);
+        assert_eq!(p.stats.datagrams, 3);
+        assert_eq!(p.stats.bytes, 24);
+        assert_eq!(p.stats.fill, 0.8);
+
+        // held until the deadline unless filled
+        let mut p = Packer::new(10, Some(Duration::from_millis(20)));
+        assert_eq!(p.push(&[1; 4], None, at(0)), None);
+        assert_eq!(p.flush(at(0)), None);
+        assert_eq!(p.push(&[2; 4], None, at(10)), None);
+        assert_eq!(p.flush(at(10)), None);
+        assert_eq!(p.flush(at(20)), Some(vec![1, 1, 1, 1, 2, 2, 2, 2]));
+        assert_eq!(p.stats.held, 2);
+
+        // latency is measured once the datagram carrying a payload is taken
+        let mut p = Packer::new(10, None);
+        assert_eq!(p.push(&[1; 4], Some((Class::Traffic, at(0))), at(5)), None);
+        assert!(p.sent.is_empty());
+        assert_eq!(p.flush(at(5)), Some(vec![1; 4]));
+        assert_eq!(p.sent, vec![(Class::Traffic, at(0))]);
+    }
+
+    #[test]
+    fn test_drain_queue() {
+        let start = Instant::now();
+        let ports = connect(Ipv4Addr::new(127, 0, 0, 1), &[4000], start);
+        let mut c = Client::new(ports, 0, start);
+        let payload = |received| Payload {
+            queueable: true,
+            payload: vec![0; 4],
+            received,
+            trace: None,
+        };
+
+        c.queue.push_back(payload(None));
+        c.queue.push_back(payload(Some((Class::Uplink, start))));
+        c.queue.push_back(payload(None));
+
+        // measured once sent, not when queued
+        let mut sent = Vec::new();
+        c.drain_queue(8, start, &mut sent);
+        assert!(c.queue.is_empty());
+        assert_eq!(sent, vec![(Class::Uplink, start)]);
+    }
+
+    #[test]
+    fn test_client_unreachable() {
+        let ip = Ipv4Addr::new(127, 0, 0, 1);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut c = Client::new(connect(ip, &[4000, 4001], start), 0, start);
+        let streak = |kind, since, last| {
+            Some(Streak {
+                kind,
+                since: at(since),
+                last: at(last),
+            })
+        };
+
+        // every port has to fail the same way for long enough
+        c.ports[0].streak = streak(SendError::Unreachable, 0, UNREACHABLE_SECS);
+        assert_eq!(c.failing(at(UNREACHABLE_SECS)), None);
+        c.ports[1].streak = streak(SendError::Refused, 0, UNREACHABLE_SECS);
+        assert_eq!(c.failing(at(UNREACHABLE_SECS)), None);
+        c.ports[1].streak = streak(SendError::Unreachable, 1, UNREACHABLE_SECS);
+        assert_eq!(c.failing(at(UNREACHABLE_SECS)), None);
+
+        // nor once sends stopped failing
+        c.ports[1].streak = streak(SendError::Unreachable, 0, UNREACHABLE_SECS);
+        assert_eq!(c.failing(at(UNREACHABLE_SECS + STREAK_GAP)), None);
+        c.ports[0].streak = streak(SendError::Unreachable, 0, UNREACHABLE_SECS + 1);
+        c.ports[1].streak = streak(SendError::Unreachable, 1, UNREACHABLE_SECS + 1);
+        assert_eq!(
+            c.failing(at(UNREACHABLE_SECS + 1)),
+            Some(SendError::Unreachable)
+        );
+
+        c.queue.push_back(Payload {
+            queueable: true,
+            payload: vec![0; 4],
+            received: None,
+            trace: None,
+        });
+        c.drop_ports(SendError::Unreachable, at(UNREACHABLE_SECS + 1));
+        assert_eq!(c.state(), ClientState::Dropped);
+        assert!(c.ports.is_empty());
+        assert!(c.queue.is_empty());
+        assert_eq!(c.failing(at(UNREACHABLE_SECS + 2)), None);
+
+        // back once it answers pings, however long that takes
+        assert!(!c.has_returned(at(UNREACHABLE_SECS + RETRY_SECS * 10)));
+        c.last_reply = at(UNREACHABLE_SECS + 5);
+        assert!(c.has_returned(at(UNREACHABLE_SECS + 5)));
+
+        c.restore(ip, at(UNREACHABLE_SECS + 5));
+        assert!(c.dropped.is_none());
+        assert_eq!(
+            c.ports.iter().map(|p| p.port).collect::<Vec<_>>(),
+            vec![4000, 4001]
+        );
+        assert!(c.ports.iter().all(|p| p.streak.is_none()));
+        assert!(c.probing.is_none());
+        assert!(!c.has_returned(at(UNREACHABLE_SECS + 6)));
+    }
+
+    #[test]
+    fn test_client_refused() {
+        let ip = Ipv4Addr::new(127, 0, 0, 1);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut c = Client::new(connect(ip, &[4000], start), 0, start);
+        let refused = |since, last| {
+            Some(Streak {
+                kind: SendError::Refused,
+                since: at(since),
+                last: at(last),
+            })
+        };
+
+        c.ports[0].streak = refused(0, REFUSED_SECS - 1);
+        assert_eq!(c.failing(at(REFUSED_SECS - 1)), None);
+        c.ports[0].streak = refused(0, REFUSED_SECS);
+        assert_eq!(c.failing(at(REFUSED_SECS)), Some(SendError::Refused));
+        c.drop_ports(SendError::Refused, at(REFUSED_SECS));
+
+        // tried again after a while, answering pings does not tell
+        let dropped = REFUSED_SECS;
+        c.last_reply = at(dropped + 1);
+        assert!(!c.has_returned(at(dropped + RETRY_SECS - 1)));
+        assert!(c.has_returned(at(dropped + RETRY_SECS)));
+        c.restore(ip, at(dropped + RETRY_SECS));
+        assert!(c.dropped.is_none());
+        assert_eq!(c.probing, Some(at(dropped + RETRY_SECS)));
+
+        // and dropped again much sooner if it still refuses
+        let retried = dropped + RETRY_SECS;
+        c.ports[0].streak = refused(retried, retried + PROBE_SECS);
+        assert_eq!(
+            c.failing(at(retried + PROBE_SECS)),
+            Some(SendError::Refused)
+        );
+
+        // or no longer probed once a port accepts
+        c.ports[0].streak = None;
+        assert_eq!(c.failing(at(retried + PROBE_SECS)), None);
+        assert!(c.probing.is_none());
+    }
+
+    #[test]
+    fn test_switch_port() {
+        let ip = Ipv4Addr::new(127, 0, 0, 1);
+        let start = Instant::now();
+        let mut c = Client::new(connect(ip, &[4000, 4001], start), 0, start);
+
+        c.switch_port(ip, 4002, start);
+        assert_eq!(c.ports.len(), 1);
+        assert_eq!(c.ports[0].port, 4002);
+
+        // a dropped client gets the port once it returns, not before
+        c.drop_ports(SendError::Unreachable, start);
+        c.switch_port(ip, 4003, start);
+        assert!(c.ports.is_empty());
+        assert_eq!(c.state(), ClientState::Dropped);
+
+        c.restore(ip, start);
+        assert_eq!(c.ports.len(), 1);
+        assert_eq!(c.ports[0].port, 4003);
+    }
+
+    #[test]
+    fn test_strip_foreflight() {
+        let heartbeat = [0x7E, 0x00, 0x81, 0x7D, 0x5E, 0x7E];
+        let id = [0x7E, 0x65, 0x00, 0x01, 0x7E];
+        let ahrs = [0x7E, 0x65, 0x01, 0x7F, 0xFF, 0x7E];
+        let traffic = [0x7E, 0x14, 0x00, 0xAB, 0x7E];
+
+        let mut datagram = Vec::new();
+        for m in &[&heartbeat[..], &id, &traffic, &ahrs] {
+            datagram.extend_from_slice(m);
+        }
+
+        let mut expected = heartbeat.to_vec();
+        expected.extend_from_slice(&traffic);
+        assert_eq!(strip_foreflight(&datagram), expected);
+        assert!(strip_foreflight(&id).is_empty());
+        assert_eq!(strip_foreflight(&traffic), traffic.to_vec());
+    }
+
+    #[test]
+    fn test_classify() {
+        let e = |kind| io::Error::new(kind, "");
+        assert_eq!(classify(&e(ErrorKind::WouldBlock)), SendError::WouldBlock);
+        assert_eq!(
+            classify(&e(ErrorKind::ConnectionRefused)),
+            SendError::Refused
+        );
+        assert_eq!(
+            classify(&io::Error::from_raw_os_error(EHOSTUNREACH)),
+            SendError::Unreachable
+        );
+        assert_eq!(
+            classify(&io::Error::from_raw_os_error(ENETUNREACH)),
+            SendError::Unreachable
+        );
+        assert_eq!(classify(&e(ErrorKind::PermissionDenied)), SendError::Other);
+    }
+
+    #[test]
+    fn test_streak() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut streak = None;
+
+        // errors alternate with sends that seem to succeed
+        for secs in 0..10 {
+            extend(&mut streak, SendError::Unreachable, at(secs * 2));
+        }
+        assert_eq!(
+            ongoing(streak, at(20)),
+            Some((SendError::Unreachable, at(0)))
+        );
+
+        // broken by not failing for a while
+        assert_eq!(ongoing(streak, at(18 + STREAK_GAP)), None);
+        extend(&mut streak, SendError::Unreachable, at(18 + STREAK_GAP));
+        assert_eq!(
+            ongoing(streak, at(20 + STREAK_GAP)),
+            Some((SendError::Unreachable, at(18 + STREAK_GAP)))
+        );
+
+        // or by failing another way
+        extend(&mut streak, SendError::Refused, at(20 + STREAK_GAP));
+        assert_eq!(
+            ongoing(streak, at(20 + STREAK_GAP)),
+            Some((SendError::Refused, at(20 + STREAK_GAP)))
+        );
+    }
+
+    #[test]
+    fn test_record_history() {
+        let mut history = VecDeque::new();
+
+        record(&mut history, 0, ClientState::Background);
+        record(&mut history, 1, ClientState::Background);
+        record(&mut history, 2, ClientState::InApp);
+        assert_eq!(
+            history,
+            vec![
+                Transition {
+                    time: 0,
+                    state: ClientState::Background,
+                },
+                Transition {
+                    time: 2,
+                    state: ClientState::InApp,
+                },
+            ]
+        );
+
+        for t in 0..HISTORY_SIZE as i64 {
+            let state = if t % 2 == 0 {
+                ClientState::Asleep
+            } else {
+                ClientState::InApp
+            };
+            record(&mut history, 10 + t, state);
         }
+        assert_eq!(history.len(), HISTORY_SIZE);
+        assert_eq!(history[0].time, 10);
     }
 }
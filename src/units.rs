@@ -0,0 +1,163 @@
+// Pitot - a customizable aviation information receiver
+// Copyright (C) 2017-2018  Datong Sun (dndx@idndx.com)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Units of the measurements in JSON reports. Reports keep their fields in
+//! the units Pitot works in internally, this adds a copy of every measurement
+//! named after its unit (e.g. `gs_kt`, or `gs_kmh` in metric mode) so
+//! clients do not have to guess.
+
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// ft, kt, ft/min and NM
+    Aviation,
+    /// m, km/h, m/s and km
+    Metric,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quantity {
+    /// in ft
+    Altitude,
+    /// in kt
+    Speed,
+    /// in ft/min
+    VerticalSpeed,
+    /// in NM
+    Distance,
+    /// in mm, always reported in m
+    Accuracy,
+}
+
+/// Measurements of each report type, the fields not listed are unitless
+/// or in degrees
+const FIELDS: [(&str, &str, Quantity); 23] = [
+    ("Ownship", "msl_altitude", Quantity::Altitude),
+    ("Ownship", "hae_altitude", Quantity::Altitude),
+    ("Ownship", "pressure_altitude", Quantity::Altitude),
+    ("Ownship", "ias", Quantity::Speed),
+    ("Ownship", "tas", Quantity::Speed),
+    ("Ownship", "gs", Quantity::Speed),
+    ("Ownship", "vs", Quantity::VerticalSpeed),
+    ("Ownship", "gnss_vs", Quantity::VerticalSpeed),
+    ("Ownship", "h_accuracy", Quantity::Accuracy),
+    ("Ownship", "v_accuracy", Quantity::Accuracy),
+    ("Traffic", "altitude", Quantity::Altitude),
+    ("Traffic", "speed", Quantity::Speed),
+    ("Traffic", "vs", Quantity::VerticalSpeed),
+    ("Traffic", "range", Quantity::Distance),
+    ("GNSS", "h_accuracy", Quantity::Accuracy),
+    ("GNSS", "v_accuracy", Quantity::Accuracy),
+    ("E6B", "wind_speed", Quantity::Speed),
+    ("E6B", "waypoint_distance", Quantity::Distance),
+    ("Navigation", "distance", Quantity::Distance),
+    ("Navigation", "cross_track", Quantity::Distance),
+    ("Event", "distance", Quantity::Distance),
+    ("Event", "altitude_diff", Quantity::Altitude),
+    ("WarmStart", "baro_offset", Quantity::Altitude),
+];
+
+impl Quantity {
+    /// Unit suffix and factor converting from the internal unit
+    fn unit(&self, units: Units) -> (&'static str, f64) {
+        match (*self, units) {
+            (Quantity::Altitude, Units::Aviation) => ("ft", 1.0),
+            (Quantity::Altitude, Units::Metric) => ("m", 0.3048),
+            (Quantity::Speed, Units::Aviation) => ("kt", 1.0),
+            (Quantity::Speed, Units::Metric) => ("kmh", 1.852),
+            (Quantity::VerticalSpeed, Units::Aviation) => ("fpm", 1.0),
+            (Quantity::VerticalSpeed, Units::Metric) => ("mps", 0.00508),
+            (Quantity::Distance, Units::Aviation) => ("nm", 1.0),
+            (Quantity::Distance, Units::Metric) => ("km", 1.852),
+            (Quantity::Accuracy, _) => ("m", 0.001),
+        }
+    }
+}
+
+/// Add the measurements of report `kind` in `units`, rounded to one decimal.
+/// Unknown measurements stay `null`.
+pub fn annotate(kind: &str, js: &mut Map<String, Value>, units: Units) {
+    for &(_, field, quantity) in FIELDS.iter().filter(|f| f.0 == kind) {
+        let (suffix, factor) = quantity.unit(units);
+
+        let value = match js.get(field) {
+            Some(v) => v.as_f64().map_or(Value::Null, |v| {
+                Value::from((v * factor * 10.0).round() / 10.0)
+            }),
+            // not in this variant of the report
+            None => continue,
+        };
+
+        js.insert(format!("{}_{}", field, suffix), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn annotated(kind: &str, js: &str, units: Units) -> Value {
+        let mut js = match serde_json::from_str(js).unwrap() {
+            Value::Object(o) => o,
+            _ => unreachable!(),
+        };
+        annotate(kind, &mut js, units);
+
+        Value::Object(js)
+    }
+
+    #[test]
+    fn test_annotate() {
+        let js = annotated(
+            "Traffic",
+            r#"{"altitude": 4500, "speed": 120, "vs": -500, "range": null}"#,
+            Units::Aviation,
+        );
+        assert_eq!(js["altitude_ft"], 4500.0);
+        assert_eq!(js["speed_kt"], 120.0);
+        assert_eq!(js["vs_fpm"], -500.0);
+        assert_eq!(js["range_nm"], Value::Null);
+        // original fields are kept
+        assert_eq!(js["altitude"], 4500);
+
+        let js = annotated(
+            "Traffic",
+            r#"{"altitude": 4500, "speed": 120, "vs": -500, "range": 2.5}"#,
+            Units::Metric,
+        );
+        assert_eq!(js["altitude_m"], 1371.6);
+        assert_eq!(js["speed_kmh"], 222.2);
+        assert_eq!(js["vs_mps"], -2.5);
+        assert_eq!(js["range_km"], 4.6);
+        assert!(js.get("altitude_ft").is_none());
+
+        let js = annotated("GNSS", r#"{"h_accuracy": 2345}"#, Units::Aviation);
+        assert_eq!(js["h_accuracy_m"], 2.3);
+        assert!(js.get("v_accuracy_m").is_none());
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(
+            serde_json::from_str::<Units>(r#""metric""#).unwrap(),
+            Units::Metric
+        );
+        assert!(serde_json::from_str::<Units>(r#""imperial""#).is_err());
+    }
+}
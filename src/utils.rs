@@ -14,6 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::time::Duration;
+
+const EARTH_RADIUS_NM: f32 = 3440.065;
+
 #[macro_export]
 macro_rules! mm_to_ft {
     ($x:expr) => {
@@ -38,3 +42,71 @@ macro_rules! run_every {
         }
     };
 }
+
+pub fn secs_f32(d: Duration) -> f32 {
+    d.as_secs() as f32 + d.subsec_nanos() as f32 * 1e-9
+}
+
+/// Great circle distance between two coordinates in degrees
+pub fn distance_nm(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+
+    let h = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_NM * h.sqrt().asin()
+}
+
+/// Initial great circle bearing from `a` to `b` in degrees true
+pub fn bearing_deg(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+
+    let y = (lon2 - lon1).sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * (lon2 - lon1).cos();
+
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+/// Distance of `p` from the great circle course `from` -> `to` in NM,
+/// positive when right of course
+pub fn cross_track_nm(from: (f32, f32), to: (f32, f32), p: (f32, f32)) -> f32 {
+    let d13 = distance_nm(from, p) / EARTH_RADIUS_NM;
+    let theta13 = bearing_deg(from, p).to_radians();
+    let theta12 = bearing_deg(from, to).to_radians();
+
+    (d13.sin() * (theta13 - theta12).sin()).asin() * EARTH_RADIUS_NM
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance_nm() {
+        assert_eq!(distance_nm((37.5, -122.0), (37.5, -122.0)), 0.0);
+        // 1 minute of latitude is 1 NM
+        assert!((distance_nm((37.0, -122.0), (38.0, -122.0)) - 60.0).abs() < 0.1);
+        // KSFO to KJFK
+        assert!((distance_nm((37.619, -122.375), (40.640, -73.779)) - 2246.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn test_bearing_deg() {
+        assert!((bearing_deg((37.0, -122.0), (38.0, -122.0)) - 0.0).abs() < 0.1);
+        assert!((bearing_deg((37.0, -122.0), (36.0, -122.0)) - 180.0).abs() < 0.1);
+        assert!((bearing_deg((0.0, -122.0), (0.0, -121.0)) - 90.0).abs() < 0.1);
+        assert!((bearing_deg((0.0, -122.0), (0.0, -123.0)) - 270.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_cross_track_nm() {
+        // course due north along the meridian, 1 minute of longitude at the equator is 1 NM
+        let from = (0.0, 0.0);
+        let to = (1.0, 0.0);
+        assert!(cross_track_nm(from, to, (0.5, 0.0)).abs() < 0.01);
+        assert!((cross_track_nm(from, to, (0.5, 1.0 / 60.0)) - 1.0).abs() < 0.01);
+        assert!((cross_track_nm(from, to, (0.5, -1.0 / 60.0)) + 1.0).abs() < 0.01);
+    }
+}
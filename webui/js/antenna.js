@@ -0,0 +1,118 @@
+(function($) {
+    var uri = "ws://192.168.0.1:9001/?types=Antenna";
+    var history = "http://192.168.0.1:9002/antenna.json";
+    // a rising noise floor or jamming indicator over weeks usually means
+    // a failing antenna, cable or connector
+    var metrics = [
+        { key: "uat_noise_floor", title: "UAT noise floor", unit: "dBFS", color: "#4af" },
+        { key: "es_noise_floor", title: "1090ES noise floor", unit: "dBFS", color: "#fc0" },
+        { key: "gnss_noise", title: "GNSS noise level", unit: "", color: "#5d5" },
+        { key: "gnss_agc", title: "GNSS AGC", unit: "%", color: "#f5f" },
+        { key: "gnss_jamming", title: "GNSS jamming indicator", unit: "", color: "#f55" }
+    ];
+
+    metrics.forEach(function(m) {
+        $('#charts').append('<h6>' + m.title + (m.unit ? " (" + m.unit + ")" : "") + '</h6>' +
+                            '<canvas id="' + m.key + '"></canvas>');
+    });
+
+    // daily mean as a line, the range between min and max shaded
+    function draw(m, days) {
+        var canvas = $('#' + m.key)[0];
+        var ctx = canvas.getContext("2d");
+
+        canvas.width = canvas.clientWidth;
+        canvas.height = canvas.clientHeight;
+
+        ctx.fillStyle = "#000";
+        ctx.fillRect(0, 0, canvas.width, canvas.height);
+        ctx.font = "12px sans-serif";
+
+        var points = [];
+        days.forEach(function(d, i) {
+            if (d[m.key] !== null) {
+                points.push({ i: i, date: d.date, s: d[m.key] });
+            }
+        });
+
+        if (points.length === 0) {
+            ctx.fillStyle = "#888";
+            ctx.fillText("No data", 10, 20);
+            return;
+        }
+
+        var lo = Math.min.apply(null, points.map(function(p) { return p.s.min; }));
+        var hi = Math.max.apply(null, points.map(function(p) { return p.s.max; }));
+        if (hi - lo < 1) {
+            lo -= 0.5;
+            hi += 0.5;
+        }
+
+        var left = 50, right = canvas.width - 10, top = 10, bottom = canvas.height - 20;
+        function x(i) {
+            return days.length > 1 ? left + (right - left) * i / (days.length - 1) : (left + right) / 2;
+        }
+        function y(v) {
+            return bottom - (bottom - top) * (v - lo) / (hi - lo);
+        }
+
+        ctx.strokeStyle = "#444";
+        ctx.strokeRect(left, top, right - left, bottom - top);
+        ctx.fillStyle = "#888";
+        ctx.fillText(hi.toFixed(1), 5, top + 10);
+        ctx.fillText(lo.toFixed(1), 5, bottom);
+        ctx.fillText(days[0].date, left, canvas.height - 5);
+        ctx.fillText(days[days.length - 1].date, right - 70, canvas.height - 5);
+
+        ctx.globalAlpha = 0.3;
+        ctx.fillStyle = m.color;
+        points.forEach(function(p) {
+            ctx.fillRect(x(p.i) - 2, y(p.s.max), 4, Math.max(y(p.s.min) - y(p.s.max), 1));
+        });
+        ctx.globalAlpha = 1;
+
+        ctx.strokeStyle = m.color;
+        ctx.beginPath();
+        points.forEach(function(p, n) {
+            if (n === 0) {
+                ctx.moveTo(x(p.i), y(p.s.mean));
+            } else {
+                ctx.lineTo(x(p.i), y(p.s.mean));
+            }
+        });
+        ctx.stroke();
+    }
+
+    var days = [];
+
+    function redraw() {
+        $('#days').text(days.length);
+        metrics.forEach(function(metric) {
+            draw(metric, days);
+        });
+    }
+
+    // the history once, then today as it is summarized
+    $.getJSON(history, function(h) {
+        days = h.days;
+        redraw();
+
+        var ws = new WebSocket(uri);
+        ws.onopen = function(evt) { $('#conn_stat').text('Connected'); };
+        ws.onclose = function(evt) { $('#conn_stat').text('Disconnected'); };
+        ws.onmessage = function(evt) {
+            var m = JSON.parse(evt.data);
+
+            if (m.type === "Antenna") {
+                if (days.length > 0 && days[days.length - 1].date === m.date) {
+                    days.pop();
+                }
+                days.push(m);
+                redraw();
+            }
+        };
+        ws.onerror = function(evt) { console.log(evt) };
+    });
+
+    redraw();
+})(jQuery);
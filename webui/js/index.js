@@ -1,5 +1,5 @@
 (function($) {
-    var uri = "ws://192.168.0.1:9001";
+    var uri = "ws://192.168.0.1:9001/?types=GNSS,Ownship,E6B,DecodeStats,Navigation,Clients,Loopback";
     var constellation = {
         "GPS": "🇺🇸",
         "SBAS": "SBAS ",
@@ -12,6 +12,8 @@
         "TwoDim": "Two dimensional",
         "ThreeDim": "Three dimensional",
         "SBAS": "SBAS (WAAS/EGNOS)",
+        "RTKFloat": "RTK float",
+        "RTKFixed": "RTK fixed",
         "Unknown": "Unknown"
     }
 
@@ -25,6 +27,30 @@
         0: ""
     }
 
+    // bands tracked, those not used for navigation in parentheses
+    function bands(signals) {
+        return signals.map(function(g) {
+            return g.in_solution ? g.band : "(" + g.band + ")";
+        }).join(" ");
+    }
+
+    var client_states = {
+        "asleep": "Asleep",
+        "background": "In background",
+        "in_app": "In app",
+    };
+
+    function time_of_day(unix) {
+        return new Date(unix * 1000).toLocaleTimeString();
+    }
+
+    function data_size(bytes) {
+        if (bytes >= 1048576) {
+            return (bytes / 1048576).toFixed(1) + " MB";
+        }
+        return (bytes / 1024).toFixed(0) + " KB";
+    }
+
     var ws = new WebSocket(uri);
     ws.onopen = function(evt) { $('#conn_stat').text('Connected'); };
     ws.onclose = function(evt) { $('#conn_stat').text('Disconnected'); };
@@ -36,6 +62,17 @@
                 $("#num_sv").text(m.num_sv);
                 $("#fix_quality").text(fix_quality[m.quality]);
 
+                if (m.h_accuracy !== null && m.v_accuracy !== null) {
+                    $("#accuracy").text((m.h_accuracy / 1000).toFixed(1) + "m / " +
+                                        (m.v_accuracy / 1000).toFixed(1) + "m");
+                }
+
+                if (m.dop !== null) {
+                    $("#dop").text(m.dop.pdop.toFixed(2) + " / " +
+                                   m.dop.hdop.toFixed(2) + " / " +
+                                   m.dop.vdop.toFixed(2));
+                }
+
                 var html = "";
                 for (i = 0; i < m.sv_status.length; i++) {
                     var s = m.sv_status[i];
@@ -45,6 +82,7 @@
                                 "<td>" + (s.acquired ? "Yes" : "No") + "</td>" +
                                 "<td>" + (s.in_solution ? "Yes" : "No") + "</td>" +
                                 "<td>" + s.signal + "</td>" +
+                                "<td>" + bands(s.signals) + "</td>" +
                                 "<td>" + s.azimuth + "</td>" +
                                 "<td>" + s.elevation + "</td>" +
                             "</tr>"
@@ -58,14 +96,106 @@
                 $('#lon').text(m.lon.toFixed(4));
                 $('#msl_alt').text(m.msl_altitude);
                 $('#true_track').text(m.true_track.toFixed(0));
+                if (m.magnetic_track !== null) {
+                    $('#magnetic_track').text(m.magnetic_track.toFixed(0));
+                }
                 $('#nacp').text('(' + nacp[m.nacp] + ')');
                 $('#gs').text(m.gs.toFixed(0));
                 $('#vs').text(m.vs);
                 $('#pres_alt').text(m.pressure_altitude);
+                if (m.ias !== null) {
+                    $('#ias').text(m.ias.toFixed(0));
+                }
+                if (m.tas !== null) {
+                    $('#tas').text(m.tas.toFixed(0));
+                }
+                if (m.baro_temperature !== null) {
+                    $('#baro_temp').text(m.baro_temperature.toFixed(1));
+                }
+                break;
+
+            case "E6B":
+                if (m.wind_direction !== null) {
+                    $('#wind').text(m.wind_direction.toFixed(0) + "° at " +
+                                    m.wind_speed.toFixed(0) + " kts");
+                }
+                if (m.waypoint_distance !== null) {
+                    var ete = "";
+                    if (m.waypoint_ete !== null) {
+                        ete = ", ETE " + Math.floor(m.waypoint_ete / 60) + " min";
+                    }
+                    $('#waypoint').text(m.waypoint_distance.toFixed(1) + " NM" + ete);
+                }
+                break;
+
+            case "DecodeStats":
+                var corrected = "-";
+                if (m.band === "UAT") {
+                    var frames = m.downlink_frames + m.uplink_frames;
+                    corrected = (frames > 0 ? (m.corrected_frames / frames * 100).toFixed(0) : 0) +
+                                "% (" + m.corrected_errors + " bytes)";
+                }
+
+                $('#decode_' + m.band).html("<td>" + (m.band === "ES" ? "1090ES" : m.band) + "</td>" +
+                                            "<td>" + (m.downlink_frames / m.interval).toFixed(1) + "</td>" +
+                                            "<td>" + (m.uplink_frames / m.interval).toFixed(1) + "</td>" +
+                                            "<td>" + corrected + "</td>");
+                break;
+
+            case "Navigation":
+                if (m.active_waypoint === null) {
+                    $('#route').text(m.route.length > 0 ? "Complete" : "Not loaded");
+                } else if (m.distance !== null) {
+                    var ete = "";
+                    if (m.ete !== null) {
+                        ete = ", ETE " + Math.floor(m.ete / 60) + " min";
+                    }
+                    $('#route').text("WPT " + (m.active_waypoint + 1) + "/" + m.route.length +
+                                     ": " + m.bearing.toFixed(0) + "°, " +
+                                     m.distance.toFixed(1) + " NM" + ete +
+                                     ", XTK " + m.cross_track.toFixed(2) + " NM");
+                }
+                break;
+
+            case "Clients":
+                var rows = m.clients.map(function(c) {
+                    var state = !c.active ? "Asleep" : (c.in_app ? "In app" : "In background");
+                    var since = c.history.length > 0 ? c.history[c.history.length - 1].time : c.connected;
+                    // full history on hover
+                    var history = c.history.map(function(t) {
+                        return time_of_day(t.time) + " " + client_states[t.state];
+                    }).join("\n");
+
+                    // the app name comes from the network, not inserted as HTML
+                    return $("<tr>").append($("<td>").text(c.ip),
+                                            $("<td>").text(c.app === null ? "Unknown" : c.app),
+                                            $("<td>").text(c.ports.join(", ")),
+                                            $("<td>").text(state + " since " + time_of_day(since))
+                                                     .attr("title", history),
+                                            $("<td>").text(data_size(c.bytes_sent)),
+                                            $("<td>").text(c.last_seen.toFixed(0) + " s ago"),
+                                            $("<td>").text(c.replays));
+                });
+                $('#clients tbody').empty().append(rows);
+                break;
+
+            case "Loopback":
+                $('#loopback').text(m.pass ? "Pass" : "FAIL: " + m.problems.join(", "));
                 break;
         }
     };
     ws.onerror = function(evt) { console.log(evt) };
 
+    $('#update_upload').click(function() {
+        var f = $('#update_bundle')[0].files[0];
+        if (f === undefined) {
+            return;
+        }
+
+        var reader = new FileReader();
+        reader.onload = function() { ws.send(reader.result); };
+        reader.readAsArrayBuffer(f);
+    });
+
 
 })(jQuery);
@@ -0,0 +1,256 @@
+(function($) {
+    var uri = "ws://192.168.0.1:9001/?types=Ownship,Traffic";
+    var ranges = [2, 5, 10, 20, 40, 80];
+    var range_index = 2;
+    // replayed targets not updated for this long are removed, in ms. Live
+    // targets are removed by Pitot.
+    var stale = 60000;
+    var trail_length = 30;
+    // characters of a callsign shown, as many as a callsign can have
+    var callsign_width = 8;
+
+    var ownship = null;
+    var targets = {};
+    // sequence number of the last traffic delta applied
+    var seq = null;
+
+    var canvas = $('#map')[0];
+    var ctx = canvas.getContext("2d");
+
+    // equirectangular projection around ownship, good enough within 100 NM
+    function project(lat, lon, scale) {
+        var dy = (lat - ownship.lat) * 60;
+        var dx = (lon - ownship.lon) * 60 * Math.cos(ownship.lat * Math.PI / 180);
+
+        return [canvas.width / 2 + dx * scale, canvas.height / 2 - dy * scale];
+    }
+
+    // ownship altitude comparable to traffic altitude, in ft
+    function ownship_altitude() {
+        return ownship.pressure_altitude !== null ? ownship.pressure_altitude : ownship.msl_altitude;
+    }
+
+    // callsign (or address) followed by relative altitude in hundreds of ft,
+    // only the callsign is cut so the altitude always reads in full
+    function label(t) {
+        var name = t.callsign !== null ? t.callsign.trim().slice(0, callsign_width) : ("000000" + t.addr.toString(16).toUpperCase()).slice(-6);
+
+        if (t.altitude !== null) {
+            var rel = Math.round((t.altitude - ownship_altitude()) / 100);
+            name += " " + (rel >= 0 ? "+" : "-") + (Math.abs(rel) < 10 ? "0" : "") + Math.abs(rel);
+            if (t.vs !== null && Math.abs(t.vs) >= 500) {
+                name += t.vs > 0 ? "↑" : "↓";
+            }
+        }
+
+        if (t.ident) {
+            name += " IDENT";
+        }
+
+        return name;
+    }
+
+    function draw() {
+        canvas.width = canvas.clientWidth;
+        canvas.height = canvas.clientHeight;
+
+        ctx.fillStyle = "#000";
+        ctx.fillRect(0, 0, canvas.width, canvas.height);
+
+        if (ownship === null) {
+            ctx.fillStyle = "#fff";
+            ctx.font = "16px sans-serif";
+            ctx.fillText("Waiting for GNSS fix", 10, 20);
+            return;
+        }
+
+        var range = ranges[range_index];
+        var scale = Math.min(canvas.width, canvas.height) / 2 / range;
+        var cx = canvas.width / 2, cy = canvas.height / 2;
+
+        // range rings
+        ctx.strokeStyle = "#444";
+        ctx.fillStyle = "#888";
+        ctx.font = "12px sans-serif";
+        [range / 2, range].forEach(function(r) {
+            ctx.beginPath();
+            ctx.arc(cx, cy, r * scale, 0, 2 * Math.PI);
+            ctx.stroke();
+            ctx.fillText(r + " NM", cx + 3, cy - r * scale + 14);
+        });
+
+        // north up, ownship symbol points along its track
+        ctx.save();
+        ctx.translate(cx, cy);
+        ctx.rotate(ownship.true_track * Math.PI / 180);
+        ctx.fillStyle = "#0f0";
+        ctx.beginPath();
+        ctx.moveTo(0, -10);
+        ctx.lineTo(7, 8);
+        ctx.lineTo(0, 4);
+        ctx.lineTo(-7, 8);
+        ctx.closePath();
+        ctx.fill();
+        ctx.restore();
+
+        var now = Date.now();
+        var count = 0;
+
+        for (var addr in targets) {
+            var t = targets[addr];
+
+            if (t.replayed && now - t.updated > stale) {
+                delete targets[addr];
+                continue;
+            }
+
+            count++;
+
+            if (t.trail.length === 0) {
+                continue;
+            }
+
+            var rel = t.data.altitude !== null ? t.data.altitude - ownship_altitude() : null;
+            var color = (rel !== null && Math.abs(rel) <= 1000) ? "#ff0" : "#0ff";
+            if (t.data.ident) {
+                color = "#fff";
+            }
+
+            ctx.strokeStyle = color;
+            ctx.beginPath();
+            for (var i = 0; i < t.trail.length; i++) {
+                var p = project(t.trail[i][0], t.trail[i][1], scale);
+                if (i === 0) {
+                    ctx.moveTo(p[0], p[1]);
+                } else {
+                    ctx.lineTo(p[0], p[1]);
+                }
+            }
+            ctx.stroke();
+
+            var last = t.trail[t.trail.length - 1];
+            var pos = project(last[0], last[1], scale);
+
+            ctx.save();
+            ctx.translate(pos[0], pos[1]);
+            ctx.fillStyle = color;
+            if (t.data.heading !== null) {
+                ctx.rotate(t.data.heading * Math.PI / 180);
+                ctx.beginPath();
+                ctx.moveTo(0, -8);
+                ctx.lineTo(6, 6);
+                ctx.lineTo(-6, 6);
+                ctx.closePath();
+                ctx.fill();
+            } else {
+                ctx.beginPath();
+                ctx.arc(0, 0, 5, 0, 2 * Math.PI);
+                ctx.fill();
+            }
+            ctx.restore();
+
+            ctx.fillStyle = color;
+            ctx.font = "12px sans-serif";
+            ctx.fillText(label(t.data), pos[0] + 9, pos[1] + 4);
+        }
+
+        $('#count').text(count);
+    }
+
+    function update_target(m, replayed) {
+        if (m.on_ground) {
+            delete targets[m.addr];
+            return;
+        }
+
+        var t = targets[m.addr];
+        if (t === undefined) {
+            t = targets[m.addr] = { trail: [] };
+        }
+
+        t.data = m;
+        t.updated = Date.now();
+        t.replayed = replayed;
+
+        if (m.lat_lon !== null) {
+            var last = t.trail[t.trail.length - 1];
+            if (last === undefined || last[0] !== m.lat_lon[0] || last[1] !== m.lat_lon[1]) {
+                t.trail.push(m.lat_lon);
+                if (t.trail.length > trail_length) {
+                    t.trail.shift();
+                }
+            }
+        }
+    }
+
+    var ws = new WebSocket(uri);
+    ws.onopen = function(evt) { $('#conn_stat').text('Connected'); };
+    ws.onclose = function(evt) { $('#conn_stat').text('Disconnected'); };
+    ws.onmessage = function(evt) {
+        var m = JSON.parse(evt.data);
+
+        switch (m.type) {
+            case "Ownship":
+                if (m.valid) {
+                    ownship = m;
+                }
+                break;
+
+            case "TrafficSnapshot":
+                var known = {};
+                m.targets.forEach(function(t) {
+                    update_target(t, false);
+                    known[t.addr] = true;
+                });
+                for (var addr in targets) {
+                    if (!known[addr]) {
+                        delete targets[addr];
+                    }
+                }
+                seq = m.seq;
+                break;
+
+            case "TrafficAdd":
+            case "TrafficUpdate":
+            case "TrafficRemove":
+                // deltas already included in the snapshot
+                if (seq === null || m.seq <= seq) {
+                    break;
+                }
+                if (m.seq !== seq + 1) {
+                    seq = null;
+                    ws.send(JSON.stringify({ type: "TrafficResync" }));
+                    break;
+                }
+                seq = m.seq;
+
+                if (m.type === "TrafficRemove") {
+                    delete targets[m.addr];
+                } else {
+                    update_target(m, false);
+                }
+                break;
+
+            // replayed logs
+            case "Traffic":
+                update_target(m, true);
+                break;
+        }
+    };
+    ws.onerror = function(evt) { console.log(evt) };
+
+    $('#zoom_in').click(function() {
+        range_index = Math.max(range_index - 1, 0);
+        $('#range').text(ranges[range_index]);
+        draw();
+    });
+
+    $('#zoom_out').click(function() {
+        range_index = Math.min(range_index + 1, ranges.length - 1);
+        $('#range').text(ranges[range_index]);
+        draw();
+    });
+
+    setInterval(draw, 1000);
+    draw();
+})(jQuery);
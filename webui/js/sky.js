@@ -0,0 +1,104 @@
+(function($) {
+    var uri = "ws://192.168.0.1:9001/?types=SkyView";
+    var colors = {
+        "GPS": "#4af",
+        "SBAS": "#fff",
+        "Galileo": "#fc0",
+        "GLONASS": "#f55",
+        "Unknown": "#888"
+    };
+
+    var canvas = $('#sky')[0];
+    var ctx = canvas.getContext("2d");
+
+    // the horizon is the outer ring and the zenith the center, north up
+    function project(azimuth, elevation, radius) {
+        var r = radius * (90 - Math.max(elevation, 0)) / 90;
+        var a = azimuth * Math.PI / 180;
+
+        return [canvas.width / 2 + r * Math.sin(a), canvas.height / 2 - r * Math.cos(a)];
+    }
+
+    function draw(sky) {
+        canvas.width = canvas.clientWidth;
+        canvas.height = canvas.clientHeight;
+
+        var cx = canvas.width / 2, cy = canvas.height / 2;
+        var radius = Math.min(cx, cy) - 20;
+
+        ctx.fillStyle = "#000";
+        ctx.fillRect(0, 0, canvas.width, canvas.height);
+
+        // elevation rings every 30 degrees
+        ctx.strokeStyle = "#444";
+        ctx.fillStyle = "#888";
+        ctx.font = "12px sans-serif";
+        [0, 30, 60].forEach(function(e) {
+            ctx.beginPath();
+            ctx.arc(cx, cy, radius * (90 - e) / 90, 0, 2 * Math.PI);
+            ctx.stroke();
+            ctx.fillText(e + "°", cx + 3, cy - radius * (90 - e) / 90 + 14);
+        });
+
+        ["N", "E", "S", "W"].forEach(function(d, i) {
+            var p = project(i * 90, -8, radius);
+            ctx.fillText(d, p[0] - 4, p[1] + 4);
+        });
+
+        if (sky === null) {
+            return;
+        }
+
+        var legend = "";
+
+        for (var system in sky.constellations) {
+            var sats = sky.constellations[system];
+            var color = colors[system];
+
+            legend += '<b style="color: ' + color + '">' + system + ":</b> ";
+
+            for (var i = 0; i < sats.length; i++) {
+                var s = sats[i];
+                var p = project(s.azimuth, s.elevation, radius);
+
+                // filled when used in solution, outlined otherwise
+                ctx.beginPath();
+                ctx.arc(p[0], p[1], 10, 0, 2 * Math.PI);
+                ctx.strokeStyle = color;
+                ctx.fillStyle = color;
+                if (s.in_solution) {
+                    ctx.fill();
+                } else {
+                    ctx.stroke();
+                }
+
+                ctx.fillStyle = s.in_solution ? "#000" : color;
+                ctx.fillText(s.sv_id, p[0] - 7, p[1] + 4);
+
+                legend += s.sv_id + " (" + (s.snr !== null ? s.snr + " dBHz" : "no signal") +
+                          (s.bands.length > 0 ? ", " + s.bands.join("+") : "") +
+                          (s.healthy === false ? ", unhealthy" : "") + ") ";
+            }
+
+            legend += "<br>";
+        }
+
+        $('#legend').html(legend);
+        $('#used').text(sky.used);
+        $('#visible').text(sky.visible);
+    }
+
+    var ws = new WebSocket(uri);
+    ws.onopen = function(evt) { $('#conn_stat').text('Connected'); };
+    ws.onclose = function(evt) { $('#conn_stat').text('Disconnected'); };
+    ws.onmessage = function(evt) {
+        var m = JSON.parse(evt.data);
+
+        if (m.type === "SkyView") {
+            draw(m);
+        }
+    };
+    ws.onerror = function(evt) { console.log(evt) };
+
+    draw(null);
+})(jQuery);